@@ -0,0 +1,9 @@
+#!/usr/bin/env python3
+
+import os
+
+print('Content-Type: text/plain\r')
+print('\r')
+for name in ['SERVER_NAME', 'SERVER_PORT', 'REMOTE_ADDR', 'SCRIPT_NAME', 'PATH_INFO',
+             'PATH', 'HOME']:
+    print('{}={}'.format(name, os.environ.get(name, '')))
@@ -0,0 +1,14 @@
+#!/usr/bin/env python3
+
+import os
+
+if __name__ == '__main__':
+    targets = {
+        'local': '/test/foo.html',
+        'script': '/cgi-bin/hello_world.py',
+        'self': '/cgi-bin/redirect.py?to=self',
+        'client': 'http://example.com/',
+    }
+
+    print('Location: {}\r'.format(targets[os.environ.get('QUERY_STRING', '')[len('to='):]]))
+    print('\r')
@@ -0,0 +1,22 @@
+#!/usr/bin/env python3
+
+import os
+import sys
+import time
+
+# or never even gets as far as its header block
+if os.environ.get('QUERY_STRING') == 'silent':
+    time.sleep(60)
+
+print('Content-Type: text/plain\r')
+print('\r')
+sys.stdout.flush()
+
+# never stops: either floods its output or trickles it out
+while True:
+    if os.environ.get('QUERY_STRING') == 'slow':
+        print('still going')
+        sys.stdout.flush()
+        time.sleep(0.1)
+    else:
+        print('x' * 1024)
@@ -0,0 +1,7 @@
+#!/usr/bin/env python3
+
+print("Status: 418 I'm a teapot\r")
+print('Content-Type: text/plain\r')
+print('X-Teapot: yes\r')
+print('\r')
+print('short and stout')
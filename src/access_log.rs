@@ -0,0 +1,163 @@
+//! A line per request in the Combined Log Format, kept apart from the server's own (debug) log.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{LineWriter, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, TimeZone};
+
+/// Where each line goes, shared between all of the connection coroutines.
+pub struct AccessLog {
+    path: PathBuf,
+    out: Mutex<Box<Write + Send>>,
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AccessLog").field("path", &self.path).finish()
+    }
+}
+
+impl AccessLog {
+    /// Log to a file, appending to whatever it already has, or to stdout if the path is `-`.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let out: Box<Write + Send> = if path == Path::new("-") {
+            Box::new(io::stdout())
+        } else {
+            let file = try!(OpenOptions::new().create(true).append(true).open(path));
+            // a line at a time, so the log can be followed as it's written
+            Box::new(LineWriter::new(file))
+        };
+
+        Ok(AccessLog {
+            path: path.to_owned(),
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn record(&self, entry: &Entry) {
+        let since_epoch = entry.time.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+        let time = Local.timestamp(since_epoch.as_secs() as i64, 0)
+            .format("%d/%b/%Y:%H:%M:%S %z")
+            .to_string();
+        let line = format_line(entry, &time);
+
+        let mut out = self.out.lock().unwrap();
+        if let Err(why) = writeln!(out, "{}", line) {
+            warn!("Unable to write to the access log {:?}: {:?}", self.path, why);
+        }
+    }
+}
+
+/// What's logged about one response.
+#[derive(Debug)]
+pub struct Entry<'a> {
+    /// When the response was sent.
+    pub time: SystemTime,
+    pub remote: IpAddr,
+    /// As the client sent it (see `request_line`), which is logged even when it doesn't parse.
+    pub request_line: &'a [u8],
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub status: u16,
+    /// How many of the body's bytes made it to the client.
+    pub body_bytes: usize,
+    /// From having the whole request to having sent the response.
+    pub duration: Duration,
+}
+
+/// The first line of a request, without its line ending.
+pub fn request_line(bytes: &[u8]) -> &[u8] {
+    let line = match bytes.iter().position(|&b| b == b'\n') {
+        Some(i) => &bytes[..i],
+        None => bytes,
+    };
+
+    if line.ends_with(b"\r") {
+        &line[..line.len() - 1]
+    } else {
+        line
+    }
+}
+
+/// E.g. `127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 2326 "-" "curl/7.50"
+/// 1042`: the Combined Log Format, followed by the time taken in microseconds (like Apache's
+/// `%D`).
+fn format_line(entry: &Entry, time: &str) -> String {
+    let body_bytes = match entry.body_bytes {
+        0 => "-".to_owned(),
+        n => n.to_string(),
+    };
+
+    let micros = entry.duration.as_secs() * 1_000_000 + entry.duration.subsec_nanos() as u64 / 1000;
+
+    format!("{} - - [{}] {} {} {} {} {} {}",
+            entry.remote,
+            time,
+            quoted(entry.request_line),
+            entry.status,
+            body_bytes,
+            quoted(entry.referer.unwrap_or("-").as_bytes()),
+            quoted(entry.user_agent.unwrap_or("-").as_bytes()),
+            micros)
+}
+
+/// A field in double quotes, with anything which could be mistaken for the end of it, or for the
+/// end of the line, escaped.
+fn quoted(field: &[u8]) -> String {
+    let mut quoted = String::with_capacity(field.len() + 2);
+    quoted.push('"');
+
+    for &b in field {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b' '..=b'~' => quoted.push(b as char),
+            _ => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let mut entry = Entry {
+            time: UNIX_EPOCH,
+            remote: "127.0.0.1".parse::<IpAddr>().unwrap(),
+            request_line: request_line(b"GET /test/foo.html HTTP/1.1\r\nHost: x\r\n\r\n"),
+            referer: Some("http://example.com/"),
+            user_agent: None,
+            status: 200,
+            body_bytes: 28,
+            duration: Duration::from_millis(3),
+        };
+
+        assert_eq!(format_line(&entry, "14/Oct/2026:17:25:14 +0000"),
+                   "127.0.0.1 - - [14/Oct/2026:17:25:14 +0000] \"GET /test/foo.html HTTP/1.1\" \
+                    200 28 \"http://example.com/\" \"-\" 3000");
+
+        entry.request_line = b"GET /\"quoted\"\x1b[0m";
+        entry.status = 404;
+        entry.body_bytes = 0;
+        assert_eq!(format_line(&entry, "14/Oct/2026:17:25:14 +0000"),
+                   "127.0.0.1 - - [14/Oct/2026:17:25:14 +0000] \"GET /\\\"quoted\\\"\\x1b[0m\" \
+                    404 - \"http://example.com/\" \"-\" 3000");
+
+        assert_eq!(request_line(b"GET / HTTP/1.1\n"), b"GET / HTTP/1.1");
+        assert_eq!(request_line(b"GET /"), b"GET /");
+    }
+}
@@ -0,0 +1,369 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use std::vec;
+
+use config::Config;
+
+const BLOCK: u64 = 512;
+
+/// Largest file a ustar header can describe: its size field holds 11 octal digits.
+const MAX_FILE_SIZE: u64 = 0o77777777777;
+
+/// Something to be put in an archive, as found when the directory was walked.
+struct Entry {
+    /// Name within the archive, `/`-separated, with a trailing slash for directories.
+    name: String,
+    path: PathBuf,
+    is_dir: bool,
+    size: u64,
+    mtime: u64,
+}
+
+/// A tar (ustar) archive of a directory, generated as it's read.
+///
+/// The directory is walked up front so the archive's length can be given in advance, but file
+/// contents are only read as they're needed, so memory use doesn't grow with their size. A file
+/// which changes in the meantime is cut short or padded out with zeroes to the size recorded, so
+/// the archive never ends up a different length than promised.
+pub struct Archive {
+    entries: vec::IntoIter<Entry>,
+    current: Box<Read>,
+    finished: bool,
+}
+
+/// Archive the directory at a (slash-stripped) URI, named for the directory, along with its exact
+/// length, or `None` if it isn't a directory under the config's root.
+///
+/// Only directories and regular files are included; symlinks and anything else are skipped, as
+/// is anything hidden or with a name too long for a ustar header. So is anything which a request
+/// for the directory wouldn't have been let at by itself: CGI scripts, whose source is never
+/// served, and anything protected by other credentials than the directory.
+pub fn tar(config: &Config, uri: &str) -> Option<(Archive, u64, String)> {
+    let uri = uri.trim_matches('/');
+    let dir = config.root_dir.join(uri);
+
+    let (root, dir) = match (config.root_dir.canonicalize(), dir.canonicalize()) {
+        (Ok(root), Ok(dir)) => (root, dir),
+        _ => return None,
+    };
+
+    if !dir.starts_with(&root) || !dir.is_dir() {
+        return None;
+    }
+
+    let base = match dir.file_name() {
+        Some(n) if dir != root => n.to_string_lossy().into_owned(),
+        _ => "root".to_owned(),
+    };
+
+    let walker = Walker {
+        config: config,
+        auth_prefix: config.auth_prefix_for(uri),
+        cgi_paths: config.cgi_paths(),
+    };
+
+    if walker.cgi_paths.iter().any(|p| dir.starts_with(p)) {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    walker.walk(&dir, uri, &format!("{}/", base), &mut entries);
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let len = entries.iter().map(|e| BLOCK + padded(e.size)).sum::<u64>() + 2 * BLOCK;
+
+    let archive = Archive {
+        entries: entries.into_iter(),
+        current: Box::new(io::empty()),
+        finished: false,
+    };
+
+    Some((archive, len, format!("{}.tar", base)))
+}
+
+/// What decides which of the files under the directory go in an archive of it.
+struct Walker<'a> {
+    config: &'a Config,
+    /// The protected prefix whose check the request for the directory passed, if any.
+    auth_prefix: Option<&'a str>,
+    cgi_paths: Vec<PathBuf>,
+}
+
+impl<'a> Walker<'a> {
+    /// Whether to put something, by its (slash-stripped) URI and path, in the archive.
+    fn includes(&self, uri: &str, path: &Path) -> bool {
+        if self.config.auth_prefix_for(uri) != self.auth_prefix {
+            debug!("Leaving {:?} out of an archive, it's protected separately", path);
+            false
+        } else if self.config.cgi_dir_for(uri).is_some() ||
+                  self.cgi_paths.iter().any(|p| path.starts_with(p)) {
+            debug!("Leaving CGI script {:?} out of an archive", path);
+            false
+        } else {
+            true
+        }
+    }
+
+    fn walk(&self, dir: &Path, uri: &str, name: &str, entries: &mut Vec<Entry>) {
+        let metadata = match fs::symlink_metadata(dir) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+
+        let entry = Entry {
+            name: name.to_owned(),
+            path: dir.to_path_buf(),
+            is_dir: true,
+            size: 0,
+            mtime: mtime(&metadata),
+        };
+
+        // anything inside would have an even longer name
+        if header(&entry).is_none() {
+            debug!("Leaving {:?} out of an archive, its name is too long", dir);
+            return;
+        }
+        entries.push(entry);
+
+        let children = match fs::read_dir(dir) {
+            Ok(c) => c,
+            Err(why) => {
+                debug!("Leaving the contents of {:?} out of an archive: {:?}", dir, why);
+                return;
+            }
+        };
+
+        for child in children.filter_map(|c| c.ok()) {
+            let file_name = child.file_name().to_string_lossy().into_owned();
+            if self.config.is_hidden(&file_name) {
+                continue;
+            }
+
+            let path = child.path();
+            let child_uri = if uri.is_empty() {
+                file_name.clone()
+            } else {
+                format!("{}/{}", uri, file_name)
+            };
+            let child_name = format!("{}{}", name, file_name);
+
+            if !self.includes(&child_uri, &path) {
+                continue;
+            }
+
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+
+            if metadata.is_dir() {
+                self.walk(&path, &child_uri, &format!("{}/", child_name), entries);
+            } else if metadata.is_file() && metadata.len() <= MAX_FILE_SIZE {
+                let entry = Entry {
+                    name: child_name,
+                    path: path,
+                    is_dir: false,
+                    size: metadata.len(),
+                    mtime: mtime(&metadata),
+                };
+
+                if header(&entry).is_some() {
+                    entries.push(entry);
+                } else {
+                    debug!("Leaving {:?} out of an archive, its name is too long", entry.path);
+                }
+            } else {
+                debug!("Leaving {:?} out of an archive", path);
+            }
+        }
+    }
+}
+
+fn mtime(metadata: &fs::Metadata) -> u64 {
+    match metadata.modified().map(|m| m.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(d)) => d.as_secs(),
+        _ => 0,
+    }
+}
+
+/// File data is padded out to a whole number of blocks.
+fn padded(size: u64) -> u64 {
+    (size + BLOCK - 1) / BLOCK * BLOCK
+}
+
+/// Write `value` as a NUL-terminated, zero-padded octal number filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()] = 0;
+}
+
+/// The ustar header block for an entry, or `None` if its name won't fit.
+fn header(entry: &Entry) -> Option<[u8; 512]> {
+    let name = entry.name.as_bytes();
+
+    // names over 100 bytes have to be split at a slash, with the first part going in the prefix
+    let (prefix, name) = if name.len() <= 100 {
+        (&b""[..], name)
+    } else {
+        let search_end = ::std::cmp::min(name.len() - 1, 156);
+        match name[..search_end].iter().rposition(|&b| b == b'/') {
+            Some(i) if i <= 155 && name.len() - i - 1 <= 100 => (&name[..i], &name[i + 1..]),
+            _ => return None,
+        }
+    };
+
+    let mut block = [0; 512];
+    block[..name.len()].copy_from_slice(name);
+    octal(&mut block[100..108], if entry.is_dir { 0o755 } else { 0o644 });
+    octal(&mut block[108..116], 0); // uid
+    octal(&mut block[116..124], 0); // gid
+    octal(&mut block[124..136], entry.size);
+    octal(&mut block[136..148], entry.mtime);
+    block[156] = if entry.is_dir { b'5' } else { b'0' };
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix);
+
+    // the checksum is calculated as if its own field were all spaces
+    for b in &mut block[148..156] {
+        *b = b' ';
+    }
+    let checksum = block.iter().map(|&b| b as u64).sum::<u64>();
+    octal(&mut block[148..155], checksum);
+
+    Some(block)
+}
+
+impl Archive {
+    /// Everything the archive holds for one entry: its header, then its contents (exactly as
+    /// long as the header says) padded out to a whole block.
+    fn open(entry: Entry) -> Box<Read> {
+        // only entries with names which fit were collected
+        let header = header(&entry).unwrap();
+
+        let contents: Box<Read> = if entry.is_dir {
+            Box::new(io::empty())
+        } else {
+            match File::open(&entry.path) {
+                Ok(f) => Box::new(f.chain(io::repeat(0)).take(entry.size)),
+                Err(why) => {
+                    warn!("Unable to add {:?} to an archive: {:?}", entry.path, why);
+                    Box::new(io::repeat(0).take(entry.size))
+                }
+            }
+        };
+
+        let padding = padded(entry.size) - entry.size;
+
+        Box::new(Cursor::new(header.to_vec())
+            .chain(contents)
+            .chain(io::repeat(0).take(padding)))
+    }
+}
+
+impl Read for Archive {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = try!(self.current.read(buf));
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            self.current = match self.entries.next() {
+                Some(entry) => Archive::open(entry),
+                // two empty blocks mark the end of the archive
+                None if !self.finished => {
+                    self.finished = true;
+                    Box::new(io::repeat(0).take(2 * BLOCK))
+                }
+                None => return Ok(0),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::str;
+
+    use config::Config;
+
+    use super::*;
+
+    fn field(block: &[u8]) -> &str {
+        str::from_utf8(block).unwrap().trim_end_matches('\0')
+    }
+
+    #[test]
+    fn tar_of_directory() {
+        let root = Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let (mut archive, len, filename) = tar(&root, "test/site/").unwrap();
+        assert_eq!(filename, "site.tar");
+
+        let mut bytes = Vec::new();
+        archive.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, len);
+        assert_eq!(len, 512 + (512 + 512) + 1024);
+
+        assert_eq!(field(&bytes[..100]), "site/");
+        assert_eq!(bytes[156], b'5');
+
+        let file = &bytes[512..];
+        assert_eq!(field(&file[..100]), "site/index.html");
+        assert_eq!(field(&file[124..136]), "00000000024");
+        assert_eq!(field(&file[257..263]), "ustar");
+        assert_eq!(&file[512..532], b"<h1>site index</h1>\n");
+        assert!(file[532..].iter().all(|&b| b == 0));
+
+        let mut unsummed = file[..512].to_vec();
+        for b in &mut unsummed[148..156] {
+            *b = b' ';
+        }
+        let checksum = unsummed.iter().map(|&b| b as u64).sum::<u64>();
+        assert_eq!(u64::from_str_radix(field(&file[148..155]), 8).unwrap(), checksum);
+
+        assert!(tar(&root, "test/foo.html").is_none());
+        assert!(tar(&Config::new(root.root_dir.join("test")), "..").is_none());
+    }
+
+    #[test]
+    fn hidden_entries() {
+        let root = Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let (mut archive, len, _) = tar(&root, "test/hidden").unwrap();
+
+        // the directory, .well-known and the file in it, and visible.txt
+        assert_eq!(len, 512 + 512 + (512 + 512) + (512 + 512) + 1024);
+        let mut bytes = Vec::new();
+        archive.read_to_end(&mut bytes).unwrap();
+        let names = bytes.chunks(512).map(|b| field(&b[..100]).to_owned()).collect::<Vec<_>>();
+        assert!(names.contains(&"hidden/visible.txt".to_owned()));
+        assert!(!names.iter().any(|n| n.contains(".env") || n.contains(".private")));
+    }
+
+    #[test]
+    fn long_names() {
+        let entry = |name: String| {
+            Entry {
+                name: name,
+                path: PathBuf::new(),
+                is_dir: false,
+                size: 0,
+                mtime: 0,
+            }
+        };
+
+        let split = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let block = header(&entry(split)).unwrap();
+        assert_eq!(field(&block[..100]), "f".repeat(90));
+        assert_eq!(field(&block[345..500]), "d".repeat(120));
+
+        assert!(header(&entry("f".repeat(101))).is_none());
+    }
+}
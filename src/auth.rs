@@ -0,0 +1,457 @@
+//! HTTP authentication for the URI prefixes which only some users may make requests under:
+//! Basic (RFC 7617) against the users given by `--auth`, or whatever an embedder plugs in with
+//! `Config::protect`.
+
+use std::fmt;
+use std::fmt::Debug;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+use config::AuthRule;
+use sha256;
+
+/// Something which decides whose credentials are good for a protected prefix, e.g. by looking
+/// users up in a database or checking tokens with an OAuth server.
+///
+/// Like a `Handler`, a provider is shared by every thread of the server and called on a mioco
+/// coroutine, so anything which blocks a thread holds up every other connection on it.
+pub trait AuthProvider: Debug + Send + Sync {
+    /// Whether a user name and password, from a `Basic` `Authorization` header, are good.
+    fn check_basic(&self, user: &str, password: &str) -> bool;
+
+    /// Whether a token, from a `Bearer` `Authorization` header, is good. None are, unless the
+    /// provider knows of some.
+    fn check_bearer(&self, _token: &str) -> bool {
+        false
+    }
+
+    /// The `WWW-Authenticate` challenge for a request without good credentials, in a realm
+    /// named after the protected prefix (e.g. `/admin`).
+    fn challenge(&self, realm: &str) -> String {
+        basic_challenge(realm)
+    }
+
+    /// Pick up any change to whatever credentials are checked against, as the server has every
+    /// provider do on a SIGHUP. There's nothing to pick up, unless the provider keeps a copy of
+    /// something which may change.
+    fn reload(&self) {}
+}
+
+/// An `AuthProvider` for everything under a URI prefix.
+#[derive(Clone, Debug)]
+pub struct AuthRoute {
+    /// Relative to the root, without a leading slash, as for a `Route`.
+    pub prefix: String,
+    pub provider: Arc<AuthProvider>,
+}
+
+/// What's kept of a password: `pbkdf2-sha256$ITERATIONS$SALT$KEY`, its PBKDF2-HMAC-SHA256 key
+/// derived with a salt of its own, both in hex, as `hppt hash-password` prints.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PasswordHash {
+    iterations: u32,
+    salt: Vec<u8>,
+    key: [u8; 32],
+}
+
+/// How many rounds of PBKDF2 `hash-password` asks for.
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+
+const SCHEME: &'static str = "pbkdf2-sha256";
+
+impl PasswordHash {
+    pub fn new(password: &str, salt: &[u8], iterations: u32) -> Self {
+        PasswordHash {
+            iterations: iterations,
+            salt: salt.to_vec(),
+            key: sha256::pbkdf2(password.as_bytes(), salt, iterations),
+        }
+    }
+
+    /// A hash of a password with a fresh random salt from the OS.
+    pub fn generate(password: &str) -> io::Result<Self> {
+        let mut salt = [0; 16];
+        try!(File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut salt)));
+        Ok(PasswordHash::new(password, &salt, DEFAULT_ITERATIONS))
+    }
+
+    /// Whether a password is the one hashed, taking as long to find out whichever it is.
+    pub fn verify(&self, password: &str) -> bool {
+        let key = sha256::pbkdf2(password.as_bytes(), &self.salt, self.iterations);
+        constant_time_eq(&key, &self.key)
+    }
+}
+
+impl FromStr for PasswordHash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.split('$');
+        let hash = match (parts.next(), parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some(SCHEME), Some(iterations), Some(salt), Some(key), None) => {
+                match (iterations.parse(), decode_hex(salt), sha256::from_hex(key)) {
+                    (Ok(iterations), Some(salt), Some(key)) if iterations > 0 => {
+                        Some(PasswordHash {
+                            iterations: iterations,
+                            salt: salt,
+                            key: key,
+                        })
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+
+        hash.ok_or_else(|| {
+            format!("{} is not of the form {}$ITERATIONS$SALT$KEY, as from `hppt hash-password`",
+                    s,
+                    SCHEME)
+        })
+    }
+}
+
+impl fmt::Display for PasswordHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{}${}${}${}",
+               SCHEME,
+               self.iterations,
+               sha256::hex(&self.salt),
+               sha256::hex(&self.key))
+    }
+}
+
+/// Users given along with the rest of the settings, as by `--auth`.
+pub struct StaticUsers {
+    users: Vec<(String, PasswordHash)>,
+}
+
+impl StaticUsers {
+    pub fn new(rules: &[&AuthRule]) -> Self {
+        StaticUsers { users: rules.iter().map(|r| (r.user.clone(), r.password.clone())).collect() }
+    }
+}
+
+// only the names, as the configuration is exported for sharing
+impl Debug for StaticUsers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names = self.users.iter().map(|u| &u.0).collect::<Vec<_>>();
+        write!(f, "StaticUsers {{ users: {:?} }}", names)
+    }
+}
+
+impl AuthProvider for StaticUsers {
+    fn check_basic(&self, user: &str, password: &str) -> bool {
+        check_users(&self.users, user, password)
+    }
+}
+
+/// Users listed in a file, one `USER:HASH` (a `PasswordHash` of their password) per line, with
+/// blank lines and those starting with `#` ignored. It's read when the provider's made, and again
+/// on `reload`, so users can be added or removed without restarting the server, but checks don't
+/// wait on the disk; a file which couldn't be read lets nobody in.
+#[derive(Debug)]
+pub struct UserFile {
+    path: PathBuf,
+    users: RwLock<Vec<(String, PasswordHash)>>,
+}
+
+impl UserFile {
+    pub fn new(path: PathBuf) -> Self {
+        let users = UserFile::read(&path);
+        UserFile {
+            path: path,
+            users: RwLock::new(users),
+        }
+    }
+
+    fn read(path: &PathBuf) -> Vec<(String, PasswordHash)> {
+        let mut contents = String::new();
+        if let Err(why) = File::open(path).and_then(|mut f| f.read_to_string(&mut contents)) {
+            warn!("Unable to read users from {:?}: {}", path, why);
+            return Vec::new();
+        }
+
+        parse_users(&contents)
+    }
+}
+
+impl AuthProvider for UserFile {
+    fn check_basic(&self, user: &str, password: &str) -> bool {
+        check_users(&self.users.read().unwrap(), user, password)
+    }
+
+    fn reload(&self) {
+        let users = UserFile::read(&self.path);
+        info!("Read {} users from {:?}", users.len(), self.path);
+        *self.users.write().unwrap() = users;
+    }
+}
+
+/// Whether an `Authorization` header value has credentials a provider says are good.
+pub fn authorized(provider: &AuthProvider, authorization: Option<&str>) -> bool {
+    let value = match authorization {
+        Some(v) => v.trim(),
+        None => return false,
+    };
+
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("Bearer") => {
+            provider.check_bearer(token.trim())
+        }
+        _ => {
+            match basic_credentials(value) {
+                Some((user, password)) => provider.check_basic(&user, &password),
+                None => false,
+            }
+        }
+    }
+}
+
+/// The `WWW-Authenticate` challenge asking for a user name and password in a realm.
+pub fn basic_challenge(realm: &str) -> String {
+    format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm.replace('"', ""))
+}
+
+/// Whether a user name and password are those of one of `users`, who come with hashes of their
+/// passwords.
+fn check_users(users: &[(String, PasswordHash)], user: &str, password: &str) -> bool {
+    // every password checked, so how long it takes doesn't say which user (if any) matched
+    users.iter().fold(false, |found, &(ref name, ref hash)| {
+        let matches = constant_time_eq(name.as_bytes(), user.as_bytes()) & hash.verify(password);
+        found | matches
+    })
+}
+
+/// The users in a file of `USER:HASH` lines, skipping any which aren't of that form.
+fn parse_users(contents: &str) -> Vec<(String, PasswordHash)> {
+    contents.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| {
+            let mut halves = l.splitn(2, ':');
+            match (halves.next(), halves.next().and_then(|h| h.parse().ok())) {
+                (Some(user), Some(hash)) if !user.is_empty() => Some((user.to_owned(), hash)),
+                _ => {
+                    warn!("Ignoring a line which isn't of the form USER:HASH in a user file");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// The user name and password in a `Basic` `Authorization` header value.
+fn basic_credentials(value: &str) -> Option<(String, String)> {
+    let mut parts = value.trim().splitn(2, ' ');
+
+    let encoded = match (parts.next(), parts.next()) {
+        (Some(scheme), Some(encoded)) if scheme.eq_ignore_ascii_case("Basic") => encoded.trim(),
+        _ => return None,
+    };
+
+    let decoded = match base64_decode(encoded).map(String::from_utf8) {
+        Some(Ok(d)) => d,
+        _ => return None,
+    };
+
+    let mut halves = decoded.splitn(2, ':');
+    match (halves.next(), halves.next()) {
+        (Some(user), Some(password)) => Some((user.to_owned(), password.to_owned())),
+        _ => None,
+    }
+}
+
+/// Decode (padded) base64, as in RFC 4648.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let bytes = encoded.as_bytes();
+    if bytes.len() % 4 != 0 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(bytes.len() / 4 * 3);
+
+    for (i, quad) in bytes.chunks(4).enumerate() {
+        let last = i == bytes.len() / 4 - 1;
+        let padding = quad.iter().rev().take_while(|&&b| b == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &b in &quad[..4 - padding] {
+            let value = match b {
+                b'+' => 62,
+                b'/' => 63,
+                _ if b.is_ascii_uppercase() => b - b'A',
+                _ if b.is_ascii_lowercase() => b - b'a' + 26,
+                _ if b.is_ascii_digit() => b - b'0' + 52,
+                _ => return None,
+            };
+            group = group << 6 | value as u32;
+        }
+        group <<= 6 * padding as u32;
+
+        let group = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        decoded.extend_from_slice(&group[..3 - padding]);
+    }
+
+    Some(decoded)
+}
+
+/// Bytes written in hex, e.g. a salt.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len() / 2).map(|i| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()).collect()
+}
+
+/// Whether two byte strings are the same, taking as long to find out for strings of a length
+/// however much of them matches.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+
+    /// A hash of `password`, with few enough iterations for a debug build.
+    const PASSWORD_HASH: &'static str = "pbkdf2-sha256$1000$6870707473616c74$\
+        3dd31095cbacb724f2d1cf08b2fc4e756f4e473f7d6fdfe6058544a705de4ff9";
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg==").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm8=").unwrap(), b"fo");
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert_eq!(base64_decode("+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+
+        for bad in &["Zg", "Zg=", "Z===", "Zg==Zg==", "Zm9v!mFy"] {
+            assert!(base64_decode(bad).is_none(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn password_hashes() {
+        let hash = PASSWORD_HASH.parse::<PasswordHash>().unwrap();
+        assert!(hash.verify("password"));
+        assert!(!hash.verify("passwort"));
+        assert_eq!(hash.to_string(), PASSWORD_HASH);
+        assert_eq!(PasswordHash::new("password", b"hpptsalt", 1000), hash);
+
+        let fresh = PasswordHash::generate("password").unwrap();
+        assert!(fresh.verify("password"));
+        assert!(fresh.salt != PasswordHash::generate("password").unwrap().salt);
+
+        let key = "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8";
+        for bad in &[key.to_owned(),
+                     format!("pbkdf2-sha256$0$00${}", key),
+                     format!("pbkdf2-sha256$1000$0${}", key),
+                     format!("pbkdf2-sha256$1000$00${}$", key),
+                     format!("md5$1000$00${}", key),
+                     "pbkdf2-sha256$1000$00$5e88".to_owned()] {
+            assert!(bad.parse::<PasswordHash>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn credentials() {
+        assert_eq!(basic_credentials("Basic YWxpY2U6cGFzczp3b3Jk"),
+                   Some(("alice".to_owned(), "pass:word".to_owned())));
+        assert_eq!(basic_credentials("basic  YWxpY2U6"),
+                   Some(("alice".to_owned(), "".to_owned())));
+        assert_eq!(basic_credentials("Basic YWxpY2U="), None);
+        assert_eq!(basic_credentials("Bearer YWxpY2U6cGFzc3dvcmQ="), None);
+
+        let rule = format!("/admin=alice:{}", PASSWORD_HASH).parse::<AuthRule>().unwrap();
+        let users = StaticUsers::new(&[&rule]);
+        // without the hash
+        assert_eq!(format!("{:?}", users), "StaticUsers { users: [\"alice\"] }");
+
+        assert!(authorized(&users, Some("Basic YWxpY2U6cGFzc3dvcmQ=")));
+        assert!(!authorized(&users, Some("Basic YWxpY2U6cGFzc3dvcmQx")));
+        assert!(!authorized(&users, Some("Basic Ym9iOnBhc3N3b3Jk")));
+        assert!(!authorized(&users, Some("Bearer YWxpY2U6cGFzc3dvcmQ=")));
+        assert!(!authorized(&users, None));
+
+        assert_eq!(users.challenge("/admin"), "Basic realm=\"/admin\", charset=\"UTF-8\"");
+    }
+
+    #[derive(Debug)]
+    struct Tokens;
+
+    impl AuthProvider for Tokens {
+        fn check_basic(&self, _user: &str, _password: &str) -> bool {
+            false
+        }
+
+        fn check_bearer(&self, token: &str) -> bool {
+            token == "s3cr3t"
+        }
+
+        fn challenge(&self, realm: &str) -> String {
+            format!("Bearer realm=\"{}\"", realm)
+        }
+    }
+
+    #[test]
+    fn bearer_tokens() {
+        assert!(authorized(&Tokens, Some("Bearer s3cr3t")));
+        assert!(authorized(&Tokens, Some("bearer  s3cr3t ")));
+        assert!(!authorized(&Tokens, Some("Bearer s3cr3")));
+        assert!(!authorized(&Tokens, Some("Basic YWxpY2U6cGFzc3dvcmQ=")));
+    }
+
+    #[test]
+    fn user_files() {
+        let users = parse_users(&format!("# who may\n\nalice:{}\nbob\n:{}\n  carol:{}  \n",
+                                         PASSWORD_HASH,
+                                         PASSWORD_HASH,
+                                         PASSWORD_HASH));
+        assert_eq!(users.iter().map(|u| &u.0[..]).collect::<Vec<_>>(), ["alice", "carol"]);
+        assert!(check_users(&users, "carol", "password"));
+        assert!(!check_users(&users, "bob", "password"));
+
+        let path = env::temp_dir().join(format!("hppt-users-{}", process::id()));
+        let file = UserFile::new(path.clone());
+        assert!(!file.check_basic("alice", "password"));
+
+        // changes are picked up on a reload, not before
+        fs::write(&path, format!("alice:{}\n", PASSWORD_HASH)).unwrap();
+        assert!(!file.check_basic("alice", "password"));
+        file.reload();
+        assert!(file.check_basic("alice", "password"));
+        assert!(!file.check_basic("alice", "passw0rd"));
+
+        fs::write(&path, "").unwrap();
+        assert!(file.check_basic("alice", "password"));
+        file.reload();
+        assert!(!file.check_basic("alice", "password"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn comparison() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secrets"));
+    }
+}
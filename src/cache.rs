@@ -0,0 +1,298 @@
+//! Keeping the contents of small, popular files in memory. A `CachedSource` stands in front of
+//! any other `ContentSource`, answering for the files it's opened lately from a `FileCache` (which
+//! several sources, e.g. those of virtual hosts, may share) as long as the source still reports
+//! the same size and modification time for them.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use source::{Content, ContentSource, DirEntry, Metadata};
+
+/// Largest file worth keeping: anything bigger takes up too much of the cache to be "small", and
+/// costs little extra to read from the source compared with sending it.
+const MAX_FILE_LEN: u64 = 1024 * 1024;
+
+/// The contents of recently opened files, up to a total size, with whichever was used longest
+/// ago making room for the newest.
+#[derive(Debug)]
+pub struct FileCache {
+    capacity: usize,
+    next_source: AtomicUsize,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    by_key: HashMap<Key, Entry>,
+    /// Keys by when they were last used, oldest first.
+    by_use: BTreeMap<u64, Key>,
+    uses: u64,
+    len: usize,
+}
+
+/// Which source a file's from, and its path there.
+type Key = (usize, PathBuf);
+
+#[derive(Debug)]
+struct Entry {
+    bytes: Arc<[u8]>,
+    metadata: Metadata,
+    content_type: Option<String>,
+    full_path: PathBuf,
+    used: u64,
+}
+
+impl FileCache {
+    pub fn new(capacity: usize) -> Self {
+        FileCache {
+            capacity: capacity,
+            next_source: AtomicUsize::new(0),
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    /// The cached contents of a file, if they're for the version of it `metadata` describes.
+    /// Those of any other version are forgotten.
+    fn get(&self, key: &Key, metadata: &Metadata) -> Option<Content> {
+        let mut entries = self.entries.lock().unwrap();
+        let fresh = match entries.by_key.get(key) {
+            Some(entry) => entry.metadata == *metadata,
+            None => return None,
+        };
+
+        if !fresh {
+            entries.remove(key);
+            return None;
+        }
+
+        entries.uses += 1;
+        let used = entries.uses;
+        let (bytes, content_type, full_path, last_used) = {
+            let entry = entries.by_key.get_mut(key).unwrap();
+            let last_used = entry.used;
+            entry.used = used;
+            (entry.bytes.clone(), entry.content_type.clone(), entry.full_path.clone(), last_used)
+        };
+        entries.by_use.remove(&last_used);
+        entries.by_use.insert(used, key.clone());
+
+        Some(Content {
+            reader: Box::new(Cursor::new(bytes)),
+            metadata: *metadata,
+            content_type: content_type,
+            full_path: full_path,
+        })
+    }
+
+    /// Keep a file's contents, making room for them by forgetting those used longest ago.
+    fn insert(&self, key: Key, bytes: Arc<[u8]>, content: &Content) {
+        if bytes.len() > self.capacity {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&key);
+
+        while entries.len + bytes.len() > self.capacity {
+            let oldest = match entries.by_use.values().next() {
+                Some(key) => key.clone(),
+                None => break,
+            };
+            entries.remove(&oldest);
+        }
+
+        entries.uses += 1;
+        let used = entries.uses;
+        entries.len += bytes.len();
+        entries.by_use.insert(used, key.clone());
+        entries.by_key.insert(key,
+                              Entry {
+                                  bytes: bytes,
+                                  metadata: content.metadata,
+                                  content_type: content.content_type.clone(),
+                                  full_path: content.full_path.clone(),
+                                  used: used,
+                              });
+    }
+
+    /// How many bytes of file contents are kept.
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len
+    }
+}
+
+impl Entries {
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.by_key.remove(key) {
+            self.by_use.remove(&entry.used);
+            self.len -= entry.bytes.len();
+        }
+    }
+}
+
+/// A content source whose small files are served from a cache once they've been read, checking
+/// the source's metadata for them each time so changes are picked up straight away.
+#[derive(Debug)]
+pub struct CachedSource {
+    inner: Arc<ContentSource>,
+    cache: Arc<FileCache>,
+    /// Tells this source's files apart from those of others sharing the cache.
+    id: usize,
+}
+
+impl CachedSource {
+    pub fn new(inner: Arc<ContentSource>, cache: Arc<FileCache>) -> Self {
+        let id = cache.next_source.fetch_add(1, Ordering::SeqCst);
+
+        CachedSource {
+            inner: inner,
+            cache: cache,
+            id: id,
+        }
+    }
+
+    fn key(&self, path: &Path) -> Key {
+        (self.id, path.to_path_buf())
+    }
+
+    /// Whether an opened file's worth keeping: it has to be small, and to have a modification
+    /// time to tell when it's changed.
+    fn cacheable(content: &Content) -> bool {
+        let metadata = &content.metadata;
+        !metadata.is_dir && metadata.modified.is_some() && metadata.len <= MAX_FILE_LEN
+    }
+
+    /// Read a file's contents in full to cache them, or `None` if it couldn't be read, or changed
+    /// while being read.
+    fn read_whole(content: &mut Content) -> Option<Arc<[u8]>> {
+        let len = content.metadata.len;
+        let mut bytes = Vec::with_capacity(len as usize);
+        match (&mut content.reader).take(len + 1).read_to_end(&mut bytes) {
+            Ok(n) if n as u64 == len => Some(Arc::from(bytes)),
+            Ok(_) => None,
+            Err(why) => {
+                debug!("Unable to read {:?} to cache it: {:?}", content.full_path, why);
+                None
+            }
+        }
+    }
+}
+
+impl ContentSource for CachedSource {
+    fn open(&self, path: &Path) -> Option<Content> {
+        let key = self.key(path);
+        let metadata = self.inner.metadata(path);
+
+        if let Some(ref metadata) = metadata {
+            if let Some(content) = self.cache.get(&key, metadata) {
+                return Some(content);
+            }
+        }
+
+        let mut content = match self.inner.open(path) {
+            Some(c) => c,
+            None => return None,
+        };
+
+        // the metadata looked up first may be older than the file which was opened
+        if metadata != Some(content.metadata) || !CachedSource::cacheable(&content) {
+            return Some(content);
+        }
+
+        match CachedSource::read_whole(&mut content) {
+            Some(bytes) => {
+                self.cache.insert(key, bytes.clone(), &content);
+                content.reader = Box::new(Cursor::new(bytes));
+                Some(content)
+            }
+            // a reader which has been read from has to be started over
+            None => self.inner.open(path),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> Option<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn list(&self, path: &Path) -> Option<Vec<DirEntry>> {
+        self.inner.list(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use source::{ContentSource, LocalFs};
+
+    use super::{CachedSource, FileCache};
+
+    fn read(source: &ContentSource, path: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        source.open(Path::new(path)).unwrap().reader.read_to_end(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn caching() {
+        let dir = env::temp_dir().join(format!("hppt-cache-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), b"first").unwrap();
+
+        let cache = Arc::new(FileCache::new(16));
+        let source = CachedSource::new(Arc::new(LocalFs::new(dir.clone())), cache.clone());
+
+        assert_eq!(read(&source, "a.txt"), b"first");
+        assert_eq!(cache.len(), 5);
+        assert_eq!(read(&source, "a.txt"), b"first");
+
+        let mut part = Vec::new();
+        let mut content = source.open(Path::new("a.txt")).unwrap();
+        content.reader.skip(1).unwrap();
+        content.reader.take(3).read_to_end(&mut part).unwrap();
+        assert_eq!(part, b"irs");
+
+        // a change is picked up as soon as the file's modification time says so
+        sleep(Duration::from_millis(10));
+        fs::write(dir.join("a.txt"), b"second").unwrap();
+        assert_eq!(read(&source, "a.txt"), b"second");
+        assert_eq!(cache.len(), 6);
+
+        // the file used longest ago makes room
+        fs::write(dir.join("b.txt"), b"bbbbbbbb").unwrap();
+        fs::write(dir.join("c.txt"), b"cccccc").unwrap();
+        read(&source, "b.txt");
+        read(&source, "a.txt");
+        read(&source, "c.txt");
+        assert_eq!(cache.len(), 12);
+
+        // a file too big for the cache is served all the same
+        fs::write(dir.join("d.txt"), vec![b'd'; 100]).unwrap();
+        assert_eq!(read(&source, "d.txt").len(), 100);
+        assert_eq!(cache.len(), 12);
+
+        // sources sharing a cache don't get each other's files
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("a.txt"), b"other").unwrap();
+        let other_source = CachedSource::new(Arc::new(LocalFs::new(other)), cache.clone());
+        assert_eq!(read(&other_source, "a.txt"), b"other");
+        assert_eq!(read(&source, "a.txt"), b"second");
+
+        assert!(source.open(Path::new("missing.txt")).is_none());
+        assert!(source.open(Path::new("other")).is_none());
+        assert!(source.list(Path::new("")).unwrap().iter().any(|e| e.name == "b.txt"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+use std::str::from_utf8;
+use std::sync::{Arc, Mutex};
+
+/// A count of the CGI scripts running at once, shared between all of the listener coroutines so
+/// a flood of requests can't fork the host to death.
+#[derive(Debug)]
+pub struct ProcessSlots {
+    max: Option<usize>,
+    running: Mutex<usize>,
+}
+
+/// A claim on one of the process slots, given back when dropped.
+#[derive(Debug)]
+pub struct ProcessSlot {
+    slots: Arc<ProcessSlots>,
+}
+
+impl ProcessSlots {
+    pub fn new(max: Option<usize>) -> Self {
+        ProcessSlots {
+            max: max,
+            running: Mutex::new(0),
+        }
+    }
+
+    /// Claim a slot for a script about to be run, or `None` if as many are running as allowed.
+    pub fn acquire(slots: &Arc<ProcessSlots>) -> Option<ProcessSlot> {
+        let mut running = slots.running.lock().unwrap();
+
+        match slots.max {
+            Some(max) if *running >= max => return None,
+            _ => (),
+        }
+
+        *running += 1;
+
+        Some(ProcessSlot { slots: slots.clone() })
+    }
+}
+
+impl Drop for ProcessSlot {
+    fn drop(&mut self) {
+        *self.slots.running.lock().unwrap() -= 1;
+    }
+}
+
+/// A CGI script's output, split into the header fields it set (RFC 3875 section 6) and the body.
+#[derive(Debug, PartialEq)]
+pub struct Output<'a> {
+    /// From a `Status: 404 Not Found` header, the code and reason phrase.
+    pub status: Option<(u16, &'a str)>,
+    pub content_type: Option<&'a str>,
+    pub location: Option<&'a str>,
+    /// Everything else the script set, to be passed on to the client.
+    pub headers: Vec<(&'a str, &'a str)>,
+    pub body: &'a [u8],
+}
+
+/// What the server should do with a script's output.
+#[derive(Debug, PartialEq)]
+pub enum Kind {
+    /// Send it as a response.
+    Document,
+    /// A `Location` pointing back at this server and nothing else: answer as if the client had
+    /// asked for that path in the first place.
+    LocalRedirect,
+    /// A `Location` pointing elsewhere: tell the client to go there.
+    ClientRedirect,
+}
+
+/// Headers we work out ourselves, or which only make sense between us and the client, so which
+/// a script doesn't get to set.
+const DROPPED_HEADERS: &'static [&'static str] = &["Connection",
+                                                   "Content-Length",
+                                                   "Keep-Alive",
+                                                   "Transfer-Encoding"];
+
+impl<'a> Output<'a> {
+    /// Parse a script's output, or `None` if it doesn't start with a well-formed header block.
+    /// Lines may end in either CRLF or a bare LF.
+    pub fn parse(stdout: &'a [u8]) -> Option<Output<'a>> {
+        let mut output = Output {
+            status: None,
+            content_type: None,
+            location: None,
+            headers: Vec::new(),
+            body: b"",
+        };
+
+        let mut rest = stdout;
+
+        loop {
+            let newline = match rest.iter().position(|&b| b == b'\n') {
+                Some(n) => n,
+                None => return None,
+            };
+
+            let line = &rest[..newline];
+            let line = if line.ends_with(b"\r") {
+                &line[..line.len() - 1]
+            } else {
+                line
+            };
+            rest = &rest[newline + 1..];
+
+            if line.is_empty() {
+                break;
+            }
+
+            let line = match from_utf8(line) {
+                Ok(l) => l,
+                Err(_) => return None,
+            };
+
+            let mut halves = line.splitn(2, ':');
+            let (name, value) = match (halves.next(), halves.next()) {
+                (Some(n), Some(v)) if !n.is_empty() && !n.contains(' ') => (n, v.trim()),
+                _ => return None,
+            };
+
+            if name.eq_ignore_ascii_case("Status") {
+                output.status = match parse_status(value) {
+                    Some(s) => Some(s),
+                    None => return None,
+                };
+            } else if name.eq_ignore_ascii_case("Content-Type") {
+                output.content_type = Some(value);
+            } else if name.eq_ignore_ascii_case("Location") {
+                output.location = Some(value);
+            } else if !DROPPED_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+                output.headers.push((name, value));
+            }
+        }
+
+        output.body = rest;
+
+        Some(output)
+    }
+
+    pub fn kind(&self) -> Kind {
+        match self.location {
+            Some(l) if l.starts_with('/') && self.status.is_none() &&
+                       self.content_type.is_none() &&
+                       self.headers.is_empty() => Kind::LocalRedirect,
+            Some(l) if !l.starts_with('/') => Kind::ClientRedirect,
+            _ => Kind::Document,
+        }
+    }
+}
+
+/// How much of a script's output is its header block, up to and including the blank line which
+/// ends it, or `None` if it hasn't printed that far yet.
+pub fn head_len(stdout: &[u8]) -> Option<usize> {
+    let mut start = 0;
+
+    loop {
+        let newline = match stdout[start..].iter().position(|&b| b == b'\n') {
+            Some(n) => start + n,
+            None => return None,
+        };
+
+        let line = &stdout[start..newline];
+        if line.is_empty() || line == b"\r" {
+            return Some(newline + 1);
+        }
+        start = newline + 1;
+    }
+}
+
+/// `404 Not Found` into its code and reason phrase (which may be empty).
+fn parse_status(value: &str) -> Option<(u16, &str)> {
+    let (code, reason) = match value.find(' ') {
+        Some(i) => (&value[..i], value[i + 1..].trim()),
+        None => (value, ""),
+    };
+
+    match code.parse::<u16>() {
+        Ok(c) if code.len() == 3 && c >= 100 => Some((c, reason)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn process_slots() {
+        let slots = Arc::new(ProcessSlots::new(Some(2)));
+
+        let first = ProcessSlots::acquire(&slots).unwrap();
+        let _second = ProcessSlots::acquire(&slots).unwrap();
+        assert!(ProcessSlots::acquire(&slots).is_none());
+
+        drop(first);
+        assert!(ProcessSlots::acquire(&slots).is_some());
+
+        let slots = Arc::new(ProcessSlots::new(None));
+        let held = (0..3).map(|_| ProcessSlots::acquire(&slots)).collect::<Vec<_>>();
+        assert!(held.iter().all(|s| s.is_some()));
+    }
+
+    #[test]
+    fn document() {
+        let output = Output::parse(b"Content-Type: text/plain\r\nX-Thing:  a \n\
+                                     Content-Length: 99\r\n\r\nbody\r\n\r\nmore")
+            .unwrap();
+
+        assert_eq!(output,
+                   Output {
+                       status: None,
+                       content_type: Some("text/plain"),
+                       location: None,
+                       headers: vec![("X-Thing", "a")],
+                       body: b"body\r\n\r\nmore",
+                   });
+        assert_eq!(output.kind(), Kind::Document);
+
+        let output = Output::parse(b"Status: 404 Not Found\nContent-Type: text/html\n\n").unwrap();
+        assert_eq!(output.status, Some((404, "Not Found")));
+        assert_eq!(output.body, b"");
+
+        assert_eq!(Output::parse(b"Status: 299\n\n").unwrap().status, Some((299, "")));
+    }
+
+    #[test]
+    fn redirects() {
+        let local = Output::parse(b"Location: /elsewhere?x=1\r\n\r\n").unwrap();
+        assert_eq!(local.kind(), Kind::LocalRedirect);
+
+        let client = Output::parse(b"Location: http://example.com/\r\n\r\n").unwrap();
+        assert_eq!(client.kind(), Kind::ClientRedirect);
+
+        // a local path with a document attached has to go to the client
+        let with_document =
+            Output::parse(b"Status: 303 See Other\nLocation: /x\nContent-Type: text/html\n\nhi")
+                .unwrap();
+        assert_eq!(with_document.kind(), Kind::Document);
+    }
+
+    #[test]
+    fn head_length() {
+        assert_eq!(head_len(b"Content-Type: text/plain\r\n\r\nbody"), Some(28));
+        assert_eq!(head_len(b"A: b\n\n"), Some(6));
+        assert_eq!(head_len(b"\r\nall body"), Some(2));
+        assert_eq!(head_len(b"Content-Type: text/plain\r\n\r"), None);
+        assert_eq!(head_len(b""), None);
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(Output::parse(b"no header block at all").is_none());
+        assert!(Output::parse(b"Content-Type: text/plain\r\n").is_none());
+        assert!(Output::parse(b"Not a header\r\n\r\n").is_none());
+        assert!(Output::parse(b"Status: teapot\r\n\r\n").is_none());
+        assert!(Output::parse(b"Status: 42\r\n\r\n").is_none());
+        assert!(Output::parse(b"\xff: x\r\n\r\n").is_none());
+    }
+}
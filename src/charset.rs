@@ -0,0 +1,56 @@
+/// Whether an Accept-Charset header value explicitly rules out a charset: either by naming it
+/// with `q=0`, or with `*;q=0` and not naming it at all.
+///
+/// Charsets the client merely doesn't mention are still served, since most clients which send
+/// the header only list a few favourites and would rather get something than a 406.
+pub fn excludes(accept_charset: &str, charset: &str) -> bool {
+    let mut wildcard = None;
+
+    for entry in accept_charset.split(',') {
+        let mut params = entry.split(';');
+
+        let name = match params.next() {
+            Some(n) => n.trim(),
+            None => continue,
+        };
+
+        let mut quality = 1.0;
+        for param in params {
+            let param = param.trim();
+            if param.starts_with("q=") {
+                quality = match param[2..].parse::<f32>() {
+                    Ok(q) if q >= 0.0 && q <= 1.0 => q,
+                    _ => 0.0,
+                };
+            }
+        }
+
+        if name.eq_ignore_ascii_case(charset) {
+            return quality == 0.0;
+        } else if name == "*" {
+            wildcard = Some(quality);
+        }
+    }
+
+    wildcard == Some(0.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn explicit_exclusion() {
+        assert!(excludes("iso-8859-1, utf-8;q=0", "utf-8"));
+        assert!(excludes("UTF-8; q=0", "utf-8"));
+        assert!(excludes("iso-8859-1, *;q=0", "utf-8"));
+    }
+
+    #[test]
+    fn not_excluded() {
+        assert!(!excludes("", "utf-8"));
+        assert!(!excludes("iso-8859-1", "utf-8"));
+        assert!(!excludes("utf-8;q=0.1, *;q=0", "utf-8"));
+        assert!(!excludes("*;q=0.5", "utf-8"));
+    }
+}
@@ -0,0 +1,91 @@
+use std::io;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use files::Validators;
+use sha256::Sha256;
+use source::Content;
+
+/// Most sidecars to keep in memory, beyond which the least recently generated is forgotten.
+const CACHE_SIZE: usize = 64;
+
+struct Cached {
+    path: PathBuf,
+    validators: Validators,
+    sidecar: String,
+}
+
+/// Shared by every server in the process, which is fine since entries are keyed by full path.
+static CACHE: Mutex<Vec<Cached>> = Mutex::new(Vec::new());
+
+/// The contents of a `sha256sum`-style sidecar (`<hex digest>  <name>\n`) for a file, hashing it
+/// only if it's changed since it was last asked about.
+///
+/// Hashing reads the whole file, which ties up the calling coroutine for a big one, so the
+/// result is cached until the file's size or modification time changes.
+pub fn sidecar(mut content: Content) -> io::Result<String> {
+    let path = &content.full_path;
+    let validators = Validators::from_metadata(&content.metadata);
+
+    if let Some(ref v) = validators {
+        let cache = CACHE.lock().unwrap();
+        if let Some(c) = cache.iter().find(|c| c.path == *path && c.validators == *v) {
+            return Ok(c.sidecar.clone());
+        }
+    }
+
+    let mut hash = Sha256::new();
+    let mut chunk = [0; 8 * 1024];
+
+    loop {
+        match content.reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => hash.update(&chunk[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let name = path.file_name().map_or("".into(), |n| n.to_string_lossy());
+    let sidecar = format!("{}  {}\n", hash.finish_hex(), name);
+
+    if let Some(v) = validators {
+        let mut cache = CACHE.lock().unwrap();
+        cache.retain(|c| c.path != *path);
+        if cache.len() == CACHE_SIZE {
+            cache.remove(0);
+        }
+        cache.push(Cached {
+            path: path.clone(),
+            validators: v,
+            sidecar: sidecar.clone(),
+        });
+    }
+
+    Ok(sidecar)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use source::{ContentSource, LocalFs};
+
+    use super::*;
+
+    #[test]
+    fn sidecar_contents() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let source = LocalFs::new(root.clone());
+        let path = Path::new("test/site/index.html");
+        let expected = "5be2a9aa5ce9faec5ba185e22fe20a9936a593c0f4cd8f9de26906f693ff4daf  \
+                        index.html\n";
+
+        assert_eq!(sidecar(source.open(path).unwrap()).unwrap(), expected);
+
+        // and again from the cache
+        assert_eq!(sidecar(source.open(path).unwrap()).unwrap(), expected);
+        assert!(CACHE.lock().unwrap().iter().any(|c| c.path == root.join(path)));
+    }
+}
@@ -0,0 +1,152 @@
+//! Blocks of IP addresses in CIDR notation (like `10.0.0.0/8` or `2001:db8::/32`), for deciding
+//! which clients may connect at all.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+/// `ADDRESS/PREFIX_LEN`, or a lone address for a block of just that one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    /// How many leading bits an address must share with the block's to be in it.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Whether an address is in the block. IPv4 clients of a dual-stack listener show up as
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), which IPv4 blocks contain too.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.addr, unmapped(addr)) {
+            (IpAddr::V4(block), IpAddr::V4(addr)) => {
+                prefix_matches(&block.octets(), &addr.octets(), self.prefix_len)
+            }
+            (IpAddr::V6(block), IpAddr::V6(addr)) => {
+                prefix_matches(&block.octets(), &addr.octets(), self.prefix_len)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The IPv4 address an IPv4-mapped IPv6 one stands for, or the address as it is.
+fn unmapped(addr: &IpAddr) -> IpAddr {
+    match *addr {
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            if s[..5] == [0; 5] && s[5] == 0xffff {
+                IpAddr::V4(Ipv4Addr::new((s[6] >> 8) as u8,
+                                         s[6] as u8,
+                                         (s[7] >> 8) as u8,
+                                         s[7] as u8))
+            } else {
+                *addr
+            }
+        }
+        v4 => v4,
+    }
+}
+
+/// Whether the first `bits` bits of two addresses (of the same family) are the same.
+fn prefix_matches(block: &[u8], addr: &[u8], bits: u8) -> bool {
+    let whole = bits as usize / 8;
+    if block[..whole] != addr[..whole] {
+        return false;
+    }
+
+    let rest = bits % 8;
+    if rest == 0 {
+        return true;
+    }
+
+    let mask = !0u8 << (8 - rest);
+    block[whole] & mask == addr[whole] & mask
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '/');
+        let addr = halves.next().unwrap().parse::<IpAddr>();
+        let max_len = match addr {
+            Ok(IpAddr::V4(_)) => 32,
+            Ok(IpAddr::V6(_)) => 128,
+            Err(_) => 0,
+        };
+        let prefix_len = match halves.next() {
+            Some(len) if !len.is_empty() && len.bytes().all(|b| b.is_ascii_digit()) => len.parse(),
+            Some(_) => "".parse(),
+            None => Ok(max_len),
+        };
+
+        match (addr, prefix_len) {
+            (Ok(addr), Ok(prefix_len)) if prefix_len <= max_len => {
+                Ok(Cidr {
+                    addr: addr,
+                    prefix_len: prefix_len,
+                })
+            }
+            _ => Err(format!("{} is not of the form ADDRESS/PREFIX_LEN, e.g. 10.0.0.0/8", s)),
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::Cidr;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let block = "10.0.0.0/8".parse::<Cidr>().unwrap();
+        assert_eq!(block.prefix_len(), 8);
+        assert_eq!(block.to_string(), "10.0.0.0/8");
+        assert_eq!("192.0.2.1".parse::<Cidr>().unwrap().to_string(), "192.0.2.1/32");
+        assert_eq!("2001:db8::/32".parse::<Cidr>().unwrap().to_string(), "2001:db8::/32");
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+
+        for bad in &["10.0.0.0/33", "::/129", "10.0.0.0/", "10.0.0.0/-1", "10.0.0.0/+8",
+                     "10.0.0/8", "example.com/8", ""] {
+            assert!(bad.parse::<Cidr>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn contains() {
+        let block = "10.1.0.0/16".parse::<Cidr>().unwrap();
+        assert!(block.contains(&ip("10.1.0.0")));
+        assert!(block.contains(&ip("10.1.255.255")));
+        assert!(!block.contains(&ip("10.2.0.1")));
+        assert!(block.contains(&ip("::ffff:10.1.2.3")));
+        assert!(!block.contains(&ip("::1")));
+
+        let odd = "192.0.2.128/25".parse::<Cidr>().unwrap();
+        assert!(odd.contains(&ip("192.0.2.200")));
+        assert!(!odd.contains(&ip("192.0.2.127")));
+
+        let v6 = "2001:db8::/32".parse::<Cidr>().unwrap();
+        assert!(v6.contains(&ip("2001:db8:1::1")));
+        assert!(!v6.contains(&ip("2001:db9::1")));
+        assert!(!v6.contains(&ip("10.1.2.3")));
+
+        let everything = "0.0.0.0/0".parse::<Cidr>().unwrap();
+        assert!(everything.contains(&ip("203.0.113.9")));
+        assert!(!everything.contains(&ip("2001:db8::1")));
+    }
+}
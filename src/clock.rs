@@ -0,0 +1,85 @@
+//! Where the server gets the time from, so that tests of anything time-dependent (304s, keep-alive
+//! expiry, logged dates) can control it rather than sleep.
+
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A source of the current time.
+pub trait Clock: Debug + Send + Sync {
+    /// The wall-clock time, for dates sent to clients or logged.
+    fn now(&self) -> SystemTime;
+
+    /// The monotonic time, for deadlines and for measuring how long things take.
+    fn instant(&self) -> Instant;
+}
+
+/// The system's own clocks.
+#[derive(Debug)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock which stands still until it's told to move on, for tests.
+///
+/// Its instants are real ones, offset by however far it's been advanced, so a server waiting on a
+/// deadline from this clock still wakes up for it eventually: advancing the clock just means it
+/// finds the deadline has passed when it next checks.
+#[derive(Debug)]
+pub struct ManualClock {
+    wall: SystemTime,
+    monotonic: Instant,
+    advanced: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// A clock stopped at the given wall-clock time.
+    pub fn new(now: SystemTime) -> Self {
+        ManualClock {
+            wall: now,
+            monotonic: Instant::now(),
+            advanced: Mutex::new(Duration::from_secs(0)),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.advanced.lock().unwrap() += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        self.wall + *self.advanced.lock().unwrap()
+    }
+
+    fn instant(&self) -> Instant {
+        self.monotonic + *self.advanced.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+
+    #[test]
+    fn manual() {
+        let clock = ManualClock::new(UNIX_EPOCH + Duration::from_secs(784111777));
+        let (then, started) = (clock.now(), clock.instant());
+        assert_eq!(clock.now(), then);
+        assert_eq!(clock.instant(), started);
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now().duration_since(then).unwrap(), Duration::from_secs(90));
+        assert_eq!(clock.instant().duration_since(started), Duration::from_secs(90));
+    }
+}
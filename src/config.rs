@@ -0,0 +1,1849 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use auth::{AuthProvider, AuthRoute, PasswordHash, StaticUsers};
+use cidr::Cidr;
+use clock::{Clock, SystemClock};
+use fastcgi;
+use proxy;
+use rewrite;
+use handler::{Handler, Route};
+use hooks::Hooks;
+use idna;
+use limits::Limits;
+use request::{ParseMode, is_tchar};
+use response::ContentType;
+use server::NThreads;
+use snapshots::IndexSnapshot;
+use source::{ContentSource, LocalFs};
+
+/// Settings for a running server, shared (read-only) between all of the listener coroutines.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Root directory from which to serve files.
+    pub root_dir: PathBuf,
+    /// Where the files served as they are come from: by default, `root_dir` on the local
+    /// filesystem. Replace it along with `root_dir`, since CGI scripts and the like still run
+    /// from there.
+    pub source: Arc<ContentSource>,
+    /// Handlers for URI prefixes, which take precedence over serving files and running scripts.
+    /// Add to them with `route`.
+    pub routes: Vec<Route>,
+    /// Host names served from document roots of their own, rather than `root_dir`.
+    pub vhosts: Vec<VirtualHost>,
+    /// What to do, when there are virtual hosts, with a request for a host which isn't one.
+    pub unknown_host: UnknownHost,
+    /// Where the time comes from for dates, deadlines and durations: the system's clock, unless
+    /// something (like a test) needs to control it.
+    pub clock: Arc<Clock>,
+    pub num_threads: NThreads,
+    /// Caps on request sizes, timeouts and connections.
+    pub limits: Limits,
+    /// Addresses (e.g. of reverse proxies) exempt from the per-address connection cap and
+    /// request rate limit.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Blocks of client addresses to serve, if only some are to be served (see `peer_allowed`).
+    pub allow: Vec<Cidr>,
+    /// Blocks of client addresses to turn away with a 403 before reading their requests.
+    pub deny: Vec<Cidr>,
+    /// Budgets of requests at once and a second for classes of requests which cost more or less
+    /// to answer, such as running scripts compared with serving files.
+    pub cost_classes: Vec<CostClass>,
+    /// Which class requests under each directory are in (see `cost_class_for`).
+    pub cost_paths: Vec<CostPath>,
+    /// Whether to run CGI scripts. Without it, requests under `cgi_dirs` get a 404, rather than
+    /// the scripts' source.
+    pub cgi: bool,
+    /// Directories whose files are run as CGI scripts, and the URI prefixes they're run for.
+    pub cgi_dirs: Vec<CgiDir>,
+    /// Programs to run scripts with by extension, for those which can't be run by themselves.
+    pub cgi_interpreters: Vec<CgiInterpreter>,
+    /// Application servers to answer requests under URI prefixes over FastCGI, which takes
+    /// precedence over serving files and running scripts.
+    pub fastcgi: Vec<FastCgiRoute>,
+    /// Upstream HTTP servers to forward requests under URI prefixes to, which takes precedence
+    /// over serving files and running scripts.
+    pub proxies: Vec<ProxyRoute>,
+    /// Upstreams to send some of the requests proxied under URI prefixes to in place of their
+    /// routes' own, for trying out a new release on them.
+    pub canaries: Vec<CanaryRoute>,
+    /// Shadow upstreams to copy a share of the proxied requests under URI prefixes to, whose
+    /// answers are dropped.
+    pub mirrors: Vec<MirrorRoute>,
+    /// Rules rewriting requests' paths, or redirecting them, before anything else looks at them.
+    pub rewrites: Vec<rewrite::Rule>,
+    /// Whether to turn away requests which don't follow the spec to the letter.
+    pub parse_mode: ParseMode,
+    /// What to do with paths like `//foo//bar`.
+    pub empty_segments: EmptySegments,
+    /// Whether to redirect to paths with a trailing slash for directories, and without for files.
+    pub trailing_slash: TrailingSlash,
+    /// Files to serve, in order of preference, for a request naming a directory.
+    pub index_files: Vec<String>,
+    /// Whether to list the contents of directories which have no index file.
+    pub autoindex: bool,
+    /// Whether those listings put numbers in names in order of their value, e.g. `file2` before
+    /// `file10`.
+    pub natural_sort: bool,
+    /// Whether to serve (and list) files and directories whose names start with a dot, like
+    /// `.git` or `.env`, which are otherwise treated as if they weren't there.
+    pub serve_hidden: bool,
+    /// Names starting with a dot which are served anyway, like `.well-known`.
+    pub allowed_hidden: Vec<String>,
+    /// Whether files and directories under the root may be reached through symlinks, as long as
+    /// they lead somewhere still inside it. This is a setting of the `LocalFs` source, so a config
+    /// changing it needs a source made with it (as `with_root` does).
+    pub follow_symlinks: bool,
+    /// Directories (e.g. of a mirror too big to scan on every request) listed, along with every
+    /// directory under them, from snapshots refreshed on a schedule rather than as requested,
+    /// whether or not `autoindex` is on.
+    pub index_snapshots: Vec<IndexSnapshot>,
+    /// Where to keep those snapshots. There are none without it.
+    pub snapshot_dir: Option<PathBuf>,
+    /// Whether `?download=tar` on a directory's URL gets a tar archive of it.
+    pub archive_downloads: bool,
+    /// Whether `?meta` on a file's URL gets what's known about the file (its size, modification
+    /// time, ETag and type) as JSON, in place of the file.
+    pub meta_queries: bool,
+    /// URI prefixes (relative to the root, without a leading slash) under which `file.sha256` is
+    /// generated for any `file` which has no sidecar on disk.
+    pub checksum_dirs: Vec<String>,
+    /// Whether to gzip text files for clients which accept it.
+    pub compress: bool,
+    /// Whether to serve `file.gz` or `file.br`, where there is one, for `file` to clients which
+    /// accept that coding.
+    pub precompressed: bool,
+    /// Most bytes of small files to keep in memory once they've been served, so popular ones
+    /// needn't be read from `source` every time (only their metadata is looked up, to tell
+    /// whether they've changed). `None` keeps nothing.
+    pub cache_size: Option<usize>,
+    /// Whether to make served HTML pages reload themselves when anything under the root changes.
+    pub live_reload: bool,
+    /// Whether to serve recent warnings and errors, and the server's totals, as JSON to clients on
+    /// the loopback interface.
+    pub admin_endpoint: bool,
+
+    /// URI prefixes (relative to the root, without a leading slash) under which a request for
+    /// `page.html` may be answered with `page.html.en`, `page.html.de`, etc.
+    pub language_dirs: Vec<String>,
+    /// Language to fall back on when the client's Accept-Language matches no variant.
+    pub default_language: Option<String>,
+
+    /// Extension to MIME type mappings which take precedence over the built-in ones.
+    pub mime_overrides: Vec<MimeOverride>,
+    /// Mappings from `mime.types` files, which take precedence over the built-in ones but not
+    /// over `mime_overrides`. Later ones win.
+    pub mime_types: Vec<MimeOverride>,
+    /// Charsets to declare on text responses.
+    pub charsets: Vec<CharsetSetting>,
+    /// Cache-Control to send with files, the first rule matching a path applying.
+    pub cache_rules: Vec<CacheRule>,
+    /// Headers to send with every response (unless it has one of the same name already), like
+    /// `X-Content-Type-Options: nosniff`.
+    pub headers: Vec<ResponseHeader>,
+    /// Whether to say which server (and version) is answering, in a Server header.
+    pub server_header: bool,
+    /// Pages to serve as the bodies of error responses which haven't got one of their own.
+    pub error_pages: Vec<ErrorPage>,
+    /// URI prefixes (relative to the root, without a leading slash) of APIs, whose error
+    /// responses get RFC 7807 problem details for a body in place of an error page.
+    pub api_prefixes: Vec<String>,
+    /// Origins (like `https://app.example`, or `*` for any) whose pages may read what's served.
+    pub cors_origins: Vec<String>,
+    /// How long browsers may keep the answers to preflight requests under URI prefixes before
+    /// asking again.
+    pub cors_max_ages: Vec<CorsMaxAge>,
+    /// Users who may make requests under URI prefixes which only they may.
+    pub auth_rules: Vec<AuthRule>,
+    /// A provider for each prefix's `auth_rules` users, made once by `load_auth_users` rather
+    /// than for every request.
+    pub auth_users: Vec<AuthRoute>,
+    /// Providers of their own (like a database of users) for protected prefixes. Add to them with
+    /// `protect`.
+    pub auth_routes: Vec<AuthRoute>,
+    /// Callbacks to tell about requests, responses and errors as they happen. Add to them with
+    /// `hook`.
+    pub hooks: Vec<Arc<Hooks>>,
+
+    /// PEM certificate chain and private key to serve HTTPS with, rather than plain HTTP.
+    pub tls_cert: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+
+    /// Where to log each request in the Combined Log Format (`-` for stdout), if anywhere.
+    pub access_log: Option<PathBuf>,
+    /// For debugging: a directory to record copies of responses in, as they were sent.
+    pub record_dir: Option<PathBuf>,
+    /// Globs (like `docs/*.html`) for the paths whose responses are recorded, all of them if this
+    /// is empty.
+    pub record_patterns: Vec<String>,
+    /// Record only every this-many-th matching response (at least 1).
+    pub record_every: usize,
+    /// Where to write a report if the server crashes.
+    pub crash_dir: Option<PathBuf>,
+    /// Whether to raise the soft limit on open files to the hard limit at startup.
+    pub raise_fd_limit: bool,
+}
+
+impl Config {
+    pub fn new(root_dir: PathBuf) -> Self {
+        Config {
+            source: Arc::new(LocalFs::new(root_dir.clone())),
+            root_dir: root_dir,
+            routes: Vec::new(),
+            vhosts: Vec::new(),
+            unknown_host: UnknownHost::Default,
+            clock: Arc::new(SystemClock),
+            num_threads: 1,
+            limits: Limits::default(),
+            trusted_proxies: Vec::new(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            cost_classes: Vec::new(),
+            cost_paths: Vec::new(),
+            cgi: true,
+            cgi_dirs: vec![CgiDir {
+                               prefix: "cgi-bin".to_owned(),
+                               dir: PathBuf::from("cgi-bin"),
+                           }],
+            cgi_interpreters: Vec::new(),
+            fastcgi: Vec::new(),
+            proxies: Vec::new(),
+            canaries: Vec::new(),
+            mirrors: Vec::new(),
+            rewrites: Vec::new(),
+            parse_mode: ParseMode::Lenient,
+            empty_segments: EmptySegments::Collapse,
+            trailing_slash: TrailingSlash::Redirect,
+            index_files: vec!["index.html".to_owned(), "index.htm".to_owned()],
+            autoindex: false,
+            natural_sort: false,
+            serve_hidden: false,
+            allowed_hidden: vec![".well-known".to_owned()],
+            follow_symlinks: false,
+            index_snapshots: Vec::new(),
+            snapshot_dir: None,
+            archive_downloads: false,
+            meta_queries: false,
+            checksum_dirs: Vec::new(),
+            compress: false,
+            precompressed: false,
+            cache_size: None,
+            live_reload: false,
+            admin_endpoint: false,
+            language_dirs: Vec::new(),
+            default_language: None,
+            mime_overrides: Vec::new(),
+            mime_types: Vec::new(),
+            charsets: Vec::new(),
+            cache_rules: Vec::new(),
+            headers: Vec::new(),
+            server_header: true,
+            error_pages: Vec::new(),
+            api_prefixes: Vec::new(),
+            cors_origins: Vec::new(),
+            cors_max_ages: Vec::new(),
+            auth_rules: Vec::new(),
+            auth_users: Vec::new(),
+            auth_routes: Vec::new(),
+            hooks: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            access_log: None,
+            record_dir: None,
+            record_patterns: Vec::new(),
+            record_every: 1,
+            crash_dir: None,
+            raise_fd_limit: false,
+        }
+    }
+
+    /// The same settings, but for serving a different root directory from the local filesystem.
+    pub fn with_root(&self, root_dir: PathBuf) -> Config {
+        let mut config = self.clone();
+        config.source = Arc::new(LocalFs::new(root_dir.clone())
+            .follow_symlinks(self.follow_symlinks));
+        config.root_dir = root_dir;
+        config
+    }
+
+    /// Answer requests for a URI prefix (relative to the root, e.g. `api`) with a handler. Where
+    /// routes overlap, the longest prefix wins.
+    pub fn route<H: Handler + 'static>(&mut self, prefix: &str, handler: H) {
+        self.routes.push(Route {
+            prefix: prefix.trim_matches('/').to_owned(),
+            handler: Arc::new(handler),
+        });
+    }
+
+    /// The handler routed for the given (slash-stripped) URI, if there is one.
+    pub fn handler_for(&self, uri: &str) -> Option<&Arc<Handler>> {
+        self.routes
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len())
+            .map(|r| &r.handler)
+    }
+
+    /// Whether language negotiation is enabled for the given (slash-stripped) URI.
+    pub fn negotiates_language(&self, uri: &str) -> bool {
+        self.language_dirs.iter().any(|dir| dir_contains(dir, uri))
+    }
+
+    /// Whether the directory at the given (slash-stripped) URI is listed from snapshots.
+    pub fn lists_from_snapshots(&self, uri: &str) -> bool {
+        self.snapshot_dir.is_some() &&
+        self.index_snapshots.iter().any(|snapshot| dir_contains(&snapshot.dir, uri))
+    }
+
+    /// Whether checksum sidecars are generated for the given (slash-stripped) URI.
+    pub fn serves_checksums(&self, uri: &str) -> bool {
+        self.checksum_dirs.iter().any(|dir| dir_contains(dir, uri))
+    }
+
+    /// Let only those a provider says have good credentials make requests under a URI prefix
+    /// (relative to the root, e.g. `admin`).
+    pub fn protect<P: AuthProvider + 'static>(&mut self, prefix: &str, provider: P) {
+        self.auth_routes.push(AuthRoute {
+            prefix: prefix.trim_matches('/').to_owned(),
+            provider: Arc::new(provider),
+        });
+    }
+
+    /// Tell `hooks` about every request the server answers, every response it sends and every
+    /// error it runs into, after any hooks added before.
+    pub fn hook<H: Hooks + 'static>(&mut self, hooks: H) {
+        self.hooks.push(Arc::new(hooks));
+    }
+
+    /// Make `auth_users` from `auth_rules`, once they're settled.
+    pub fn load_auth_users(&mut self) {
+        let mut prefixes = self.auth_rules.iter().map(|r| &r.prefix).collect::<Vec<_>>();
+        prefixes.sort();
+        prefixes.dedup();
+
+        self.auth_users = prefixes.into_iter()
+            .map(|prefix| {
+                let users = self.auth_rules
+                    .iter()
+                    .filter(|r| &r.prefix == prefix)
+                    .collect::<Vec<_>>();
+                AuthRoute {
+                    prefix: prefix.clone(),
+                    provider: Arc::new(StaticUsers::new(&users)),
+                }
+            })
+            .collect();
+    }
+
+    /// Have every provider for a protected prefix pick up any change to the credentials it checks.
+    pub fn reload_auth(&self) {
+        for route in &self.auth_routes {
+            route.provider.reload();
+        }
+    }
+
+    /// What decides who may make requests for a (slash-stripped) URI, if it's protected: the
+    /// provider or `auth_rules` users for the longest protected prefix containing it, with a
+    /// provider taking precedence over users for the same prefix.
+    pub fn auth_route_for(&self, uri: &str) -> Option<AuthRoute> {
+        let route = self.auth_routes
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len());
+
+        let users = self.auth_rules_for(uri);
+        match (route, users.first()) {
+            (Some(route), Some(user)) if user.prefix.len() > route.prefix.len() => (),
+            (Some(route), _) => return Some(route.clone()),
+            (None, None) => return None,
+            (None, Some(_)) => (),
+        }
+
+        // made here only for a config which hasn't been through `load_auth_users`
+        let prefix = &users[0].prefix;
+        self.auth_users
+            .iter()
+            .find(|r| &r.prefix == prefix)
+            .cloned()
+            .or_else(|| {
+                Some(AuthRoute {
+                    prefix: prefix.clone(),
+                    provider: Arc::new(StaticUsers::new(&users)),
+                })
+            })
+    }
+
+    /// The longest protected prefix containing a (slash-stripped) URI, which decides who may make
+    /// requests for it, if any does.
+    pub fn auth_prefix_for(&self, uri: &str) -> Option<&str> {
+        let routes = self.auth_routes.iter().map(|r| &r.prefix);
+        let rules = self.auth_rules.iter().map(|r| &r.prefix);
+
+        routes.chain(rules)
+            .filter(|p| dir_contains(p, uri))
+            .max_by_key(|p| p.len())
+            .map(|p| &p[..])
+    }
+
+    /// The users who may make requests for a (slash-stripped) URI, those of the longest protected
+    /// prefix containing it, or none if it isn't protected.
+    pub fn auth_rules_for(&self, uri: &str) -> Vec<&AuthRule> {
+        let longest = self.auth_rules
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .map(|r| r.prefix.len())
+            .max();
+
+        self.auth_rules
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri) && Some(r.prefix.len()) == longest)
+            .collect()
+    }
+
+    /// Whether to serve a client connecting from an address. The most specific block containing
+    /// it decides, with a tie going to `deny`; an address in neither list is served only if
+    /// `allow` is empty.
+    pub fn peer_allowed(&self, addr: &IpAddr) -> bool {
+        let longest = |blocks: &[Cidr]| {
+            blocks.iter().filter(|b| b.contains(addr)).map(|b| b.prefix_len()).max()
+        };
+
+        match (longest(&self.allow), longest(&self.deny)) {
+            (Some(allowed), Some(denied)) => allowed > denied,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => self.allow.is_empty(),
+        }
+    }
+
+    /// The class of requests for the given (slash-stripped) URI, that of the deepest directory
+    /// containing it which has one, if any does.
+    pub fn cost_class_for(&self, uri: &str) -> Option<&CostClass> {
+        let path = self.cost_paths
+            .iter()
+            .filter(|p| dir_contains(&p.dir, uri))
+            .max_by_key(|p| p.dir.len());
+
+        path.and_then(|p| self.cost_classes.iter().find(|c| c.name == p.class))
+    }
+
+    /// The CGI directory for the given (slash-stripped) URI, the one with the longest prefix
+    /// containing it, if any does (whether or not CGI is on).
+    pub fn cgi_dir_for(&self, uri: &str) -> Option<&CgiDir> {
+        self.cgi_dirs
+            .iter()
+            .filter(|d| dir_contains(&d.prefix, uri))
+            .max_by_key(|d| d.prefix.len())
+    }
+
+    /// Whether a file, by its canonical path, is in one of the CGI directories however it's
+    /// reached, so that its source is never served as it is.
+    pub fn in_cgi_dir(&self, path: &Path) -> bool {
+        self.cgi_paths().iter().any(|d| path.starts_with(d))
+    }
+
+    /// The canonical paths of the CGI directories which exist.
+    pub fn cgi_paths(&self) -> Vec<PathBuf> {
+        self.cgi_dirs
+            .iter()
+            .filter_map(|d| self.root_dir.join(&d.dir).canonicalize().ok())
+            .collect()
+    }
+
+    /// The program to run the CGI script at a path with, if it's not to be run by itself.
+    pub fn cgi_interpreter_for(&self, script: &Path) -> Option<&Path> {
+        let extension = match script.extension().and_then(|e| e.to_str()) {
+            Some(e) => e,
+            None => return None,
+        };
+
+        self.cgi_interpreters
+            .iter()
+            .find(|i| i.extension.eq_ignore_ascii_case(extension))
+            .map(|i| i.program.as_path())
+    }
+
+    /// The application server for the given (slash-stripped) URI, the one with the longest
+    /// FastCGI prefix containing it, if any does.
+    pub fn fastcgi_for(&self, uri: &str) -> Option<&FastCgiRoute> {
+        self.fastcgi
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len())
+    }
+
+    /// The upstream for the given (slash-stripped) URI, the one with the longest proxied prefix
+    /// containing it, if any does.
+    pub fn proxy_for(&self, uri: &str) -> Option<&ProxyRoute> {
+        self.proxies
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len())
+    }
+
+    /// The canary upstream for the given (slash-stripped) URI, the one with the longest prefix
+    /// containing it, if any does.
+    pub fn canary_for(&self, uri: &str) -> Option<&CanaryRoute> {
+        self.canaries
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len())
+    }
+
+    /// The shadow upstream for the given (slash-stripped) URI, the one with the longest mirrored
+    /// prefix containing it, if any does.
+    pub fn mirror_for(&self, uri: &str) -> Option<&MirrorRoute> {
+        self.mirrors
+            .iter()
+            .filter(|r| dir_contains(&r.prefix, uri))
+            .max_by_key(|r| r.prefix.len())
+    }
+
+    /// Whether the given (slash-stripped) URI is under an API prefix.
+    pub fn is_api(&self, uri: &str) -> bool {
+        self.api_prefixes.iter().any(|prefix| dir_contains(prefix, uri))
+    }
+
+    /// Whether a (slash-stripped) URI, or a name in a directory, has a segment starting with a dot
+    /// which isn't to be served.
+    pub fn is_hidden(&self, uri: &str) -> bool {
+        !self.serve_hidden &&
+        uri.split('/').any(|s| s.starts_with('.') && !self.allowed_hidden.iter().any(|a| a == s))
+    }
+
+    /// Seconds browsers may keep the answer to a preflight for the given (slash-stripped) URI, by
+    /// the longest prefix with a Max-Age containing it, if any does.
+    pub fn cors_max_age_for(&self, uri: &str) -> Option<u64> {
+        self.cors_max_ages
+            .iter()
+            .filter(|m| dir_contains(&m.prefix, uri))
+            .max_by_key(|m| m.prefix.len())
+            .map(|m| m.seconds)
+    }
+
+    /// The first cache rule matching a (slash-stripped) URI, if any does.
+    pub fn cache_rule_for(&self, uri: &str) -> Option<&CacheRule> {
+        self.cache_rules.iter().find(|rule| rule.matches(uri))
+    }
+
+    /// The (slash-stripped) URI of the page for responses with a status code, if there is one.
+    pub fn error_page(&self, status: u16) -> Option<&str> {
+        self.error_pages.iter().find(|p| p.status == status).map(|p| &p.page[..])
+    }
+
+    /// Content-Type to serve a (slash-stripped) URI with: the most specific matching override if
+    /// there is one, otherwise a mapping from a `mime.types` file, the type it was `stored` with
+    /// (if its source keeps one) or the built-in mapping, with the most specific charset applied.
+    pub fn content_type(&self, uri: &str, stored: Option<&String>) -> ContentType {
+        let extension = match uri.rfind('.') {
+            Some(o) => &uri[o + 1..],
+            None => "",
+        };
+
+        let content_type = self.mime_overrides
+            .iter()
+            .filter(|o| o.extension.eq_ignore_ascii_case(extension))
+            .filter(|o| o.dir.as_ref().map_or(true, |d| dir_contains(d, uri)))
+            .max_by_key(|o| specificity(&o.dir))
+            .map(|o| ContentType::Custom(o.mime_type.clone()))
+            .or_else(|| {
+                self.mime_types
+                    .iter()
+                    .rev()
+                    .find(|t| t.extension.eq_ignore_ascii_case(extension))
+                    .map(|t| ContentType::Custom(t.mime_type.clone()))
+            })
+            .or_else(|| stored.map(|t| ContentType::Custom(t.clone())))
+            .unwrap_or_else(|| ContentType::from_path(uri));
+
+        let charset = self.charsets
+            .iter()
+            .filter(|c| c.dir.as_ref().map_or(true, |d| dir_contains(d, uri)))
+            .max_by_key(|c| specificity(&c.dir));
+
+        match charset {
+            Some(c) => content_type.with_charset(&c.charset),
+            None => content_type,
+        }
+    }
+}
+
+/// Policy for request paths containing empty segments (duplicate or leading slashes).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmptySegments {
+    /// Drop the empty segments and serve whatever is left, e.g. `foo//bar` as `foo/bar`.
+    Collapse,
+    /// Answer with a 400.
+    Reject,
+}
+
+impl FromStr for EmptySegments {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "collapse" => Ok(EmptySegments::Collapse),
+            "reject" => Ok(EmptySegments::Reject),
+            _ => Err(format!("{} is neither \"collapse\" nor \"reject\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for EmptySegments {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            EmptySegments::Collapse => "collapse",
+            EmptySegments::Reject => "reject",
+        })
+    }
+}
+
+/// Policy for trailing slashes on paths naming directories and files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrailingSlash {
+    /// Redirect a directory's path without its trailing slash to the path with one, so relative
+    /// links in its index file or listing resolve against the directory, not its parent.
+    Redirect,
+    /// Redirect for directories, and redirect a file's path with a trailing slash to the path
+    /// without one.
+    Canonical,
+    /// Serve either path as it is.
+    Off,
+}
+
+impl FromStr for TrailingSlash {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "redirect" => Ok(TrailingSlash::Redirect),
+            "canonical" => Ok(TrailingSlash::Canonical),
+            "off" => Ok(TrailingSlash::Off),
+            _ => Err(format!("{} is none of \"redirect\", \"canonical\" and \"off\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for TrailingSlash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            TrailingSlash::Redirect => "redirect",
+            TrailingSlash::Canonical => "canonical",
+            TrailingSlash::Off => "off",
+        })
+    }
+}
+
+/// `HOST=DIR`, e.g. `blog.example.com=/srv/blog`: requests for a host name served from a root
+/// directory of its own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualHost {
+    /// Lowercased and in ASCII form, to compare with `Request::host`.
+    pub host: String,
+    pub root: PathBuf,
+}
+
+impl FromStr for VirtualHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+
+        match (halves.next(), halves.next()) {
+            (Some(host), Some(root)) if !host.is_empty() && !root.is_empty() => {
+                match idna::to_ascii(host.trim_end_matches('.')) {
+                    Some(host) => {
+                        Ok(VirtualHost {
+                            host: host,
+                            root: PathBuf::from(root),
+                        })
+                    }
+                    None => Err(format!("{} is not a valid host name", host)),
+                }
+            }
+            _ => Err(format!("{} is not of the form HOST=DIR", s)),
+        }
+    }
+}
+
+impl fmt::Display for VirtualHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.host, self.root.display())
+    }
+}
+
+/// Policy for requests for a host name which isn't one of the virtual hosts.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum UnknownHost {
+    /// Serve them from the default root.
+    Default,
+    /// Answer with a 421, as they were meant for some other server.
+    Reject,
+}
+
+impl FromStr for UnknownHost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "default" => Ok(UnknownHost::Default),
+            "reject" => Ok(UnknownHost::Reject),
+            _ => Err(format!("{} is neither \"default\" nor \"reject\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for UnknownHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            UnknownHost::Default => "default",
+            UnknownHost::Reject => "reject",
+        })
+    }
+}
+
+/// Whether a (slash-stripped) URI falls within a directory given relative to the server root.
+fn dir_contains(dir: &str, uri: &str) -> bool {
+    let dir = dir.trim_matches('/');
+    dir.is_empty() || uri == dir || (uri.starts_with(dir) && uri[dir.len()..].starts_with('/'))
+}
+
+/// Rank directory-scoped settings so deeper directories win over shallower ones, and any
+/// directory wins over a server-wide setting.
+fn specificity(dir: &Option<String>) -> isize {
+    match *dir {
+        Some(ref d) => d.trim_matches('/').len() as isize,
+        None => -1,
+    }
+}
+
+/// Put an optional `DIR:` scope back on the front of a setting.
+fn join_dir(f: &mut fmt::Formatter, dir: &Option<String>) -> fmt::Result {
+    match *dir {
+        Some(ref dir) => write!(f, "{}:", dir),
+        None => Ok(()),
+    }
+}
+
+/// Split an optional `DIR:` scope off the front of a setting.
+fn split_dir(s: &str) -> (Option<String>, &str) {
+    match s.rfind(':') {
+        Some(i) => (Some(s[..i].to_owned()), &s[i + 1..]),
+        None => (None, s),
+    }
+}
+
+/// `[DIR:]EXT=TYPE`, e.g. `map=application/json` or `api/docs:map=application/json`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MimeOverride {
+    pub dir: Option<String>,
+    pub extension: String,
+    pub mime_type: String,
+}
+
+impl FromStr for MimeOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (dir, mapping) = split_dir(s);
+        let mut halves = mapping.splitn(2, '=');
+
+        match (halves.next(), halves.next()) {
+            (Some(ext), Some(mime)) if !ext.is_empty() && mime.contains('/') => {
+                Ok(MimeOverride {
+                    dir: dir,
+                    extension: ext.trim_start_matches('.').to_owned(),
+                    mime_type: mime.to_owned(),
+                })
+            }
+            _ => Err(format!("{} is not of the form [DIR:]EXT=TYPE", s)),
+        }
+    }
+}
+
+impl fmt::Display for MimeOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(join_dir(f, &self.dir));
+        write!(f, "{}={}", self.extension, self.mime_type)
+    }
+}
+
+/// `[DIR:]CHARSET`, e.g. `utf-8` or `legacy:iso-8859-1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CharsetSetting {
+    pub dir: Option<String>,
+    pub charset: String,
+}
+
+impl FromStr for CharsetSetting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (dir, charset) = split_dir(s);
+
+        let valid = !charset.is_empty() &&
+                    charset.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c));
+
+        if valid {
+            Ok(CharsetSetting {
+                dir: dir,
+                charset: charset.to_owned(),
+            })
+        } else {
+            Err(format!("{} is not of the form [DIR:]CHARSET", s))
+        }
+    }
+}
+
+impl fmt::Display for CharsetSetting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(join_dir(f, &self.dir));
+        f.write_str(&self.charset)
+    }
+}
+
+/// `PATTERN[,PATTERN...]=DIRECTIVES`, e.g. `*.css,*.js=max-age=86400` or `/=no-cache`: the
+/// Cache-Control to send with files matching any of the patterns, each of which is `*.EXT` for
+/// files with an extension, or a file or directory (relative to the root, `/` for all).
+#[derive(Clone, Debug, PartialEq)]
+pub struct CacheRule {
+    pub patterns: Vec<String>,
+    pub directives: String,
+}
+
+impl FromStr for CacheRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+        let (patterns, directives) = match (halves.next(), halves.next()) {
+            (Some(p), Some(d)) => (p, d.trim()),
+            _ => return Err(format!("{} is not of the form PATTERN[,PATTERN...]=DIRECTIVES", s)),
+        };
+
+        let patterns = patterns.split(',').map(|p| p.trim().to_owned()).collect::<Vec<_>>();
+        // a * only goes at the start of an extension pattern
+        let is_pattern = |p: &String| {
+            !p.is_empty() && (!p.contains('*') || p.len() > 2 && p.rfind('*') == Some(0) &&
+                                                  p.starts_with("*."))
+        };
+        if let Some(bad) = patterns.iter().find(|p| !is_pattern(p)) {
+            return Err(format!("{:?} is neither *.EXT nor a path", bad));
+        }
+
+        if directives.is_empty() || !directives.chars().all(|c| c >= ' ' && c <= '~') {
+            return Err(format!("{:?} isn't a Cache-Control value", directives));
+        }
+
+        Ok(CacheRule {
+            patterns: patterns,
+            directives: directives.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for CacheRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.patterns.join(","), self.directives)
+    }
+}
+
+impl CacheRule {
+    /// Whether any of the patterns matches a (slash-stripped) URI.
+    pub fn matches(&self, uri: &str) -> bool {
+        self.patterns.iter().any(|p| if p.starts_with("*.") {
+            let extension = p[1..].to_ascii_lowercase();
+            uri.len() > extension.len() && uri.to_ascii_lowercase().ends_with(&extension)
+        } else {
+            dir_contains(p, uri)
+        })
+    }
+
+    /// The `max-age` the directives give, in seconds, which an Expires date is worked out from.
+    pub fn max_age(&self) -> Option<u64> {
+        self.directives
+            .split(',')
+            .filter_map(|d| {
+                let mut halves = d.trim().splitn(2, '=');
+                match (halves.next(), halves.next()) {
+                    (Some(name), Some(value)) if name.eq_ignore_ascii_case("max-age") => {
+                        value.trim_matches('"').parse().ok()
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+    }
+}
+
+/// `NAME: VALUE`, e.g. `X-Frame-Options: DENY`: a header to send with every response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseHeader {
+    pub name: String,
+    pub value: String,
+}
+
+/// Headers the server works out for itself, which a fixed value would contradict.
+const RESERVED_HEADERS: &'static [&'static str] = &["Connection",
+                                                    "Content-Length",
+                                                    "Content-Type",
+                                                    "Date",
+                                                    "Keep-Alive",
+                                                    "Transfer-Encoding"];
+
+impl FromStr for ResponseHeader {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, ':');
+        let (name, value) = match (halves.next(), halves.next()) {
+            (Some(n), Some(v)) => (n.trim(), v.trim()),
+            _ => return Err(format!("{} is not of the form NAME: VALUE", s)),
+        };
+
+        if name.is_empty() || !name.bytes().all(is_tchar) {
+            return Err(format!("{:?} isn't a header name", name));
+        }
+        if RESERVED_HEADERS.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+            return Err(format!("{} is set by the server itself", name));
+        }
+        if value.is_empty() || !value.chars().all(|c| c >= ' ' && c <= '~') {
+            return Err(format!("{:?} isn't a header value", value));
+        }
+
+        Ok(ResponseHeader {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for ResponseHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.value)
+    }
+}
+
+/// `CODE=PAGE`, e.g. `404=/errors/404.html`: a page (relative to the root) to serve as the body of
+/// error responses with a status code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorPage {
+    /// From 400 to 599.
+    pub status: u16,
+    /// Slash-stripped, like a request's URI.
+    pub page: String,
+}
+
+impl FromStr for ErrorPage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+
+        match (halves.next().map(|c| c.parse::<u16>()), halves.next()) {
+            (Some(Ok(status)), Some(page)) if status >= 400 && status < 600 &&
+                                              !page.trim_matches('/').is_empty() => {
+                Ok(ErrorPage {
+                    status: status,
+                    page: page.trim_matches('/').to_owned(),
+                })
+            }
+            _ => Err(format!("{} is not of the form CODE=PAGE, with a 4xx or 5xx CODE", s)),
+        }
+    }
+}
+
+impl fmt::Display for ErrorPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}=/{}", self.status, self.page)
+    }
+}
+
+/// `NAME=MAX/PER_SEC`, e.g. `cgi=4/20`: a class of requests, at most `MAX` of which are worked
+/// on at once and `PER_SEC` started a second (in bursts of up to a second's worth). Either may be
+/// `-` for no limit, and `/PER_SEC` may be left off.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostClass {
+    pub name: String,
+    pub max: Option<usize>,
+    pub per_sec: Option<usize>,
+}
+
+impl FromStr for CostClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let limit = |l: &str| match l {
+            "-" => Some(None),
+            l => l.parse::<usize>().ok().and_then(|n| if n > 0 { Some(Some(n)) } else { None }),
+        };
+
+        let mut halves = s.splitn(2, '=');
+        let (name, budget) = (halves.next().unwrap(), halves.next().unwrap_or(""));
+        let mut limits = budget.splitn(2, '/');
+        let max = limits.next().and_then(&limit);
+        let per_sec = limits.next().map_or(Some(None), &limit);
+
+        match (max, per_sec) {
+            (Some(max), Some(per_sec)) if !name.is_empty() => {
+                Ok(CostClass {
+                    name: name.to_owned(),
+                    max: max,
+                    per_sec: per_sec,
+                })
+            }
+            _ => {
+                Err(format!("{} is not of the form NAME=MAX/PER_SEC, with each at least 1 or -",
+                            s))
+            }
+        }
+    }
+}
+
+impl fmt::Display for CostClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = |l: Option<usize>| l.map_or("-".to_owned(), |n| n.to_string());
+        write!(f, "{}={}/{}", self.name, limit(self.max), limit(self.per_sec))
+    }
+}
+
+/// `DIR=CLASS`, e.g. `/cgi-bin=cgi`: a directory (relative to the root) whose requests are in a
+/// cost class.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostPath {
+    /// Slash-stripped, like a request's URI.
+    pub dir: String,
+    pub class: String,
+}
+
+impl FromStr for CostPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.rsplitn(2, '=');
+
+        match (halves.next(), halves.next()) {
+            (Some(class), Some(dir)) if !class.is_empty() => {
+                Ok(CostPath {
+                    dir: dir.trim_matches('/').to_owned(),
+                    class: class.to_owned(),
+                })
+            }
+            _ => Err(format!("{} is not of the form DIR=CLASS", s)),
+        }
+    }
+}
+
+impl fmt::Display for CostPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}", self.dir, self.class)
+    }
+}
+
+/// `[PREFIX=]DIR`, e.g. `cgi-bin` or `/scripts=/srv/cgi`: a directory whose files are run as CGI
+/// scripts for requests under a URI prefix. A relative `DIR` is taken relative to the root (of
+/// each virtual host), even in a config file, and without a `PREFIX` it's also the prefix.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CgiDir {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub dir: PathBuf,
+}
+
+impl FromStr for CgiDir {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+        let (prefix, dir) = match (halves.next(), halves.next()) {
+            (Some(prefix), Some(dir)) => (prefix, dir),
+            (Some(dir), None) if !Path::new(dir).is_absolute() => (dir, dir),
+            _ => return Err(format!("{} is not of the form [PREFIX=]DIR, with a PREFIX for a \
+                                     DIR outside the root",
+                                    s)),
+        };
+
+        if prefix.trim_matches('/').is_empty() || dir.is_empty() {
+            return Err(format!("{} is not of the form [PREFIX=]DIR, with neither empty", s));
+        }
+
+        Ok(CgiDir {
+            prefix: prefix.trim_matches('/').to_owned(),
+            dir: PathBuf::from(dir.trim_end_matches('/')),
+        })
+    }
+}
+
+impl fmt::Display for CgiDir {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}", self.prefix, self.dir.display())
+    }
+}
+
+/// `EXT=PROGRAM`, e.g. `py=/usr/bin/python3`: a program to run CGI scripts with an extension with,
+/// passing it the script's path, for scripts which can't be run by themselves (having no shebang
+/// line or permission to execute). A bare `PROGRAM` is looked up on the `PATH`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CgiInterpreter {
+    pub extension: String,
+    pub program: PathBuf,
+}
+
+impl FromStr for CgiInterpreter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+
+        match (halves.next(), halves.next()) {
+            (Some(ext), Some(program)) if !ext.trim_start_matches('.').is_empty() &&
+                                          !program.is_empty() => {
+                Ok(CgiInterpreter {
+                    extension: ext.trim_start_matches('.').to_owned(),
+                    program: PathBuf::from(program),
+                })
+            }
+            _ => Err(format!("{} is not of the form EXT=PROGRAM", s)),
+        }
+    }
+}
+
+impl fmt::Display for CgiInterpreter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.extension, self.program.display())
+    }
+}
+
+/// `PREFIX=ADDRESS`, e.g. `/php=127.0.0.1:9000` or `/app=unix:/run/php-fpm.sock`: an application
+/// server to answer requests under a URI prefix over FastCGI.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FastCgiRoute {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub address: fastcgi::Address,
+    /// Where a `HOST:PORT` address was found to be by `resolve`, once it's been looked up.
+    pub addr: Option<SocketAddr>,
+}
+
+impl FastCgiRoute {
+    /// Look up the application's address, once, so requests needn't each wait on DNS.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        self.addr = try!(self.address.resolve());
+        Ok(())
+    }
+}
+
+impl FromStr for FastCgiRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+
+        match (halves.next(), halves.next()) {
+            (Some(prefix), Some(address)) => {
+                Ok(FastCgiRoute {
+                    prefix: prefix.trim_matches('/').to_owned(),
+                    address: try!(address.parse()),
+                    addr: None,
+                })
+            }
+            _ => Err(format!("{} is not of the form PREFIX=ADDRESS", s)),
+        }
+    }
+}
+
+impl fmt::Display for FastCgiRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}", self.prefix, self.address)
+    }
+}
+
+/// `PREFIX=URL`, e.g. `/api=http://127.0.0.1:3000`: an upstream HTTP server to forward requests
+/// under a URI prefix to. Or several, each with a weight for its share of the requests,
+/// `PREFIX=URL WEIGHT, URL WEIGHT...`, e.g. `/api=http://10.0.0.1:3000 95, http://10.0.0.2:3000 5`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyRoute {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    /// At least one, with weights of at least 1.
+    pub upstreams: Vec<(proxy::Upstream, u32)>,
+}
+
+impl FromStr for ProxyRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+        let (prefix, upstreams) = match (halves.next(), halves.next()) {
+            (Some(prefix), Some(upstreams)) => (prefix, upstreams),
+            _ => return Err(format!("{} is not of the form PREFIX=URL", s)),
+        };
+
+        let upstreams = try!(upstreams.split(',')
+            .map(|upstream| {
+                let mut words = upstream.split_whitespace();
+                let url = try!(words.next().unwrap_or("").parse());
+                match (words.next().map(|w| w.parse::<u32>()), words.next()) {
+                    (None, _) => Ok((url, 1)),
+                    (Some(Ok(weight)), None) if weight > 0 => Ok((url, weight)),
+                    _ => Err(format!("{:?} is not of the form URL [WEIGHT]", upstream.trim())),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>());
+
+        Ok(ProxyRoute {
+            prefix: prefix.trim_matches('/').to_owned(),
+            upstreams: upstreams,
+        })
+    }
+}
+
+impl fmt::Display for ProxyRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.upstreams.len() == 1 {
+            return write!(f, "/{}={}", self.prefix, self.upstreams[0].0);
+        }
+
+        let upstreams = self.upstreams
+            .iter()
+            .map(|&(ref upstream, weight)| format!("{} {}", upstream, weight))
+            .collect::<Vec<_>>();
+        write!(f, "/{}={}", self.prefix, upstreams.join(", "))
+    }
+}
+
+/// `PREFIX=URL [PERCENT%] [NAME:VALUE]`, e.g. `/api=http://127.0.0.1:3001 5% X-Canary:1`: an
+/// upstream to send a share of the requests proxied under a URI prefix to in place of the route's
+/// own, and any with a header of the given value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CanaryRoute {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub upstream: proxy::Upstream,
+    /// From 0 to 100.
+    pub percent: u8,
+    pub header: Option<(String, String)>,
+}
+
+impl FromStr for CanaryRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let malformed = || format!("{} is not of the form PREFIX=URL [PERCENT%] [NAME:VALUE]", s);
+
+        let mut words = s.split_whitespace();
+        let mut halves = words.next().unwrap_or("").splitn(2, '=');
+        let (prefix, url) = match (halves.next(), halves.next()) {
+            (Some(prefix), Some(url)) => (prefix, url),
+            _ => return Err(malformed()),
+        };
+
+        let mut percent = None;
+        let mut header = None;
+        for word in words {
+            if word.ends_with('%') && percent.is_none() {
+                match word.trim_end_matches('%').parse::<u8>() {
+                    Ok(p) if p <= 100 => percent = Some(p),
+                    _ => return Err(format!("{} isn't a percentage from 0% to 100%", word)),
+                }
+            } else if word.contains(':') && header.is_none() {
+                let mut halves = word.splitn(2, ':');
+                let name = halves.next().unwrap_or("");
+                if name.is_empty() || !name.bytes().all(is_tchar) {
+                    return Err(format!("{:?} isn't a header name", name));
+                }
+                header = Some((name.to_owned(), halves.next().unwrap_or("").to_owned()));
+            } else {
+                return Err(malformed());
+            }
+        }
+        if percent.is_none() && header.is_none() {
+            return Err(format!("{} sends nothing to the canary", s));
+        }
+
+        Ok(CanaryRoute {
+            prefix: prefix.trim_matches('/').to_owned(),
+            upstream: try!(url.parse()),
+            percent: percent.unwrap_or(0),
+            header: header,
+        })
+    }
+}
+
+impl fmt::Display for CanaryRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "/{}={} {}%", self.prefix, self.upstream, self.percent));
+        match self.header {
+            Some((ref name, ref value)) => write!(f, " {}:{}", name, value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `PREFIX=URL [PERCENT%]`, e.g. `/api=http://127.0.0.1:4000 10%`: a shadow upstream to copy a
+/// share (all, if it isn't given) of the requests proxied under a URI prefix to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorRoute {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub upstream: proxy::Upstream,
+    /// From 1 to 100.
+    pub percent: u8,
+}
+
+impl FromStr for MirrorRoute {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut words = s.split_whitespace();
+        let mut halves = words.next().unwrap_or("").splitn(2, '=');
+        let (prefix, url) = match (halves.next(), halves.next()) {
+            (Some(prefix), Some(url)) => (prefix, url),
+            _ => return Err(format!("{} is not of the form PREFIX=URL [PERCENT%]", s)),
+        };
+
+        let percent = match (words.next(), words.next()) {
+            (None, _) => 100,
+            (Some(percent), None) => {
+                match percent.trim_end_matches('%').parse::<u8>() {
+                    Ok(p) if p >= 1 && p <= 100 && percent.ends_with('%') => p,
+                    _ => return Err(format!("{} isn't a percentage from 1% to 100%", percent)),
+                }
+            }
+            _ => return Err(format!("{} is not of the form PREFIX=URL [PERCENT%]", s)),
+        };
+
+        Ok(MirrorRoute {
+            prefix: prefix.trim_matches('/').to_owned(),
+            upstream: try!(url.parse()),
+            percent: percent,
+        })
+    }
+}
+
+impl fmt::Display for MirrorRoute {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={} {}%", self.prefix, self.upstream, self.percent)
+    }
+}
+
+/// `PREFIX=SECONDS`, e.g. `/api=600`: how long browsers may keep the answers to preflight
+/// requests under a URI prefix, as `Access-Control-Max-Age`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CorsMaxAge {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub seconds: u64,
+}
+
+impl FromStr for CorsMaxAge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.rsplitn(2, '=');
+
+        match (halves.next().map(|n| n.trim().parse::<u64>()), halves.next()) {
+            (Some(Ok(seconds)), Some(prefix)) => {
+                Ok(CorsMaxAge {
+                    prefix: prefix.trim_matches('/').to_owned(),
+                    seconds: seconds,
+                })
+            }
+            _ => Err(format!("{} is not of the form PREFIX=SECONDS", s)),
+        }
+    }
+}
+
+impl fmt::Display for CorsMaxAge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}", self.prefix, self.seconds)
+    }
+}
+
+/// `PREFIX=USER:HASH`, e.g. `/admin=alice:pbkdf2-sha256$100000$...`: a user who may make
+/// requests under a URI prefix, with a salted hash of their password.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthRule {
+    /// Slash-stripped, like a request's URI.
+    pub prefix: String,
+    pub user: String,
+    pub password: PasswordHash,
+}
+
+impl FromStr for AuthRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, '=');
+        let (prefix, credentials) = (halves.next().unwrap(), halves.next().unwrap_or(""));
+        let mut credentials = credentials.splitn(2, ':');
+
+        match (credentials.next(), credentials.next()) {
+            (Some(user), Some(hash)) if !user.is_empty() => {
+                Ok(AuthRule {
+                    prefix: prefix.trim_matches('/').to_owned(),
+                    user: user.to_owned(),
+                    password: try!(hash.parse()),
+                })
+            }
+            _ => Err(format!("{} is not of the form PREFIX=USER:HASH", s)),
+        }
+    }
+}
+
+impl fmt::Display for AuthRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}:{}", self.prefix, self.user, self.password)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use auth::StaticUsers;
+    use handler::Handler;
+    use request::Request;
+    use response::{Response, Status};
+
+    use super::{AuthRule, CacheRule, CanaryRoute, CgiDir, CgiInterpreter, CharsetSetting, Config,
+                CorsMaxAge, CostClass, CostPath, ErrorPage, FastCgiRoute, MimeOverride,
+                MirrorRoute, ProxyRoute, ResponseHeader, VirtualHost};
+
+    #[derive(Debug)]
+    struct Answer(u16);
+
+    impl Handler for Answer {
+        fn handle(&self, _: &Request) -> Response {
+            Response::builder().status(Status::Custom(self.0, String::new())).build()
+        }
+    }
+
+    #[test]
+    fn language_dir_matching() {
+        let mut config = Config::new(PathBuf::from("."));
+        assert!(!config.negotiates_language("docs/page.html"));
+
+        config.language_dirs.push("/docs".to_owned());
+        assert!(config.negotiates_language("docs/page.html"));
+        assert!(!config.negotiates_language("docsfoo/page.html"));
+        assert!(!config.negotiates_language("page.html"));
+
+        config.language_dirs.push("/".to_owned());
+        assert!(config.negotiates_language("page.html"));
+    }
+
+    #[test]
+    fn hidden_paths() {
+        let mut config = Config::new(PathBuf::from("."));
+        assert!(config.is_hidden(".env"));
+        assert!(config.is_hidden("repo/.git/config"));
+        assert!(config.is_hidden(".."));
+        assert!(!config.is_hidden(".well-known/security.txt"));
+        assert!(!config.is_hidden("docs/file.tar.gz"));
+
+        config.allowed_hidden = Vec::new();
+        assert!(config.is_hidden(".well-known/security.txt"));
+
+        config.serve_hidden = true;
+        assert!(!config.is_hidden("repo/.git/config"));
+    }
+
+    #[test]
+    fn route_matching() {
+        let mut config = Config::new(PathBuf::from("."));
+        assert!(config.handler_for("api/things").is_none());
+
+        config.route("/api/", Answer(1));
+        config.route("api/v2", Answer(2));
+
+        let answer = |uri| config.handler_for(uri).map(|h| format!("{:?}", h));
+        assert_eq!(answer("api"), Some("Answer(1)".to_owned()));
+        assert_eq!(answer("api/things"), Some("Answer(1)".to_owned()));
+        assert_eq!(answer("api/v2/things"), Some("Answer(2)".to_owned()));
+        assert_eq!(answer("apiary"), None);
+    }
+
+    #[test]
+    fn cache_rules() {
+        let rule = "*.css, *.JS = public, max-age=86400".parse::<CacheRule>().unwrap();
+        assert_eq!(rule.to_string(), "*.css,*.JS=public, max-age=86400");
+        assert_eq!(rule.max_age(), Some(86400));
+        assert!(rule.matches("static/site.css"));
+        assert!(rule.matches("app.min.js"));
+        assert!(!rule.matches("css"));
+        assert!(!rule.matches("site.css.map"));
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.cache_rules.push(rule);
+        config.cache_rules.push("assets=max-age=3600".parse().unwrap());
+        config.cache_rules.push("/=no-cache".parse().unwrap());
+        let directives = |uri| config.cache_rule_for(uri).map(|r| &r.directives[..]);
+        assert_eq!(directives("assets/site.css"), Some("public, max-age=86400"));
+        assert_eq!(directives("assets/logo.png"), Some("max-age=3600"));
+        assert_eq!(directives("assetsfoo/logo.png"), Some("no-cache"));
+        assert_eq!(config.cache_rule_for("index.html").unwrap().max_age(), None);
+
+        for bad in &["*.css", "=max-age=60", "*=no-cache", "*.=no-cache", "a*.css=no-cache",
+                     "*.css=", "*.css=max-age=60\r\nX-Injected: 1"] {
+            assert!(bad.parse::<CacheRule>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn parse_response_headers() {
+        let header = "Strict-Transport-Security:max-age=63072000; includeSubDomains"
+            .parse::<ResponseHeader>()
+            .unwrap();
+        assert_eq!(header.name, "Strict-Transport-Security");
+        assert_eq!(header.to_string(),
+                   "Strict-Transport-Security: max-age=63072000; includeSubDomains");
+
+        for bad in &["X-Frame-Options", "X-Frame-Options:", "X Frame: DENY", ": DENY",
+                     "content-length: 0", "Date: today",
+                     "X-Frame-Options: DENY\r\nSet-Cookie: a=b"] {
+            assert!(bad.parse::<ResponseHeader>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn parse_mime_overrides() {
+        assert_eq!("map=application/json".parse::<MimeOverride>(),
+                   Ok(MimeOverride {
+                       dir: None,
+                       extension: "map".to_owned(),
+                       mime_type: "application/json".to_owned(),
+                   }));
+        assert_eq!("api/docs:.map=application/json".parse::<MimeOverride>(),
+                   Ok(MimeOverride {
+                       dir: Some("api/docs".to_owned()),
+                       extension: "map".to_owned(),
+                       mime_type: "application/json".to_owned(),
+                   }));
+        assert!("map".parse::<MimeOverride>().is_err());
+        assert!("map=json".parse::<MimeOverride>().is_err());
+        assert!("legacy:".parse::<CharsetSetting>().is_err());
+
+        // and back again, as exported
+        let mime = "api/docs:map=application/json".parse::<MimeOverride>().unwrap();
+        assert_eq!(mime.to_string(), "api/docs:map=application/json");
+        assert_eq!("legacy:iso-8859-1".parse::<CharsetSetting>().unwrap().to_string(),
+                   "legacy:iso-8859-1");
+    }
+
+    #[test]
+    fn parse_vhosts() {
+        assert_eq!("Blog.Example.com.=/srv/blog".parse::<VirtualHost>(),
+                   Ok(VirtualHost {
+                       host: "blog.example.com".to_owned(),
+                       root: PathBuf::from("/srv/blog"),
+                   }));
+        assert_eq!("bücher.example=/srv/books".parse::<VirtualHost>().unwrap().host,
+                   "xn--bcher-kva.example");
+        assert!("xn--.example=/srv/books".parse::<VirtualHost>().is_err());
+        assert!("example.com".parse::<VirtualHost>().is_err());
+        assert!("=/srv/blog".parse::<VirtualHost>().is_err());
+    }
+
+    #[test]
+    fn parse_error_pages() {
+        let page = "404=/errors/404.html".parse::<ErrorPage>().unwrap();
+        assert_eq!(page,
+                   ErrorPage {
+                       status: 404,
+                       page: "errors/404.html".to_owned(),
+                   });
+        assert_eq!(page.to_string(), "404=/errors/404.html");
+
+        assert!("200=/ok.html".parse::<ErrorPage>().is_err());
+        assert!("404".parse::<ErrorPage>().is_err());
+        assert!("404=/".parse::<ErrorPage>().is_err());
+        assert!("not-found=/errors/404.html".parse::<ErrorPage>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.error_pages.push(page);
+        assert_eq!(config.error_page(404), Some("errors/404.html"));
+        assert_eq!(config.error_page(500), None);
+    }
+
+    #[test]
+    fn parse_cost_classes() {
+        let cgi = "cgi=4/20".parse::<CostClass>().unwrap();
+        assert_eq!(cgi,
+                   CostClass {
+                       name: "cgi".to_owned(),
+                       max: Some(4),
+                       per_sec: Some(20),
+                   });
+        assert_eq!(cgi.to_string(), "cgi=4/20");
+        assert_eq!("static=256".parse::<CostClass>().unwrap().to_string(), "static=256/-");
+        assert_eq!("api=-/5".parse::<CostClass>().unwrap().max, None);
+
+        for bad in &["cgi", "cgi=", "=4", "cgi=0", "cgi=4/0", "cgi=lots/5", "cgi=4/5/6"] {
+            assert!(bad.parse::<CostClass>().is_err(), "{} should be rejected", bad);
+        }
+
+        let path = "/cgi-bin/=cgi".parse::<CostPath>().unwrap();
+        assert_eq!(path.to_string(), "/cgi-bin=cgi");
+        assert!("/cgi-bin".parse::<CostPath>().is_err());
+        assert!("/cgi-bin=".parse::<CostPath>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.cost_classes = vec![cgi, "reports=1".parse().unwrap()];
+        config.cost_paths = vec![path,
+                                 "/cgi-bin/reports=reports".parse().unwrap(),
+                                 "/old=gone".parse().unwrap()];
+
+        let class = |uri| config.cost_class_for(uri).map(|c| &c.name[..]);
+        assert_eq!(class("cgi-bin/hello.py"), Some("cgi"));
+        assert_eq!(class("cgi-bin/reports/daily.py"), Some("reports"));
+        assert_eq!(class("old/page.html"), None);
+        assert_eq!(class("index.html"), None);
+    }
+
+    #[test]
+    fn parse_cgi_settings() {
+        let dir = "/scripts/=/srv/cgi/".parse::<CgiDir>().unwrap();
+        assert_eq!(dir,
+                   CgiDir {
+                       prefix: "scripts".to_owned(),
+                       dir: PathBuf::from("/srv/cgi"),
+                   });
+        assert_eq!(dir.to_string(), "/scripts=/srv/cgi");
+        assert_eq!("cgi-bin".parse::<CgiDir>().unwrap().to_string(), "/cgi-bin=cgi-bin");
+        for bad in &["/srv/cgi", "=/srv/cgi", "/=cgi-bin", "scripts="] {
+            assert!(bad.parse::<CgiDir>().is_err(), "{} should be rejected", bad);
+        }
+
+        let interpreter = ".py=/usr/bin/python3".parse::<CgiInterpreter>().unwrap();
+        assert_eq!(interpreter.to_string(), "py=/usr/bin/python3");
+        assert!("py".parse::<CgiInterpreter>().is_err());
+        assert!(".=python3".parse::<CgiInterpreter>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        let prefix = |config: &Config, uri| config.cgi_dir_for(uri).map(|d| d.prefix.clone());
+        assert_eq!(prefix(&config, "cgi-bin/hello.py"), Some("cgi-bin".to_owned()));
+        assert_eq!(prefix(&config, "cgi-binary"), None);
+
+        config.cgi_dirs.push(dir);
+        config.cgi_dirs.push("scripts/admin=admin-cgi".parse().unwrap());
+        assert_eq!(prefix(&config, "scripts/a.py"), Some("scripts".to_owned()));
+        assert_eq!(prefix(&config, "scripts/admin/a.py"), Some("scripts/admin".to_owned()));
+
+        config.cgi_interpreters.push(interpreter);
+        assert_eq!(config.cgi_interpreter_for(Path::new("/srv/cgi/a.PY")),
+                   Some(Path::new("/usr/bin/python3")));
+        assert_eq!(config.cgi_interpreter_for(Path::new("/srv/cgi/a.sh")), None);
+        assert_eq!(config.cgi_interpreter_for(Path::new("/srv/cgi/py")), None);
+    }
+
+    #[test]
+    fn parse_fastcgi_routes() {
+        let route = "/php/=127.0.0.1:9000".parse::<FastCgiRoute>().unwrap();
+        assert_eq!(route.prefix, "php");
+        assert_eq!(route.to_string(), "/php=127.0.0.1:9000");
+        assert_eq!("app=unix:/run/php-fpm.sock".parse::<FastCgiRoute>().unwrap().to_string(),
+                   "/app=unix:/run/php-fpm.sock");
+        assert!("/php".parse::<FastCgiRoute>().is_err());
+        assert!("/php=php-fpm".parse::<FastCgiRoute>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.fastcgi = vec![route, "/php/admin=unix:/run/admin.sock".parse().unwrap()];
+        let address = |uri| config.fastcgi_for(uri).map(|r| r.address.to_string());
+        assert_eq!(address("php/index.php"), Some("127.0.0.1:9000".to_owned()));
+        assert_eq!(address("php/admin/index.php"), Some("unix:/run/admin.sock".to_owned()));
+        assert_eq!(address("phpinfo.php"), None);
+    }
+
+    #[test]
+    fn parse_proxy_routes() {
+        let route = "/api/=http://127.0.0.1:3000/".parse::<ProxyRoute>().unwrap();
+        assert_eq!(route.prefix, "api");
+        assert_eq!(route.to_string(), "/api=http://127.0.0.1:3000");
+        assert!("/api".parse::<ProxyRoute>().is_err());
+        assert!("/api=127.0.0.1:3000".parse::<ProxyRoute>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.proxies = vec![route, "/api/v2=http://[::1]:3002/v2".parse().unwrap()];
+        let upstream = |uri| config.proxy_for(uri).map(|r| r.upstreams[0].0.to_string());
+        assert_eq!(upstream("api/users"), Some("http://127.0.0.1:3000".to_owned()));
+        assert_eq!(upstream("api/v2/users"), Some("http://[::1]:3002/v2".to_owned()));
+        assert_eq!(upstream("apidocs.html"), None);
+
+        let route = "/api=http://10.0.0.1:3000 95,http://10.0.0.2:3000  5".parse::<ProxyRoute>()
+            .unwrap();
+        assert_eq!(route.upstreams.iter().map(|u| u.1).collect::<Vec<_>>(), [95, 5]);
+        assert_eq!(route.to_string(), "/api=http://10.0.0.1:3000 95, http://10.0.0.2:3000 5");
+        for bad in &["/api=http://10.0.0.1:3000 0", "/api=http://10.0.0.1:3000 95,",
+                     "/api=http://10.0.0.1:3000 95 5", "/api=http://10.0.0.1:3000 -1"] {
+            assert!(bad.parse::<ProxyRoute>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn parse_canary_routes() {
+        let route = "/api/=http://127.0.0.1:3001 5% X-Canary:1".parse::<CanaryRoute>().unwrap();
+        assert_eq!((&route.prefix[..], route.percent), ("api", 5));
+        assert_eq!(route.header, Some(("X-Canary".to_owned(), "1".to_owned())));
+        assert_eq!(route.to_string(), "/api=http://127.0.0.1:3001 5% X-Canary:1");
+
+        let route = "/api=http://127.0.0.1:3001 X-Canary:".parse::<CanaryRoute>().unwrap();
+        assert_eq!(route.percent, 0);
+        assert_eq!(route.to_string(), "/api=http://127.0.0.1:3001 0% X-Canary:");
+        for bad in &["/api=http://127.0.0.1:3001", "/api=http://127.0.0.1:3001 101%",
+                     "/api=http://127.0.0.1:3001 5% 6%", "/api=http://127.0.0.1:3001 :1",
+                     "/api=http://127.0.0.1:3001 5% canary"] {
+            assert!(bad.parse::<CanaryRoute>().is_err(), "{} should be rejected", bad);
+        }
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.canaries = vec![route];
+        assert!(config.canary_for("api/users").is_some());
+        assert!(config.canary_for("apidocs.html").is_none());
+    }
+
+    #[test]
+    fn parse_mirror_routes() {
+        let route = "/api/=http://127.0.0.1:4000 10%".parse::<MirrorRoute>().unwrap();
+        assert_eq!((&route.prefix[..], route.percent), ("api", 10));
+        assert_eq!(route.to_string(), "/api=http://127.0.0.1:4000 10%");
+        assert_eq!("/api=http://127.0.0.1:4000".parse::<MirrorRoute>().unwrap().percent, 100);
+        for bad in &["/api", "/api=http://127.0.0.1:4000 0%", "/api=http://127.0.0.1:4000 101%",
+                     "/api=http://127.0.0.1:4000 10", "/api=http://127.0.0.1:4000 10% 20%"] {
+            assert!(bad.parse::<MirrorRoute>().is_err(), "{} should be rejected", bad);
+        }
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.mirrors = vec![route];
+        assert!(config.mirror_for("api/users").is_some());
+        assert!(config.mirror_for("apidocs.html").is_none());
+    }
+
+    #[test]
+    fn parse_cors_max_ages() {
+        let max_age = "/api/=600".parse::<CorsMaxAge>().unwrap();
+        assert_eq!((&max_age.prefix[..], max_age.seconds), ("api", 600));
+        assert_eq!(max_age.to_string(), "/api=600");
+        assert!("/api".parse::<CorsMaxAge>().is_err());
+        assert!("/api=ten".parse::<CorsMaxAge>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.cors_max_ages = vec![max_age, "/=5".parse().unwrap()];
+        assert_eq!(config.cors_max_age_for("api/users"), Some(600));
+        assert_eq!(config.cors_max_age_for("index.html"), Some(5));
+        config.cors_max_ages.pop();
+        assert_eq!(config.cors_max_age_for("index.html"), None);
+    }
+
+    #[test]
+    fn parse_auth_rules() {
+        let digest = "pbkdf2-sha256$1000$6870707473616c74$\
+                      3dd31095cbacb724f2d1cf08b2fc4e756f4e473f7d6fdfe6058544a705de4ff9";
+        let rule = format!("/admin/=alice:{}", digest).parse::<AuthRule>().unwrap();
+        assert_eq!(rule.prefix, "admin");
+        assert_eq!(rule.user, "alice");
+        assert_eq!(rule.to_string(), format!("/admin=alice:{}", digest));
+
+        assert!(format!("/admin=:{}", digest).parse::<AuthRule>().is_err());
+        assert!("/admin=alice:password".parse::<AuthRule>().is_err());
+        assert!(format!("/admin=alice:{}", &digest[digest.len() - 64..])
+            .parse::<AuthRule>()
+            .is_err());
+        assert!("/admin=alice".parse::<AuthRule>().is_err());
+        assert!("/admin".parse::<AuthRule>().is_err());
+
+        let mut config = Config::new(PathBuf::from("."));
+        config.auth_rules.push(rule);
+        config.auth_rules.push(format!("admin=bob:{}", digest).parse().unwrap());
+        config.auth_rules.push(format!("admin/logs=carol:{}", digest).parse().unwrap());
+
+        let users = |uri| {
+            config.auth_rules_for(uri).iter().map(|r| r.user.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(users("admin/index.html"), ["alice", "bob"]);
+        assert_eq!(users("admin/logs/today.log"), ["carol"]);
+        assert!(users("administrivia.html").is_empty());
+        assert!(users("index.html").is_empty());
+
+        // a provider of its own for a prefix beats the users for it, but not those for a longer one
+        config.protect("/admin/", StaticUsers::new(&[]));
+        let prefix = |uri: &str| config.auth_route_for(uri).map(|r| r.prefix);
+        assert_eq!(prefix("admin/logs/today.log"), Some("admin/logs".to_owned()));
+        assert_eq!(prefix("index.html"), None);
+        assert_eq!(config.auth_prefix_for("admin/logs/today.log"), Some("admin/logs"));
+        assert_eq!(config.auth_prefix_for("admin/index.html"), Some("admin"));
+        assert_eq!(config.auth_prefix_for("index.html"), None);
+
+        let route = config.auth_route_for("admin/index.html").unwrap();
+        assert_eq!(route.prefix, "admin");
+        assert!(!route.provider.check_basic("alice", "password"));
+
+        let route = config.auth_route_for("admin/logs/today.log").unwrap();
+        assert!(route.provider.check_basic("carol", "password"));
+
+        // the same users, made once
+        config.load_auth_users();
+        assert_eq!(config.auth_users.len(), 2);
+        let route = config.auth_route_for("admin/logs/today.log").unwrap();
+        assert!(Arc::ptr_eq(&route.provider, &config.auth_users[1].provider));
+        assert!(route.provider.check_basic("carol", "password"));
+    }
+
+    #[test]
+    fn peer_access() {
+        let mut config = Config::new(PathBuf::from("."));
+        let allowed = |config: &Config, ip: &str| config.peer_allowed(&ip.parse().unwrap());
+        assert!(allowed(&config, "203.0.113.9"));
+
+        config.deny = vec!["10.0.0.0/8".parse().unwrap()];
+        config.allow = vec!["10.1.0.0/16".parse().unwrap()];
+        assert!(!allowed(&config, "10.2.3.4"));
+        assert!(allowed(&config, "10.1.3.4"));
+        assert!(!allowed(&config, "203.0.113.9"));
+
+        config.allow.clear();
+        assert!(allowed(&config, "203.0.113.9"));
+        assert!(!allowed(&config, "::ffff:10.2.3.4"));
+
+        config.allow = vec!["10.0.0.0/8".parse().unwrap()];
+        assert!(!allowed(&config, "10.2.3.4"));
+    }
+
+    #[test]
+    fn content_type_resolution() {
+        let mut config = Config::new(PathBuf::from("."));
+        config.mime_overrides.push("map=application/json".parse().unwrap());
+        config.mime_overrides.push("maps:map=text/x-map".parse().unwrap());
+        config.charsets.push("utf-8".parse().unwrap());
+        config.charsets.push("legacy:iso-8859-1".parse().unwrap());
+
+        assert_eq!(config.content_type("a.map", None).as_bytes(), b"application/json");
+        assert_eq!(config.content_type("maps/a.MAP", None).as_bytes(),
+                   b"text/x-map; charset=utf-8");
+        assert_eq!(config.content_type("a.html", None).as_bytes(), b"text/html; charset=utf-8");
+        assert_eq!(config.content_type("legacy/a.txt", None).as_bytes(),
+                   b"text/plain; charset=iso-8859-1");
+        assert_eq!(config.content_type("legacy/a.bin", None).as_bytes(),
+                   b"application/octet-stream");
+
+        assert_eq!(config.content_type("style.css", None).as_bytes(),
+                   b"text/css; charset=utf-8");
+        config.mime_types.push("css=text/x-other".parse().unwrap());
+        config.mime_types.push("map=text/x-overridden".parse().unwrap());
+        config.mime_types.push("css=text/x-later".parse().unwrap());
+        assert_eq!(config.content_type("style.css", None).as_bytes(),
+                   b"text/x-later; charset=utf-8");
+        assert_eq!(config.content_type("a.map", None).as_bytes(), b"application/json");
+
+        let stored = "text/x-stored".to_owned();
+        assert_eq!(config.content_type("a.txt", Some(&stored)).as_bytes(),
+                   b"text/x-stored; charset=utf-8");
+        assert_eq!(config.content_type("a.map", Some(&stored)).as_bytes(),
+                   b"application/json");
+    }
+}
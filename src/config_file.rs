@@ -0,0 +1,588 @@
+//! Settings read from a TOML file (`--config hppt.toml`) rather than given as flags. Each setting
+//! is named after the flag it stands in for, and flags which can be repeated take an array:
+//!
+//! ```toml
+//! root = "public"
+//! listen = "0.0.0.0:8080"
+//! threads = 4
+//! autoindex = true
+//! index-file = ["index.html", "home.html"]
+//! mime-type = ["map=application/json"]
+//! ```
+//!
+//! Relative paths are relative to the directory the file is in. Flags given on the command line
+//! take precedence over the file.
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use cidr::Cidr;
+use config::{AuthRule, CacheRule, CanaryRoute, CgiDir, CgiInterpreter, CharsetSetting, Config,
+             CorsMaxAge, CostClass, CostPath, ErrorPage, FastCgiRoute, MimeOverride, MirrorRoute,
+             ProxyRoute, ResponseHeader, VirtualHost};
+use json;
+use mime;
+use request::ParseMode;
+use rewrite;
+use snapshots::IndexSnapshot;
+use toml;
+use toml::{Entry, Value};
+
+/// A file of settings, parsed but not yet checked.
+#[derive(Debug)]
+pub struct ConfigFile {
+    path: PathBuf,
+    lines: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+/// A problem with a config file, and the line it's on, if it's on one.
+#[derive(Debug)]
+pub struct Error {
+    path: PathBuf,
+    line: Option<(usize, String)>,
+    message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some((n, ref text)) => {
+                write!(f, "{}:{}: {}\n    {}", self.path.display(), n, self.message, text.trim())
+            }
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let mut text = String::new();
+        if let Err(why) = File::open(path).and_then(|mut f| f.read_to_string(&mut text)) {
+            return Err(Error {
+                path: path.to_owned(),
+                line: None,
+                message: format!("unable to read: {}", why),
+            });
+        }
+
+        Self::parse(path, &text)
+    }
+
+    /// Parse the text of a file, which relative paths in it are taken to be relative to.
+    pub fn parse(path: &Path, text: &str) -> Result<Self, Error> {
+        let lines = text.lines().map(String::from).collect::<Vec<_>>();
+
+        match toml::parse(text) {
+            Ok(entries) => {
+                Ok(ConfigFile {
+                    path: path.to_owned(),
+                    lines: lines,
+                    entries: entries,
+                })
+            }
+            Err(why) => {
+                Err(Error {
+                    path: path.to_owned(),
+                    line: Some((why.line, lines[why.line - 1].clone())),
+                    message: why.message,
+                })
+            }
+        }
+    }
+
+    /// The directory to serve, which can also be given as `root`.
+    pub fn root(&self) -> Result<Option<PathBuf>, Error> {
+        match self.get("root") {
+            Some(entry) => self.dir(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// The address to listen on, which can also be given as `listen`.
+    pub fn listen(&self) -> Result<Option<SocketAddr>, Error> {
+        match self.get("listen") {
+            Some(entry) => self.parsed(entry, &entry.value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// A setting which the caller deals with itself, as it isn't part of a `Config`.
+    pub fn string(&self, key: &str) -> Result<Option<String>, Error> {
+        match self.get(key) {
+            Some(entry) => self.string_value(entry, &entry.value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// A setting which the caller deals with itself, as it isn't part of a `Config`.
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, Error> {
+        match self.get(key) {
+            Some(entry) => self.boolean_value(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Set everything the file has settings for on a config.
+    pub fn apply(&self, config: &mut Config) -> Result<(), Error> {
+        for entry in &self.entries {
+            match &entry.key[..] {
+                // for the caller, as they're needed before there's a config or aren't part of one
+                "root" | "listen" | "verbose" | "s3-bucket" | "s3-endpoint" | "s3-region" => (),
+
+                "threads" => config.num_threads = try!(self.count(entry, 1)),
+                "header-timeout" => config.limits.header_timeout = try!(self.timeout(entry)),
+                "body-timeout" => config.limits.body_timeout = try!(self.timeout(entry)),
+                "write-timeout" => config.limits.write_timeout = try!(self.timeout(entry)),
+                "keep-alive-timeout" => {
+                    config.limits.keep_alive_timeout = try!(self.timeout(entry))
+                }
+                "keep-alive-max" => config.limits.keep_alive_max = try!(self.count(entry, 1)),
+                "rate-limit" => config.limits.rate_limit = try!(self.cap(entry)),
+                "max-connections" => config.limits.max_connections = try!(self.cap(entry)),
+                "max-connections-per-ip" => {
+                    config.limits.max_connections_per_ip = try!(self.cap(entry))
+                }
+                "requests-per-sec" => config.limits.requests_per_sec = try!(self.cap(entry)),
+                "max-cgi-output" => config.limits.max_cgi_output = try!(self.count(entry, 1)),
+                "cgi-timeout" => config.limits.cgi_timeout = try!(self.timeout(entry)),
+                "max-cgi-processes" => config.limits.max_cgi_processes = try!(self.cap(entry)),
+                "cgi-dir" => config.cgi_dirs = try!(self.list::<CgiDir>(entry)),
+                "no-cgi" => config.cgi = !try!(self.boolean_value(entry)),
+                "cgi-interpreter" => {
+                    config.cgi_interpreters = try!(self.list::<CgiInterpreter>(entry))
+                }
+                "fastcgi" => config.fastcgi = try!(self.list::<FastCgiRoute>(entry)),
+                "max-fastcgi-requests" => {
+                    config.limits.max_fastcgi_requests = try!(self.cap(entry))
+                }
+                "proxy" => config.proxies = try!(self.list::<ProxyRoute>(entry)),
+                "canary" => config.canaries = try!(self.list::<CanaryRoute>(entry)),
+                "mirror" => config.mirrors = try!(self.list::<MirrorRoute>(entry)),
+                "upstream-timeout" => config.limits.upstream_timeout = try!(self.timeout(entry)),
+                "breaker-threshold" => config.limits.breaker_threshold = try!(self.cap(entry)),
+                "breaker-cooldown" => {
+                    config.limits.breaker_cooldown =
+                        Duration::from_secs(try!(self.count(entry, 1)) as u64)
+                }
+                "rewrite" => config.rewrites = try!(self.list::<rewrite::Rule>(entry)),
+                "shutdown-grace" => {
+                    config.limits.shutdown_grace =
+                        Duration::from_secs(try!(self.count(entry, 0)) as u64)
+                }
+                "trusted-proxy" => config.trusted_proxies = try!(self.list::<IpAddr>(entry)),
+                "allow" => config.allow = try!(self.list::<Cidr>(entry)),
+                "deny" => config.deny = try!(self.list::<Cidr>(entry)),
+                "cost-class" => config.cost_classes = try!(self.list::<CostClass>(entry)),
+                "cost-path" => config.cost_paths = try!(self.list::<CostPath>(entry)),
+                "max-request-size" => config.limits.max_request_size = try!(self.count(entry, 0)),
+                "max-headers" => config.limits.max_headers = try!(self.count(entry, 0)),
+                "strict-http" => {
+                    config.parse_mode = if try!(self.boolean_value(entry)) {
+                        ParseMode::Strict
+                    } else {
+                        ParseMode::Lenient
+                    }
+                }
+                "empty-segments" => config.empty_segments = try!(self.parsed(entry, &entry.value)),
+                "trailing-slash" => config.trailing_slash = try!(self.parsed(entry, &entry.value)),
+                "index-file" => config.index_files = try!(self.list(entry)),
+                "autoindex" => config.autoindex = try!(self.boolean_value(entry)),
+                "serve-hidden" => config.serve_hidden = try!(self.boolean_value(entry)),
+                "allow-hidden" => config.allowed_hidden = try!(self.list(entry)),
+                "follow-symlinks" => config.follow_symlinks = try!(self.boolean_value(entry)),
+                "natural-sort" => config.natural_sort = try!(self.boolean_value(entry)),
+                "index-snapshot" => {
+                    config.index_snapshots = try!(self.list::<IndexSnapshot>(entry))
+                }
+                "snapshot-dir" => config.snapshot_dir = Some(try!(self.dir(entry))),
+                "archive-downloads" => config.archive_downloads = try!(self.boolean_value(entry)),
+                "meta-queries" => config.meta_queries = try!(self.boolean_value(entry)),
+                "compress" => config.compress = try!(self.boolean_value(entry)),
+                "precompressed" => config.precompressed = try!(self.boolean_value(entry)),
+                "cache-size" => config.cache_size = try!(self.cap(entry)),
+                "live-reload" => config.live_reload = try!(self.boolean_value(entry)),
+                "admin-endpoint" => config.admin_endpoint = try!(self.boolean_value(entry)),
+                "checksum-dir" => config.checksum_dirs = try!(self.list(entry)),
+                "language-dir" => config.language_dirs = try!(self.list(entry)),
+                "default-language" => {
+                    config.default_language = Some(try!(self.string_value(entry, &entry.value)))
+                }
+                "mime-type" => config.mime_overrides = try!(self.list::<MimeOverride>(entry)),
+                "mime-types" => config.mime_types = try!(self.mime_types(entry)),
+                "charset" => config.charsets = try!(self.list::<CharsetSetting>(entry)),
+                "cache-control" => config.cache_rules = try!(self.list::<CacheRule>(entry)),
+                "header" => config.headers = try!(self.list::<ResponseHeader>(entry)),
+                "server-header" => config.server_header = try!(self.boolean_value(entry)),
+                "error-page" => config.error_pages = try!(self.list::<ErrorPage>(entry)),
+                "api-prefix" => config.api_prefixes = try!(self.list(entry)),
+                "cors-origin" => config.cors_origins = try!(self.list(entry)),
+                "cors-max-age" => config.cors_max_ages = try!(self.list::<CorsMaxAge>(entry)),
+                "auth" => config.auth_rules = try!(self.list::<AuthRule>(entry)),
+                "vhost" => config.vhosts = try!(self.vhosts(entry)),
+                "unknown-host" => config.unknown_host = try!(self.parsed(entry, &entry.value)),
+                "tls-cert" => config.tls_cert = Some(try!(self.path(entry))),
+                "tls-key" => config.tls_key = Some(try!(self.path(entry))),
+                "access-log" => {
+                    // - is stdout, not a file next to this one
+                    config.access_log = match try!(self.string_value(entry, &entry.value)) {
+                        ref s if s == "-" => Some(PathBuf::from(s)),
+                        _ => Some(try!(self.path(entry))),
+                    }
+                }
+                "record-dir" => config.record_dir = Some(try!(self.dir(entry))),
+                "record" => config.record_patterns = try!(self.list(entry)),
+                "record-every" => config.record_every = try!(self.count(entry, 1)),
+                "raise-fd-limit" => config.raise_fd_limit = try!(self.boolean_value(entry)),
+                "crash-dir" => config.crash_dir = Some(try!(self.dir(entry))),
+
+                key => return Err(self.error(entry, format!("there's no {} setting", key))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.key == key)
+    }
+
+    fn error(&self, entry: &Entry, message: String) -> Error {
+        Error {
+            path: self.path.clone(),
+            line: Some((entry.line, self.lines[entry.line - 1].clone())),
+            message: message,
+        }
+    }
+
+    fn expected(&self, entry: &Entry, value: &Value, what: &str) -> Error {
+        self.error(entry,
+                   format!("{} must be {}, not {}", entry.key, what, value.kind()))
+    }
+
+    fn string_value(&self, entry: &Entry, value: &Value) -> Result<String, Error> {
+        match *value {
+            Value::String(ref s) => Ok(s.clone()),
+            ref other => Err(self.expected(entry, other, "a string")),
+        }
+    }
+
+    fn boolean_value(&self, entry: &Entry) -> Result<bool, Error> {
+        match entry.value {
+            Value::Boolean(b) => Ok(b),
+            ref other => Err(self.expected(entry, other, "true or false")),
+        }
+    }
+
+    /// A whole number of at least `min`.
+    fn count(&self, entry: &Entry, min: usize) -> Result<usize, Error> {
+        match entry.value {
+            Value::Integer(n) if n >= min as i64 => Ok(n as usize),
+            Value::Integer(n) => {
+                Err(self.error(entry, format!("{} must be at least {}, not {}", entry.key, min, n)))
+            }
+            ref other => Err(self.expected(entry, other, "an integer")),
+        }
+    }
+
+    /// A number of seconds, where 0 means to wait forever.
+    fn timeout(&self, entry: &Entry) -> Result<Option<Duration>, Error> {
+        match try!(self.count(entry, 0)) {
+            0 => Ok(None),
+            secs => Ok(Some(Duration::from_secs(secs as u64))),
+        }
+    }
+
+    /// A limit, where 0 means there isn't one.
+    fn cap(&self, entry: &Entry) -> Result<Option<usize>, Error> {
+        match try!(self.count(entry, 0)) {
+            0 => Ok(None),
+            n => Ok(Some(n)),
+        }
+    }
+
+    /// A string parsed the same way as the flag's value.
+    fn parsed<T>(&self, entry: &Entry, value: &Value) -> Result<T, Error>
+        where T: FromStr,
+              T::Err: fmt::Debug
+    {
+        let s = try!(self.string_value(entry, value));
+        s.parse().map_err(|why| self.error(entry, format!("invalid {}: {:?}", entry.key, why)))
+    }
+
+    /// An array of strings for a repeatable flag, or one on its own.
+    fn list<T>(&self, entry: &Entry) -> Result<Vec<T>, Error>
+        where T: FromStr,
+              T::Err: fmt::Debug
+    {
+        match entry.value {
+            Value::Array(ref values) => values.iter().map(|v| self.parsed(entry, v)).collect(),
+            ref value => Ok(vec![try!(self.parsed(entry, value))]),
+        }
+    }
+
+    fn path(&self, entry: &Entry) -> Result<PathBuf, Error> {
+        let path = PathBuf::from(try!(self.string_value(entry, &entry.value)));
+        Ok(self.relative(&path))
+    }
+
+    fn dir(&self, entry: &Entry) -> Result<PathBuf, Error> {
+        let path = try!(self.path(entry));
+        self.check_dir(entry, path)
+    }
+
+    fn vhosts(&self, entry: &Entry) -> Result<Vec<VirtualHost>, Error> {
+        let vhosts = try!(self.list::<VirtualHost>(entry));
+
+        vhosts.into_iter()
+            .map(|mut vhost| {
+                vhost.root = try!(self.check_dir(entry, self.relative(&vhost.root)));
+                Ok(vhost)
+            })
+            .collect()
+    }
+
+    /// Mappings from `mime.types` files, or given as `EXT=TYPE` (as exported) in their place.
+    fn mime_types(&self, entry: &Entry) -> Result<Vec<MimeOverride>, Error> {
+        let values = match entry.value {
+            Value::Array(ref values) => values.clone(),
+            ref value => vec![value.clone()],
+        };
+
+        let mut mappings = Vec::new();
+        for value in &values {
+            let s = try!(self.string_value(entry, value));
+            if s.contains('=') {
+                mappings.push(try!(self.parsed(entry, value)));
+            } else {
+                let loaded = mime::load_types(&self.relative(Path::new(&s)));
+                mappings.extend(try!(loaded.map_err(|why| self.error(entry, why))));
+            }
+        }
+
+        Ok(mappings)
+    }
+
+    /// A path from the file, taken relative to the directory the file is in.
+    fn relative(&self, path: &Path) -> PathBuf {
+        self.path.parent().unwrap_or(Path::new("")).join(path)
+    }
+
+    fn check_dir(&self, entry: &Entry, path: PathBuf) -> Result<PathBuf, Error> {
+        if path.is_dir() {
+            Ok(path)
+        } else {
+            Err(self.error(entry, format!("{} is not a directory", path.display())))
+        }
+    }
+}
+
+/// The settings a server resolves to from its flags, file and defaults, as a JSON object with a
+/// key per line for diffing. Keys and values mean what they would in a config file, with the
+/// addition of the content source, and any routes and auth providers a program embedding the
+/// server has set up.
+/// Password hashes are left out, as the output is for sharing.
+pub fn to_json(config: &Config, listen: &SocketAddr) -> String {
+    let path = |p: &Path| json::string(&p.to_string_lossy());
+    let optional = |s: &Option<String>| s.as_ref().map_or("null".to_owned(), |s| json::string(s));
+    let optional_path = |p: &Option<PathBuf>| p.as_ref().map_or("null".to_owned(), |p| path(p));
+    let seconds = |d: &Option<Duration>| d.map_or(0, |d| d.as_secs()).to_string();
+    let cap = |n: &Option<usize>| n.unwrap_or(0).to_string();
+    fn array<T: ToString>(values: &[T]) -> String {
+        let values = values.iter().map(|v| json::string(&v.to_string())).collect::<Vec<_>>();
+        format!("[{}]", values.join(", "))
+    }
+
+    let limits = &config.limits;
+    let auth = config.auth_rules
+        .iter()
+        .map(|r| format!("/{}={}:[redacted]", r.prefix, r.user))
+        .collect::<Vec<_>>();
+    let routes = config.routes.iter().map(|r| r.prefix.clone()).collect::<Vec<_>>();
+    let auth_routes = config.auth_routes
+        .iter()
+        .map(|r| format!("/{}={:?}", r.prefix, r.provider))
+        .collect::<Vec<_>>();
+    let fields = vec![("root", path(&config.root_dir)),
+                      ("listen", json::string(&listen.to_string())),
+                      ("source", json::string(&format!("{:?}", config.source))),
+                      ("routes", array(&routes)),
+                      ("threads", config.num_threads.to_string()),
+                      ("header-timeout", seconds(&limits.header_timeout)),
+                      ("body-timeout", seconds(&limits.body_timeout)),
+                      ("write-timeout", seconds(&limits.write_timeout)),
+                      ("keep-alive-timeout", seconds(&limits.keep_alive_timeout)),
+                      ("keep-alive-max", limits.keep_alive_max.to_string()),
+                      ("rate-limit", cap(&limits.rate_limit)),
+                      ("max-connections", cap(&limits.max_connections)),
+                      ("max-connections-per-ip", cap(&limits.max_connections_per_ip)),
+                      ("requests-per-sec", cap(&limits.requests_per_sec)),
+                      ("max-cgi-output", limits.max_cgi_output.to_string()),
+                      ("cgi-timeout", seconds(&limits.cgi_timeout)),
+                      ("max-cgi-processes", cap(&limits.max_cgi_processes)),
+                      ("cgi-dir", array(&config.cgi_dirs)),
+                      ("no-cgi", (!config.cgi).to_string()),
+                      ("cgi-interpreter", array(&config.cgi_interpreters)),
+                      ("fastcgi", array(&config.fastcgi)),
+                      ("max-fastcgi-requests", cap(&limits.max_fastcgi_requests)),
+                      ("proxy", array(&config.proxies)),
+                      ("canary", array(&config.canaries)),
+                      ("mirror", array(&config.mirrors)),
+                      ("upstream-timeout", seconds(&limits.upstream_timeout)),
+                      ("breaker-threshold", cap(&limits.breaker_threshold)),
+                      ("breaker-cooldown", limits.breaker_cooldown.as_secs().to_string()),
+                      ("rewrite", array(&config.rewrites)),
+                      ("shutdown-grace", limits.shutdown_grace.as_secs().to_string()),
+                      ("trusted-proxy", array(&config.trusted_proxies)),
+                      ("allow", array(&config.allow)),
+                      ("deny", array(&config.deny)),
+                      ("cost-class", array(&config.cost_classes)),
+                      ("cost-path", array(&config.cost_paths)),
+                      ("max-request-size", limits.max_request_size.to_string()),
+                      ("max-headers", limits.max_headers.to_string()),
+                      ("strict-http", (config.parse_mode == ParseMode::Strict).to_string()),
+                      ("empty-segments", json::string(&config.empty_segments.to_string())),
+                      ("trailing-slash", json::string(&config.trailing_slash.to_string())),
+                      ("index-file", array(&config.index_files)),
+                      ("autoindex", config.autoindex.to_string()),
+                      ("serve-hidden", config.serve_hidden.to_string()),
+                      ("allow-hidden", array(&config.allowed_hidden)),
+                      ("follow-symlinks", config.follow_symlinks.to_string()),
+                      ("natural-sort", config.natural_sort.to_string()),
+                      ("index-snapshot", array(&config.index_snapshots)),
+                      ("snapshot-dir", optional_path(&config.snapshot_dir)),
+                      ("archive-downloads", config.archive_downloads.to_string()),
+                      ("meta-queries", config.meta_queries.to_string()),
+                      ("compress", config.compress.to_string()),
+                      ("precompressed", config.precompressed.to_string()),
+                      ("cache-size", cap(&config.cache_size)),
+                      ("live-reload", config.live_reload.to_string()),
+                      ("admin-endpoint", config.admin_endpoint.to_string()),
+                      ("checksum-dir", array(&config.checksum_dirs)),
+                      ("language-dir", array(&config.language_dirs)),
+                      ("default-language", optional(&config.default_language)),
+                      ("error-page", array(&config.error_pages)),
+                      ("api-prefix", array(&config.api_prefixes)),
+                      ("cors-origin", array(&config.cors_origins)),
+                      ("cors-max-age", array(&config.cors_max_ages)),
+                      ("auth", array(&auth)),
+                      ("auth-routes", array(&auth_routes)),
+                      ("vhost", array(&config.vhosts)),
+                      ("unknown-host", json::string(&config.unknown_host.to_string())),
+                      ("mime-type", array(&config.mime_overrides)),
+                      ("mime-types", array(&config.mime_types)),
+                      ("charset", array(&config.charsets)),
+                      ("cache-control", array(&config.cache_rules)),
+                      ("header", array(&config.headers)),
+                      ("server-header", config.server_header.to_string()),
+                      ("tls-cert", optional_path(&config.tls_cert)),
+                      ("tls-key", optional_path(&config.tls_key)),
+                      ("access-log", optional_path(&config.access_log)),
+                      ("record-dir", optional_path(&config.record_dir)),
+                      ("record", array(&config.record_patterns)),
+                      ("record-every", config.record_every.to_string()),
+                      ("raise-fd-limit", config.raise_fd_limit.to_string()),
+                      ("crash-dir", optional_path(&config.crash_dir))];
+
+    let lines = fields.iter()
+        .map(|&(key, ref value)| format!("  {}: {}", json::string(key), value))
+        .collect::<Vec<_>>();
+    format!("{{\n{}\n}}", lines.join(",\n"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use auth::StaticUsers;
+    use config::Config;
+
+    use super::*;
+
+    fn applied(text: &str) -> Result<Config, String> {
+        let mut config = Config::new(PathBuf::from("."));
+        let file = try!(ConfigFile::parse(Path::new("hppt.toml"), text).map_err(|e| e.to_string()));
+        try!(file.apply(&mut config).map_err(|e| e.to_string()));
+        Ok(config)
+    }
+
+    #[test]
+    fn settings() {
+        let config = applied("threads = 4\n\
+                              write-timeout = 0\n\
+                              max-cgi-processes = 8\n\
+                              autoindex = true\n\
+                              index-file = [\"home.html\"]\n\
+                              language-dir = \"docs\"\n\
+                              mime-type = [\"map=application/json\"]\n\
+                              tls-cert = \"certs/cert.pem\"\n\
+                              root = \"ignored\"\n")
+            .unwrap();
+
+        assert_eq!(config.num_threads, 4);
+        assert_eq!(config.limits.write_timeout, None);
+        assert_eq!(config.limits.keep_alive_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.limits.max_cgi_processes, Some(8));
+        assert!(config.autoindex);
+        assert_eq!(config.index_files, vec!["home.html".to_owned()]);
+        assert_eq!(config.language_dirs, vec!["docs".to_owned()]);
+        assert_eq!(config.mime_overrides[0].mime_type, "application/json");
+        assert_eq!(config.tls_cert, Some(PathBuf::from("certs/cert.pem")));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(applied("autoindex = true\nthreads = \"four\"\n").unwrap_err(),
+                   "hppt.toml:2: threads must be an integer, not a string\n    threads = \"four\"");
+        assert_eq!(applied("keep-alive-max = 0\n").unwrap_err(),
+                   "hppt.toml:1: keep-alive-max must be at least 1, not 0\n    keep-alive-max = 0");
+        assert_eq!(applied("\n  autoindx = true\n").unwrap_err(),
+                   "hppt.toml:2: there's no autoindx setting\n    autoindx = true");
+        assert_eq!(applied("threads = four\n").unwrap_err(),
+                   "hppt.toml:1: \"four\" isn't a value (strings need quotes)\n    threads = four");
+        assert!(applied("mime-type = [\"json\"]\n").unwrap_err().starts_with("hppt.toml:1: "));
+    }
+
+    #[test]
+    fn export() {
+        let mut config = applied("cgi-timeout = 0\n\
+                              cgi-dir = [\"/scripts=/srv/cgi\"]\n\
+                              fastcgi = [\"/php=127.0.0.1:9000\"]\n\
+                              rewrite = [\"/old/* -> 301 /new/*\"]\n\
+                              charset = [\"legacy:iso-8859-1\"]\n\
+                              default-language = \"en\"\n\
+                              auth = [\"/admin=alice:pbkdf2-sha256$1$00$5e884898da28047151d0e\
+                              56f8dc6292773603d0d6aabbdd62a11ef721d1542d8\"]\n")
+            .unwrap();
+        let users = StaticUsers::new(&config.auth_rules.iter().collect::<Vec<_>>());
+        config.protect("/private/", users);
+        let json = to_json(&config, &"127.0.0.1:8080".parse().unwrap());
+
+        assert!(json.starts_with("{\n  \"root\": \".\",\n  \"listen\": \"127.0.0.1:8080\",\n"));
+        assert!(json.contains("\n  \"cgi-timeout\": 0,\n"));
+        assert!(json.contains("\n  \"cgi-dir\": [\"/scripts=/srv/cgi\"],\n"));
+        assert!(json.contains("\n  \"no-cgi\": false,\n"));
+        assert!(json.contains("\n  \"fastcgi\": [\"/php=127.0.0.1:9000\"],\n"));
+        assert!(json.contains("\n  \"rewrite\": [\"/old/* -> 301 /new/*\"],\n"));
+        assert!(json.contains("\n  \"charset\": [\"legacy:iso-8859-1\"],\n"));
+        assert!(json.contains("\n  \"default-language\": \"en\",\n"));
+        assert!(json.contains("\n  \"auth\": [\"/admin=alice:[redacted]\"],\n"));
+        assert!(json.contains("\n  \"auth-routes\": \
+                               [\"/private=StaticUsers { users: [\\\"alice\\\"] }\"],\n"));
+        assert!(json.contains("\n  \"index-file\": [\"index.html\", \"index.htm\"],\n"));
+        assert!(json.ends_with("\n  \"crash-dir\": null\n}"));
+    }
+}
@@ -0,0 +1,348 @@
+use std::io;
+use std::io::{Read, Write};
+use std::net;
+use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use libc;
+use mioco;
+use mioco::Evented;
+use mioco::tcp::TcpStream;
+use mioco::timer::Timer;
+use mioco::unix::UnixStream;
+
+use clock::Clock;
+
+/// A client connection whose writes give up with `TimedOut` once we've waited too long on the
+/// client, rather than blocking the coroutine forever on a client which has stopped reading.
+///
+/// The wait is counted from the first write after a read, i.e. when we start sending a response,
+/// so every response gets the full `write_timeout` to be delivered. Only time spent waiting for
+/// the client to take more counts, not time between writes (as when a body's sent at a limited
+/// rate).
+///
+/// Likewise, once a response has been written, reads give up with `TimedOut` if the client sends
+/// nothing more within `idle_timeout`, so kept-alive connections don't linger forever. Reads also
+/// give up once any deadline set with `set_read_deadline` has passed.
+pub struct Connection {
+    stream: TcpStream,
+    peer: Arc<Peer>,
+    clock: Arc<Clock>,
+    write_timeout: Option<Duration>,
+    /// How long we've waited on the client during the response being sent, if one is.
+    write_waited: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    idle_deadline: Option<Instant>,
+    read_deadline: Option<Instant>,
+}
+
+/// A stream whose reads can be made to give up with `TimedOut` from some moment on, so a client
+/// which trickles in a request (or never sends one) can't hold on to its connection forever.
+pub trait ReadDeadline {
+    /// Give up on reads from `deadline` on, or not at all if it's `None`.
+    fn set_read_deadline(&mut self, deadline: Option<Instant>);
+}
+
+impl Connection {
+    pub fn new(stream: TcpStream,
+               peer: Arc<Peer>,
+               clock: Arc<Clock>,
+               write_timeout: Option<Duration>,
+               idle_timeout: Option<Duration>)
+               -> Self {
+        Connection {
+            stream: stream,
+            peer: peer,
+            clock: clock,
+            write_timeout: write_timeout,
+            write_waited: None,
+            idle_timeout: idle_timeout,
+            idle_deadline: None,
+            read_deadline: None,
+        }
+    }
+}
+
+impl ReadDeadline for Connection {
+    fn set_read_deadline(&mut self, deadline: Option<Instant>) {
+        self.read_deadline = deadline;
+    }
+}
+
+/// Whether a connection's client has gone away, for anything working on one of its requests,
+/// which may ask from another thread, or after the connection's been closed and its descriptor
+/// reused for some other connection's socket. Also how a shutdown ends a connection which has
+/// outstayed the grace period.
+///
+/// The socket is only peeked at (or shut down) while the connection's owner hasn't hung up, which
+/// it does (in dropping the connection) before the socket's closed.
+#[derive(Debug)]
+pub struct Peer {
+    socket: Mutex<Option<RawFd>>,
+    /// Set once the client's been found gone, or the owner's hung up.
+    gone: AtomicBool,
+}
+
+impl Peer {
+    pub fn new(socket: RawFd) -> Self {
+        Peer {
+            socket: Mutex::new(Some(socket)),
+            gone: AtomicBool::new(false),
+        }
+    }
+
+    /// Say the connection's done with, before its socket's closed.
+    pub fn hang_up(&self) {
+        self.gone.store(true, Ordering::SeqCst);
+        *self.socket.lock().unwrap() = None;
+    }
+
+    /// Whether the client has gone away, going by a peek at the socket which neither waits nor
+    /// takes anything the client's sent. A client which has only shut down its sending side
+    /// looks the same as one which has hung up altogether.
+    pub fn is_gone(&self) -> bool {
+        if self.gone.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        // held through the peek, so the owner can't hang up (and close the socket) meanwhile
+        let socket = self.socket.lock().unwrap();
+        let gone = match *socket {
+            Some(fd) => peer_closed(fd),
+            None => true,
+        };
+        if gone {
+            self.gone.store(true, Ordering::SeqCst);
+        }
+        gone
+    }
+
+    /// Shut the socket down both ways, unless the owner's already hung up, so the connection ends
+    /// at its owner's next read or write.
+    pub fn shut_down(&self) {
+        // held through the shutdown, so the owner can't hang up (and close the socket) meanwhile
+        if let Some(fd) = *self.socket.lock().unwrap() {
+            unsafe { libc::shutdown(fd, libc::SHUT_RDWR) };
+        }
+    }
+}
+
+fn peer_closed(socket: RawFd) -> bool {
+    let mut byte = [0u8; 1];
+    let peeked = unsafe {
+        libc::recv(socket,
+                   byte.as_mut_ptr() as *mut libc::c_void,
+                   1,
+                   libc::MSG_PEEK | libc::MSG_DONTWAIT)
+    };
+
+    match peeked {
+        0 => true,
+        n if n > 0 => false,
+        _ => {
+            match io::Error::last_os_error().kind() {
+                io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => false,
+                _ => true,
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.peer.hang_up();
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.write_waited = None;
+
+        // only the wait for the first bytes after a response counts as idling
+        let (deadline, why) = match (self.idle_deadline.take(), self.read_deadline) {
+            (Some(idle), Some(read)) if read < idle => (read, "read deadline exceeded"),
+            (Some(idle), _) => (idle, "idle timeout exceeded"),
+            (None, Some(read)) => (read, "read deadline exceeded"),
+            (None, None) => return self.stream.read(buf),
+        };
+
+        loop {
+            // checked first, so a request which only turns up after the deadline is refused,
+            // whether the timer or the request wakes us
+            if self.clock.instant() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, why));
+            }
+
+            if let Some(n) = try!(self.stream.try_read(buf)) {
+                return Ok(n);
+            }
+
+            let mut timer = Timer::new();
+            timer.set_timeout_absolute(deadline);
+
+            select!(
+                r:self.stream => {},
+                r:timer => {},
+            );
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let now = self.clock.instant();
+        self.idle_deadline = self.idle_timeout.map(|t| now + t);
+
+        let timeout = match self.write_timeout {
+            Some(t) => t,
+            None => return self.stream.write(buf),
+        };
+
+        let mut waited = self.write_waited.unwrap_or(Duration::from_secs(0));
+
+        loop {
+            self.write_waited = Some(waited);
+
+            if let Some(n) = try!(self.stream.try_write(buf)) {
+                return Ok(n);
+            }
+
+            if waited >= timeout {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "write deadline exceeded"));
+            }
+
+            let started = self.clock.instant();
+            let mut timer = Timer::new();
+            timer.set_timeout_absolute(started + (timeout - waited));
+
+            // either the socket becomes writable again and we loop around to try_write, or the
+            // timer fires and the deadline check above fails
+            select!(
+                w:self.stream => {},
+                r:timer => {},
+            );
+
+            waited += self.clock.instant().duration_since(started);
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// A connection to a server we're the client of, such as an upstream behind the proxy, whose
+/// reads and writes give up with `TimedOut` once the server has gone `timeout` without sending or
+/// taking anything, so one which stops answering can't hold on to a coroutine forever.
+pub struct TimedStream<S> {
+    stream: S,
+    timeout: Option<Duration>,
+}
+
+/// A stream which can be tried without blocking, and waited on with `select!`, as mioco's sockets
+/// can.
+pub trait NonBlocking: Evented + Read + Write {
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>>;
+    fn try_write(&mut self, buf: &[u8]) -> io::Result<Option<usize>>;
+}
+
+impl NonBlocking for TcpStream {
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        TcpStream::try_read(self, buf)
+    }
+
+    fn try_write(&mut self, buf: &[u8]) -> io::Result<Option<usize>> {
+        TcpStream::try_write(self, buf)
+    }
+}
+
+impl NonBlocking for UnixStream {
+    fn try_read(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        UnixStream::try_read(self, buf)
+    }
+
+    fn try_write(&mut self, buf: &[u8]) -> io::Result<Option<usize>> {
+        UnixStream::try_write(self, buf)
+    }
+}
+
+impl<S: NonBlocking> TimedStream<S> {
+    pub fn new(stream: S, timeout: Option<Duration>) -> Self {
+        TimedStream {
+            stream: stream,
+            timeout: timeout,
+        }
+    }
+}
+
+impl<S: NonBlocking> Read for TimedStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let deadline = match self.timeout {
+            Some(t) => Instant::now() + t,
+            None => return self.stream.read(buf),
+        };
+
+        loop {
+            if let Some(n) = try!(self.stream.try_read(buf)) {
+                return Ok(n);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "upstream read timed out"));
+            }
+
+            let mut timer = Timer::new();
+            timer.set_timeout_absolute(deadline);
+            select!(
+                r:self.stream => {},
+                r:timer => {},
+            );
+        }
+    }
+}
+
+impl<S: NonBlocking> Write for TimedStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let deadline = match self.timeout {
+            Some(t) => Instant::now() + t,
+            None => return self.stream.write(buf),
+        };
+
+        loop {
+            if let Some(n) = try!(self.stream.try_write(buf)) {
+                return Ok(n);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "upstream write timed out"));
+            }
+
+            let mut timer = Timer::new();
+            timer.set_timeout_absolute(deadline);
+            select!(
+                w:self.stream => {},
+                r:timer => {},
+            );
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Connect to a server, giving up with `TimedOut` if it hasn't accepted within `timeout`. This
+/// has to be called on a mioco coroutine.
+pub fn connect(addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(t) => t,
+        None => return TcpStream::connect(addr),
+    };
+
+    // mioco's own connect waits as long as it takes, so the waiting's done off the coroutine
+    let addr = *addr;
+    let stream = try!(mioco::offload(move || net::TcpStream::connect_timeout(&addr, timeout)));
+    try!(stream.set_nonblocking(true));
+    Ok(unsafe { TcpStream::from_raw_fd(stream.into_raw_fd()) })
+}
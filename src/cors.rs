@@ -0,0 +1,118 @@
+//! Cross-origin resource sharing: telling browsers that pages from other origins (such as a
+//! single-page app on its own dev server) may read what's served, with `Access-Control-*` headers
+//! on responses and answers to the preflight requests browsers send before anything unusual.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Most preflight answers to keep, beyond which they're all dropped and the cache starts over.
+const MAX_PREFLIGHTS: usize = 256;
+
+/// What a preflight's answer is worked out from: the origin it's from, the method and headers it
+/// asks about, and the Max-Age for its path.
+pub type PreflightKey = (String, String, Option<String>, Option<u64>);
+
+/// The headers answering preflight requests, kept by what they were worked out from, so that the
+/// same preflight repeated (as single-page apps do, for every cross-origin request) costs little.
+#[derive(Debug, Default)]
+pub struct Preflights {
+    answers: Mutex<HashMap<PreflightKey, Arc<Vec<(&'static str, String)>>>>,
+}
+
+impl Preflights {
+    pub fn new() -> Self {
+        Preflights::default()
+    }
+
+    /// The headers answering a preflight, as kept from the last time it was made, or as worked out
+    /// by `compute` if it hasn't been.
+    pub fn answer<F>(&self, key: PreflightKey, compute: F) -> Arc<Vec<(&'static str, String)>>
+        where F: FnOnce() -> Vec<(&'static str, String)>
+    {
+        let mut answers = self.answers.lock().unwrap();
+        if let Some(answer) = answers.get(&key) {
+            return answer.clone();
+        }
+
+        if answers.len() >= MAX_PREFLIGHTS {
+            answers.clear();
+        }
+        let answer = Arc::new(compute());
+        answers.insert(key, answer.clone());
+        answer
+    }
+}
+
+/// The `Access-Control-Allow-Origin` value for a request from `origin`, if it's one of the
+/// allowed `origins`: `*` if they include it, or else the origin itself.
+pub fn allow_origin(origins: &[String], origin: &str) -> Option<String> {
+    if origins.iter().any(|o| o == "*") {
+        return Some("*".to_owned());
+    }
+
+    if origins.iter().any(|o| normalize(o) == normalize(origin)) {
+        Some(origin.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Whether responses depend on the request's `Origin`, as they do when only some origins are
+/// allowed, so that caches must keep a copy for each.
+pub fn varies(origins: &[String]) -> bool {
+    !origins.is_empty() && !origins.iter().any(|o| o == "*")
+}
+
+/// Origins are compared without case (their schemes and hosts have none) or a trailing slash.
+fn normalize(origin: &str) -> String {
+    origin.trim_end_matches('/').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn preflights() {
+        let preflights = Preflights::new();
+        let key = |origin: &str| {
+            (origin.to_owned(), "GET".to_owned(), Some("x-requested-with".to_owned()), Some(600))
+        };
+        let answer = || vec![("Access-Control-Max-Age", "600".to_owned())];
+
+        let first = preflights.answer(key("https://app.example"), answer);
+        let again = preflights.answer(key("https://app.example"), || panic!("not kept"));
+        assert_eq!(first, again);
+
+        // nothing's kept forever
+        for i in 0..MAX_PREFLIGHTS {
+            preflights.answer(key(&format!("https://{}.example", i)), answer);
+        }
+        let mut computed = false;
+        preflights.answer(key("https://app.example"), || {
+            computed = true;
+            answer()
+        });
+        assert!(computed);
+    }
+
+    #[test]
+    fn origins() {
+        let some = vec!["https://app.example".to_owned(), "http://localhost:3000/".to_owned()];
+
+        assert_eq!(allow_origin(&some, "https://app.example"),
+                   Some("https://app.example".to_owned()));
+        assert_eq!(allow_origin(&some, "HTTP://LOCALHOST:3000"),
+                   Some("HTTP://LOCALHOST:3000".to_owned()));
+        assert_eq!(allow_origin(&some, "http://localhost:3001"), None);
+        assert_eq!(allow_origin(&some, "null"), None);
+        assert!(varies(&some));
+
+        let any = vec!["*".to_owned()];
+        assert_eq!(allow_origin(&any, "https://elsewhere.example"), Some("*".to_owned()));
+        assert!(!varies(&any));
+
+        assert_eq!(allow_origin(&[], "https://app.example"), None);
+        assert!(!varies(&[]));
+    }
+}
@@ -0,0 +1,199 @@
+use std::backtrace::Backtrace;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::Write;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+
+use chrono::Local;
+
+use config::Config;
+use error_log;
+
+/// What we know about a panic, gathered as it happens since the backtrace is gone by the time
+/// the supervisor finds out.
+#[derive(Debug)]
+struct Panic {
+    thread: String,
+    message: String,
+    location: String,
+    backtrace: String,
+}
+
+/// The most recent panic anywhere in the process.
+static LAST_PANIC: Mutex<Option<Panic>> = Mutex::new(None);
+
+/// Remember every panic for a crash report, on top of the usual message on stderr.
+pub fn install_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let message = match info.payload().downcast_ref::<&str>() {
+            Some(s) => s.to_string(),
+            None => {
+                match info.payload().downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None => "(no message)".to_owned(),
+                }
+            }
+        };
+
+        let panic = Panic {
+            thread: thread::current().name().unwrap_or("<unnamed>").to_owned(),
+            message: message,
+            location: info.location().map_or("unknown".to_owned(), |l| l.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+        };
+
+        // a panic while holding the lock would poison it, but the report's still worth having
+        match LAST_PANIC.lock() {
+            Ok(mut last) => *last = Some(panic),
+            Err(poisoned) => *poisoned.into_inner() = Some(panic),
+        }
+
+        default_hook(info);
+    }));
+}
+
+/// Write a report on the server having crashed into `dir`, returning the path to it.
+pub fn write_report(dir: &Path, config: &Config) -> io::Result<PathBuf> {
+    let now = Local::now();
+    let path = dir.join(format!("hppt-crash-{}.txt", now.format("%Y%m%d-%H%M%S")));
+
+    let report = {
+        let last = match LAST_PANIC.lock() {
+            Ok(last) => last,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        render(&now.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+               config,
+               last.as_ref(),
+               &error_log::recent())
+    };
+
+    let mut file = try!(File::create(&path));
+    try!(file.write_all(report.as_bytes()));
+
+    Ok(path)
+}
+
+/// A hash of every setting, so reports from servers configured alike can be told apart from
+/// others without including the settings (or the paths in them) outright.
+fn config_hash(config: &Config) -> String {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", config).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn render(time: &str,
+          config: &Config,
+          panic: Option<&Panic>,
+          errors: &[error_log::Entry])
+          -> String {
+    let mut report = String::new();
+
+    // writing to a String can't fail
+    let _ = writeln!(report,
+                     "{} {} crashed at {}",
+                     env!("CARGO_PKG_NAME"),
+                     env!("CARGO_PKG_VERSION"),
+                     time);
+    let _ = writeln!(report, "config hash: {}", config_hash(config));
+
+    match panic {
+        Some(p) => {
+            let _ = writeln!(report,
+                             "panic in thread '{}' at {}: {}",
+                             p.thread,
+                             p.location,
+                             p.message);
+        }
+        None => {
+            let _ = writeln!(report, "panic: unknown");
+        }
+    }
+
+    let _ = writeln!(report, "\nrecent errors:");
+    if errors.is_empty() {
+        let _ = writeln!(report, "(none)");
+    }
+    for e in errors {
+        let _ = writeln!(report, "[{} {} {}] {}", e.level, e.time, e.module, e.message);
+    }
+
+    if let Some(p) = panic {
+        let _ = write!(report, "\nbacktrace:\n{}", p.backtrace);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use log::LogLevel;
+
+    use config::Config;
+    use error_log;
+
+    use super::*;
+
+    #[test]
+    fn report_contents() {
+        let config = Config::new(PathBuf::from("."));
+        let panic = Panic {
+            thread: "main".to_owned(),
+            message: "oh no".to_owned(),
+            location: "src/server.rs:1:1".to_owned(),
+            backtrace: "0: main\n".to_owned(),
+        };
+        let errors = vec![error_log::Entry {
+                              level: LogLevel::Warn,
+                              time: "2016-09-01 12:00:00.000".to_owned(),
+                              module: "hppt::server".to_owned(),
+                              message: "something's up".to_owned(),
+                          }];
+
+        let report = render("2016-09-01 12:00:01.000", &config, Some(&panic), &errors);
+        let expected = format!("hppt {} crashed at 2016-09-01 12:00:01.000
+config hash: {}
+panic in thread 'main' at src/server.rs:1:1: oh no
+
+recent errors:
+[WARN 2016-09-01 12:00:00.000 hppt::server] something's up
+
+backtrace:
+0: main
+",
+                               env!("CARGO_PKG_VERSION"),
+                               config_hash(&config));
+        assert_eq!(report, expected);
+
+        let mut other = Config::new(PathBuf::from("."));
+        other.autoindex = true;
+        assert!(config_hash(&config) != config_hash(&other));
+    }
+
+    #[test]
+    fn report_file() {
+        let path = write_report(&env::temp_dir(), &Config::new(PathBuf::from("."))).unwrap();
+
+        let mut report = String::new();
+        File::open(&path).unwrap().read_to_string(&mut report).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(report.starts_with("hppt "));
+        assert!(report.contains("\nrecent errors:\n"));
+    }
+}
@@ -0,0 +1,49 @@
+/// Whether an Accept-Encoding header (RFC 7231 section 5.3.4) allows a content-coding: named
+/// with a non-zero quality, or not named but covered by a non-zero `*`.
+pub fn accepts(accept_encoding: &str, coding: &str) -> bool {
+    let mut wildcard = None;
+
+    for entry in accept_encoding.split(',') {
+        let mut params = entry.split(';');
+
+        let name = match params.next() {
+            Some(n) => n.trim(),
+            None => continue,
+        };
+
+        let mut quality = 1.0;
+        for param in params {
+            let param = param.trim();
+            if param.starts_with("q=") {
+                quality = param[2..].parse::<f32>().unwrap_or(0.0);
+            }
+        }
+
+        // x-gzip is gzip's old name, still sent by some clients
+        let name = if name.eq_ignore_ascii_case("x-gzip") { "gzip" } else { name };
+
+        if name.eq_ignore_ascii_case(coding) {
+            return quality > 0.0;
+        } else if name == "*" {
+            wildcard = Some(quality > 0.0);
+        }
+    }
+
+    wildcard.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::accepts;
+
+    #[test]
+    fn negotiation() {
+        assert!(accepts("gzip, deflate", "gzip"));
+        assert!(accepts("deflate, GZIP;q=0.5", "gzip"));
+        assert!(accepts("x-gzip", "gzip"));
+        assert!(accepts("br, *;q=0.1", "gzip"));
+        assert!(!accepts("gzip;q=0, *", "gzip"));
+        assert!(!accepts("deflate, br", "gzip"));
+        assert!(!accepts("", "gzip"));
+    }
+}
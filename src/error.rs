@@ -7,9 +7,16 @@ pub type HpptResult<T> = Result<T, HpptError>;
 #[derive(Debug)]
 pub enum HpptError {
     RequestTooLarge,
+    TooManyHeaders,
     Parsing,
+    /// A syntactically valid method which we've never heard of.
+    UnknownMethod,
     UnsupportedHttpVersion,
+    /// A request body in a transfer coding we can't decode: anything besides `chunked` alone.
+    UnsupportedTransferCoding,
     IoError(io::Error),
+    /// Sending a response failed after this many body bytes had been delivered.
+    IncompleteWrite(usize, io::Error),
 }
 
 impl From<io::Error> for HpptError {
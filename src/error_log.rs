@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use log::{LogLevel, LogRecord};
+
+use json;
+
+/// How many of the most recent warnings and errors to remember.
+const CAPACITY: usize = 100;
+
+/// Where the admin endpoint serves them, relative to the root.
+pub const ADMIN_URI: &'static str = "__admin/errors";
+
+/// A warning or error, as logged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub level: LogLevel,
+    /// Local time, formatted the same way as in the log itself.
+    pub time: String,
+    pub module: String,
+    pub message: String,
+}
+
+/// Shared by everything in the process, like the logger which feeds it.
+static RECENT: Mutex<VecDeque<Entry>> = Mutex::new(VecDeque::new());
+
+/// Remember a log record if it's a warning or worse, forgetting the oldest beyond `CAPACITY`.
+pub fn record(record: &LogRecord, time: &str) {
+    if record.level() > LogLevel::Warn {
+        return;
+    }
+
+    push(Entry {
+        level: record.level(),
+        time: time.to_owned(),
+        module: record.location().module_path().to_owned(),
+        message: record.args().to_string(),
+    });
+}
+
+fn push(entry: Entry) {
+    let mut recent = RECENT.lock().unwrap();
+
+    if recent.len() == CAPACITY {
+        recent.pop_front();
+    }
+
+    recent.push_back(entry);
+}
+
+/// The warnings and errors remembered so far, oldest first.
+pub fn recent() -> Vec<Entry> {
+    RECENT.lock().unwrap().iter().cloned().collect()
+}
+
+/// The given entries as a JSON object, e.g.
+/// `{"errors":[{"level":"WARN","time":"...","module":"hppt::server","message":"..."}]}`.
+pub fn to_json(entries: &[Entry]) -> String {
+    let objects = entries.iter()
+        .map(|e| {
+            format!("{{\"level\":\"{}\",\"time\":{},\"module\":{},\"message\":{}}}",
+                    e.level,
+                    json::string(&e.time),
+                    json::string(&e.module),
+                    json::string(&e.message))
+        })
+        .collect::<Vec<_>>();
+
+    format!("{{\"errors\":[{}]}}", objects.join(","))
+}
+
+#[cfg(test)]
+mod test {
+    use log::LogLevel;
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_latest() {
+        // the tests' own servers log here too, so only ours are counted
+        for i in 0..(CAPACITY + 5) {
+            push(Entry {
+                level: LogLevel::Error,
+                time: String::new(),
+                module: "error_log::test".to_owned(),
+                message: i.to_string(),
+            });
+        }
+
+        let recent = recent();
+        assert_eq!(recent.len(), CAPACITY);
+        assert!(recent.iter().any(|e| e.message == (CAPACITY + 4).to_string()));
+        assert!(!recent.iter().any(|e| e.module == "error_log::test" && e.message == "0"));
+    }
+
+    #[test]
+    fn json() {
+        let entries = vec![Entry {
+                               level: LogLevel::Warn,
+                               time: "2016-09-01 12:00:00.000".to_owned(),
+                               module: "hppt::server".to_owned(),
+                               message: "bad \"thing\"\n\u{1}\\".to_owned(),
+                           }];
+
+        assert_eq!(to_json(&entries),
+                   "{\"errors\":[{\"level\":\"WARN\",\"time\":\"2016-09-01 12:00:00.000\",\
+                    \"module\":\"hppt::server\",\
+                    \"message\":\"bad \\\"thing\\\"\\n\\u0001\\\\\"}]}");
+        assert_eq!(to_json(&[]), "{\"errors\":[]}");
+    }
+}
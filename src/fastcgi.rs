@@ -0,0 +1,337 @@
+//! A FastCGI client, so requests can be answered by an application server (php-fpm, say) which
+//! keeps its workers running between requests, rather than by running a CGI script for each one.
+//! Only the Responder role of the FastCGI 1.0 spec is used: the server hands over the same
+//! environment and body a CGI script would get, and gets back what a CGI script would print.
+//!
+//! Each request gets a connection of its own, which the application closes once it's answered.
+
+use std::cmp;
+use std::fmt;
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use mioco::tcp::TcpStream;
+use mioco::unix::UnixStream;
+
+use connection::{self, TimedStream};
+
+const VERSION: u8 = 1;
+
+// record types (section 8)
+const BEGIN_REQUEST: u8 = 1;
+const END_REQUEST: u8 = 3;
+const PARAMS: u8 = 4;
+const STDIN: u8 = 5;
+const STDOUT: u8 = 6;
+const STDERR: u8 = 7;
+
+const RESPONDER: u16 = 1;
+
+/// The only request on each connection.
+const REQUEST_ID: u16 = 1;
+
+/// Most content a record can carry.
+const MAX_CONTENT_LEN: usize = 0xffff;
+
+/// Where an application server listens: `HOST:PORT`, e.g. `127.0.0.1:9000`, or `unix:PATH`, e.g.
+/// `unix:/run/php-fpm.sock`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Address {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        if s.starts_with("unix:") && s.len() > "unix:".len() {
+            return Ok(Address::Unix(PathBuf::from(&s["unix:".len()..])));
+        }
+
+        match s.rfind(':').map(|i| s[i + 1..].parse::<u16>()) {
+            Some(Ok(_)) if !s.starts_with(':') => Ok(Address::Tcp(s.to_owned())),
+            _ => Err(format!("{} is neither HOST:PORT nor unix:PATH", s)),
+        }
+    }
+}
+
+impl Address {
+    /// Where a `HOST:PORT` address is, looked up once so requests needn't each wait on DNS, or
+    /// `None` for a socket path.
+    pub fn resolve(&self) -> io::Result<Option<SocketAddr>> {
+        match *self {
+            Address::Tcp(ref addr) => {
+                match try!(addr.to_socket_addrs()).next() {
+                    Some(a) => Ok(Some(a)),
+                    None => {
+                        let why = format!("no address for FastCGI application {}", addr);
+                        Err(io::Error::new(ErrorKind::NotFound, why))
+                    }
+                }
+            }
+            Address::Unix(_) => Ok(None),
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Tcp(ref addr) => f.write_str(addr),
+            Address::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A connection to an application server.
+enum Stream {
+    Tcp(TimedStream<TcpStream>),
+    Unix(TimedStream<UnixStream>),
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.read(buf),
+            Stream::Unix(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.write(buf),
+            Stream::Unix(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Stream::Tcp(ref mut s) => s.flush(),
+            Stream::Unix(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Send a request, with its CGI environment and body, to the application server at an address
+/// (which, if it's `HOST:PORT`, has to have been resolved to `resolved`), and start reading its
+/// answer. Connecting, and each read or write after, fails with `TimedOut` if the application
+/// takes longer than `timeout`. This has to be called on a mioco coroutine.
+pub fn request(address: &Address,
+               resolved: Option<SocketAddr>,
+               params: &[(String, String)],
+               body: &[u8],
+               timeout: Option<Duration>)
+               -> io::Result<Output<Box<Read>>> {
+    let mut stream = match (address, resolved) {
+        (&Address::Tcp(_), Some(addr)) => {
+            Stream::Tcp(TimedStream::new(try!(connection::connect(&addr, timeout)), timeout))
+        }
+        (&Address::Tcp(_), None) => {
+            return Err(io::Error::new(ErrorKind::NotFound, "server address not resolved"));
+        }
+        (&Address::Unix(ref path), _) => {
+            Stream::Unix(TimedStream::new(try!(UnixStream::connect(path)), timeout))
+        }
+    };
+
+    try!(stream.write_all(&encode_request(params, body)));
+    try!(stream.flush());
+
+    Ok(Output::new(Box::new(stream) as Box<Read>, address.to_string()))
+}
+
+/// Everything a request is sent as: its beginning, its parameters and its body, each stream
+/// ended with an empty record.
+fn encode_request(params: &[(String, String)], body: &[u8]) -> Vec<u8> {
+    let mut request = Vec::new();
+
+    // the role, and no flags, so the application closes the connection once it's answered
+    let role = [(RESPONDER >> 8) as u8, RESPONDER as u8, 0, 0, 0, 0, 0, 0];
+    write_record(&mut request, BEGIN_REQUEST, &role);
+
+    let mut pairs = Vec::new();
+    for &(ref name, ref value) in params {
+        write_length(&mut pairs, name.len());
+        write_length(&mut pairs, value.len());
+        pairs.extend_from_slice(name.as_bytes());
+        pairs.extend_from_slice(value.as_bytes());
+    }
+    write_stream(&mut request, PARAMS, &pairs);
+    write_stream(&mut request, STDIN, body);
+
+    request
+}
+
+/// A stream's content in as many records as it takes, then the empty record which ends it.
+fn write_stream(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    for chunk in content.chunks(MAX_CONTENT_LEN) {
+        write_record(out, kind, chunk);
+    }
+    write_record(out, kind, b"");
+}
+
+/// A record, padded to a multiple of 8 bytes as the spec recommends.
+fn write_record(out: &mut Vec<u8>, kind: u8, content: &[u8]) {
+    let padding = (8 - content.len() % 8) % 8;
+
+    out.extend_from_slice(&[VERSION,
+                            kind,
+                            (REQUEST_ID >> 8) as u8,
+                            REQUEST_ID as u8,
+                            (content.len() >> 8) as u8,
+                            content.len() as u8,
+                            padding as u8,
+                            0]);
+    out.extend_from_slice(content);
+    out.extend_from_slice(&[0; 8][..padding]);
+}
+
+/// A name-value pair's length: in a byte if it fits in 7 bits, otherwise in four with the top
+/// bit set.
+fn write_length(out: &mut Vec<u8>, len: usize) {
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        out.extend_from_slice(&[(len >> 24) as u8 | 0x80, (len >> 16) as u8, (len >> 8) as u8,
+                                len as u8]);
+    }
+}
+
+/// What an application prints in answer to a request, as it arrives: the same as a CGI script's
+/// output. Whatever it prints to its stderr stream is logged, and the stream ends once the
+/// application says the request is over.
+pub struct Output<S> {
+    stream: S,
+    /// Where the output's coming from, for logging.
+    source: String,
+    /// Output which has been received but not yet read.
+    pending: Vec<u8>,
+    ended: bool,
+}
+
+impl<S: Read> Output<S> {
+    fn new(stream: S, source: String) -> Self {
+        Output {
+            stream: stream,
+            source: source,
+            pending: Vec::new(),
+            ended: false,
+        }
+    }
+
+    /// The next record's type and content.
+    fn read_record(&mut self) -> io::Result<(u8, Vec<u8>)> {
+        let mut header = [0; 8];
+        try!(self.stream.read_exact(&mut header));
+
+        if header[0] != VERSION {
+            return Err(io::Error::new(ErrorKind::InvalidData, "unknown FastCGI version"));
+        }
+
+        let content_len = (header[4] as usize) << 8 | header[5] as usize;
+        let mut content = vec![0; content_len + header[6] as usize];
+        try!(self.stream.read_exact(&mut content));
+        content.truncate(content_len);
+
+        Ok((header[1], content))
+    }
+}
+
+impl<S: Read> Read for Output<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() && !self.ended {
+            let (kind, content) = try!(self.read_record());
+
+            match kind {
+                STDOUT => self.pending = content,
+                STDERR => {
+                    for line in String::from_utf8_lossy(&content).lines() {
+                        warn!("FastCGI application at {}: {}", self.source, line);
+                    }
+                }
+                END_REQUEST => self.ended = true,
+                // nothing else is meant for a request's client
+                _ => (),
+            }
+        }
+
+        let n = cmp::min(buf.len(), self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn addresses() {
+        assert_eq!("127.0.0.1:9000".parse::<Address>(),
+                   Ok(Address::Tcp("127.0.0.1:9000".to_owned())));
+        assert_eq!("unix:/run/php-fpm.sock".parse::<Address>(),
+                   Ok(Address::Unix(PathBuf::from("/run/php-fpm.sock"))));
+        assert_eq!("unix:/run/php-fpm.sock".parse::<Address>().unwrap().to_string(),
+                   "unix:/run/php-fpm.sock");
+
+        for bad in &["localhost", ":9000", "localhost:fpm", "unix:"] {
+            assert!(bad.parse::<Address>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn request_records() {
+        let params = vec![("SCRIPT_NAME".to_owned(), "/index.php".to_owned()),
+                          ("HTTP_X_LONG".to_owned(), "x".repeat(200))];
+        let request = encode_request(&params, b"body");
+
+        // a beginning asking for a responder, which is all of 8 bytes
+        assert_eq!(&request[..16], &[1, 1, 0, 1, 0, 8, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+        // the parameters, with the long value's length in four bytes
+        let params_len = 1 + 1 + 11 + 10 + 1 + 4 + 11 + 200;
+        assert_eq!(&request[16..24], &[1, 4, 0, 1, 0, params_len as u8, 1, 0]);
+        assert_eq!(&request[24..37], b"\x0b\x0aSCRIPT_NAME");
+        assert_eq!(&request[47..52], &[11, 0x80, 0, 0, 200]);
+        let rest = &request[24 + params_len + 1..];
+        assert_eq!(&rest[..8], &[1, 4, 0, 1, 0, 0, 0, 0]);
+
+        // and the body, padded, then the end of it
+        assert_eq!(&rest[8..24], b"\x01\x05\x00\x01\x00\x04\x04\x00body\x00\x00\x00\x00");
+        assert_eq!(&rest[24..], &[1, 5, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn output() {
+        let mut records = Vec::new();
+        write_record(&mut records, STDOUT, b"Content-Type: text/plain\r\n\r\n");
+        write_record(&mut records, STDERR, b"a warning\n");
+        write_record(&mut records, STDOUT, b"hello");
+        write_record(&mut records, STDOUT, b"");
+        write_record(&mut records, END_REQUEST, &[0; 8]);
+        write_record(&mut records, STDOUT, b"not for anyone");
+
+        let mut output = Output::new(Cursor::new(records), "test".to_owned());
+        let mut received = String::new();
+        output.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "Content-Type: text/plain\r\n\r\nhello");
+
+        // an application which hangs up without ending the request
+        let mut records = Vec::new();
+        write_record(&mut records, STDOUT, b"Content-Type: text/plain\r\n\r\n");
+        let mut output = Output::new(Cursor::new(records), "test".to_owned());
+        assert!(output.read_to_string(&mut String::new()).is_err());
+    }
+}
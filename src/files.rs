@@ -1,13 +1,34 @@
-use std::fs::File;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use libc;
+
+use language::is_language_tag;
+use request::Uri;
+use source::{Content, ContentSource, Metadata};
 
 /// Hide all I/O errors behind an Option. This will mean that any I/O issue will just cause a 404.
 /// Could be handled better, but ideally we don't want to expose permissions issues as a 500.
 ///
 /// Also: checks to make sure canonical path matches requested path. This prevents escaping the
-/// content directory under most circumstances, but also means symlinks won't work anymore.
-pub fn find_file_relative(root_dir: &Path, uri: &Path) -> Option<(File, PathBuf)> {
-    let full_path = root_dir.join(uri);
+/// content directory under most circumstances, but also means symlinks won't work, unless
+/// `follow_symlinks`, in which case the canonical path only has to be inside the root's.
+///
+/// Only regular files are found: a FIFO would hold up whoever opened (or read) it until something
+/// else wrote to it, and a device or socket could be anything.
+pub fn find_file_relative(root_dir: &Path,
+                          uri: &Path,
+                          follow_symlinks: bool)
+                          -> Option<(File, PathBuf)> {
+    let full_path = match full_path(root_dir, uri) {
+        Some(p) => p,
+        None => {
+            debug!("Refusing to look up {:?}, which might not be under the root", uri);
+            return None;
+        }
+    };
 
     debug!("{:?} requested, seeing if it exists in root directory ({:?})...",
            &full_path,
@@ -21,42 +42,208 @@ pub fn find_file_relative(root_dir: &Path, uri: &Path) -> Option<(File, PathBuf)
         }
     };
 
-    if canonical != full_path {
+    if !is_contained(root_dir, &full_path, &canonical, follow_symlinks) {
         debug!("Mismatched canonical ({:?}) and provided ({:?})",
                canonical,
                full_path);
         return None;
     }
 
-    // NOTE: this is subject to race conditions, unfortunately.
-    // would need to handle this logic purely through the io::Error type to avoid (TODO?)
-    if full_path.exists() {
-        if full_path.is_file() {
-            debug!("{:?} found, returning.", &full_path);
+    // without blocking, in case it's a FIFO, and checked once it's open, so nothing can be swapped
+    // in after the check (or deleted before the open, which is then just a file that isn't there).
+    // The canonical path is opened so a symlink changed since can't lead anywhere else.
+    let file = match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(&canonical) {
+        Ok(f) => f,
+        Err(why) => {
+            debug!("{:?} couldn't be opened: {:?}", &full_path, why);
+            return None;
+        }
+    };
 
-            // if there's an issue opening the file, just say None
-            File::open(full_path).ok().map(|f| (f, canonical))
-        } else {
-            debug!("{:?} found, but is not a file.", &full_path);
+    match file.metadata() {
+        Ok(ref m) if m.is_file() => {
+            debug!("{:?} found, returning.", &full_path);
+            Some((file, canonical))
+        }
+        _ => {
+            debug!("{:?} found, but is not a regular file.", &full_path);
             None
         }
-    } else {
-        debug!("{:?} not found", &full_path);
-        None
     }
 }
 
+/// Whether the canonical path of a full path under a root directory may be served from it: if
+/// it's the same path, or if symlinks are followed, if it's anywhere inside the root's own
+/// canonical path.
+pub fn is_contained(root_dir: &Path,
+                    full_path: &Path,
+                    canonical: &Path,
+                    follow_symlinks: bool)
+                    -> bool {
+    canonical == full_path ||
+    follow_symlinks && root_dir.canonicalize().map_or(false, |root| canonical.starts_with(root))
+}
+
+/// The full path of a relative one under a root directory, if it can only be under it (see
+/// `Uri::under`).
+pub fn full_path(root_dir: &Path, uri: &Path) -> Option<PathBuf> {
+    uri.to_str().and_then(|u| Uri::new(u).under(root_dir))
+}
+
+/// Find the index document for a URI naming a directory: the first of `index_files` which exists
+/// in it, along with its (root-relative) path so it can be served as if requested directly.
+pub fn find_index(source: &ContentSource,
+                  uri: &Uri,
+                  index_files: &[String])
+                  -> Option<(Content, Uri<'static>)> {
+    if !source.metadata(uri.as_path()).map_or(false, |m| m.is_dir) {
+        return None;
+    }
+
+    for name in index_files {
+        let index_path = uri.join(name);
+
+        if let Some(content) = source.open(index_path.as_path()) {
+            debug!("Serving {:?} for directory {:?}", index_path, uri);
+            return Some((content, index_path));
+        }
+    }
+
+    None
+}
+
+/// Content-codings a file may be found precompressed in, by the extension of the sibling it's
+/// in, most preferred (i.e. smallest, usually) first.
+const PRECOMPRESSED: [(&'static str, &'static str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// Find a precompressed sibling of the file at a (root-relative) path, e.g. `page.html.gz` for
+/// `page.html`, in a content-coding the client accepts: the sibling, its path and its coding.
+pub fn find_precompressed<F>(source: &ContentSource,
+                             uri: &Uri,
+                             accepts: F)
+                             -> Option<(Content, Uri<'static>, &'static str)>
+    where F: Fn(&str) -> bool
+{
+    for &(coding, extension) in &PRECOMPRESSED {
+        if !accepts(coding) {
+            continue;
+        }
+
+        let sibling_path = uri.with_suffix(&format!(".{}", extension));
+
+        if let Some(sibling) = source.open(sibling_path.as_path()) {
+            debug!("Serving {:?} for {:?}", sibling_path, uri);
+            return Some((sibling, sibling_path, coding));
+        }
+    }
+
+    None
+}
+
+/// A CGI script named by a prefix of a request path, with whatever follows it.
+#[derive(Debug)]
+pub struct Script {
+    pub full_path: PathBuf,
+    /// The path to the script itself, relative to the directory it was found in, e.g.
+    /// `cgi-bin/app.py`.
+    pub name: String,
+    /// The rest of the request path after the script's name, e.g. `/users/42`, or empty.
+    pub path_info: String,
+}
+
+/// Find the script a (root-relative) path runs: the shortest prefix of it, ending at a segment
+/// boundary, which names a file.
+pub fn find_script(root_dir: &Path, uri: &Uri) -> Option<Script> {
+    for (name, path_info) in uri.splits() {
+        if let Some((_, full_path)) = find_file_relative(root_dir, Path::new(name), false) {
+            return Some(Script {
+                full_path: full_path,
+                name: name.to_owned(),
+                path_info: path_info.to_owned(),
+            });
+        }
+    }
+
+    None
+}
+
+/// What a client needs to tell whether its cached copy of a file is still current.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Validators {
+    /// A strong entity-tag, quotes included, built from the file's size and modification time.
+    pub etag: String,
+    /// Modification time in whole seconds since the Unix epoch.
+    pub last_modified: i64,
+}
+
+impl Validators {
+    /// Validators for anything a content source can give the metadata of, if that includes a
+    /// usable modification time.
+    pub fn from_metadata(metadata: &Metadata) -> Option<Validators> {
+        let since_epoch = match metadata.modified.map(|m| m.duration_since(UNIX_EPOCH)) {
+            Some(Ok(d)) => d,
+            _ => return None,
+        };
+
+        Some(Validators {
+            etag: format!("\"{:x}-{:x}-{:x}\"",
+                          metadata.len,
+                          since_epoch.as_secs(),
+                          since_epoch.subsec_nanos()),
+            last_modified: since_epoch.as_secs() as i64,
+        })
+    }
+}
+
+/// List the language tags of the variants available for a URI, i.e. for `docs/page.html` the `en`
+/// and `de` of `docs/page.html.en` and `docs/page.html.de`, sorted so the result is stable.
+///
+/// Each variant still has to be opened through the source, so this doesn't weaken the content
+/// directory checks.
+pub fn find_language_variants(source: &ContentSource, uri: &Uri) -> Vec<String> {
+    let (parent, file_name) = match (uri.parent(), uri.file_name()) {
+        (Some(p), Some(f)) => (p, f),
+        _ => return Vec::new(),
+    };
+
+    let entries = match source.list(parent.as_path()) {
+        Some(e) => e,
+        None => {
+            debug!("Unable to list {:?} for language variants", parent);
+            return Vec::new();
+        }
+    };
+
+    let prefix = format!("{}.", file_name);
+
+    let mut tags = entries.into_iter()
+        .map(|e| e.name)
+        .filter(|name| name.starts_with(&prefix))
+        .map(|name| name[prefix.len()..].to_owned())
+        .filter(|tag| is_language_tag(tag))
+        .collect::<Vec<_>>();
+
+    tags.sort();
+
+    tags
+}
+
 #[cfg(test)]
 mod test {
-    use super::find_file_relative;
+    use super::{Validators, find_file_relative, find_index, find_language_variants,
+                find_precompressed, find_script};
+
+    use std::path::{Path, PathBuf};
 
-    use std::path::PathBuf;
+    use request::Uri;
+    use source::{ContentSource, LocalFs};
 
     #[test]
     fn successful_find_file() {
 
         find_file_relative(&PathBuf::from(env!("CARGO_MANIFEST_DIR")),
-                           &PathBuf::from("Cargo.toml"))
+                           &PathBuf::from("Cargo.toml"),
+                           false)
             .unwrap();
     }
 
@@ -64,16 +251,91 @@ mod test {
     fn fail_find_file() {
 
         let f = find_file_relative(&PathBuf::from(env!("CARGO_MANIFEST_DIR")),
-                                   &PathBuf::from("DOES_NOT_EXIST"));
+                                   &PathBuf::from("DOES_NOT_EXIST"),
+                                   false);
 
         assert!(f.is_none());
     }
 
+    #[test]
+    fn script_with_path_info() {
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+
+        let script = find_script(&root, &Uri::new("cgi-bin/hello_world.py/a/b")).unwrap();
+        assert_eq!(script.full_path, root.join("cgi-bin/hello_world.py"));
+        assert_eq!(script.name, "cgi-bin/hello_world.py");
+        assert_eq!(script.path_info, "/a/b");
+
+        assert_eq!(find_script(&root, &Uri::new("cgi-bin/hello_world.py")).unwrap().path_info, "");
+        assert!(find_script(&root, &Uri::new("cgi-bin")).is_none());
+        assert!(find_script(&root, &Uri::new("cgi-bin/nonexistent.py/a")).is_none());
+    }
+
     #[test]
     fn fail_escape_content_dir() {
         let f = find_file_relative(&PathBuf::from(env!("CARGO_MANIFEST_DIR")),
-                                   &PathBuf::from("../../../../../../../../../etc/passwd"));
+                                   &PathBuf::from("../../../../../../../../../etc/passwd"),
+                                   true);
 
         assert!(f.is_none());
     }
+
+    #[test]
+    fn fail_absolute_path() {
+        let f = find_file_relative(&PathBuf::from(env!("CARGO_MANIFEST_DIR")),
+                                   &PathBuf::from("/etc/hostname"),
+                                   true);
+
+        assert!(f.is_none());
+    }
+
+    #[test]
+    fn language_variants() {
+        let root = LocalFs::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let tags = find_language_variants(&root, &Uri::new("test/lang/page.html"));
+
+        assert_eq!(tags, vec!["de".to_owned(), "en".to_owned()]);
+    }
+
+    #[test]
+    fn index_files() {
+        let root = LocalFs::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let index_files = vec!["index.htm".to_owned(), "index.html".to_owned()];
+
+        let (_, path) = find_index(&root, &Uri::new("test/site/"), &index_files).unwrap();
+        assert_eq!(path, "test/site/index.html");
+
+        assert!(find_index(&root, &Uri::new("test"), &index_files).is_none());
+        assert!(find_index(&root, &Uri::new("test/foo.html"), &index_files).is_none());
+    }
+
+    #[test]
+    fn precompressed_siblings() {
+        let root = LocalFs::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let uri = Uri::new("test/precompressed/page.html");
+
+        // there's no .br sibling, so gzip it is
+        let (sibling, path, coding) = find_precompressed(&root, &uri, |_| true).unwrap();
+        assert_eq!(path, "test/precompressed/page.html.gz");
+        assert_eq!(coding, "gzip");
+        assert_eq!(sibling.metadata, root.metadata(path.as_path()).unwrap());
+
+        assert!(find_precompressed(&root, &uri, |c| c == "br").is_none());
+        assert!(find_precompressed(&root, &Uri::new("test/foo.html"), |_| true).is_none());
+    }
+
+    #[test]
+    fn validators() {
+        let root = LocalFs::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let of = |path| {
+            Validators::from_metadata(&root.metadata(Path::new(path)).unwrap()).unwrap()
+        };
+
+        let foo = of("test/foo.html");
+        let bin = of("test/1k.bin");
+
+        assert!(foo.etag.starts_with("\"1c-") && foo.etag.ends_with('"'));
+        assert!(foo.etag != bin.etag);
+        assert!(foo.last_modified > 0);
+    }
 }
@@ -0,0 +1,254 @@
+//! Gzip (RFC 1952) compression, for text responses. The DEFLATE (RFC 1951) stream is a single
+//! block with the fixed Huffman codes and greedy LZ77 matching: not the tightest, but most of the
+//! savings on markup and prose for very little code.
+
+use std::cmp;
+
+/// How far back a match may reach.
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// How many earlier occurrences of a 3-byte prefix to try before settling for the best so far.
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+                              513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385,
+                              24577];
+const DIST_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10,
+                              10, 11, 11, 12, 12, 13, 13];
+
+/// Compress `data` into a complete gzip member.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    // magic, deflate, no flags, no modification time, no extra flags, unknown OS
+    let mut out = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+    let mut bits = BitWriter::new(out);
+    deflate(data, &mut bits);
+    out = bits.finish();
+
+    out.extend_from_slice(&le_bytes(crc32(data)));
+    out.extend_from_slice(&le_bytes(data.len() as u32));
+    out
+}
+
+fn le_bytes(n: u32) -> [u8; 4] {
+    [n as u8, (n >> 8) as u8, (n >> 16) as u8, (n >> 24) as u8]
+}
+
+fn deflate(data: &[u8], bits: &mut BitWriter) {
+    // final block, fixed Huffman codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![usize::max_value(); 1 << HASH_BITS];
+    let mut prev = vec![usize::max_value(); WINDOW_SIZE];
+
+    let mut i = 0;
+    while i < data.len() {
+        let (len, dist) = longest_match(data, i, &head, &prev);
+
+        let step = if len >= MIN_MATCH {
+            write_match(bits, len, dist);
+            len
+        } else {
+            write_literal_or_length(bits, data[i] as u16);
+            1
+        };
+
+        // remember every position passed over, so later matches can start at any of them
+        for pos in i..cmp::min(i + step, data.len().saturating_sub(MIN_MATCH - 1)) {
+            let h = hash(&data[pos..]);
+            prev[pos % WINDOW_SIZE] = head[h];
+            head[h] = pos;
+        }
+
+        i += step;
+    }
+
+    // end of block
+    write_literal_or_length(bits, 256);
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let n = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+    (n.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+}
+
+/// The longest earlier match for the bytes at `i`, as (length, distance), or a length of 0.
+fn longest_match(data: &[u8], i: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    if i + MIN_MATCH > data.len() {
+        return (0, 0);
+    }
+
+    let max_len = cmp::min(MAX_MATCH, data.len() - i);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(&data[i..])];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::max_value() || i - candidate > WINDOW_SIZE {
+            break;
+        }
+
+        let len = data[candidate..]
+            .iter()
+            .zip(&data[i..i + max_len])
+            .take_while(|&(a, b)| a == b)
+            .count();
+
+        if len > best.0 {
+            best = (len, i - candidate);
+            if len == max_len {
+                break;
+            }
+        }
+
+        let next = prev[candidate % WINDOW_SIZE];
+        // the chain slot may have been reused by a position past this one
+        if next == usize::max_value() || next >= candidate {
+            break;
+        }
+        candidate = next;
+    }
+
+    best
+}
+
+/// A literal byte (0-255), the end of block (256), or a length code (257-285), in the fixed
+/// Huffman code (RFC 1951 section 3.2.6).
+fn write_literal_or_length(bits: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+
+    bits.write_huffman(code as u32, len);
+}
+
+fn write_match(bits: &mut BitWriter, len: usize, dist: usize) {
+    let len_code = LENGTH_BASE.iter().rposition(|&base| base as usize <= len).unwrap();
+    write_literal_or_length(bits, 257 + len_code as u16);
+    bits.write((len - LENGTH_BASE[len_code] as usize) as u32,
+               LENGTH_EXTRA[len_code] as u32);
+
+    let dist_code = DIST_BASE.iter().rposition(|&base| base as usize <= dist).unwrap();
+    bits.write_huffman(dist_code as u32, 5);
+    bits.write((dist - DIST_BASE[dist_code] as usize) as u32,
+               DIST_EXTRA[dist_code] as u32);
+}
+
+/// Packs bits into bytes least significant first, as DEFLATE wants.
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    acc_len: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        BitWriter {
+            out: out,
+            acc: 0,
+            acc_len: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, len: u32) {
+        self.acc |= value << self.acc_len;
+        self.acc_len += len;
+
+        while self.acc_len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.acc_len -= 8;
+        }
+    }
+
+    /// Huffman codes are packed most significant bit first, unlike everything else.
+    fn write_huffman(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.acc_len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// The CRC-32 (as used by gzip and zip) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        *entry = c;
+    }
+
+    !data.iter().fold(!0, |crc, &b| table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::process::{Command, Stdio};
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    /// Run compressed data back through the system's gzip.
+    fn gunzip(compressed: &[u8]) -> Vec<u8> {
+        let mut gzip = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // on a thread of its own, so neither side's pipe can fill up on the other
+        let mut stdin = gzip.stdin.take().unwrap();
+        let compressed = compressed.to_vec();
+        let writer = thread::spawn(move || stdin.write_all(&compressed).unwrap());
+
+        let mut out = Vec::new();
+        gzip.stdout.take().unwrap().read_to_end(&mut out).unwrap();
+        writer.join().unwrap();
+        assert!(gzip.wait().unwrap().success());
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut text = Vec::new();
+        for i in 0..2000 {
+            text.extend_from_slice(format!("<li>item {} of many, {}</li>\n", i, i * 7 % 13)
+                .as_bytes());
+        }
+        // far-apart repeats and bytes from the top of the literal range too
+        text.extend((0..=255u8).cycle().take(70000));
+
+        let compressed = compress(&text);
+        assert!(compressed.len() < text.len() / 3);
+        assert_eq!(gunzip(&compressed), text);
+
+        for short in &[&b""[..], b"a", b"ab", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"] {
+            assert_eq!(&gunzip(&compress(short))[..], *short);
+        }
+    }
+}
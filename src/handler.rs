@@ -0,0 +1,156 @@
+//! Pluggable request handling. A server answers requests for the URI prefixes routed to a
+//! `Handler` (see `Config::route`) with that handler, and anything else with its own
+//! `StaticFiles` and `Cgi` handlers.
+
+use std::fmt;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use clock::Clock;
+use request::Request;
+use response::Response;
+
+/// Something which answers requests.
+///
+/// A handler is shared by every thread of the server and may be called from any of them, on a
+/// mioco coroutine: anything which blocks a thread (like a std socket's `read`) holds up every
+/// other connection on it.
+pub trait Handler: Debug + Send + Sync {
+    /// Answer a request. The server takes care of the connection (keep-alive and framing), and
+    /// of leaving the body out when answering a HEAD.
+    fn handle(&self, req: &Request) -> Response;
+
+    /// Answer a request which may stop being worth answering partway through: because the
+    /// client's hung up, the server's shutting down or the handler's run past its deadline (see
+    /// `Limits::handler_timeout`). The server calls this for the prefixes routed to a handler;
+    /// handlers which take long enough for that to matter should check `cancel` as they go
+    /// (between chunks of work, or events of a stream) and give up once it's cancelled, and the
+    /// rest can leave it to call `handle`.
+    fn handle_cancellable(&self, req: &Request, cancel: &Cancellation) -> Response {
+        let _ = cancel;
+        self.handle(req)
+    }
+}
+
+/// Whether the answer to a request is still wanted. Clones share their state, so one can be
+/// handed to whatever's doing the work (like a thread producing a stream's events).
+#[derive(Clone)]
+pub struct Cancellation {
+    clock: Arc<Clock>,
+    deadline: Option<Instant>,
+    cancelled: Arc<AtomicBool>,
+    conditions: Vec<Arc<Fn() -> bool + Send + Sync>>,
+}
+
+impl Cancellation {
+    /// A token which is only cancelled by `cancel`, or once whatever's added to it says so.
+    pub fn new(clock: Arc<Clock>) -> Self {
+        Cancellation {
+            clock: clock,
+            deadline: None,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            conditions: Vec::new(),
+        }
+    }
+
+    /// Also cancelled once the clock reaches `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Also cancelled whenever `condition` is true, which is asked on every check, so should be
+    /// quick.
+    pub fn or_when<F: Fn() -> bool + Send + Sync + 'static>(mut self, condition: F) -> Self {
+        self.conditions.push(Arc::new(condition));
+        self
+    }
+
+    /// When the handler's expected to have answered by, if ever.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Cancel this and every clone of it, for good.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether to stop working on the answer. Once true, it stays true.
+    pub fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        let past_deadline = self.deadline.map_or(false, |d| self.clock.instant() >= d);
+        if past_deadline || self.conditions.iter().any(|condition| condition()) {
+            self.cancel();
+            return true;
+        }
+
+        false
+    }
+}
+
+impl fmt::Debug for Cancellation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cancellation")
+            .field("deadline", &self.deadline)
+            .field("cancelled", &self.cancelled.load(Ordering::SeqCst))
+            .field("conditions", &self.conditions.len())
+            .finish()
+    }
+}
+
+/// A handler for everything under a URI prefix.
+#[derive(Clone, Debug)]
+pub struct Route {
+    /// Relative to the root, without a leading slash: `api` routes `/api`, `/api/` and
+    /// `/api/anything`, but not `/apiary`. An empty prefix routes everything.
+    pub prefix: String,
+    pub handler: Arc<Handler>,
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use clock::{Clock, ManualClock};
+
+    use super::Cancellation;
+
+    #[test]
+    fn cancellation() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let cancel = Cancellation::new(clock.clone());
+        assert!(!cancel.is_cancelled());
+        assert_eq!(cancel.deadline(), None);
+
+        let clone = cancel.clone();
+        cancel.cancel();
+        assert!(clone.is_cancelled());
+
+        let deadline = clock.instant() + Duration::from_secs(5);
+        let cancel = Cancellation::new(clock.clone()).with_deadline(deadline);
+        assert_eq!(cancel.deadline(), Some(deadline));
+        clock.advance(Duration::from_secs(4));
+        assert!(!cancel.is_cancelled());
+        clock.advance(Duration::from_secs(1));
+        assert!(cancel.is_cancelled());
+
+        let hung_up = Arc::new(AtomicBool::new(false));
+        let condition = hung_up.clone();
+        let cancel = Cancellation::new(clock).or_when(move || condition.load(Ordering::SeqCst));
+        assert!(!cancel.is_cancelled());
+        hung_up.store(true, Ordering::SeqCst);
+        assert!(cancel.is_cancelled());
+
+        // staying cancelled even if the condition stops holding
+        hung_up.store(false, Ordering::SeqCst);
+        assert!(cancel.is_cancelled());
+    }
+}
@@ -0,0 +1,146 @@
+use std::slice;
+
+/// Fields which are about the connection they're sent over rather than the message, so which
+/// don't go any further than the next hop (RFC 7230 section 6.1), whether or not `Connection`
+/// names them.
+const HOP_BY_HOP: &'static [&'static str] = &["Connection",
+                                              "Keep-Alive",
+                                              "Proxy-Authenticate",
+                                              "Proxy-Authorization",
+                                              "Proxy-Connection",
+                                              "TE",
+                                              "Trailer",
+                                              "Transfer-Encoding",
+                                              "Upgrade"];
+
+/// A request's header fields, in the order they were received. Names are matched
+/// case-insensitively, and the same name may appear more than once.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Headers<'a> {
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> Headers<'a> {
+    pub fn new() -> Self {
+        Headers { fields: Vec::new() }
+    }
+
+    /// Split a `Name: value` line into its whitespace-trimmed halves, or `None` if it has no
+    /// colon.
+    pub fn parse_line(line: &'a str) -> Option<(&'a str, &'a str)> {
+        let mut halves = line.splitn(2, ':');
+
+        match (halves.next(), halves.next()) {
+            (Some(name), Some(value)) => Some((name.trim(), value.trim())),
+            _ => None,
+        }
+    }
+
+    pub fn push(&mut self, name: &'a str, value: &'a str) {
+        self.fields.push((name, value));
+    }
+
+    /// Value of the first field with a matching name.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.fields.iter().find(|&&(n, _)| n.eq_ignore_ascii_case(name)).map(|&(_, v)| v)
+    }
+
+    /// Values of every field with a matching name, in order.
+    pub fn get_all(&self, name: &str) -> Vec<&'a str> {
+        self.fields
+            .iter()
+            .filter(|&&(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, v)| v)
+            .collect()
+    }
+
+    /// All of the fields as `(name, value)` pairs, names as the client spelled them.
+    pub fn iter(&self) -> slice::Iter<(&'a str, &'a str)> {
+        self.fields.iter()
+    }
+
+    /// The options of every `Connection` field, e.g. `close` or the names of other fields which
+    /// are only for this hop.
+    pub fn connection_options(&self) -> Vec<&'a str> {
+        self.get_all("Connection")
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(|o| o.trim())
+            .filter(|o| !o.is_empty())
+            .collect()
+    }
+
+    /// The codings of every `Transfer-Encoding` field, in the order they were applied, e.g.
+    /// `chunked`.
+    pub fn transfer_codings(&self) -> Vec<&'a str> {
+        self.get_all("Transfer-Encoding")
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(|c| c.trim())
+            .filter(|c| !c.is_empty())
+            .collect()
+    }
+
+    /// Whether a field is only meant for the hop it arrived over: one of the standard hop-by-hop
+    /// fields, or one `Connection` names.
+    pub fn is_hop_by_hop(&self, name: &str) -> bool {
+        HOP_BY_HOP.iter().chain(&self.connection_options()).any(|h| h.eq_ignore_ascii_case(name))
+    }
+
+    /// The fields to pass on to whatever answers the request (a CGI script, say), which are all
+    /// but the hop-by-hop ones.
+    pub fn end_to_end(&self) -> Vec<(&'a str, &'a str)> {
+        self.fields.iter().cloned().filter(|&(n, _)| !self.is_hop_by_hop(n)).collect()
+    }
+}
+
+impl<'a> From<Vec<(&'a str, &'a str)>> for Headers<'a> {
+    fn from(fields: Vec<(&'a str, &'a str)>) -> Self {
+        Headers { fields: fields }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Headers;
+
+    #[test]
+    fn lookup() {
+        let mut headers = Headers::new();
+        assert_eq!(headers.iter().count(), 0);
+
+        for line in &["Accept:  text/html ", "host: a", "ACCEPT: text/plain"] {
+            let (name, value) = Headers::parse_line(line).unwrap();
+            headers.push(name, value);
+        }
+
+        assert_eq!(headers.iter().count(), 3);
+        assert_eq!(headers.get("Host"), Some("a"));
+        assert_eq!(headers.get("accept"), Some("text/html"));
+        assert_eq!(headers.get_all("Accept"), vec!["text/html", "text/plain"]);
+        assert_eq!(headers.get("Range"), None);
+        assert!(headers.get_all("Range").is_empty());
+
+        assert_eq!(headers.iter().next(), Some(&("Accept", "text/html")));
+        assert_eq!(Headers::parse_line("no colon here"), None);
+        assert_eq!(Headers::parse_line("Empty:"), Some(("Empty", "")));
+    }
+
+    #[test]
+    fn hop_by_hop() {
+        let headers = Headers::from(vec![("Host", "a"),
+                                         ("Connection", "keep-alive, X-Trace"),
+                                         ("keep-alive", "timeout=5"),
+                                         ("x-trace", "1"),
+                                         ("Connection", "Upgrade"),
+                                         ("Upgrade", "websocket"),
+                                         ("TE", "trailers"),
+                                         ("Accept", "*/*")]);
+
+        assert_eq!(headers.connection_options(), vec!["keep-alive", "X-Trace", "Upgrade"]);
+        assert!(headers.is_hop_by_hop("X-TRACE"));
+        assert!(headers.is_hop_by_hop("Proxy-Authorization"));
+        assert!(!headers.is_hop_by_hop("Accept"));
+        assert_eq!(headers.end_to_end(), vec![("Host", "a"), ("Accept", "*/*")]);
+    }
+}
@@ -0,0 +1,50 @@
+//! Callbacks for a program embedding the server, to feed metrics or logs of its own from what the
+//! server does without adopting its access log or `Stats`. Add them with `Config::hook`.
+
+use std::fmt::Debug;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use error::HpptError;
+use request::Request;
+
+/// Something which hears about requests as they're answered. Every method does nothing unless
+/// it's overridden, so implement only those you need.
+///
+/// Hooks are shared by every thread of the server and called on the coroutine serving the
+/// connection, so like a `Handler` they mustn't block: hand anything slow to a thread of your own.
+pub trait Hooks: Debug + Send + Sync {
+    /// A request has been read and parsed, and is about to be answered.
+    fn on_request(&self, req: &Request) {
+        let _ = req;
+    }
+
+    /// A response has been sent, or sending it was given up on partway through. This is called
+    /// for every response, including those to requests which never made it to `on_request`.
+    fn on_response(&self, completed: &Completed) {
+        let _ = completed;
+    }
+
+    /// Something went wrong: a request couldn't be read or made sense of, or a response couldn't
+    /// be sent.
+    fn on_error(&self, error: &HpptError) {
+        let _ = error;
+    }
+}
+
+/// What became of a response.
+#[derive(Clone, Debug)]
+pub struct Completed<'a> {
+    /// The client's end of the connection.
+    pub remote: SocketAddr,
+    /// The request line (without its line ending) as the client sent it, which may be empty or
+    /// not a request line at all.
+    pub request_line: &'a [u8],
+    pub status: u16,
+    /// How much of the body was delivered.
+    pub body_bytes: usize,
+    /// From having the request in full to having sent the response.
+    pub duration: Duration,
+    /// Whether the whole response was delivered.
+    pub complete: bool,
+}
@@ -0,0 +1,43 @@
+use chrono::{TimeZone, UTC};
+
+/// The preferred HTTP-date format (RFC 7231 section 7.1.1.1), e.g.
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+const IMF_FIXDATE: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Obsolete formats which recipients still have to accept.
+const RFC_850: &'static str = "%A, %d-%b-%y %H:%M:%S GMT";
+const ASCTIME: &'static str = "%a %b %e %H:%M:%S %Y";
+
+/// Format seconds since the Unix epoch as an HTTP-date.
+pub fn format(secs: i64) -> String {
+    UTC.timestamp(secs, 0).format(IMF_FIXDATE).to_string()
+}
+
+/// Parse an HTTP-date in any of the three allowed formats into seconds since the Unix epoch.
+pub fn parse(date: &str) -> Option<i64> {
+    let date = date.trim();
+
+    [IMF_FIXDATE, RFC_850, ASCTIME]
+        .iter()
+        .filter_map(|fmt| UTC.datetime_from_str(date, fmt).ok())
+        .next()
+        .map(|d| d.timestamp())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(format(784111777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(784111777));
+    }
+
+    #[test]
+    fn obsolete_formats() {
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), Some(784111777));
+        assert_eq!(parse("Sun Nov  6 08:49:37 1994"), Some(784111777));
+        assert_eq!(parse("yesterday"), None);
+    }
+}
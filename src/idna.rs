@@ -0,0 +1,268 @@
+//! Internationalized domain names: host names with non-ASCII labels, which go over the wire (and
+//! are compared) in their ASCII form, each such label Punycode-encoded (RFC 3492) behind `xn--`.
+//! Clients send either form in a Host header, so both are brought to the one form here.
+
+const PREFIX: &'static str = "xn--";
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+/// The longest a label may be, in its ASCII form.
+const MAX_LABEL_LEN: usize = 63;
+
+/// The lowercased ASCII form of a host name given in either form (or a mix of the two), e.g.
+/// `xn--bcher-kva.example` for `Bücher.example`, or `None` if it isn't a valid name.
+pub fn to_ascii(host: &str) -> Option<String> {
+    let labels = host.split('.').map(|label| {
+        let label = if label.is_ascii() {
+            let label = label.to_ascii_lowercase();
+            if !label.starts_with(PREFIX) {
+                return Some(label);
+            }
+
+            // round-tripped, so an encoding of capitals comes out the same as one of lowercase
+            match decode(&label[PREFIX.len()..]) {
+                Some(ref decoded) if !decoded.is_ascii() => decoded.to_lowercase(),
+                _ => return None,
+            }
+        } else {
+            match label.to_lowercase() {
+                // as for the Kelvin sign, which lowercases to a k
+                ref lower if lower.is_ascii() => return Some(lower.clone()),
+                lower => lower,
+            }
+        };
+
+        encode(&label).map(|encoded| format!("{}{}", PREFIX, encoded))
+    });
+
+    let labels = match labels.collect::<Option<Vec<_>>>() {
+        Some(l) => l,
+        None => return None,
+    };
+    if labels.iter().any(|l| l.len() > MAX_LABEL_LEN) {
+        return None;
+    }
+
+    Some(labels.join("."))
+}
+
+/// An absolute URL (e.g. in a `Location` header) with its host in ASCII form, or a relative one
+/// as it is.
+pub fn url_to_ascii(url: &str) -> String {
+    let authority = match url.find("://") {
+        Some(i) => i + 3,
+        None => return url.to_owned(),
+    };
+
+    let rest = &url[authority..];
+    let end = rest.find(|c| c == '/' || c == '?' || c == '#').unwrap_or(rest.len());
+    let (host_port, path) = rest.split_at(end);
+
+    // user info and IPv6 literals are ASCII already, if they're valid at all
+    if host_port.is_ascii() || host_port.contains('@') || host_port.starts_with('[') {
+        return url.to_owned();
+    }
+
+    let (host, port) = match host_port.rfind(':') {
+        Some(i) => host_port.split_at(i),
+        None => (host_port, ""),
+    };
+
+    match to_ascii(host) {
+        Some(host) => format!("{}{}{}{}", &url[..authority], host, port, path),
+        None => url.to_owned(),
+    }
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+
+    k + (BASE - T_MIN + 1) * delta / (delta + SKEW)
+}
+
+/// The threshold for the digit at position `k`.
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        T_MIN
+    } else if k >= bias + T_MAX {
+        T_MAX
+    } else {
+        k - bias
+    }
+}
+
+fn encode_digit(d: u32) -> char {
+    (if d < 26 { b'a' + d as u8 } else { b'0' + (d - 26) as u8 }) as char
+}
+
+fn decode_digit(c: char) -> Option<u32> {
+    if c.is_ascii_lowercase() {
+        Some(c as u32 - 'a' as u32)
+    } else if c.is_ascii_uppercase() {
+        Some(c as u32 - 'A' as u32)
+    } else if c.is_ascii_digit() {
+        Some(c as u32 - '0' as u32 + 26)
+    } else {
+        None
+    }
+}
+
+/// The Punycode encoding of a label (without the `xn--`), or `None` if it's too long to encode.
+fn encode(label: &str) -> Option<String> {
+    let input = label.chars().map(|c| c as u32).collect::<Vec<_>>();
+    let mut output = label.chars().filter(|c| c.is_ascii()).collect::<String>();
+
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let (mut n, mut delta, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut handled = basic;
+
+    while (handled as usize) < input.len() {
+        let m = input.iter().cloned().filter(|&c| c >= n).min().unwrap();
+        delta = match (m - n).checked_mul(handled + 1).and_then(|d| delta.checked_add(d)) {
+            Some(v) => v,
+            None => return None,
+        };
+        n = m;
+
+        for &c in &input {
+            if c < n {
+                delta = match delta.checked_add(1) {
+                    Some(v) => v,
+                    None => return None,
+                };
+            }
+
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(encode_digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+
+                output.push(encode_digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+
+        delta += 1;
+        n += 1;
+    }
+
+    Some(output)
+}
+
+/// The label a Punycode encoding (without the `xn--`) stands for, if it's valid.
+fn decode(encoded: &str) -> Option<String> {
+    let (basic, digits) = match encoded.rfind('-') {
+        Some(i) => (&encoded[..i], &encoded[i + 1..]),
+        None => ("", encoded),
+    };
+
+    let mut output = basic.chars().collect::<Vec<_>>();
+    let (mut n, mut i, mut bias) = (INITIAL_N, 0u32, INITIAL_BIAS);
+    let mut digits = digits.chars().peekable();
+
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+
+        loop {
+            let digit = match digits.next().and_then(decode_digit) {
+                Some(d) => d,
+                None => return None,
+            };
+            i = match digit.checked_mul(w).and_then(|d| i.checked_add(d)) {
+                Some(v) => v,
+                None => return None,
+            };
+
+            let t = threshold(k, bias);
+            if digit < t {
+                break;
+            }
+            w = match w.checked_mul(BASE - t) {
+                Some(v) => v,
+                None => return None,
+            };
+            k += BASE;
+        }
+
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = match n.checked_add(i / len) {
+            Some(v) => v,
+            None => return None,
+        };
+        i %= len;
+
+        let c = match ::std::char::from_u32(n) {
+            Some(c) => c,
+            None => return None,
+        };
+        output.insert(i as usize, c);
+        i += 1;
+    }
+
+    Some(output.into_iter().collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn punycode() {
+        for &(label, encoded) in &[("bücher", "bcher-kva"),
+                                   ("münchen", "mnchen-3ya"),
+                                   ("пример", "e1afmkfd"),
+                                   ("испытание", "80akhbyknj4f")] {
+            assert_eq!(encode(label), Some(encoded.to_owned()));
+            assert_eq!(decode(encoded), Some(label.to_owned()));
+        }
+
+        assert_eq!(decode("bcher-kv!"), None);
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(to_ascii("Bücher.Example"), Some("xn--bcher-kva.example".to_owned()));
+        assert_eq!(to_ascii("XN--BCHER-KVA.example"), Some("xn--bcher-kva.example".to_owned()));
+        assert_eq!(to_ascii("пример.испытание"),
+                   Some("xn--e1afmkfd.xn--80akhbyknj4f".to_owned()));
+        assert_eq!(to_ascii("127.0.0.1"), Some("127.0.0.1".to_owned()));
+        assert_eq!(to_ascii("xn--!.example"), None);
+        assert_eq!(to_ascii(&"ü".repeat(60)), None);
+
+        assert_eq!(url_to_ascii("http://bücher.example:8080/b%C3%BCcher?q"),
+                   "http://xn--bcher-kva.example:8080/b%C3%BCcher?q");
+        assert_eq!(url_to_ascii("https://example.com/"), "https://example.com/");
+        assert_eq!(url_to_ascii("/elsewhere"), "/elsewhere");
+    }
+}
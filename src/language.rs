@@ -0,0 +1,175 @@
+/// A single language-range from an Accept-Language header, e.g. `de-CH;q=0.8`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LanguageRange {
+    pub range: String,
+    pub quality: f32,
+}
+
+/// Parse an Accept-Language header value into its ranges, sorted by descending quality.
+///
+/// Malformed entries are skipped rather than failing the whole header, since this is only ever
+/// used to pick a "best" variant.
+pub fn parse_accept_language(header: &str) -> Vec<LanguageRange> {
+    let mut ranges = Vec::new();
+
+    for entry in header.split(',') {
+        let mut params = entry.split(';');
+
+        let range = match params.next() {
+            Some(r) => r.trim(),
+            None => continue,
+        };
+
+        let valid = range.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '*');
+        if range.is_empty() || !valid {
+            continue;
+        }
+
+        let mut quality = 1.0;
+        for param in params {
+            let param = param.trim();
+            if param.starts_with("q=") {
+                quality = match param[2..].parse::<f32>() {
+                    Ok(q) if q >= 0.0 && q <= 1.0 => q,
+                    _ => 0.0,
+                };
+            }
+        }
+
+        ranges.push(LanguageRange {
+            range: range.to_ascii_lowercase(),
+            quality: quality,
+        });
+    }
+
+    // stable, so equal-quality ranges keep the client's order
+    ranges.sort_by(|a, b| b.quality.partial_cmp(&a.quality).unwrap());
+
+    ranges
+}
+
+/// Quality the client assigns to a language tag: that of the most specific matching range
+/// (RFC 4647 basic filtering), or `None` if no range matches.
+fn quality_of(ranges: &[LanguageRange], tag: &str) -> Option<f32> {
+    let tag = tag.to_ascii_lowercase();
+
+    ranges.iter()
+        .filter(|r| {
+            r.range == "*" || r.range == tag ||
+            (tag.starts_with(&r.range) && tag[r.range.len()..].starts_with('-'))
+        })
+        .max_by_key(|r| if r.range == "*" { 0 } else { r.range.len() })
+        .map(|r| r.quality)
+}
+
+/// Pick the best of the available language tags for the client's Accept-Language header.
+///
+/// Falls back on the default language (if available), then on the first variant, when the client
+/// didn't express a preference or nothing it asked for is available.
+pub fn negotiate<'a>(available: &'a [String],
+                     accept_language: Option<&str>,
+                     default: Option<&str>)
+                     -> Option<&'a str> {
+    let fallback = default.and_then(|d| available.iter().find(|a| a.eq_ignore_ascii_case(d)))
+        .or_else(|| available.first())
+        .map(|s| &s[..]);
+
+    let ranges = match accept_language {
+        Some(h) => parse_accept_language(h),
+        None => return fallback,
+    };
+
+    let mut best = None;
+    let mut best_quality = 0.0;
+
+    for tag in available {
+        if let Some(q) = quality_of(&ranges, tag) {
+            if q > best_quality {
+                best = Some(&tag[..]);
+                best_quality = q;
+            }
+        }
+    }
+
+    best.or(fallback)
+}
+
+/// Whether a filename extension looks like a language tag (`en`, `pt-br`, ...): a two or three
+/// letter primary subtag, optionally followed by short alphanumeric subtags.
+pub fn is_language_tag(s: &str) -> bool {
+    let mut subtags = s.split('-');
+
+    let primary_ok = match subtags.next() {
+        Some(p) => p.len() >= 2 && p.len() <= 3 && p.chars().all(|c| c.is_ascii_alphabetic()),
+        None => false,
+    };
+
+    primary_ok &&
+    subtags.all(|sub| {
+        !sub.is_empty() && sub.len() <= 8 && sub.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_with_qualities() {
+        let ranges = parse_accept_language("en;q=0.5, de-CH, de;q=0.9, *;q=0.1");
+
+        assert_eq!(ranges,
+                   vec![LanguageRange {
+                            range: "de-ch".to_owned(),
+                            quality: 1.0,
+                        },
+                        LanguageRange {
+                            range: "de".to_owned(),
+                            quality: 0.9,
+                        },
+                        LanguageRange {
+                            range: "en".to_owned(),
+                            quality: 0.5,
+                        },
+                        LanguageRange {
+                            range: "*".to_owned(),
+                            quality: 0.1,
+                        }]);
+    }
+
+    #[test]
+    fn parse_skips_garbage() {
+        let ranges = parse_accept_language("en, ;q=1, fr$;q=0.4,");
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].range, "en");
+    }
+
+    #[test]
+    fn negotiate_prefers_highest_quality() {
+        let available = vec!["de".to_owned(), "en".to_owned(), "fr".to_owned()];
+
+        assert_eq!(negotiate(&available, Some("fr;q=0.3, en;q=0.8"), None), Some("en"));
+        assert_eq!(negotiate(&available, Some("en-US, de;q=0.5"), None), Some("de"));
+        assert_eq!(negotiate(&available, Some("*;q=0.2, fr;q=0"), Some("fr")), Some("de"));
+    }
+
+    #[test]
+    fn negotiate_falls_back() {
+        let available = vec!["de".to_owned(), "en".to_owned()];
+
+        assert_eq!(negotiate(&available, None, Some("en")), Some("en"));
+        assert_eq!(negotiate(&available, Some("ja"), Some("en")), Some("en"));
+        assert_eq!(negotiate(&available, Some("ja"), None), Some("de"));
+        assert_eq!(negotiate(&[], Some("ja"), None), None);
+    }
+
+    #[test]
+    fn language_tags() {
+        assert!(is_language_tag("en"));
+        assert!(is_language_tag("pt-BR"));
+        assert!(!is_language_tag(""));
+        assert!(!is_language_tag("html"));
+        assert!(!is_language_tag("1en"));
+        assert!(!is_language_tag("en--us"));
+    }
+}
@@ -0,0 +1,140 @@
+//! A basic HTTP static file server, which can also be embedded in another program.
+//!
+//! `server::run` serves a `Config`'s root directory: static files, and CGI scripts under
+//! `cgi-bin` (or wherever `Config::cgi_dirs` says), and can forward prefixes of the URI space to
+//! FastCGI application servers (`Config::fastcgi`) or upstream HTTP servers (`Config::proxies`).
+//! Any part of the URI space can be handed to a `Handler` of your own with `Config::route`, and
+//! `StaticFiles` and `Cgi` are the handlers behind the server's own behavior, for reuse
+//! elsewhere. `Config::hook` tells `Hooks` of your own about requests, responses and errors as
+//! they happen, for metrics or logging.
+//!
+//! ```no_run
+//! extern crate hppt;
+//! extern crate mioco;
+//!
+//! use std::path::PathBuf;
+//! use std::sync::mpsc;
+//!
+//! use hppt::{Config, Handler, Request, Response, Status};
+//!
+//! #[derive(Debug)]
+//! struct Hello;
+//!
+//! impl Handler for Hello {
+//!     fn handle(&self, _: &Request) -> Response {
+//!         Response::builder().status(Status::Ok).body_reader("Hello!".as_bytes()).build()
+//!     }
+//! }
+//!
+//! fn main() {
+//!     let mut config = Config::new(PathBuf::from("/srv/www"));
+//!     config.route("hello", Hello);
+//!
+//!     let listener = mioco::tcp::TcpListener::bind(&"127.0.0.1:8080".parse().unwrap()).unwrap();
+//!     let (_shutdown, recv) = mpsc::channel();
+//!     hppt::server::run(listener, config, recv).unwrap();
+//! }
+//! ```
+
+#[cfg(test)]
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate log;
+#[macro_use]
+extern crate mioco;
+
+extern crate chrono;
+extern crate env_logger;
+extern crate libc;
+
+mod access_log;
+mod archive;
+pub mod auth;
+pub mod cache;
+mod cgi;
+mod charset;
+mod checksum;
+pub mod cidr;
+pub mod clock;
+pub mod config;
+pub mod config_file;
+mod connection;
+mod cors;
+pub mod crash;
+mod encoding;
+pub mod error;
+pub mod error_log;
+pub mod fastcgi;
+mod files;
+mod gzip;
+pub mod handler;
+pub mod headers;
+pub mod hooks;
+mod http_date;
+mod idna;
+mod json;
+mod language;
+pub mod limits;
+mod listing;
+mod live_reload;
+pub mod mime;
+mod peers;
+mod problem;
+pub mod proxy;
+mod recording;
+pub mod request;
+pub mod resources;
+pub mod response;
+pub mod rewrite;
+pub mod s3;
+pub mod server;
+mod sha256;
+pub mod signals;
+pub mod snapshots;
+pub mod source;
+pub mod stats;
+mod throttle;
+mod tls;
+mod toml;
+
+use chrono::Local;
+use env_logger::LogBuilder;
+use log::{LogLevelFilter, LogRecord};
+
+pub use config::Config;
+pub use handler::{Cancellation, Handler};
+pub use hooks::Hooks;
+pub use request::Request;
+pub use response::{Response, Status};
+pub use server::{Cgi, StaticFiles};
+
+/// Log to stderr (at debug level if `verbose`), keeping the recent warnings and errors which crash
+/// reports and the admin endpoint show.
+pub fn init_logging(verbose: bool) {
+    let level = if verbose {
+        LogLevelFilter::Debug
+    } else {
+        LogLevelFilter::Info
+    };
+
+    let init_result = LogBuilder::new()
+        .filter(None, level)
+        .format(|record: &LogRecord| {
+            let time = Local::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+            error_log::record(record, &time);
+
+            format!("[{} {} {}] {}",
+                    record.level(),
+                    time,
+                    record.location().module_path(),
+                    record.args())
+        })
+        .init();
+
+    match init_result {
+        Ok(_) => debug!("Initialized logging."),
+        Err(why) => println!("Unable to initialize logging: {:?}", why),
+    }
+}
@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+/// Caps on how much of the server's time, memory and connections a client gets, gathered in one
+/// place so they can be checked against each other before the server starts.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// Largest request, head and body together, which we'll buffer. Bigger ones get a 413.
+    pub max_request_size: usize,
+    /// Requests with more header lines than this are rejected with a 431.
+    pub max_headers: usize,
+    /// How long a client gets to send a request's head, from when we start waiting for it, before
+    /// it gets a 408.
+    pub header_timeout: Option<Duration>,
+    /// How long a client gets to send a request's body once the head has arrived, before it gets
+    /// a 408.
+    pub body_timeout: Option<Duration>,
+    /// How long a client gets to accept a response before we give up on it.
+    pub write_timeout: Option<Duration>,
+    /// How long to wait for another request on a connection after a response. `None` closes the
+    /// connection after every response instead of keeping it alive.
+    pub keep_alive_timeout: Option<Duration>,
+    /// Most requests to serve on one connection before closing it.
+    pub keep_alive_max: usize,
+    /// Most bytes per second to send a response's body at, so one client can't take up all of
+    /// the uplink.
+    pub rate_limit: Option<usize>,
+    /// Most connections to have open at once, beyond which clients are turned away with a 503.
+    pub max_connections: Option<usize>,
+    /// Most connections one client address may have open at once, beyond which it's turned away
+    /// with a 503.
+    pub max_connections_per_ip: Option<usize>,
+    /// Most requests per second one client address may make (in bursts of up to a second's
+    /// worth), beyond which they get a 429.
+    pub requests_per_sec: Option<usize>,
+    /// Most output a CGI script may produce for one request, beyond which it's killed and the
+    /// client gets a 502, or if the script's header block has already been sent, a body cut short.
+    pub max_cgi_output: usize,
+    /// How long a CGI script gets to produce its output and exit before it's killed and the
+    /// client gets a 504, or as with `max_cgi_output`, a body cut short.
+    pub cgi_timeout: Option<Duration>,
+    /// How long an upstream server behind the proxy, a FastCGI application, or an S3 store gets to
+    /// accept a connection, or to take or send the next piece of a request or its answer, before
+    /// the client gets a 504, or if the answer's head has already been sent, a body cut short.
+    pub upstream_timeout: Option<Duration>,
+    /// How many failures in a row (no answer, or a 502, 503 or 504) an upstream behind the proxy
+    /// may have before requests for its route are failed fast with a 503, rather than all left
+    /// waiting on it. `None` never fails them fast.
+    pub breaker_threshold: Option<usize>,
+    /// How long a route failed fast for waits before one request is let through to probe whether
+    /// its upstream's recovered, and between probes after.
+    pub breaker_cooldown: Duration,
+    /// Most CGI scripts to run at once, beyond which requests for them get a 503.
+    pub max_cgi_processes: Option<usize>,
+    /// Most requests to have open with FastCGI applications at once, beyond which requests for
+    /// them get a 503.
+    pub max_fastcgi_requests: Option<usize>,
+    /// How long a routed handler gets to answer before its `Cancellation` says to give up. The
+    /// server doesn't cut it off: it's up to the handler to check in time.
+    pub handler_timeout: Option<Duration>,
+    /// How long open connections get to finish once the server's been asked to shut down,
+    /// before they're closed regardless.
+    pub shutdown_grace: Duration,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_size: 64 * 1024, // 64KB
+            max_headers: 100,
+            header_timeout: Some(Duration::from_secs(10)),
+            body_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Some(Duration::from_secs(5)),
+            keep_alive_max: 100,
+            rate_limit: None,
+            max_connections: None,
+            max_connections_per_ip: None,
+            requests_per_sec: None,
+            max_cgi_output: 10 * 1024 * 1024, // 10MB
+            cgi_timeout: Some(Duration::from_secs(30)),
+            upstream_timeout: Some(Duration::from_secs(30)),
+            breaker_threshold: None,
+            breaker_cooldown: Duration::from_secs(10),
+            max_cgi_processes: None,
+            max_fastcgi_requests: None,
+            handler_timeout: None,
+            shutdown_grace: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Limits {
+    /// Check for settings which would leave the server unable to serve anything, or which only
+    /// make sense spelled some other way (a zero timeout is `None`).
+    pub fn validate(&self) -> Result<(), String> {
+        // room for at least a minimal request line and a Host header
+        if self.max_request_size < 64 {
+            return Err(format!("max request size of {} bytes is below the minimum of 64",
+                               self.max_request_size));
+        }
+
+        if self.keep_alive_max == 0 {
+            return Err("connections must be allowed at least one request".to_owned());
+        }
+
+        if self.rate_limit == Some(0) {
+            return Err("responses must be allowed to be sent at some rate".to_owned());
+        }
+
+        if self.max_connections == Some(0) || self.max_connections_per_ip == Some(0) {
+            return Err("clients must be allowed at least one connection".to_owned());
+        }
+
+        if self.requests_per_sec == Some(0) {
+            return Err("clients must be allowed to make requests at some rate".to_owned());
+        }
+
+        if self.max_cgi_processes == Some(0) {
+            return Err("at least one CGI script must be allowed to run".to_owned());
+        }
+
+        if self.max_fastcgi_requests == Some(0) {
+            return Err("at least one FastCGI request must be allowed at a time".to_owned());
+        }
+
+        if self.breaker_threshold == Some(0) {
+            return Err("upstreams must be allowed at least one failure".to_owned());
+        }
+
+        if self.breaker_cooldown == Duration::from_secs(0) {
+            return Err("routes failed fast must be so for some time".to_owned());
+        }
+
+        if self.max_cgi_output == 0 {
+            return Err("CGI scripts must be allowed to produce some output".to_owned());
+        }
+
+        let zero = Some(Duration::from_secs(0));
+        if self.header_timeout == zero || self.body_timeout == zero ||
+           self.write_timeout == zero || self.keep_alive_timeout == zero ||
+           self.cgi_timeout == zero || self.upstream_timeout == zero ||
+           self.handler_timeout == zero {
+            return Err("timeouts must be positive if set".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Limits;
+
+    #[test]
+    fn validation() {
+        assert_eq!(Limits::default().validate(), Ok(()));
+
+        let mut limits = Limits::default();
+        limits.max_request_size = 10;
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.keep_alive_max = 0;
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.rate_limit = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.max_connections = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.max_connections_per_ip = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.requests_per_sec = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.keep_alive_timeout = Some(Duration::from_secs(0));
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.max_cgi_processes = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.max_fastcgi_requests = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.breaker_threshold = Some(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.breaker_cooldown = Duration::from_secs(0);
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.max_cgi_output = 0;
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.cgi_timeout = Some(Duration::from_secs(0));
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.upstream_timeout = Some(Duration::from_secs(0));
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.body_timeout = Some(Duration::from_secs(0));
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.handler_timeout = Some(Duration::from_secs(0));
+        assert!(limits.validate().is_err());
+
+        let mut limits = Limits::default();
+        limits.keep_alive_timeout = None;
+        limits.header_timeout = None;
+        limits.body_timeout = None;
+        limits.write_timeout = None;
+        limits.cgi_timeout = None;
+        limits.upstream_timeout = None;
+        assert_eq!(limits.validate(), Ok(()));
+    }
+}
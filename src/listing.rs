@@ -0,0 +1,262 @@
+use std::cmp::Ordering;
+use std::iter::Peekable;
+use std::path::Path;
+use std::str::Chars;
+use std::time::UNIX_EPOCH;
+
+use config::Config;
+use http_date;
+use json;
+use source::DirEntry;
+
+/// Render an HTML listing of a directory's entries (names, sizes and modification times) for a
+/// (slash-stripped) URI naming it, or `None` if it isn't a directory the config's source will
+/// list.
+///
+/// Links are absolute, so they work whether or not the request ended in a slash. Entries are in
+/// the order of `compare_names`, with numbers in order of their value if the config sorts
+/// naturally.
+pub fn render(config: &Config, uri: &str) -> Option<String> {
+    entries(config, uri).map(|entries| render_html(uri, &entries))
+}
+
+/// The same listing as `render`, as a JSON array of entries with their `name`, `dir` (whether
+/// they're directories), `size` (`null` for a directory) and `modified` (an HTTP-date, or
+/// `null` if the source doesn't know).
+pub fn render_json(config: &Config, uri: &str) -> Option<String> {
+    entries(config, uri).map(|entries| to_json(&entries))
+}
+
+/// A directory's entries, in the order they're listed in, leaving out those which are hidden.
+pub fn entries(config: &Config, uri: &str) -> Option<Vec<DirEntry>> {
+    config.source.list(Path::new(uri)).map(|entries| {
+        let mut entries = entries.into_iter()
+            .filter(|e| !config.is_hidden(&e.name))
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| compare_names(&a.name, &b.name, config.natural_sort));
+        entries
+    })
+}
+
+/// An HTML listing of the entries of the directory at a URI.
+pub fn render_html(uri: &str, entries: &[DirEntry]) -> String {
+    let base = if uri.is_empty() {
+        "/".to_owned()
+    } else {
+        format!("/{}/", uri.trim_end_matches('/'))
+    };
+
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><title>Index of {0}</title></head>\n\
+                            <body>\n<h1>Index of {0}</h1>\n<table>\n\
+                            <tr><th>Name</th><th>Size</th><th>Last modified</th></tr>\n",
+                           escape_html(&base));
+
+    if base != "/" {
+        html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n");
+    }
+
+    for entry in entries {
+        let (name, size) = if entry.metadata.is_dir {
+            (format!("{}/", entry.name), "-".to_owned())
+        } else {
+            (entry.name.clone(), entry.metadata.len.to_string())
+        };
+
+        html.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+                               escape_html(&encode_href(&format!("{}{}", base, name))),
+                               escape_html(&name),
+                               size,
+                               modified(entry).map(http_date::format).unwrap_or_default()));
+    }
+
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    html
+}
+
+/// A JSON listing of a directory's entries.
+pub fn to_json(entries: &[DirEntry]) -> String {
+    let entries = entries.iter()
+        .map(|entry| {
+            let size = if entry.metadata.is_dir {
+                "null".to_owned()
+            } else {
+                entry.metadata.len.to_string()
+            };
+
+            let modified = modified(entry)
+                .map_or("null".to_owned(), |m| json::string(&http_date::format(m)));
+
+            format!("{{\"name\": {}, \"dir\": {}, \"size\": {}, \"modified\": {}}}",
+                    json::string(&entry.name),
+                    entry.metadata.is_dir,
+                    size,
+                    modified)
+        })
+        .collect::<Vec<_>>();
+
+    format!("[{}]", entries.join(", "))
+}
+
+/// When an entry was last modified, in seconds since the Unix epoch, if the source knows.
+fn modified(entry: &DirEntry) -> Option<i64> {
+    match entry.metadata.modified.map(|m| m.duration_since(UNIX_EPOCH)) {
+        Some(Ok(d)) => Some(d.as_secs() as i64),
+        _ => None,
+    }
+}
+
+/// The order entries are listed in: by character, ignoring case, and with each run of digits
+/// compared as the number it is (so `file2` comes before `file10`) if `natural`. Names which are
+/// still level are compared byte by byte, so the order is the same whatever order the source
+/// listed them in.
+fn compare_names(a: &str, b: &str, natural: bool) -> Ordering {
+    let (mut x, mut y) = (a.chars().peekable(), b.chars().peekable());
+
+    loop {
+        let ordering = match (x.peek().cloned(), y.peek().cloned()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(c), Some(d)) if natural && c.is_ascii_digit() && d.is_ascii_digit() => {
+                let (m, n) = (digits(&mut x), digits(&mut y));
+                let (m, n) = (m.trim_start_matches('0'), n.trim_start_matches('0'));
+                m.len().cmp(&n.len()).then_with(|| m.cmp(n))
+            }
+            (Some(c), Some(d)) => {
+                x.next();
+                y.next();
+                c.to_lowercase().cmp(d.to_lowercase())
+            }
+        };
+
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Take the run of digits at the front of some characters.
+fn digits(chars: &mut Peekable<Chars>) -> String {
+    let mut run = String::new();
+
+    while let Some(&c) = chars.peek() {
+        if !c.is_ascii_digit() {
+            break;
+        }
+        run.push(c);
+        chars.next();
+    }
+
+    run
+}
+
+/// Escape text for use in HTML content or a quoted attribute.
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Percent-encode everything in a path except unreserved characters and slashes.
+fn encode_href(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use config::Config;
+
+    use super::*;
+
+    #[test]
+    fn listing() {
+        let html = render(&Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR"))), "test")
+            .unwrap();
+
+        assert!(html.contains("<title>Index of /test/</title>"));
+        assert!(html.contains("<a href=\"../\">../</a>"));
+        assert!(html.contains("<tr><td><a href=\"/test/foo.html\">foo.html</a></td><td>28</td>"));
+        assert!(html.contains("<tr><td><a href=\"/test/lang/\">lang/</a></td><td>-</td>"));
+
+        let bin = html.find("1k.bin").unwrap();
+        let foo = html.find("foo.html").unwrap();
+        assert!(bin < foo);
+
+        let root = Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test"));
+        assert!(render(&root, "nonexistent").is_none());
+        assert!(render(&root, "..").is_none());
+    }
+
+    #[test]
+    fn hidden_entries() {
+        let mut config = Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let names = |config: &Config| {
+            entries(config, "test/hidden").unwrap().into_iter().map(|e| e.name).collect::<Vec<_>>()
+        };
+        assert_eq!(names(&config), [".well-known", "visible.txt"]);
+
+        config.serve_hidden = true;
+        assert_eq!(names(&config), [".env", ".private", ".well-known", "visible.txt"]);
+    }
+
+    #[test]
+    fn json_listing() {
+        let json = render_json(&Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR"))), "test")
+            .unwrap();
+
+        assert!(json.starts_with("[{\"name\": \"1k.bin\", \"dir\": false, \"size\": 1024, "));
+        assert!(json.contains("{\"name\": \"lang\", \"dir\": true, \"size\": null, "));
+        assert!(json.ends_with("}]"));
+        assert_eq!(to_json(&[]), "[]");
+    }
+
+    #[test]
+    fn ordering() {
+        let mut names = vec!["file10", "File2", "file2", "file1", "Zebra", "apple", "file02",
+                             "ábc"];
+
+        names.sort_by(|a, b| compare_names(a, b, false));
+        assert_eq!(names,
+                   ["apple", "file02", "file1", "file10", "File2", "file2", "Zebra", "ábc"]);
+
+        names.sort_by(|a, b| compare_names(a, b, true));
+        assert_eq!(names,
+                   ["apple", "file1", "File2", "file02", "file2", "file10", "Zebra", "ábc"]);
+
+        // the same whatever order they started in
+        names.reverse();
+        names.sort_by(|a, b| compare_names(a, b, true));
+        assert_eq!(names,
+                   ["apple", "file1", "File2", "file02", "file2", "file10", "Zebra", "ábc"]);
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_html("<a href=\"x\">&'"), "&lt;a href=&quot;x&quot;&gt;&amp;&#39;");
+        assert_eq!(encode_href("/a b/ü?#.txt"), "/a%20b/%C3%BC%3F%23.txt");
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use mioco;
+
+/// (Slash-stripped) URI of the event stream the injected script listens to.
+pub const EVENTS_URI: &'static str = "__live-reload";
+
+/// Events are the same length whether or not anything changed, so a stream's Content-Length can
+/// be sent before we know which it'll be.
+pub const EVENT_LEN: usize = 14;
+const RELOAD: &'static [u8] = b"data: reload\n\n";
+const NO_CHANGE: &'static [u8] = b": no changes\n\n";
+
+const POLL_MS: u64 = 500;
+
+/// How long a stream waits for a change before ending, so that connections from pages which
+/// have gone away don't pile up. The browser reconnects on its own.
+const MAX_WAIT_SECS: u64 = 30;
+
+/// Most recent modification time of anything under a directory, in milliseconds since the Unix
+/// epoch, or 0 if it's empty or unreadable. Symlinks aren't followed.
+pub fn latest_change(dir: &Path) -> u64 {
+    let mut latest = 0;
+    walk(dir, &mut latest);
+    latest
+}
+
+fn walk(dir: &Path, latest: &mut u64) {
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(|e| e.ok()) {
+        let metadata = match fs::symlink_metadata(entry.path()) {
+            Ok(m) => m,
+            Err(_) => continue,
+        };
+
+        if let Ok(Ok(modified)) = metadata.modified().map(|m| m.duration_since(UNIX_EPOCH)) {
+            let millis = modified.as_secs() * 1000 + modified.subsec_nanos() as u64 / 1_000_000;
+            *latest = ::std::cmp::max(*latest, millis);
+        }
+
+        if metadata.is_dir() {
+            walk(&entry.path(), latest);
+        }
+    }
+}
+
+/// Script to append to served HTML, which reloads the page once anything under the root changes
+/// after `since` (as returned by `latest_change` when the page was served).
+pub fn script(since: u64) -> String {
+    format!("<script>new EventSource(\"/{}?since={}\").onmessage = function() {{ \
+             location.reload(); }};</script>\n",
+            EVENTS_URI,
+            since)
+}
+
+/// Body of an event stream: a single reload event, sent once something under the root has
+/// changed since `since`, or a comment if nothing has by the time the wait runs out.
+pub struct ChangeEvent {
+    root: PathBuf,
+    since: u64,
+    event: Option<Cursor<&'static [u8]>>,
+}
+
+impl ChangeEvent {
+    pub fn new(root: PathBuf, since: u64) -> Self {
+        ChangeEvent {
+            root: root,
+            since: since,
+            event: None,
+        }
+    }
+
+    fn wait(&self) -> &'static [u8] {
+        let deadline = Instant::now() + Duration::from_secs(MAX_WAIT_SECS);
+
+        loop {
+            if latest_change(&self.root) > self.since {
+                return RELOAD;
+            }
+
+            if Instant::now() >= deadline {
+                return NO_CHANGE;
+            }
+
+            mioco::sleep(Duration::from_millis(POLL_MS));
+        }
+    }
+}
+
+impl Read for ChangeEvent {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.event.is_none() {
+            self.event = Some(Cursor::new(self.wait()));
+        }
+
+        self.event.as_mut().unwrap().read(buf)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Read;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn events() {
+        assert_eq!(RELOAD.len(), EVENT_LEN);
+        assert_eq!(NO_CHANGE.len(), EVENT_LEN);
+
+        let dir = env::temp_dir().join(format!("hppt-live-reload-{}", ::std::process::id()));
+        create_dir_all(dir.join("sub")).unwrap();
+        assert!(latest_change(&dir) > 0);
+
+        let before = latest_change(&dir);
+        ::std::thread::sleep(Duration::from_millis(20));
+        File::create(dir.join("sub").join("page.html")).unwrap();
+        let after = latest_change(&dir);
+        assert!(after > before);
+
+        let mut event = Vec::new();
+        ChangeEvent::new(dir.clone(), before).read_to_end(&mut event).unwrap();
+        assert_eq!(event, RELOAD);
+
+        remove_dir_all(&dir).unwrap();
+
+        assert_eq!(latest_change(&PathBuf::from("/nonexistent")), 0);
+        assert!(script(123).contains("\"/__live-reload?since=123\""));
+    }
+}
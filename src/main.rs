@@ -1,43 +1,47 @@
-#[cfg(test)]
-#[macro_use]
-extern crate lazy_static;
-
 #[macro_use]
 extern crate log;
-#[macro_use]
-extern crate mioco;
 
-extern crate chrono;
 extern crate clap;
-extern crate env_logger;
-
-mod error;
-mod files;
-mod request;
-mod response;
-mod server;
+extern crate hppt;
+extern crate mioco;
 
-use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::env;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::str::FromStr;
-use std::sync::mpsc;
+use std::sync::{Arc, mpsc};
+use std::time::Duration;
 
-use chrono::Local;
-use clap::{App, Arg};
-use env_logger::LogBuilder;
-use log::{LogLevelFilter, LogRecord};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use mioco::tcp::TcpListener;
 
+use hppt::{crash, init_logging, mime, resources, rewrite, server, signals};
+use hppt::auth::PasswordHash;
+use hppt::cidr::Cidr;
+use hppt::config::{AuthRule, CacheRule, CanaryRoute, CgiDir, CgiInterpreter, CharsetSetting,
+                   Config, CorsMaxAge, CostClass, CostPath, ErrorPage, FastCgiRoute, MimeOverride,
+                   MirrorRoute, ProxyRoute, ResponseHeader, VirtualHost};
+use hppt::config_file;
+use hppt::config_file::{ConfigFile, Error};
+use hppt::request::ParseMode;
+use hppt::s3::{Credentials, S3};
+use hppt::snapshots::IndexSnapshot;
+use hppt::stats::ShutdownReason;
+
 fn main() {
     let args = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        // SERVER_ROOT can come from a --config file for `config export`, like it can for serving
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("SERVER_ROOT")
             .takes_value(true)
             .index(1)
             .help("Root directory from which to serve files.")
-            .required(true)
+            .required_unless("CONFIG")
             .validator(|s| {
                 let p = PathBuf::from(&s);
 
@@ -54,8 +58,13 @@ fn main() {
             .index(2)
             .help("Address and port to listen on.")
             .default_value("127.0.0.1:8080")
-            .required(true)
             .validator(|s| SocketAddr::from_str(&s).map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("CONFIG")
+            .takes_value(true)
+            .long("config")
+            .help("TOML file of settings, named like these flags (e.g. threads = 4, or \
+                   index-file = [\"index.html\"] for a repeatable one), plus root and listen for \
+                   SERVER_ROOT and LISTEN_ADDRESS. Flags given here take precedence over it."))
         .arg(Arg::with_name("NUM_THREADS")
             .takes_value(true)
             .long("threads")
@@ -65,52 +74,1021 @@ fn main() {
             .validator(|s| {
                 s.parse::<server::NThreads>().map(|_| ()).map_err(|e| format!("{:?}", e))
             }))
+        .arg(Arg::with_name("HEADER_TIMEOUT")
+            .takes_value(true)
+            .long("header-timeout")
+            .help("Seconds a client has to send a request's headers, from when we start waiting \
+                   for them, before it gets a 408 and the connection is closed (0 to wait \
+                   forever).")
+            .default_value("10")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("BODY_TIMEOUT")
+            .takes_value(true)
+            .long("body-timeout")
+            .help("Seconds a client has to send a request's body once its headers have arrived, \
+                   before it gets a 408 and the connection is closed (0 to wait forever).")
+            .default_value("30")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("WRITE_TIMEOUT")
+            .takes_value(true)
+            .long("write-timeout")
+            .help("Seconds a client has to accept a response before the connection is dropped \
+                   (0 to wait forever).")
+            .default_value("30")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("KEEP_ALIVE_TIMEOUT")
+            .takes_value(true)
+            .long("keep-alive-timeout")
+            .help("Seconds to wait for another request on a connection after a response (0 to \
+                   close the connection after every response).")
+            .default_value("5")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("KEEP_ALIVE_MAX")
+            .takes_value(true)
+            .long("keep-alive-max")
+            .help("Maximum number of requests served on one connection.")
+            .default_value("100")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(0) => Err("must be at least 1".to_owned()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{:?}", e)),
+            }))
+        .arg(Arg::with_name("RATE_LIMIT")
+            .takes_value(true)
+            .long("rate-limit")
+            .help("Maximum number of bytes per second to send a response's body at, so that one \
+                   big download can't saturate the uplink. 0 means no limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("MAX_CONNECTIONS")
+            .takes_value(true)
+            .long("max-connections")
+            .help("Maximum number of connections to have open at once; any more are answered \
+                   with a 503 and closed. 0 means no limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("MAX_CONNECTIONS_PER_IP")
+            .takes_value(true)
+            .long("max-connections-per-ip")
+            .help("Maximum number of connections one client address may have open at once; \
+                   any more are answered with a 503 and closed. 0 means no limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("REQUESTS_PER_SEC")
+            .takes_value(true)
+            .long("requests-per-sec")
+            .help("Maximum number of requests per second one client address may make, in bursts \
+                   of up to a second's worth; any more are answered with a 429. 0 means no \
+                   limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("MAX_CGI_OUTPUT")
+            .takes_value(true)
+            .long("max-cgi-output")
+            .help("Maximum number of bytes a CGI script may output for one request; a script \
+                   which outputs more is killed and answered for with a 502, or has its \
+                   response cut short if that's already on its way.")
+            .default_value("10485760")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(0) => Err("must be at least 1".to_owned()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{:?}", e)),
+            }))
+        .arg(Arg::with_name("CGI_TIMEOUT")
+            .takes_value(true)
+            .long("cgi-timeout")
+            .help("Seconds a CGI script has to finish before it's killed and answered for with a \
+                   504, or has its response cut short (0 to wait forever).")
+            .default_value("30")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("MAX_CGI_PROCESSES")
+            .takes_value(true)
+            .long("max-cgi-processes")
+            .help("Maximum number of CGI scripts to run at once; requests for any more are \
+                   answered with a 503. 0 means no limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("CGI_DIR")
+            .takes_value(true)
+            .long("cgi-dir")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Run the files in a directory as CGI scripts, [PREFIX=]DIR, where a relative \
+                   DIR is relative to SERVER_ROOT and is also the URI PREFIX if there's none, \
+                   e.g. cgi-bin or /scripts=/srv/cgi. Repeatable; replaces the default of \
+                   cgi-bin.")
+            .validator(|s| s.parse::<CgiDir>().map(|_| ())))
+        .arg(Arg::with_name("NO_CGI")
+            .long("no-cgi")
+            .conflicts_with("CGI_DIR")
+            .help("Don't run CGI scripts; requests for them are answered with a 404."))
+        .arg(Arg::with_name("CGI_INTERPRETER")
+            .takes_value(true)
+            .long("cgi-interpreter")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Run CGI scripts with an extension with a program, EXT=PROGRAM, e.g. \
+                   py=/usr/bin/python3, for scripts without a shebang line or permission to \
+                   execute. Repeatable.")
+            .validator(|s| s.parse::<CgiInterpreter>().map(|_| ())))
+        .arg(Arg::with_name("FASTCGI")
+            .takes_value(true)
+            .long("fastcgi")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Forward requests under a URI prefix to a FastCGI application server, \
+                   PREFIX=ADDRESS, e.g. /php=127.0.0.1:9000 or /app=unix:/run/php-fpm.sock. \
+                   Repeatable.")
+            .validator(|s| s.parse::<FastCgiRoute>().map(|_| ())))
+        .arg(Arg::with_name("MAX_FASTCGI_REQUESTS")
+            .takes_value(true)
+            .long("max-fastcgi-requests")
+            .help("Maximum number of requests to have open with FastCGI applications at once; \
+                   any more are answered with a 503. 0 means no limit.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("PROXY")
+            .takes_value(true)
+            .long("proxy")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Forward requests under a URI prefix to an upstream HTTP server, PREFIX=URL, \
+                   e.g. /api=http://127.0.0.1:3000, adding X-Forwarded-For and \
+                   X-Forwarded-Host. Or share them between several by weight, \
+                   \"PREFIX=URL WEIGHT, URL WEIGHT...\", e.g. \"/api=http://10.0.0.1:3000 95, \
+                   http://10.0.0.2:3000 5\". Repeatable.")
+            .validator(|s| s.parse::<ProxyRoute>().map(|_| ())))
+        .arg(Arg::with_name("CANARY")
+            .takes_value(true)
+            .long("canary")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Send a share of the requests proxied under a URI prefix, and any with a \
+                   header of some value, to a canary upstream in place of the route's own, \
+                   \"PREFIX=URL [PERCENT%] [NAME:VALUE]\", e.g. \
+                   \"/api=http://127.0.0.1:3001 5% X-Canary:1\". Repeatable.")
+            .validator(|s| s.parse::<CanaryRoute>().map(|_| ())))
+        .arg(Arg::with_name("MIRROR")
+            .takes_value(true)
+            .long("mirror")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Copy a share of the requests proxied under a URI prefix to a shadow upstream, \
+                   \"PREFIX=URL [PERCENT%]\", e.g. \"/api=http://127.0.0.1:4000 10%\", without \
+                   waiting on it or passing on its answers. Repeatable.")
+            .validator(|s| s.parse::<MirrorRoute>().map(|_| ())))
+        .arg(Arg::with_name("UPSTREAM_TIMEOUT")
+            .takes_value(true)
+            .long("upstream-timeout")
+            .help("Seconds an upstream server behind --proxy, a --fastcgi application, or the \
+                   --s3-bucket store has to accept a connection, or to take or send the next \
+                   piece of a request or its answer, before it's answered for with a 504, or \
+                   has its response cut short (0 to wait forever).")
+            .default_value("30")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("BREAKER_THRESHOLD")
+            .takes_value(true)
+            .long("breaker-threshold")
+            .help("Failures in a row (no answer, or a 502, 503 or 504) an upstream server behind \
+                   --proxy may have before requests for its route are answered with a 503 \
+                   straight away, rather than left waiting on it. 0 means never.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("BREAKER_COOLDOWN")
+            .takes_value(true)
+            .long("breaker-cooldown")
+            .help("Seconds a route answered for with 503s by --breaker-threshold waits before \
+                   one request is let through to see whether its upstream has recovered, and \
+                   between such requests after.")
+            .default_value("10")
+            .validator(|s| match s.parse::<u64>() {
+                Ok(0) => Err("must be at least 1".to_owned()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{:?}", e)),
+            }))
+        .arg(Arg::with_name("REWRITE")
+            .takes_value(true)
+            .long("rewrite")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Rewrite the paths of requests matching a pattern, \"PATTERN -> TARGET\", or \
+                   redirect them, \"PATTERN -> STATUS TARGET\" with a 301, 302, 307 or 308, \
+                   e.g. \"/old/* -> /new/*\". A * at the end of a pattern matches the rest of \
+                   the path, and stands for it in the target. The request's query is added to \
+                   the target's, or with a last word of query=drop left out, or with \
+                   query=replace used instead. The first matching rule applies. Repeatable.")
+            .validator(|s| s.parse::<rewrite::Rule>().map(|_| ())))
+        .arg(Arg::with_name("SHUTDOWN_GRACE")
+            .takes_value(true)
+            .long("shutdown-grace")
+            .help("Seconds open connections get to finish after a SIGINT or SIGTERM, before \
+                   they're closed and the server exits. A second signal exits straight away.")
+            .default_value("10")
+            .validator(|s| s.parse::<u64>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("TRUSTED_PROXY")
+            .takes_value(true)
+            .long("trusted-proxy")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Address of a reverse proxy, which many clients may be sharing, to exempt from \
+                   --max-connections-per-ip and --requests-per-sec. Repeatable.")
+            .validator(|s| s.parse::<IpAddr>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("ALLOW")
+            .takes_value(true)
+            .long("allow")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only serve clients with addresses in a block, e.g. 10.0.0.0/8 or 2001:db8::/32 \
+                   (or a lone address), answering any others with a 403. Repeatable.")
+            .validator(|s| s.parse::<Cidr>().map(|_| ())))
+        .arg(Arg::with_name("DENY")
+            .takes_value(true)
+            .long("deny")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Answer clients with addresses in a block with a 403, without reading their \
+                   requests. Repeatable; where --allow and --deny blocks overlap, the more \
+                   specific one decides.")
+            .validator(|s| s.parse::<Cidr>().map(|_| ())))
+        .arg(Arg::with_name("COST_CLASS")
+            .takes_value(true)
+            .long("cost-class")
+            .multiple(true)
+            .number_of_values(1)
+            .help("A class of requests (see --cost-path), at most MAX of which are answered at \
+                   once and PER_SEC a second; any more are answered with a 503. NAME=MAX/PER_SEC, \
+                   with - for no limit, e.g. cgi=4/20 or static=256/-. Repeatable.")
+            .validator(|s| s.parse::<CostClass>().map(|_| ())))
+        .arg(Arg::with_name("COST_PATH")
+            .takes_value(true)
+            .long("cost-path")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Put requests under a directory, relative to the root, in a --cost-class, \
+                   DIR=CLASS, e.g. /cgi-bin=cgi. Repeatable; the deepest directory decides.")
+            .validator(|s| s.parse::<CostPath>().map(|_| ())))
+        .arg(Arg::with_name("MAX_REQUEST_SIZE")
+            .takes_value(true)
+            .long("max-request-size")
+            .help("Maximum size in bytes of a request, head and body together; bigger ones are \
+                   answered with a 413.")
+            .default_value("65536")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("MAX_HEADERS")
+            .takes_value(true)
+            .long("max-headers")
+            .help("Maximum number of header lines accepted in a request.")
+            .default_value("100")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("STRICT_HTTP")
+            .long("strict-http")
+            .help("Reject requests which don't follow the HTTP spec exactly (bare LF line endings, \
+                   a missing Host header, malformed header lines...) instead of tolerating them."))
+        .arg(Arg::with_name("EMPTY_SEGMENTS")
+            .takes_value(true)
+            .long("empty-segments")
+            .help("How to treat request paths with empty segments like //foo//bar: \"collapse\" \
+                   them and serve foo/bar, or \"reject\" the request with a 400.")
+            .default_value("collapse")
+            .possible_values(&["collapse", "reject"]))
+        .arg(Arg::with_name("TRAILING_SLASH")
+            .takes_value(true)
+            .long("trailing-slash")
+            .help("Whether to \"redirect\" requests for directories without a trailing slash \
+                   to the path with one, so relative links in their index pages work; to do \
+                   that and redirect requests for files with a trailing slash to the path \
+                   without, \"canonical\"; or neither, \"off\".")
+            .default_value("redirect")
+            .possible_values(&["redirect", "canonical", "off"]))
+        .arg(Arg::with_name("INDEX_FILE")
+            .takes_value(true)
+            .long("index-file")
+            .multiple(true)
+            .number_of_values(1)
+            .help("File to serve for a request naming a directory, tried in the order given \
+                   (defaults to index.html, then index.htm)."))
+        .arg(Arg::with_name("AUTOINDEX")
+            .long("autoindex")
+            .help("List the contents of directories which don't have an index file, rather than \
+                   answering with a 404."))
+        .arg(Arg::with_name("SERVE_HIDDEN")
+            .long("serve-hidden")
+            .help("Serve and list files and directories whose names start with a dot, like .git \
+                   and .env, rather than answering with a 404 as if they weren't there."))
+        .arg(Arg::with_name("ALLOW_HIDDEN")
+            .takes_value(true)
+            .long("allow-hidden")
+            .multiple(true)
+            .number_of_values(1)
+            .conflicts_with("SERVE_HIDDEN")
+            .help("A name starting with a dot to serve anyway. Repeatable; replaces the default \
+                   of .well-known."))
+        .arg(Arg::with_name("FOLLOW_SYMLINKS")
+            .long("follow-symlinks")
+            .help("Serve files and directories reached through symlinks, like current -> \
+                   releases/123, as long as they lead somewhere inside SERVER_ROOT. Otherwise \
+                   anything reached through a symlink is a 404."))
+        .arg(Arg::with_name("NATURAL_SORT")
+            .long("natural-sort")
+            .help("Put numbers in names in a directory listing in order of their value, so \
+                   file2 comes before file10."))
+        .arg(Arg::with_name("INDEX_SNAPSHOT")
+            .takes_value(true)
+            .long("index-snapshot")
+            .multiple(true)
+            .number_of_values(1)
+            .help("List a directory (relative to SERVER_ROOT) and every directory under it from \
+                   snapshots in --snapshot-dir refreshed every so many seconds, DIR=SECS, e.g. \
+                   /pub=300, rather than scanning them on every request. Repeatable.")
+            .validator(|s| s.parse::<IndexSnapshot>().map(|_| ())))
+        .arg(Arg::with_name("SNAPSHOT_DIR")
+            .takes_value(true)
+            .long("snapshot-dir")
+            .help("Directory to keep the snapshots of --index-snapshot directories' listings in.")
+            .validator(|s| if PathBuf::from(&s).is_dir() {
+                Ok(())
+            } else {
+                Err(format!("{} is not a directory.", s))
+            }))
+        .arg(Arg::with_name("ARCHIVE_DOWNLOADS")
+            .long("archive-downloads")
+            .help("Let clients download a whole directory as a tar archive by adding \
+                   ?download=tar to its URL."))
+        .arg(Arg::with_name("META_QUERIES")
+            .long("meta-queries")
+            .help("Let clients ask for a file's size, modification time, ETag and MIME type as \
+                   JSON, without its contents, by adding ?meta to its URL."))
+        .arg(Arg::with_name("COMPRESS")
+            .long("compress")
+            .help("Gzip text files (HTML, plain text, Markdown, ...) for clients which accept \
+                   it."))
+        .arg(Arg::with_name("PRECOMPRESSED")
+            .long("precompressed")
+            .help("Serve FILE.br or FILE.gz, if there is one, in place of FILE to clients which \
+                   accept Brotli or gzip."))
+        .arg(Arg::with_name("CACHE_SIZE")
+            .takes_value(true)
+            .long("cache-size")
+            .help("Maximum number of bytes of small files to keep in memory, so popular ones are \
+                   served without reading them from disk every time. 0 means no cache.")
+            .default_value("0")
+            .validator(|s| s.parse::<usize>().map(|_| ()).map_err(|e| format!("{:?}", e))))
+        .arg(Arg::with_name("LIVE_RELOAD")
+            .long("live-reload")
+            .help("For local development: make served HTML pages reload themselves whenever \
+                   anything under SERVER_ROOT changes."))
+        .arg(Arg::with_name("ADMIN_ENDPOINT")
+            .long("admin-endpoint")
+            .help("Serve the most recent warnings and errors as JSON at /__admin/errors, and \
+                   request and compression totals at /__admin/stats, to clients connecting \
+                   over the loopback interface."))
+        .arg(Arg::with_name("CHECKSUM_DIR")
+            .takes_value(true)
+            .long("checksum-dir")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Directory (relative to SERVER_ROOT, \"/\" for all) in which a request for \
+                   file.sha256 is answered with a SHA-256 checksum of file, if there's no such \
+                   sidecar on disk."))
+        .arg(Arg::with_name("LANGUAGE_DIR")
+            .takes_value(true)
+            .long("language-dir")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Directory (relative to SERVER_ROOT, \"/\" for all) in which to serve \
+                   page.html.en/page.html.de variants negotiated from Accept-Language."))
+        .arg(Arg::with_name("DEFAULT_LANGUAGE")
+            .takes_value(true)
+            .long("default-language")
+            .help("Language variant to serve when none match the client's Accept-Language."))
+        .arg(Arg::with_name("MIME_TYPE")
+            .takes_value(true)
+            .long("mime-type")
+            .alias("mime")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Serve files with an extension as a MIME type, [DIR:]EXT=TYPE, e.g. \
+                   map=application/json. Scoped to DIR (relative to SERVER_ROOT) if given.")
+            .validator(|s| s.parse::<MimeOverride>().map(|_| ())))
+        .arg(Arg::with_name("MIME_TYPES")
+            .takes_value(true)
+            .long("mime-types")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Serve files with the MIME types mapped in a mime.types file (of \"TYPE EXT...\" \
+                   lines, as in /etc/mime.types), over the built-in ones but not --mime-type. \
+                   Repeatable, with later files taking precedence."))
+        .arg(Arg::with_name("CHARSET")
+            .takes_value(true)
+            .long("charset")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Charset to declare on text responses, [DIR:]CHARSET, e.g. utf-8. Scoped to \
+                   DIR (relative to SERVER_ROOT) if given.")
+            .validator(|s| s.parse::<CharsetSetting>().map(|_| ())))
+        .arg(Arg::with_name("CACHE_CONTROL")
+            .takes_value(true)
+            .long("cache-control")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Send a Cache-Control header with files matching any of the patterns, each *.EXT \
+                   or a path relative to SERVER_ROOT (\"/\" for all), and an Expires header to \
+                   go with any max-age: PATTERN[,PATTERN...]=DIRECTIVES, e.g. \
+                   \"*.css,*.js=max-age=86400\". Repeatable; the first rule matching a path \
+                   applies.")
+            .validator(|s| s.parse::<CacheRule>().map(|_| ())))
+        .arg(Arg::with_name("HEADER")
+            .takes_value(true)
+            .long("header")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Send a header with every response, \"NAME: VALUE\", e.g. \
+                   \"X-Content-Type-Options: nosniff\", unless the response has one of its \
+                   own. Repeatable, and a name given more than once is sent with each value.")
+            .validator(|s| s.parse::<ResponseHeader>().map(|_| ())))
+        .arg(Arg::with_name("NO_SERVER_HEADER")
+            .long("no-server-header")
+            .help("Don't send a Server header naming hppt and its version. One given with \
+                   --header is sent in its place either way."))
+        .arg(Arg::with_name("ERROR_PAGE")
+            .takes_value(true)
+            .long("error-page")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Serve a page (relative to SERVER_ROOT) as the body of error responses with a \
+                   status code, CODE=PAGE, e.g. 404=/errors/404.html. Repeatable.")
+            .validator(|s| s.parse::<ErrorPage>().map(|_| ())))
+        .arg(Arg::with_name("API_PREFIX")
+            .takes_value(true)
+            .long("api-prefix")
+            .multiple(true)
+            .number_of_values(1)
+            .help("URI prefix of an API, whose error responses get RFC 7807 \
+                   application/problem+json bodies in place of error pages. Repeatable."))
+        .arg(Arg::with_name("CORS_ORIGIN")
+            .takes_value(true)
+            .long("cors-origin")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Let pages from an origin, e.g. http://localhost:3000, or from any origin if \
+                   it's *, read files served with GET or HEAD, answering browsers' preflight \
+                   requests for them. Repeatable."))
+        .arg(Arg::with_name("CORS_MAX_AGE")
+            .takes_value(true)
+            .long("cors-max-age")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Seconds browsers may keep the answers to preflight requests under a URI \
+                   prefix before asking again, PREFIX=SECONDS, e.g. /api=600. Repeatable.")
+            .validator(|s| s.parse::<CorsMaxAge>().map(|_| ())))
+        .arg(Arg::with_name("AUTH")
+            .takes_value(true)
+            .long("auth")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Only let a user (with HTTP Basic authentication) make requests under a URI \
+                   prefix, PREFIX=USER:HASH, where HASH is the salted hash of their password \
+                   printed by `hash-password`. Repeatable, for more users or prefixes; under \
+                   nested prefixes, only the users of the longest count.")
+            .validator(|s| s.parse::<AuthRule>().map(|_| ())))
+        .arg(Arg::with_name("VHOST")
+            .takes_value(true)
+            .long("vhost")
+            .multiple(true)
+            .number_of_values(1)
+            .help("Serve requests for a host name from a root directory of its own, HOST=DIR, \
+                   e.g. blog.example.com=/srv/blog. Repeatable.")
+            .validator(|s| match s.parse::<VirtualHost>() {
+                Ok(ref vhost) if vhost.root.is_dir() => Ok(()),
+                Ok(vhost) => Err(format!("{} is not a directory.", vhost.root.display())),
+                Err(why) => Err(why),
+            }))
+        .arg(Arg::with_name("UNKNOWN_HOST")
+            .takes_value(true)
+            .long("unknown-host")
+            .help("With --vhost, whether requests for any other host are served from SERVER_ROOT \
+                   (\"default\") or answered with a 421 (\"reject\").")
+            .default_value("default")
+            .possible_values(&["default", "reject"]))
+        .arg(Arg::with_name("S3_BUCKET")
+            .takes_value(true)
+            .long("s3-bucket")
+            .requires("S3_ENDPOINT")
+            .help("Serve files from this bucket of an S3-compatible store rather than from \
+                   SERVER_ROOT, which CGI scripts are still run from. Requests are signed with \
+                   the credentials in AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY."))
+        .arg(Arg::with_name("S3_ENDPOINT")
+            .takes_value(true)
+            .long("s3-endpoint")
+            .help("HOST[:PORT] of the S3-compatible store, which is reached over plain HTTP."))
+        .arg(Arg::with_name("S3_REGION")
+            .takes_value(true)
+            .long("s3-region")
+            .default_value("us-east-1")
+            .help("Region the S3 bucket is in, as requests are signed for it."))
+        .arg(Arg::with_name("TLS_CERT")
+            .takes_value(true)
+            .long("tls-cert")
+            .requires("TLS_KEY")
+            .help("PEM file with the certificate (followed by any intermediates) to serve HTTPS \
+                   with, rather than plain HTTP."))
+        .arg(Arg::with_name("TLS_KEY")
+            .takes_value(true)
+            .long("tls-key")
+            .requires("TLS_CERT")
+            .help("PEM file with the private key for --tls-cert."))
+        .arg(Arg::with_name("ACCESS_LOG")
+            .takes_value(true)
+            .long("access-log")
+            .help("File to log each request to in the Combined Log Format (followed by the \
+                   microseconds it took), or - for stdout."))
+        .arg(Arg::with_name("RECORD_DIR")
+            .takes_value(true)
+            .long("record-dir")
+            .help("For debugging: save a copy of each response, exactly as it was sent, to a \
+                   file in this directory.")
+            .validator(|s| if PathBuf::from(&s).is_dir() {
+                Ok(())
+            } else {
+                Err(format!("{} is not a directory.", s))
+            }))
+        .arg(Arg::with_name("RECORD")
+            .takes_value(true)
+            .long("record")
+            .multiple(true)
+            .number_of_values(1)
+            .requires("RECORD_DIR")
+            .help("Only record responses for paths matching this glob (e.g. docs/*.html, where * \
+                   stays within a directory and ** doesn't). Repeatable."))
+        .arg(Arg::with_name("RECORD_EVERY")
+            .takes_value(true)
+            .long("record-every")
+            .help("Only record one in every this many of the responses which match.")
+            .default_value("1")
+            .validator(|s| match s.parse::<usize>() {
+                Ok(0) => Err("must be at least 1".to_owned()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{:?}", e)),
+            }))
+        .arg(Arg::with_name("RAISE_FD_LIMIT")
+            .long("raise-fd-limit")
+            .help("Raise the soft limit on open files to the hard limit at startup."))
+        .arg(Arg::with_name("CRASH_DIR")
+            .takes_value(true)
+            .long("crash-dir")
+            .help("Directory to write a report (version, config hash, recent errors and a \
+                   backtrace) to if the server crashes.")
+            .validator(|s| if PathBuf::from(&s).is_dir() {
+                Ok(())
+            } else {
+                Err(format!("{} is not a directory.", s))
+            }))
         .arg(Arg::with_name("VERBOSE")
             .short("v")
             .long("verbose")
             .help("Enable debug-level logging."))
+        .subcommand(SubCommand::with_name("config")
+            .about("Inspect the configuration the other arguments resolve to.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("export")
+                .about("Print every setting, after merging flags, --config file and defaults, for \
+                        auditing or comparing servers.")
+                .arg(Arg::with_name("JSON")
+                    .long("json")
+                    .required(true)
+                    .help("Print as a JSON object (the only format so far)."))))
+        .subcommand(SubCommand::with_name("hash-password")
+            .about("Read a password from a line of stdin and print a salted hash of it for \
+                    --auth or a user file."))
         .get_matches();
 
-    init_logging(args.is_present("VERBOSE"));
+    if args.subcommand_matches("hash-password").is_some() {
+        init_logging(false);
+        let mut password = String::new();
+        let hash = io::stdin()
+            .read_line(&mut password)
+            .and_then(|_| PasswordHash::generate(password.trim_end_matches(&['\r', '\n'][..])));
+        match hash {
+            Ok(hash) => println!("{}", hash),
+            Err(why) => {
+                error!("Unable to hash a password: {}", why);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let file = args.value_of("CONFIG").map(|path| ConfigFile::load(Path::new(path)));
+
+    // the file may ask for debug logging, but any problem with it needs logging regardless
+    let verbose = match file {
+        Some(Ok(ref file)) => file.boolean("verbose"),
+        _ => Ok(None),
+    };
+    init_logging(args.is_present("VERBOSE") || verbose.as_ref().ok() == Some(&Some(true)));
+    or_exit(verbose);
+    let file = file.map(or_exit);
+
+    let file_string = |key| match file {
+        Some(ref file) => or_exit(file.string(key)),
+        None => None,
+    };
 
     // these have already been validated by the clap validators, and are required arguments
-    let num_threads = args.value_of("NUM_THREADS").unwrap().parse::<server::NThreads>().unwrap();
+    let file_listen = match file {
+        Some(ref file) => or_exit(file.listen()),
+        None => None,
+    };
+    let listen_addr = match (given(&args, "LISTEN_ADDRESS"), file_listen) {
+        (None, Some(addr)) => addr,
+        _ => SocketAddr::from_str(&args.value_of("LISTEN_ADDRESS").unwrap()).unwrap(),
+    };
 
-    let listen_addr = SocketAddr::from_str(&args.value_of("LISTEN_ADDRESS").unwrap()).unwrap();
+    // clap makes sure there's a config file to look in if the argument's missing
+    let content_dir = match args.value_of("SERVER_ROOT") {
+        Some(root) => PathBuf::from(root),
+        None => {
+            match file.as_ref().and_then(|file| or_exit(file.root())) {
+                Some(root) => root,
+                None => {
+                    error!("No SERVER_ROOT given, and no root in the config file");
+                    process::exit(1);
+                }
+            }
+        }
+    };
 
-    let content_dir = PathBuf::from(&args.value_of("SERVER_ROOT").unwrap());
+    let mut config = Config::new(content_dir);
+    if let Some(ref file) = file {
+        or_exit(file.apply(&mut config));
+    }
 
-    let (_, recv) = mpsc::channel();
+    if let Some(n) = given(&args, "NUM_THREADS") {
+        config.num_threads = n.parse::<server::NThreads>().unwrap();
+    }
+    if let Some(s) = given(&args, "EMPTY_SEGMENTS") {
+        config.empty_segments = s.parse().unwrap();
+    }
+    if let Some(s) = given(&args, "TRAILING_SLASH") {
+        config.trailing_slash = s.parse().unwrap();
+    }
+    if let Some(n) = given(&args, "KEEP_ALIVE_MAX") {
+        config.limits.keep_alive_max = n.parse::<usize>().unwrap();
+    }
+    if args.is_present("STRICT_HTTP") {
+        config.parse_mode = ParseMode::Strict;
+    }
+    if let Some(n) = given(&args, "CACHE_SIZE") {
+        config.cache_size = cap(n);
+    }
+    if let Some(n) = given(&args, "RATE_LIMIT") {
+        config.limits.rate_limit = cap(n);
+    }
+    if let Some(n) = given(&args, "MAX_CONNECTIONS") {
+        config.limits.max_connections = cap(n);
+    }
+    if let Some(n) = given(&args, "MAX_CONNECTIONS_PER_IP") {
+        config.limits.max_connections_per_ip = cap(n);
+    }
+    if let Some(n) = given(&args, "REQUESTS_PER_SEC") {
+        config.limits.requests_per_sec = cap(n);
+    }
+    if let Some(proxies) = args.values_of("TRUSTED_PROXY") {
+        config.trusted_proxies = proxies.map(|p| p.parse().unwrap()).collect();
+    }
+    if let Some(blocks) = args.values_of("ALLOW") {
+        config.allow = blocks.map(|b| b.parse().unwrap()).collect();
+    }
+    if let Some(blocks) = args.values_of("DENY") {
+        config.deny = blocks.map(|b| b.parse().unwrap()).collect();
+    }
+    if let Some(classes) = args.values_of("COST_CLASS") {
+        config.cost_classes = classes.map(|c| c.parse().unwrap()).collect();
+    }
+    if let Some(paths) = args.values_of("COST_PATH") {
+        config.cost_paths = paths.map(|p| p.parse().unwrap()).collect();
+    }
+    if let Some(n) = given(&args, "MAX_REQUEST_SIZE") {
+        config.limits.max_request_size = n.parse::<usize>().unwrap();
+    }
+    if let Some(n) = given(&args, "MAX_HEADERS") {
+        config.limits.max_headers = n.parse::<usize>().unwrap();
+    }
+    if let Some(secs) = given(&args, "HEADER_TIMEOUT") {
+        config.limits.header_timeout = timeout(secs);
+    }
+    if let Some(secs) = given(&args, "BODY_TIMEOUT") {
+        config.limits.body_timeout = timeout(secs);
+    }
+    if let Some(secs) = given(&args, "WRITE_TIMEOUT") {
+        config.limits.write_timeout = timeout(secs);
+    }
+    if let Some(secs) = given(&args, "KEEP_ALIVE_TIMEOUT") {
+        config.limits.keep_alive_timeout = timeout(secs);
+    }
+    if let Some(n) = given(&args, "MAX_CGI_OUTPUT") {
+        config.limits.max_cgi_output = n.parse::<usize>().unwrap();
+    }
+    if let Some(secs) = given(&args, "CGI_TIMEOUT") {
+        config.limits.cgi_timeout = timeout(secs);
+    }
+    if let Some(n) = given(&args, "MAX_CGI_PROCESSES") {
+        config.limits.max_cgi_processes = cap(n);
+    }
+    if let Some(dirs) = args.values_of("CGI_DIR") {
+        config.cgi_dirs = dirs.map(|d| d.parse().unwrap()).collect();
+    }
+    if args.is_present("NO_CGI") {
+        config.cgi = false;
+    }
+    if let Some(interpreters) = args.values_of("CGI_INTERPRETER") {
+        config.cgi_interpreters = interpreters.map(|i| i.parse().unwrap()).collect();
+    }
+    if let Some(routes) = args.values_of("FASTCGI") {
+        config.fastcgi = routes.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(n) = given(&args, "MAX_FASTCGI_REQUESTS") {
+        config.limits.max_fastcgi_requests = cap(n);
+    }
+    if let Some(routes) = args.values_of("PROXY") {
+        config.proxies = routes.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(routes) = args.values_of("CANARY") {
+        config.canaries = routes.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(routes) = args.values_of("MIRROR") {
+        config.mirrors = routes.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(secs) = given(&args, "UPSTREAM_TIMEOUT") {
+        config.limits.upstream_timeout = timeout(secs);
+    }
+    if let Some(n) = given(&args, "BREAKER_THRESHOLD") {
+        config.limits.breaker_threshold = cap(n);
+    }
+    if let Some(secs) = given(&args, "BREAKER_COOLDOWN") {
+        config.limits.breaker_cooldown = Duration::from_secs(secs.parse::<u64>().unwrap());
+    }
+    if let Some(rules) = args.values_of("REWRITE") {
+        config.rewrites = rules.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(secs) = given(&args, "SHUTDOWN_GRACE") {
+        config.limits.shutdown_grace = Duration::from_secs(secs.parse::<u64>().unwrap());
+    }
 
-    // will block until exited or until shutdown queue is filled with num_threads items
+    if let Some(index_files) = args.values_of("INDEX_FILE") {
+        config.index_files = index_files.map(String::from).collect();
+    }
+    if let Some(names) = args.values_of("ALLOW_HIDDEN") {
+        config.allowed_hidden = names.map(String::from).collect();
+    }
 
+    // a flag given on the command line wins over the file, which decides when it's left out
+    if args.is_present("AUTOINDEX") {
+        config.autoindex = true;
+    }
+    if args.is_present("SERVE_HIDDEN") {
+        config.serve_hidden = true;
+    }
+    if args.is_present("FOLLOW_SYMLINKS") {
+        config.follow_symlinks = true;
+    }
+    if args.is_present("NATURAL_SORT") {
+        config.natural_sort = true;
+    }
+    if args.is_present("ARCHIVE_DOWNLOADS") {
+        config.archive_downloads = true;
+    }
+    if args.is_present("META_QUERIES") {
+        config.meta_queries = true;
+    }
+    if args.is_present("COMPRESS") {
+        config.compress = true;
+    }
+    if args.is_present("PRECOMPRESSED") {
+        config.precompressed = true;
+    }
+    if args.is_present("LIVE_RELOAD") {
+        config.live_reload = true;
+    }
+    if args.is_present("ADMIN_ENDPOINT") {
+        config.admin_endpoint = true;
+    }
+
+    if let Some(dirs) = args.values_of("CHECKSUM_DIR") {
+        config.checksum_dirs = dirs.map(String::from).collect();
+    }
+
+    if let Some(dirs) = args.values_of("LANGUAGE_DIR") {
+        config.language_dirs = dirs.map(String::from).collect();
+    }
+    if let Some(language) = args.value_of("DEFAULT_LANGUAGE") {
+        config.default_language = Some(language.to_owned());
+    }
+
+    // these have been validated by clap too
+    if let Some(overrides) = args.values_of("MIME_TYPE") {
+        config.mime_overrides = overrides.map(|o| o.parse().unwrap()).collect();
+    }
+    if let Some(paths) = args.values_of("MIME_TYPES") {
+        config.mime_types = paths.flat_map(|path| match mime::load_types(Path::new(path)) {
+                Ok(mappings) => mappings,
+                Err(why) => {
+                    error!("Invalid mime.types file {}", why);
+                    process::exit(1);
+                }
+            })
+            .collect();
+    }
+    if let Some(charsets) = args.values_of("CHARSET") {
+        config.charsets = charsets.map(|c| c.parse().unwrap()).collect();
+    }
+    if let Some(rules) = args.values_of("CACHE_CONTROL") {
+        config.cache_rules = rules.map(|r| r.parse().unwrap()).collect();
+    }
+    if let Some(headers) = args.values_of("HEADER") {
+        config.headers = headers.map(|h| h.parse().unwrap()).collect();
+    }
+    if args.is_present("NO_SERVER_HEADER") {
+        config.server_header = false;
+    }
+
+    if let Some(pages) = args.values_of("ERROR_PAGE") {
+        config.error_pages = pages.map(|p| p.parse().unwrap()).collect();
+    }
+    if let Some(prefixes) = args.values_of("API_PREFIX") {
+        config.api_prefixes = prefixes.map(String::from).collect();
+    }
+    if let Some(origins) = args.values_of("CORS_ORIGIN") {
+        config.cors_origins = origins.map(String::from).collect();
+    }
+    if let Some(max_ages) = args.values_of("CORS_MAX_AGE") {
+        config.cors_max_ages = max_ages.map(|m| m.parse().unwrap()).collect();
+    }
+    if let Some(rules) = args.values_of("AUTH") {
+        config.auth_rules = rules.map(|r| r.parse().unwrap()).collect();
+    }
+
+    if let Some(vhosts) = args.values_of("VHOST") {
+        config.vhosts = vhosts.map(|v| v.parse().unwrap()).collect();
+    }
+    if let Some(policy) = given(&args, "UNKNOWN_HOST") {
+        config.unknown_host = policy.parse().unwrap();
+    }
+
+    // the source was made before anything said to follow symlinks
+    if config.follow_symlinks {
+        config = config.with_root(config.root_dir.clone());
+    }
+
+    if let Some(bucket) = args.value_of("S3_BUCKET").map(String::from)
+        .or_else(|| file_string("s3-bucket")) {
+        let endpoint = match args.value_of("S3_ENDPOINT").map(String::from)
+            .or_else(|| file_string("s3-endpoint")) {
+            Some(endpoint) => endpoint,
+            None => {
+                error!("An S3 bucket needs an endpoint to go with it");
+                process::exit(1);
+            }
+        };
+        let region = match (given(&args, "S3_REGION"), file_string("s3-region")) {
+            (None, Some(region)) => region,
+            _ => args.value_of("S3_REGION").unwrap().to_owned(),
+        };
+
+        let credentials = match (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+            (Ok(access_key), Ok(secret_key)) => {
+                Credentials {
+                    access_key: access_key,
+                    secret_key: secret_key,
+                }
+            }
+            _ => {
+                error!("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set to use S3");
+                process::exit(1);
+            }
+        };
+
+        let timeout = config.limits.upstream_timeout;
+        config.source = Arc::new(S3::new(endpoint, bucket, region, credentials, timeout));
+    }
+
+    if let Some(cert) = args.value_of("TLS_CERT") {
+        config.tls_cert = Some(PathBuf::from(cert));
+    }
+    if let Some(key) = args.value_of("TLS_KEY") {
+        config.tls_key = Some(PathBuf::from(key));
+    }
+    if config.tls_cert.is_some() != config.tls_key.is_some() {
+        error!("A TLS certificate and key are needed together");
+        process::exit(1);
+    }
+
+    if let Some(path) = args.value_of("ACCESS_LOG") {
+        config.access_log = Some(PathBuf::from(path));
+    }
+    if let Some(snapshots) = args.values_of("INDEX_SNAPSHOT") {
+        config.index_snapshots = snapshots.map(|s| s.parse().unwrap()).collect();
+    }
+    if let Some(dir) = args.value_of("SNAPSHOT_DIR") {
+        config.snapshot_dir = Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = args.value_of("RECORD_DIR") {
+        config.record_dir = Some(PathBuf::from(dir));
+    }
+    if let Some(patterns) = args.values_of("RECORD") {
+        config.record_patterns = patterns.map(String::from).collect();
+    }
+    if let Some(n) = given(&args, "RECORD_EVERY") {
+        config.record_every = n.parse::<usize>().unwrap();
+    }
+    if let Some(dir) = args.value_of("CRASH_DIR") {
+        config.crash_dir = Some(PathBuf::from(dir));
+    }
+    if args.is_present("RAISE_FD_LIMIT") {
+        config.raise_fd_limit = true;
+    }
+
+    // clap has checked each of these on its own, but not whether they make sense together
+    if let Err(why) = config.limits.validate() {
+        error!("Invalid limits: {}", why);
+        process::exit(1);
+    }
+    if !config.index_snapshots.is_empty() && config.snapshot_dir.is_none() {
+        error!("Index snapshots need a --snapshot-dir to be kept in");
+        process::exit(1);
+    }
+    for path in &config.cost_paths {
+        if !config.cost_classes.iter().any(|c| c.name == path.class) {
+            error!("--cost-path {} is for a class with no --cost-class", path);
+            process::exit(1);
+        }
+    }
+
+    let export = args.subcommand_matches("config").and_then(|c| c.subcommand_matches("export"));
+    if export.is_some() {
+        // --json is required, as the only format
+        println!("{}", config_file::to_json(&config, &listen_addr));
+        return;
+    }
+
+    resources::check(&config.limits,
+                     !config.mirrors.is_empty(),
+                     config.cache_size,
+                     config.raise_fd_limit);
+    crash::install_hook();
+
+    // the providers (and with them, what they've loaded) are shared with every virtual host
+    let reloading = config.clone();
+    let (send, recv) = mpsc::channel();
+    if let Err(why) = signals::forward(send, move || reloading.reload_auth()) {
+        error!("Unable to handle signals: {:?}", why);
+        process::exit(1);
+    }
+
+    // will block until a signal's been handled, or something goes wrong
     let listener = TcpListener::bind(&listen_addr).unwrap();
-    match server::run(listener, &content_dir, recv, num_threads) {
-        Ok(()) => (),
-        Err(why) => error!("Error running server: {:?}", why),
+    match server::run(listener, config, recv) {
+        Ok(ShutdownReason::Fatal(_)) => process::exit(1),
+        Ok(_) => (),
+        Err(why) => {
+            error!("Error running server: {:?}", why);
+            process::exit(1);
+        }
     }
 }
 
-pub fn init_logging(verbose: bool) {
-    let level = if verbose {
-        LogLevelFilter::Debug
+/// A flag's value if it was given, rather than left at its default, which shouldn't override a
+/// setting from the config file.
+fn given<'a>(args: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    if args.occurrences_of(name) > 0 {
+        args.value_of(name)
     } else {
-        LogLevelFilter::Info
-    };
+        None
+    }
+}
+
+/// A number of seconds to wait, where 0 is forever.
+fn timeout(secs: &str) -> Option<Duration> {
+    match secs.parse::<u64>().unwrap() {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// A limit, where 0 is none.
+fn cap(n: &str) -> Option<usize> {
+    match n.parse::<usize>().unwrap() {
+        0 => None,
+        n => Some(n),
+    }
+}
 
-    let init_result = LogBuilder::new()
-        .filter(None, level)
-        .format(|record: &LogRecord| {
-            format!("[{} {} {}] {}",
-                    record.level(),
-                    Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
-                    record.location().module_path(),
-                    record.args())
-        })
-        .init();
-
-    match init_result {
-        Ok(_) => debug!("Initialized logging."),
-        Err(why) => println!("Unable to initialize logging: {:?}", why),
+fn or_exit<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(why) => {
+            error!("Invalid config file {}", why);
+            process::exit(1);
+        }
     }
 }
@@ -0,0 +1,172 @@
+//! What MIME type to serve a file as, going by its extension: a built-in table of the types a
+//! website is likely to have, which `mime.types` files (in the format of `/etc/mime.types`) and
+//! `--mime-type` can add to or override.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use config::MimeOverride;
+
+/// Lowercase extensions and their types, sorted by extension so they can be binary searched.
+static TYPES: &'static [(&'static str, &'static str)] = &[
+    ("aac", "audio/aac"),
+    ("apng", "image/apng"),
+    ("atom", "application/atom+xml"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("bz2", "application/x-bzip2"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("eot", "application/vnd.ms-fontobject"),
+    ("epub", "application/epub+zip"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("ics", "text/calendar"),
+    ("jar", "application/java-archive"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("jsonld", "application/ld+json"),
+    ("m4a", "audio/mp4"),
+    ("m4v", "video/mp4"),
+    ("map", "application/json"),
+    ("md", "text/markdown"),
+    ("mid", "audio/midi"),
+    ("midi", "audio/midi"),
+    ("mjs", "text/javascript"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("mpeg", "video/mpeg"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("oga", "audio/ogg"),
+    ("ogg", "audio/ogg"),
+    ("ogv", "video/ogg"),
+    ("opus", "audio/opus"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("rss", "application/rss+xml"),
+    ("rtf", "application/rtf"),
+    ("sh", "application/x-sh"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("toml", "text/plain"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wasm", "application/wasm"),
+    ("wav", "audio/wav"),
+    ("weba", "audio/webm"),
+    ("webm", "video/webm"),
+    ("webmanifest", "application/manifest+json"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xhtml", "application/xhtml+xml"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("xml", "application/xml"),
+    ("xz", "application/x-xz"),
+    ("yaml", "text/yaml"),
+    ("yml", "text/yaml"),
+    ("zip", "application/zip"),
+];
+
+/// The built-in type for an extension (in any case), if there is one.
+pub fn for_extension(extension: &str) -> Option<&'static str> {
+    let extension = extension.to_ascii_lowercase();
+
+    TYPES.binary_search_by(|&(e, _)| e.cmp(&extension[..]))
+        .ok()
+        .map(|i| TYPES[i].1)
+}
+
+/// The mappings in a `mime.types` file: a type followed by the extensions it's for on each line,
+/// e.g. `text/css css`, with `#` comments. A type with no extensions is skipped.
+pub fn parse_types(text: &str) -> Result<Vec<MimeOverride>, String> {
+    let mut mappings = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+
+        let mime_type = match words.next() {
+            Some(t) if t.contains('/') => t,
+            Some(t) => return Err(format!("line {}: {} is not a MIME type", i + 1, t)),
+            None => continue,
+        };
+
+        for extension in words {
+            mappings.push(MimeOverride {
+                dir: None,
+                extension: extension.trim_start_matches('.').to_owned(),
+                mime_type: mime_type.to_owned(),
+            });
+        }
+    }
+
+    Ok(mappings)
+}
+
+/// The mappings in the `mime.types` file at a path.
+pub fn load_types(path: &Path) -> Result<Vec<MimeOverride>, String> {
+    let mut text = String::new();
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|why| format!("{}: {}", path.display(), why)));
+
+    parse_types(&text).map_err(|why| format!("{}: {}", path.display(), why))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn built_in() {
+        assert!(TYPES.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(TYPES.iter().all(|&(e, _)| e == e.to_ascii_lowercase()));
+
+        assert_eq!(for_extension("css"), Some("text/css"));
+        assert_eq!(for_extension("JS"), Some("text/javascript"));
+        assert_eq!(for_extension("woff2"), Some("font/woff2"));
+        assert_eq!(for_extension("bin"), None);
+        assert_eq!(for_extension(""), None);
+    }
+
+    #[test]
+    fn mime_types_files() {
+        let text = "# comment\n\
+                    text/x-rst\trst rest\n\
+                    \n\
+                    application/x-nothing\n\
+                    application/json  map # for source maps\n";
+        let mappings = parse_types(text).unwrap();
+
+        let pairs = mappings.iter()
+            .map(|m| (&m.extension[..], &m.mime_type[..]))
+            .collect::<Vec<_>>();
+        assert_eq!(pairs,
+                   vec![("rst", "text/x-rst"),
+                        ("rest", "text/x-rst"),
+                        ("map", "application/json")]);
+
+        assert_eq!(parse_types("text/plain txt\ntxt text/plain\n").unwrap_err(),
+                   "line 2: txt is not a MIME type");
+    }
+}
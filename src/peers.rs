@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Most connections to be turning away at once, each with a 403 or 503 and a moment to finish
+/// sending before it's closed. Any more are closed straight away, so a flood of them can't take
+/// up more descriptors than this on top of those for the connections being served.
+pub const MAX_TURNED_AWAY: usize = 32;
+
+/// Counts of open connections, in all and per client address, shared between all of the listener
+/// coroutines so the caps hold across threads.
+#[derive(Debug)]
+pub struct PeerConnections {
+    max_total: Option<usize>,
+    max_per_ip: Option<usize>,
+    trusted: Vec<IpAddr>,
+    open: Mutex<OpenCounts>,
+}
+
+#[derive(Debug, Default)]
+struct OpenCounts {
+    total: usize,
+    by_ip: HashMap<IpAddr, usize>,
+    /// Those being turned away, which don't count towards the others.
+    turned_away: usize,
+}
+
+/// A claim on one of the server's connection slots (and one of its client's, if the client's
+/// capped), given back when dropped.
+#[derive(Debug)]
+pub struct PeerSlot {
+    peers: Arc<PeerConnections>,
+    ip: Option<IpAddr>,
+}
+
+impl PeerConnections {
+    /// Connections from addresses in `trusted` (e.g. a reverse proxy, behind which many clients
+    /// share an address) are never capped per address, though they count towards `max_total`.
+    pub fn new(max_total: Option<usize>, max_per_ip: Option<usize>, trusted: Vec<IpAddr>) -> Self {
+        PeerConnections {
+            max_total: max_total,
+            max_per_ip: max_per_ip,
+            trusted: trusted,
+            open: Mutex::new(OpenCounts::default()),
+        }
+    }
+
+    /// Claim a connection slot for a client, or `None` if the server already has as many
+    /// connections open as it's allowed, or the client does.
+    pub fn acquire(peers: &Arc<PeerConnections>, ip: IpAddr) -> Option<PeerSlot> {
+        let mut open = peers.open.lock().unwrap();
+
+        if let Some(max) = peers.max_total {
+            if open.total >= max {
+                return None;
+            }
+        }
+
+        let capped = match peers.max_per_ip {
+            Some(max) if !peers.trusted.contains(&ip) => {
+                let count = open.by_ip.entry(ip).or_insert(0);
+                if *count >= max {
+                    return None;
+                }
+
+                *count += 1;
+                Some(ip)
+            }
+            _ => None,
+        };
+
+        open.total += 1;
+
+        Some(PeerSlot {
+            peers: peers.clone(),
+            ip: capped,
+        })
+    }
+
+    /// Claim a slot for a connection about to be turned away, or `None` if `MAX_TURNED_AWAY`
+    /// already are.
+    pub fn turn_away(peers: &Arc<PeerConnections>) -> Option<TurnAwaySlot> {
+        let mut open = peers.open.lock().unwrap();
+        if open.turned_away >= MAX_TURNED_AWAY {
+            return None;
+        }
+
+        open.turned_away += 1;
+        Some(TurnAwaySlot { peers: peers.clone() })
+    }
+
+    /// How many connections a client has open, as far as the per-address cap is concerned.
+    #[cfg(test)]
+    fn open_count(&self, ip: &IpAddr) -> usize {
+        self.open.lock().unwrap().by_ip.get(ip).cloned().unwrap_or(0)
+    }
+
+    /// How many connections are open in all.
+    #[cfg(test)]
+    fn total_count(&self) -> usize {
+        self.open.lock().unwrap().total
+    }
+}
+
+/// A claim on one of the slots for connections being turned away, given back when dropped.
+#[derive(Debug)]
+pub struct TurnAwaySlot {
+    peers: Arc<PeerConnections>,
+}
+
+impl Drop for TurnAwaySlot {
+    fn drop(&mut self) {
+        self.peers.open.lock().unwrap().turned_away -= 1;
+    }
+}
+
+impl Drop for PeerSlot {
+    fn drop(&mut self) {
+        let mut open = self.peers.open.lock().unwrap();
+        open.total -= 1;
+
+        let ip = match self.ip {
+            Some(ip) => ip,
+            None => return,
+        };
+
+        // forget clients as they leave, so the table only ever holds those currently connected
+        let remaining = match open.by_ip.get_mut(&ip) {
+            Some(count) => {
+                *count -= 1;
+                *count
+            }
+            None => return,
+        };
+
+        if remaining == 0 {
+            open.by_ip.remove(&ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[test]
+    fn per_ip_cap() {
+        let a = IpAddr::from_str("192.0.2.1").unwrap();
+        let b = IpAddr::from_str("192.0.2.2").unwrap();
+        let peers = Arc::new(PeerConnections::new(None, Some(2), Vec::new()));
+
+        let first = PeerConnections::acquire(&peers, a).unwrap();
+        let second = PeerConnections::acquire(&peers, a).unwrap();
+        assert!(PeerConnections::acquire(&peers, a).is_none());
+        assert!(PeerConnections::acquire(&peers, b).is_some());
+
+        drop(first);
+        assert_eq!(peers.open_count(&a), 1);
+        assert!(PeerConnections::acquire(&peers, a).is_some());
+
+        drop(second);
+        assert_eq!(peers.open_count(&a), 0);
+    }
+
+    #[test]
+    fn trusted_and_uncapped() {
+        let proxy = IpAddr::from_str("::1").unwrap();
+
+        let peers = Arc::new(PeerConnections::new(None, Some(1), vec![proxy]));
+        let slots = (0..3).map(|_| PeerConnections::acquire(&peers, proxy)).collect::<Vec<_>>();
+        assert!(slots.iter().all(|s| s.is_some()));
+        assert_eq!(peers.open_count(&proxy), 0);
+
+        let peers = Arc::new(PeerConnections::new(None, None, Vec::new()));
+        let slots = (0..3).map(|_| PeerConnections::acquire(&peers, proxy)).collect::<Vec<_>>();
+        assert!(slots.iter().all(|s| s.is_some()));
+    }
+
+    #[test]
+    fn total_cap() {
+        let a = IpAddr::from_str("192.0.2.1").unwrap();
+        let b = IpAddr::from_str("192.0.2.2").unwrap();
+        let peers = Arc::new(PeerConnections::new(Some(2), Some(1), vec![b]));
+
+        let first = PeerConnections::acquire(&peers, a).unwrap();
+        assert!(PeerConnections::acquire(&peers, a).is_none());
+        let second = PeerConnections::acquire(&peers, b).unwrap();
+        assert!(PeerConnections::acquire(&peers, b).is_none());
+        assert_eq!(peers.total_count(), 2);
+
+        drop(first);
+        assert_eq!(peers.total_count(), 1);
+        assert!(PeerConnections::acquire(&peers, b).is_some());
+
+        drop(second);
+        assert_eq!(peers.total_count(), 0);
+    }
+
+    #[test]
+    fn turned_away() {
+        let peers = Arc::new(PeerConnections::new(Some(1), None, Vec::new()));
+
+        let slots = (0..MAX_TURNED_AWAY)
+            .map(|_| PeerConnections::turn_away(&peers).unwrap())
+            .collect::<Vec<_>>();
+        assert!(PeerConnections::turn_away(&peers).is_none());
+        // and they don't count against connections being served
+        assert!(PeerConnections::acquire(&peers, "192.0.2.1".parse().unwrap()).is_some());
+
+        drop(slots);
+        assert!(PeerConnections::turn_away(&peers).is_some());
+    }
+}
@@ -0,0 +1,75 @@
+//! RFC 7807 problem details, the `application/problem+json` bodies of error responses under API
+//! prefixes, which programs can make sense of more easily than an HTML page.
+
+use json;
+use response::Status;
+
+pub const CONTENT_TYPE: &'static str = "application/problem+json";
+
+/// A name for a status which programs can match on, which stays the same whatever the reason
+/// phrase is translated or reworded to: `not_found`, `too_many_requests` and so on.
+pub fn code(status: &Status) -> String {
+    let mut code = String::new();
+    for word in status.reason().split(|c: char| !c.is_ascii_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+        if !code.is_empty() {
+            code.push('_');
+        }
+        code.push_str(&word.to_ascii_lowercase());
+    }
+
+    if code.is_empty() {
+        format!("status_{}", status.code())
+    } else {
+        code
+    }
+}
+
+/// The problem details of an error response to the request with the given ID.
+pub fn render(status: &Status, request_id: &str) -> String {
+    format!("{{\"type\": \"about:blank\", \"title\": {}, \"status\": {}, \"code\": {}, \
+             \"request_id\": {}}}",
+            json::string(&status.reason()),
+            status.code(),
+            json::string(&code(status)),
+            json::string(request_id))
+}
+
+/// Whether an ID a client (or a proxy in front of the server) sent in `X-Request-Id` is fit to
+/// be used in place of one of our own.
+pub fn usable_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 &&
+    id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+#[cfg(test)]
+mod test {
+    use response::Status;
+
+    use super::{code, render, usable_request_id};
+
+    #[test]
+    fn codes() {
+        assert_eq!(code(&Status::NotFound), "not_found");
+        assert_eq!(code(&Status::RequestHeaderFieldsTooLarge), "request_header_fields_too_large");
+        assert_eq!(code(&Status::HttpVersionNotSupported), "http_version_not_supported");
+        assert_eq!(code(&Status::Custom(599, "Network Connect-Timeout".to_owned())),
+                   "network_connect_timeout");
+        assert_eq!(code(&Status::Custom(599, "".to_owned())), "status_599");
+    }
+
+    #[test]
+    fn rendering() {
+        assert_eq!(render(&Status::MethodNotAllowed, "abc-1"),
+                   "{\"type\": \"about:blank\", \"title\": \"Method Not Allowed\", \"status\": \
+                    405, \"code\": \"method_not_allowed\", \"request_id\": \"abc-1\"}");
+
+        assert!(usable_request_id("7f3c9a2e-17"));
+        assert!(!usable_request_id(""));
+        assert!(!usable_request_id("has space"));
+        assert!(!usable_request_id("\"quoted\""));
+        assert!(!usable_request_id(&"x".repeat(65)));
+    }
+}
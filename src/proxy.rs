@@ -0,0 +1,708 @@
+//! A reverse proxy, so requests under a prefix can be answered by an upstream HTTP server (an
+//! application, say) while hppt serves everything else. Requests are made over plain HTTP, one
+//! connection each, and the upstream's answers are relayed back as they arrive. A share of them
+//! can be copied to a shadow upstream too, whose answers are dropped, and a route whose upstream
+//! keeps failing can be failed fast until it recovers.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mioco;
+
+use connection;
+use connection::TimedStream;
+use headers::Headers;
+use request::{Method, Request, percent_decode};
+
+/// Longest response head to take from an upstream.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// Longest chunk size line (or trailer field) to take from an upstream's chunked answer.
+const MAX_CHUNK_LINE_LEN: u64 = 4096;
+
+/// Most requests to have in flight to shadow upstreams at once, beyond which requests go
+/// unmirrored rather than pile up behind a slow shadow.
+pub const MAX_MIRRORING: usize = 32;
+
+/// Fields of a request which the proxy sets itself, rather than passing on the client's.
+const REPLACED_HEADERS: &'static [&'static str] = &["Host",
+                                                    "Content-Length",
+                                                    "X-Forwarded-For",
+                                                    "X-Forwarded-Host"];
+
+/// An upstream server, as an `http` URL: `http://127.0.0.1:3000`, or with a path,
+/// `http://app.internal:8080/v1`, which the rest of a request's path is appended to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upstream {
+    /// `host[:port]`, as sent in the Host header.
+    pub authority: String,
+    /// Empty, or starting with a slash and without a trailing one.
+    pub path: String,
+    /// Where `authority` was found to be by `resolve`, once it's been looked up.
+    pub addr: Option<SocketAddr>,
+}
+
+impl Upstream {
+    /// Look up the upstream's address, once, so requests needn't each wait on DNS.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        let mut authority = self.authority.clone();
+        if !authority.contains(':') || authority.ends_with(']') {
+            authority.push_str(":80");
+        }
+
+        match try!(authority.to_socket_addrs()).next() {
+            Some(addr) => {
+                self.addr = Some(addr);
+                Ok(())
+            }
+            None => {
+                let why = format!("no address for upstream {}", self.authority);
+                Err(io::Error::new(ErrorKind::NotFound, why))
+            }
+        }
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let rest = match s.find("://") {
+            Some(i) if s[..i].eq_ignore_ascii_case("http") => &s[i + 3..],
+            _ => return Err(format!("{} isn't an http:// URL", s)),
+        };
+
+        let authority_len = rest.find('/').unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_len);
+        if authority.is_empty() || authority.contains(|c: char| c == '?' || c == '@') {
+            return Err(format!("{} doesn't name a server to forward to", s));
+        }
+
+        Ok(Upstream {
+            authority: authority.to_owned(),
+            path: path.trim_end_matches('/').to_owned(),
+            addr: None,
+        })
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.path)
+    }
+}
+
+/// An upstream's answer to a request.
+pub struct Answer {
+    pub status: u16,
+    pub reason: String,
+    /// Everything but the hop-by-hop fields and Content-Length.
+    pub headers: Vec<(String, String)>,
+    /// The body's length, if the upstream said.
+    pub len: Option<u64>,
+    /// Whatever followed the head, up to its length if it had one.
+    pub body: Box<Read>,
+}
+
+/// Forward a request to an upstream (which has been `resolve`d) as `target` (see
+/// `upstream_target`), and start reading its answer. This has to be called on a mioco coroutine.
+/// The upstream gets `timeout` to accept the connection and for each read and write after.
+///
+/// The upstream hears where the request came from in `X-Forwarded-For`: the client's address,
+/// after whatever the client said it was forwarding for, if the client is one of the `trusted`
+/// proxies. The Host the client asked for goes in `X-Forwarded-Host`.
+///
+/// A `GET` or `HEAD` whose connection dies before any of the answer has arrived (as when the
+/// upstream restarts, or closes an idle connection as it's used) is tried once more on a fresh
+/// one, as no harm can come of the upstream seeing it twice. One which times out isn't, as that
+/// would only keep the client waiting twice as long.
+pub fn forward(upstream: &Upstream,
+               target: &str,
+               req: &Request,
+               trusted: &[IpAddr],
+               timeout: Option<Duration>)
+               -> io::Result<Answer> {
+    let head = request_head(upstream, target, req, trusted);
+    let addr = match upstream.addr {
+        Some(a) => a,
+        None => return Err(io::Error::new(ErrorKind::NotFound, "upstream not resolved")),
+    };
+
+    let is_head = req.method() == Method::Head;
+    let exchange = || exchange(&addr, head.as_bytes(), req.body, is_head, timeout);
+
+    match exchange() {
+        Err(ref e) if is_idempotent(req.method()) && is_dropped(e) => {
+            debug!("Trying {} again, as the upstream dropped the connection: {}", target, e);
+            exchange()
+        }
+        answer => answer,
+    }
+}
+
+/// The head of a request as forwarded to an upstream (see `forward`).
+fn request_head(upstream: &Upstream, target: &str, req: &Request, trusted: &[IpAddr]) -> String {
+    // HTTP/1.0 so the upstream can't answer in chunks, and closes the connection when it's done
+    let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\n",
+                           req.method().as_bytes(),
+                           target,
+                           upstream.authority);
+    for (name, value) in req.headers().end_to_end() {
+        if !REPLACED_HEADERS.iter().any(|r| r.eq_ignore_ascii_case(name)) {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+
+    if let Some(remote) = req.remote_addr() {
+        let ip = remote.ip();
+        match req.header("X-Forwarded-For") {
+            Some(chain) if trusted.contains(&ip) => {
+                head.push_str(&format!("X-Forwarded-For: {}, {}\r\n", chain, ip))
+            }
+            _ => head.push_str(&format!("X-Forwarded-For: {}\r\n", ip)),
+        }
+    }
+    if let Some(host) = req.header("Host") {
+        head.push_str(&format!("X-Forwarded-Host: {}\r\n", host));
+    }
+
+    // the body was read in full, and any chunks joined, before we got here
+    if !req.body.is_empty() || req.method() == Method::Post {
+        head.push_str(&format!("Content-Length: {}\r\n", req.body.len()));
+    }
+    head.push_str("\r\n");
+
+    head
+}
+
+/// Send a request on a fresh connection to `addr`, and start reading the answer.
+fn exchange(addr: &SocketAddr,
+            head: &[u8],
+            body: &[u8],
+            is_head: bool,
+            timeout: Option<Duration>)
+            -> io::Result<Answer> {
+    let stream = try!(connection::connect(addr, timeout));
+    let mut stream = TimedStream::new(stream, timeout);
+    try!(stream.write_all(head));
+    try!(stream.write_all(body));
+
+    read_answer(stream, is_head)
+}
+
+/// What's kept track of for the routes to upstreams, for every site to share.
+#[derive(Debug, Default)]
+pub struct Routes {
+    /// By route prefix.
+    states: Mutex<HashMap<String, Arc<RouteState>>>,
+    /// Requests in flight to shadow upstreams.
+    mirroring: Arc<AtomicUsize>,
+}
+
+/// What's kept track of for one route.
+#[derive(Debug, Default)]
+pub struct RouteState {
+    /// Which requests under a mirrored prefix to copy to its shadow.
+    pub mirrored: Sampler,
+    /// Which of a proxied prefix's upstreams to send requests to.
+    pub balanced: Sampler,
+    /// Which requests under a canary's prefix to send to it.
+    pub canaried: Sampler,
+    /// Whether a proxied prefix's upstream is failing.
+    pub breaker: Breaker,
+}
+
+impl Routes {
+    pub fn new() -> Self {
+        Routes::default()
+    }
+
+    /// The state of the route with a (slash-stripped) prefix, fresh the first time it's asked for.
+    pub fn state(&self, prefix: &str) -> Arc<RouteState> {
+        self.states
+            .lock()
+            .unwrap()
+            .entry(prefix.to_owned())
+            .or_insert_with(Default::default)
+            .clone()
+    }
+
+    /// Send a copy of a request to a shadow upstream (which has been `resolve`d) as `target`, on a
+    /// coroutine of its own which reads the answer and drops it, so the client neither waits on
+    /// the shadow nor hears of it failing. This has to be called on a mioco coroutine. Nothing's
+    /// sent if `MAX_MIRRORING` copies are already in flight.
+    pub fn mirror(&self,
+                  shadow: &Upstream,
+                  target: &str,
+                  req: &Request,
+                  trusted: &[IpAddr],
+                  timeout: Option<Duration>) {
+        let addr = match shadow.addr {
+            Some(a) => a,
+            None => return,
+        };
+        if self.mirroring.fetch_add(1, Ordering::SeqCst) >= MAX_MIRRORING {
+            self.mirroring.fetch_sub(1, Ordering::SeqCst);
+            debug!("Not mirroring {} to {}, with too many copies in flight", target, shadow);
+            return;
+        }
+
+        let mirroring = self.mirroring.clone();
+        let head = request_head(shadow, target, req, trusted);
+        let body = req.body.to_vec();
+        let is_head = req.method() == Method::Head;
+        let shadow = shadow.to_string();
+        mioco::spawn(move || {
+            let copied = exchange(&addr, head.as_bytes(), &body, is_head, timeout)
+                .and_then(|mut answer| io::copy(&mut answer.body, &mut io::sink()));
+            if let Err(e) = copied {
+                debug!("Couldn't mirror a request to {}: {}", shadow, e);
+            }
+            mirroring.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Fails requests for a route fast once its upstream has failed enough of them in a row, rather
+/// than leave every client waiting on it, letting one through now and then to see whether it's
+/// recovered.
+#[derive(Debug, Default)]
+pub struct Breaker {
+    state: Mutex<BreakerState>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    /// Failures since the last answer which wasn't one.
+    failures: usize,
+    /// When requests started being failed fast, or the last probe was let through.
+    opened: Option<Instant>,
+}
+
+impl Breaker {
+    /// Whether to let a request through at `now`, or if not, how long until a probe will be.
+    pub fn admit(&self, now: Instant, cooldown: Duration) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened {
+            Some(opened) if now < opened + cooldown => Err(opened + cooldown - now),
+            Some(_) => {
+                // this one's the probe, and the rest wait for another cooldown
+                state.opened = Some(now);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Note how a request let through went at `now`: whether the upstream failed it, and if so
+    /// how many failures in a row are allowed before requests are failed fast.
+    pub fn record(&self, failed: bool, threshold: usize, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        if !failed {
+            if state.opened.is_some() {
+                info!("Upstream recovered, letting requests through again");
+            }
+            *state = BreakerState::default();
+            return;
+        }
+
+        state.failures += 1;
+        if state.failures >= threshold {
+            if state.opened.is_none() {
+                warn!("Failing requests fast after {} upstream failures in a row", state.failures);
+            }
+            state.opened = Some(now);
+        }
+    }
+}
+
+/// Picks a steady share of what it's shown, going by how much it's seen, so that 10% is every
+/// tenth rather than a random tenth.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    seen: AtomicUsize,
+}
+
+impl Sampler {
+    /// Whether to pick the next one, for a share of `percent` (at most 100).
+    pub fn pick(&self, percent: u8) -> bool {
+        let n = self.seen.fetch_add(1, Ordering::Relaxed) % 100;
+        let percent = percent as usize;
+        (n + 1) * percent / 100 != n * percent / 100
+    }
+
+    /// Which of some weights (at least one, none of them 0) to pick the next one for, each having
+    /// its share in turn.
+    pub fn choose(&self, weights: &[u32]) -> usize {
+        let total = weights.iter().map(|&w| w as usize).sum::<usize>();
+        let mut n = self.seen.fetch_add(1, Ordering::Relaxed) % total;
+        for (i, &weight) in weights.iter().enumerate() {
+            if n < weight as usize {
+                return i;
+            }
+            n -= weight as usize;
+        }
+
+        weights.len() - 1
+    }
+}
+
+/// Whether a request can be sent twice without the upstream doing twice what it asks.
+fn is_idempotent(method: Method) -> bool {
+    method == Method::Get || method == Method::Head
+}
+
+/// Whether an error is the connection going away, rather than the upstream being unreachable,
+/// slow or unintelligible.
+fn is_dropped(error: &io::Error) -> bool {
+    match error.kind() {
+        ErrorKind::ConnectionReset |
+        ErrorKind::ConnectionAborted |
+        ErrorKind::BrokenPipe |
+        ErrorKind::UnexpectedEof => true,
+        _ => false,
+    }
+}
+
+/// What a request's target becomes upstream: its path with the prefix's segments swapped for
+/// the upstream's path, and its query as it was. The raw path is used rather than the decoded
+/// one so that the upstream sees the path as the client escaped it.
+///
+/// `None` if what's left of the path has a `.` or `..` segment, however it's escaped, as the
+/// upstream could take one to lead out of its path.
+pub fn upstream_target(upstream: &Upstream,
+                       prefix: &str,
+                       raw_path: &str,
+                       query: Option<&str>)
+                       -> Option<String> {
+    let prefix_segments = if prefix.is_empty() { 0 } else { prefix.split('/').count() };
+    let mut rest = raw_path.trim_start_matches('/');
+    for _ in 0..prefix_segments {
+        rest = rest.find('/').map_or("", |i| &rest[i + 1..]);
+    }
+
+    if rest.split('/').any(is_dot_segment) {
+        return None;
+    }
+
+    Some(match query {
+        Some(query) => format!("{}/{}?{}", upstream.path, rest, query),
+        None => format!("{}/{}", upstream.path, rest),
+    })
+}
+
+/// Whether a raw path segment is, or once unescaped holds, a `.` or `..` segment.
+fn is_dot_segment(segment: &str) -> bool {
+    let decoded = percent_decode(segment, false).unwrap_or_else(|_| segment.into());
+    decoded.split(|c| c == '/' || c == '\\').any(|s| s == "." || s == "..")
+}
+
+fn read_answer<R: Read + 'static>(mut stream: R, is_head: bool) -> io::Result<Answer> {
+    let malformed = || io::Error::new(ErrorKind::InvalidData, "malformed response head");
+
+    let mut buf = Vec::new();
+    let mut chunk = [0; 4096];
+    let head_len = loop {
+        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+        if buf.len() > MAX_HEAD_LEN {
+            return Err(malformed());
+        }
+
+        match try!(stream.read(&mut chunk)) {
+            0 if buf.is_empty() => {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof,
+                                          "upstream closed the connection without answering"))
+            }
+            0 => return Err(malformed()),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let (status, reason, headers, len, chunked) = {
+        let head = match ::std::str::from_utf8(&buf[..head_len]) {
+            Ok(h) => h,
+            Err(_) => return Err(malformed()),
+        };
+        let mut lines = head.split("\r\n");
+
+        // HTTP/1.1 200 OK, though the reason phrase may be left out
+        let mut status_line = lines.next().unwrap_or("").splitn(3, ' ');
+        if !status_line.next().map_or(false, |v| v.starts_with("HTTP/")) {
+            return Err(malformed());
+        }
+        let status = match status_line.next().and_then(|s| s.parse::<u16>().ok()) {
+            Some(s) if s >= 100 && s < 600 => s,
+            _ => return Err(malformed()),
+        };
+        let reason = status_line.next().unwrap_or("").to_owned();
+
+        let fields = Headers::from(lines.filter_map(Headers::parse_line).collect::<Vec<_>>());
+        let chunked = fields.get("Transfer-Encoding")
+            .map_or(false, |te| te.to_ascii_lowercase().contains("chunked"));
+        // a chunked body's length is only known once it's all arrived
+        let len = if chunked {
+            None
+        } else {
+            fields.get("Content-Length").and_then(|l| l.parse::<u64>().ok())
+        };
+        let headers = fields.end_to_end()
+            .into_iter()
+            .filter(|&(name, _)| !name.eq_ignore_ascii_case("Content-Length"))
+            .map(|(name, value)| (name.to_owned(), value.to_owned()))
+            .collect::<Vec<_>>();
+
+        (status, reason, headers, len, chunked)
+    };
+
+    // a 204 or 304 has no body, whatever its Content-Length says
+    let has_body = !is_head && status != 204 && status != 304;
+    let rest = Cursor::new(buf[head_len..].to_vec()).chain(stream);
+    let body: Box<Read> = match (has_body, len) {
+        (false, _) => Box::new(io::empty()),
+        (true, _) if chunked => Box::new(Dechunked::new(rest)),
+        (true, Some(len)) => Box::new(rest.take(len)),
+        (true, None) => Box::new(rest),
+    };
+
+    Ok(Answer {
+        status: status,
+        reason: reason,
+        headers: headers,
+        len: len,
+        body: body,
+    })
+}
+
+/// The data of a chunked body (RFC 7230 section 4.1), as it arrives, with the chunk sizes and
+/// any trailer left out.
+struct Dechunked<R> {
+    inner: BufReader<R>,
+    /// What's left of the current chunk.
+    remaining: u64,
+    finished: bool,
+}
+
+impl<R: Read> Dechunked<R> {
+    fn new(inner: R) -> Self {
+        Dechunked {
+            inner: BufReader::new(inner),
+            remaining: 0,
+            finished: false,
+        }
+    }
+
+    /// The next line, without its line ending.
+    fn line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        try!((&mut self.inner).take(MAX_CHUNK_LINE_LEN).read_until(b'\n', &mut line));
+        if !line.ends_with(b"\n") {
+            return Err(io::Error::new(ErrorKind::InvalidData, "malformed chunk"));
+        }
+
+        String::from_utf8(line)
+            .map(|l| l.trim_end_matches(|c| c == '\r' || c == '\n').to_owned())
+            .map_err(|_| io::Error::new(ErrorKind::InvalidData, "malformed chunk"))
+    }
+}
+
+impl<R: Read> Read for Dechunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.finished || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            let line = try!(self.line());
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.remaining = match u64::from_str_radix(size, 16) {
+                Ok(s) => s,
+                Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "bad chunk size")),
+            };
+
+            // the last chunk, then the trailer up to a blank line
+            if self.remaining == 0 {
+                while !try!(self.line()).is_empty() {}
+                self.finished = true;
+                return Ok(0);
+            }
+        }
+
+        let wanted = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = try!(self.inner.read(&mut buf[..wanted]));
+        if n == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "chunked body cut short"));
+        }
+
+        self.remaining -= n as u64;
+        if self.remaining == 0 && !try!(self.line()).is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "malformed chunk"));
+        }
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+    use std::time::{Duration, Instant};
+
+    use super::{Breaker, Sampler, Upstream, read_answer, upstream_target};
+
+    #[test]
+    fn upstreams() {
+        let upstream = "http://127.0.0.1:3000".parse::<Upstream>().unwrap();
+        assert_eq!(upstream.authority, "127.0.0.1:3000");
+        assert_eq!(upstream.path, "");
+
+        let upstream = "HTTP://app.internal/v1/".parse::<Upstream>().unwrap();
+        assert_eq!(upstream.path, "/v1");
+        assert_eq!(upstream.to_string(), "http://app.internal/v1");
+
+        for bad in &["127.0.0.1:3000", "https://app.internal", "http://", "http:///v1",
+                     "http://user@app.internal"] {
+            assert!(bad.parse::<Upstream>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn targets() {
+        let root = "http://127.0.0.1:3000".parse::<Upstream>().unwrap();
+        let v1 = "http://127.0.0.1:3000/v1".parse::<Upstream>().unwrap();
+
+        let target = |upstream, prefix, path, query| upstream_target(upstream, prefix, path, query);
+        assert_eq!(target(&root, "api", "/api/users/42", Some("full")),
+                   Some("/users/42?full".to_owned()));
+        assert_eq!(target(&root, "api", "/api", None), Some("/".to_owned()));
+        assert_eq!(target(&root, "api", "/api/", None), Some("/".to_owned()));
+        assert_eq!(target(&root, "api", "/api/users/", None), Some("/users/".to_owned()));
+        assert_eq!(target(&v1, "api", "/api", Some("q")), Some("/v1/?q".to_owned()));
+        assert_eq!(target(&v1, "api/app", "/api/app/a%2Fb", None), Some("/v1/a%2Fb".to_owned()));
+        assert_eq!(target(&v1, "", "/index.html", None), Some("/v1/index.html".to_owned()));
+        assert_eq!(target(&v1, "api", "/api/..x/.y", None), Some("/v1/..x/.y".to_owned()));
+
+        // nothing which could lead out of the upstream's path
+        for path in &["/api/../../x", "/api/./x", "/api/%2e%2E/x", "/api/a/.%2e", "/api/a%2F..%2Fb",
+                      "/api/a%5c..%5cb"] {
+            assert_eq!(target(&v1, "api", path, None), None, "{} should be refused", path);
+        }
+    }
+
+    #[test]
+    fn answers() {
+        let answer = read_answer(Cursor::new(&b"HTTP/1.1 201 Created\r\n\
+                                                 Content-Type: text/plain\r\n\
+                                                 Content-Length: 5\r\n\
+                                                 Connection: close, X-Trace\r\n\
+                                                 X-Trace: 1\r\n\
+                                                 X-Request: a\r\n\r\n\
+                                                 helloextra"[..]),
+                                 false)
+            .unwrap();
+        assert_eq!((answer.status, &answer.reason[..]), (201, "Created"));
+        assert_eq!(answer.len, Some(5));
+        assert_eq!(answer.headers,
+                   vec![("Content-Type".to_owned(), "text/plain".to_owned()),
+                        ("X-Request".to_owned(), "a".to_owned())]);
+        let mut body = String::new();
+        answer.body.take(100).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello");
+
+        // no reason phrase or length, so the body runs until the upstream hangs up
+        let mut answer = read_answer(Cursor::new(&b"HTTP/1.0 200\r\n\r\nall of it"[..]), false)
+            .unwrap();
+        assert_eq!((answer.status, &answer.reason[..], answer.len), (200, "", None));
+        let mut body = String::new();
+        answer.body.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "all of it");
+
+        // chunks joined, whatever the length said
+        let answer = read_answer(Cursor::new(&b"HTTP/1.1 200 OK\r\n\
+                                                 Transfer-Encoding: chunked\r\n\
+                                                 Content-Length: 2\r\n\r\n\
+                                                 5;ext=1\r\nhello\r\n7\r\n, world\r\n\
+                                                 0\r\nX-Trailer: 1\r\n\r\nextra"[..]),
+                                 false)
+            .unwrap();
+        assert_eq!(answer.len, None);
+        assert!(answer.headers.is_empty());
+        let mut body = String::new();
+        answer.body.take(100).read_to_string(&mut body).unwrap();
+        assert_eq!(body, "hello, world");
+
+        let answer = read_answer(Cursor::new(&b"HTTP/1.1 200 OK\r\n\
+                                                 Transfer-Encoding: chunked\r\n\r\n\
+                                                 5\r\nhel"[..]),
+                                 false)
+            .unwrap();
+        assert!(answer.body.take(100).read_to_string(&mut String::new()).is_err());
+
+        for bad in &[&b"HTTP/1.1 200 OK\r\n"[..],
+                     b"SSH-2.0-OpenSSH\r\n\r\n",
+                     b"HTTP/1.1 99\r\n\r\n"] {
+            assert!(read_answer(Cursor::new(*bad), false).is_err());
+        }
+    }
+
+    #[test]
+    fn sampling() {
+        for &percent in &[0, 1, 10, 33, 95, 100] {
+            let sampler = Sampler::default();
+            let picked = (0..1000).filter(|_| sampler.pick(percent)).count();
+            assert_eq!(picked, percent as usize * 10, "{}%", percent);
+        }
+
+        // spread out, rather than all at the start
+        let sampler = Sampler::default();
+        let picked = (0..10).map(|_| sampler.pick(50)).collect::<Vec<_>>();
+        assert_eq!(picked, [false, true, false, true, false, true, false, true, false, true]);
+
+        let sampler = Sampler::default();
+        let chosen = (0..10).map(|_| sampler.choose(&[3, 1])).collect::<Vec<_>>();
+        assert_eq!(chosen, [0, 0, 0, 1, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(Sampler::default().choose(&[5]), 0);
+    }
+
+    #[test]
+    fn breaker() {
+        let breaker = Breaker::default();
+        let cooldown = Duration::from_secs(10);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // a success in between starts the count again
+        breaker.record(false, 3, at(0));
+        for _ in 0..2 {
+            breaker.record(false, 2, at(0));
+            breaker.record(true, 2, at(0));
+            assert_eq!(breaker.admit(at(0), cooldown), Ok(()));
+        }
+
+        breaker.record(true, 2, at(1));
+        assert_eq!(breaker.admit(at(1), cooldown), Err(Duration::from_secs(10)));
+        assert_eq!(breaker.admit(at(5), cooldown), Err(Duration::from_secs(6)));
+
+        // one probe, which fails, so the wait starts over
+        assert_eq!(breaker.admit(at(11), cooldown), Ok(()));
+        assert_eq!(breaker.admit(at(11), cooldown), Err(Duration::from_secs(10)));
+        breaker.record(true, 2, at(12));
+        assert_eq!(breaker.admit(at(21), cooldown), Err(Duration::from_secs(1)));
+
+        // then one which doesn't
+        assert_eq!(breaker.admit(at(22), cooldown), Ok(()));
+        breaker.record(false, 2, at(22));
+        assert_eq!(breaker.admit(at(22), cooldown), Ok(()));
+        breaker.record(true, 2, at(23));
+        assert_eq!(breaker.admit(at(23), cooldown), Ok(()));
+    }
+}
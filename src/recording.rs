@@ -0,0 +1,167 @@
+//! Copies of responses, exactly as they were sent (head and all), kept on disk for debugging what
+//! was served. Files are written from the connection's coroutine, so this is for investigating
+//! problems rather than for a busy server.
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+use clock::Clock;
+use request::percent_decode;
+
+/// Decides which responses to record, and where.
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    patterns: Vec<String>,
+    every: usize,
+    /// For the time in file names.
+    clock: Arc<Clock>,
+    /// How many responses have matched so far.
+    matched: AtomicUsize,
+}
+
+impl Recorder {
+    /// Record every `every`th response for a path matching any of `patterns` (or for any path, if
+    /// there are none) to a file in `dir`.
+    pub fn new(dir: PathBuf, patterns: Vec<String>, every: usize, clock: Arc<Clock>) -> Self {
+        Recorder {
+            dir: dir,
+            patterns: patterns.iter().map(|p| p.trim_start_matches('/').to_owned()).collect(),
+            every: every,
+            clock: clock,
+            matched: AtomicUsize::new(0),
+        }
+    }
+
+    /// The file to record the response to a request with the given request-target to, if it's
+    /// one to record.
+    pub fn start(&self, target: &str) -> Option<File> {
+        let path = target.splitn(2, '?').next().unwrap_or("");
+        let path = match percent_decode(path, false) {
+            Ok(p) => p,
+            Err(_) => return None,
+        };
+        let path = path.trim_start_matches('/');
+
+        let matches = self.patterns.is_empty() ||
+                      self.patterns.iter().any(|p| glob_matches(p.as_bytes(), path.as_bytes()));
+        if !matches {
+            return None;
+        }
+
+        let n = self.matched.fetch_add(1, Ordering::SeqCst);
+        if n % self.every != 0 {
+            return None;
+        }
+
+        let secs = self.clock.now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let file_name = format!("{}-{}-{}.http", secs, n, sanitize(path));
+        let full_path = self.dir.join(file_name);
+
+        match File::create(&full_path) {
+            Ok(f) => Some(f),
+            Err(why) => {
+                warn!("Unable to record a response to {:?}: {:?}", full_path, why);
+                None
+            }
+        }
+    }
+}
+
+/// A path made safe to use in a file name.
+fn sanitize(path: &str) -> String {
+    if path.is_empty() {
+        return "index".to_owned();
+    }
+
+    path.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect()
+}
+
+/// Whether a path matches a glob, in which `*` stands for anything within one segment, `**` for
+/// anything at all, and `?` for any one character but `/`.
+fn glob_matches(pattern: &[u8], path: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&b'*', rest)) if rest.first() == Some(&b'*') => {
+            (0..path.len() + 1).any(|i| glob_matches(&rest[1..], &path[i..]))
+        }
+        Some((&b'*', rest)) => {
+            (0..path.len() + 1)
+                .take_while(|&i| i == 0 || path[i - 1] != b'/')
+                .any(|i| glob_matches(rest, &path[i..]))
+        }
+        Some((&b'?', rest)) => {
+            !path.is_empty() && path[0] != b'/' && glob_matches(rest, &path[1..])
+        }
+        Some((&c, rest)) => path.first() == Some(&c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+/// Writes to a connection, and copies whatever the connection took to a recording.
+pub struct Tee<'a, C: 'a + Write> {
+    connection: &'a mut C,
+    /// Gone once writing to it has failed, which shouldn't hold up the response.
+    recording: Option<File>,
+}
+
+impl<'a, C: Write> Tee<'a, C> {
+    pub fn new(connection: &'a mut C, recording: File) -> Self {
+        Tee {
+            connection: connection,
+            recording: Some(recording),
+        }
+    }
+}
+
+impl<'a, C: Write> Write for Tee<'a, C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = try!(self.connection.write(buf));
+
+        let failed = match self.recording {
+            Some(ref mut f) => f.write_all(&buf[..n]).is_err(),
+            None => false,
+        };
+        if failed {
+            warn!("Unable to finish recording a response");
+            self.recording = None;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(ref mut f) = self.recording {
+            let _ = f.flush();
+        }
+
+        self.connection.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glob_matches, sanitize};
+
+    #[test]
+    fn globs() {
+        let matches = |pattern: &str, path: &str| glob_matches(pattern.as_bytes(), path.as_bytes());
+
+        assert!(matches("docs/*.html", "docs/page.html"));
+        assert!(!matches("docs/*.html", "docs/old/page.html"));
+        assert!(matches("docs/**.html", "docs/old/page.html"));
+        assert!(matches("**", ""));
+        assert!(matches("page.htm?", "page.html"));
+        assert!(!matches("page?html", "page/html"));
+        assert!(!matches("docs/*", "docsfoo/page.html"));
+
+        assert_eq!(sanitize("docs/grüße welt.txt"), "docs_gr__e_welt.txt");
+        assert_eq!(sanitize(""), "index");
+    }
+}
@@ -1,35 +1,352 @@
-use std::ops::Deref;
+use std::borrow::Cow;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::ops::{Deref, Range};
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
 
 use error::{HpptResult, HpptError};
+use headers::Headers;
+use http_date;
+use idna;
+use limits::Limits;
+
+/// Longer than any method we know, so anything past this can be turned away as unimplemented.
+pub const MAX_METHOD_LEN: usize = 16;
+
+/// How closely requests have to follow the spec.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseMode {
+    /// Put up with bare `\n` line endings, stray whitespace and the like from sloppy clients.
+    Lenient,
+    /// Reject anything RFC 7230 doesn't allow, for spec-compliance testing.
+    Strict,
+}
+
+/// Whether a byte may appear in a token (RFC 7230 section 3.2.6), such as a method name.
+pub fn is_tchar(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// Check as much of the method as has been received so far, so that a garbage or absurdly long
+/// method can be rejected without waiting for (or buffering) the rest of the request.
+pub fn check_method_prefix(received: &[u8]) -> HpptResult<()> {
+    let token_len = received.iter().position(|&b| b == b' ').unwrap_or(received.len());
+    let token = &received[..token_len];
+
+    if !token.iter().all(|&b| is_tchar(b)) {
+        Err(HpptError::Parsing)
+    } else if token.len() > MAX_METHOD_LEN {
+        Err(HpptError::UnknownMethod)
+    } else {
+        Ok(())
+    }
+}
+
+/// Length of the header block at the start of `bytes` (up to and including the blank line which
+/// ends it), or `None` if it hasn't all arrived yet.
+pub fn head_len(bytes: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+
+    for (i, &b) in bytes.iter().enumerate() {
+        if b == b'\n' {
+            let line = &bytes[line_start..i];
+
+            // the request line can't be blank, any later blank line ends the headers
+            if line_start > 0 && (line.is_empty() || line == b"\r") {
+                return Some(i + 1);
+            }
+
+            line_start = i + 1;
+        }
+    }
+
+    None
+}
+
+/// Length of the first complete request (header block plus Content-Length or chunked body) in
+/// `bytes`, or `None` if it hasn't all arrived yet. Anything after it belongs to the next,
+/// pipelined request.
+pub fn request_len(bytes: &[u8], limits: &Limits, mode: ParseMode)
+                   -> HpptResult<Option<usize>> {
+    let head = match head_len(bytes) {
+        Some(h) => h,
+        None => return Ok(None),
+    };
+
+    let request = try!(Request::from_bytes(&bytes[..head], limits, mode));
+    if request.is_chunked() {
+        return Ok(try!(parse_chunked(&bytes[head..])).map(|body| head + body.len));
+    }
+
+    let body = try!(request.content_length());
+
+    if bytes.len() >= head + body {
+        Ok(Some(head + body))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Fields a trailer can't be trusted with (RFC 7230 section 4.1.2): they'd have had to arrive
+/// before the body to mean what they say about framing, routing, authorization, the client, the
+/// answer wanted or the body itself, so they're dropped rather than moved up among the headers,
+/// where handlers, upstreams and CGI scripts (as `HTTP_*` variables) would take them for headers.
+const UNTRUSTED_TRAILERS: &'static [&'static str] = &["Authorization",
+                                                      "Cache-Control",
+                                                      "Connection",
+                                                      "Content-Encoding",
+                                                      "Content-Length",
+                                                      "Content-Range",
+                                                      "Content-Type",
+                                                      "Cookie",
+                                                      "Expect",
+                                                      "Forwarded",
+                                                      "Host",
+                                                      "If-Match",
+                                                      "If-Modified-Since",
+                                                      "If-None-Match",
+                                                      "If-Range",
+                                                      "If-Unmodified-Since",
+                                                      "Keep-Alive",
+                                                      "Max-Forwards",
+                                                      "Origin",
+                                                      "Pragma",
+                                                      "Proxy",
+                                                      "Proxy-Authorization",
+                                                      "Proxy-Connection",
+                                                      "Range",
+                                                      "TE",
+                                                      "Trailer",
+                                                      "Transfer-Encoding",
+                                                      "Upgrade",
+                                                      "X-Forwarded-For",
+                                                      "X-Forwarded-Host",
+                                                      "X-Forwarded-Proto",
+                                                      "X-Real-IP",
+                                                      "X-Request-Id"];
+
+/// A chunked request (one for which `is_chunked`) rewritten the way RFC 7230 section 4.1.3 decodes
+/// one: its chunks joined into a body with a Content-Length, the fields of its trailer (but for
+/// `UNTRUSTED_TRAILERS`) moved up among its headers, and its Transfer-Encoding dropped. Any other
+/// request is left as it is.
+pub fn dechunk<'a>(bytes: &'a [u8], limits: &Limits, mode: ParseMode)
+                   -> HpptResult<Cow<'a, [u8]>> {
+    let head = match head_len(bytes) {
+        Some(h) => h,
+        None => return Ok(Cow::Borrowed(bytes)),
+    };
+
+    if !try!(Request::from_bytes(&bytes[..head], limits, mode)).is_chunked() {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let body = &bytes[head..];
+    let chunked = match try!(parse_chunked(body)) {
+        Some(c) => c,
+        None => return Err(HpptError::Parsing),
+    };
+
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut line_start = 0;
+
+    // the request line and headers, line endings and all, up to the blank line
+    for (i, &b) in bytes[..head].iter().enumerate() {
+        if b != b'\n' {
+            continue;
+        }
+
+        let line = &bytes[line_start..i + 1];
+        let is_end = line == b"\n" || line == b"\r\n";
+        if line_start == 0 || !(is_end || field_name_is(line, "Transfer-Encoding")) {
+            decoded.extend_from_slice(line);
+        }
+        line_start = i + 1;
+    }
+
+    for trailer in &chunked.trailers {
+        let line = &body[trailer.clone()];
+        if !UNTRUSTED_TRAILERS.iter().any(|name| field_name_is(line, name)) {
+            decoded.extend_from_slice(line);
+            decoded.extend_from_slice(b"\r\n");
+        }
+    }
+
+    let len = chunked.chunks.iter().map(|c| c.len()).sum::<usize>();
+    decoded.extend_from_slice(format!("Content-Length: {}\r\n\r\n", len).as_bytes());
+    for chunk in &chunked.chunks {
+        decoded.extend_from_slice(&body[chunk.clone()]);
+    }
+
+    Ok(Cow::Owned(decoded))
+}
+
+/// Where the pieces of a chunked body (RFC 7230 section 4.1) are, from its start.
+#[derive(Debug, Eq, PartialEq)]
+struct ChunkedBody {
+    /// Its length in full, last chunk and trailer included.
+    len: usize,
+    /// The data of each chunk.
+    chunks: Vec<Range<usize>>,
+    /// The trailer's field lines, without their line endings.
+    trailers: Vec<Range<usize>>,
+}
+
+/// Find the pieces of a chunked body at the start of `body`, or `None` if it hasn't all arrived
+/// yet. However lenient the request's head may be, every line here has to end in CRLF and every
+/// chunk's data has to be exactly as long as it says, so a proxy in front of us can't make out
+/// different chunks (or where the next request starts) from us.
+fn parse_chunked(body: &[u8]) -> HpptResult<Option<ChunkedBody>> {
+    let mut pos = 0;
+    let mut chunks = Vec::new();
+
+    loop {
+        let line = match try!(crlf_line(body, pos)) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        pos = line.end + 2;
+
+        // extensions after a `;` mean nothing to us
+        let size = try!(parse_chunk_size(&body[line]));
+        if size == 0 {
+            break;
+        }
+
+        let end = match pos.checked_add(size) {
+            Some(end) => end,
+            None => return Err(HpptError::Parsing),
+        };
+        if body.len() < end + 2 {
+            return Ok(None);
+        }
+        if &body[end..end + 2] != b"\r\n" {
+            return Err(HpptError::Parsing);
+        }
+
+        chunks.push(pos..end);
+        pos = end + 2;
+    }
+
+    let mut trailers = Vec::new();
+
+    loop {
+        let line = match try!(crlf_line(body, pos)) {
+            Some(l) => l,
+            None => return Ok(None),
+        };
+        pos = line.end + 2;
+
+        if line.start == line.end {
+            break;
+        }
+        if !is_unambiguous_header_line(&body[line.clone()]) {
+            return Err(HpptError::Parsing);
+        }
+        trailers.push(line);
+    }
+
+    Ok(Some(ChunkedBody {
+        len: pos,
+        chunks: chunks,
+        trailers: trailers,
+    }))
+}
+
+/// The line starting at `start` (without its CRLF), or `None` if it hasn't ended yet. A line
+/// which ends in a bare LF, or has a CR anywhere else, is an error.
+fn crlf_line(bytes: &[u8], start: usize) -> HpptResult<Option<Range<usize>>> {
+    let end = match bytes[start..].iter().position(|&b| b == b'\n') {
+        Some(n) => start + n,
+        None => return Ok(None),
+    };
+
+    if end == start || bytes[end - 1] != b'\r' || bytes[start..end - 1].contains(&b'\r') {
+        return Err(HpptError::Parsing);
+    }
+
+    Ok(Some(start..end - 1))
+}
+
+/// The size from a chunk's size line, which is hex digits followed by any extensions.
+fn parse_chunk_size(line: &[u8]) -> HpptResult<usize> {
+    let end = line.iter().position(|&b| b == b';').unwrap_or(line.len());
+    let digits = match from_utf8(&line[..end]) {
+        Ok(d) => d.trim_end_matches(|c| c == ' ' || c == '\t'),
+        Err(_) => return Err(HpptError::Parsing),
+    };
+
+    // a size with more digits than that is a lie, and too many to add up
+    if digits.is_empty() || digits.len() > 15 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(HpptError::Parsing);
+    }
+
+    usize::from_str_radix(digits, 16).map_err(|_| HpptError::Parsing)
+}
+
+/// Whether a header line is a field with the given (case-insensitive) name.
+fn field_name_is(line: &[u8], name: &str) -> bool {
+    match line.iter().position(|&b| b == b':') {
+        Some(colon) => line[..colon].eq_ignore_ascii_case(name.as_bytes()),
+        None => false,
+    }
+}
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Request<'a> {
     method: Method,
-    uri: Uri<'a>,
+    raw_target: &'a str,
+    target: RequestTarget<'a>,
     query: Option<Query<'a>>,
     version: Version,
-    header_lines: Vec<&'a str>,
+    headers: Headers<'a>,
     pub body: &'a [u8],
+    /// The two ends of the connection the request arrived on, if it's known.
+    addrs: Option<(SocketAddr, SocketAddr)>,
 }
 
 impl<'a> Request<'a> {
-    pub fn from_bytes(bytes: &'a [u8]) -> HpptResult<Request<'a>> {
+    /// Parse a request, rejecting it with `TooManyHeaders` if it has more header lines than the
+    /// limits allow.
+    ///
+    /// However lenient the mode, anything which could leave us and a proxy in front of us
+    /// disagreeing about where the request ends (and so where the next one starts) is rejected:
+    /// a CR which doesn't end a line, whitespace between a header's name and its colon, both
+    /// Transfer-Encoding and Content-Length, or Content-Lengths which don't agree.
+    ///
+    /// A chunked body is left as it was received: `dechunk` the request first to decode it.
+    pub fn from_bytes(bytes: &'a [u8],
+                      limits: &Limits,
+                      mode: ParseMode)
+                      -> HpptResult<Request<'a>> {
 
         // standard says \r\n is the line terminator, but there are many non-conforming impls
-        // so we'll split on newlines, and then trim the \r
+        // so we'll split on newlines, and then trim the \r (unless we're being strict)
+
+        if mode == ParseMode::Strict {
+            let head = &bytes[..try!(head_len(bytes).ok_or(HpptError::Parsing))];
+            let bare_newline = head.iter()
+                .enumerate()
+                .any(|(i, &b)| b == b'\n' && (i == 0 || head[i - 1] != b'\r'));
+
+            if bare_newline {
+                return Err(HpptError::Parsing);
+            }
+        }
 
         let mut body_start = 0;
         let method;
-        let uri;
+        let raw;
+        let target;
         let query;
         let version;
-        let mut headers = Vec::new();
+        let mut headers = Headers::new();
+        let mut header_lines = 0;
 
         {
             let mut lines = bytes.split(|&b| b == b'\n')
                 .map(|l| {
                     if l.len() == 0 {
+                        body_start += 1; // a bare \n, or the end of the bytes (see below)
                         l
                     } else if l[l.len() - 1] == b'\r' {
                         body_start += l.len() + 1; // the \n byte was stripped
@@ -46,56 +363,32 @@ impl<'a> Request<'a> {
                 None => return Err(HpptError::Parsing),
             };
 
+            if request_line.contains(&b'\r') {
+                return Err(HpptError::Parsing);
+            }
+
             let mut request_line_tokens = request_line.split(|&b| b == b' ');
 
             method = match request_line_tokens.next() {
-                Some(m) => {
-                    match Method::from_bytes(m) {
-                        Ok(m) => m,
-                        Err(_) => return Err(HpptError::Parsing),
-                    }
-                }
+                Some(m) => try!(Method::from_bytes(m)),
                 None => return Err(HpptError::Parsing),
             };
 
             match request_line_tokens.next() {
-                Some(mut u) => {
-                    // URIs must have at least one character
-                    if u.len() > 0 {
-
-                        // joining this uri onto an OS path won't work if has a preceding slash
-                        if u[0] == b'/' {
-                            u = &u[1..];
-                        }
-
-                        let uri_fromstr = match from_utf8(u) {
-                            Ok(s) => s,
-                            Err(_) => return Err(HpptError::Parsing),
-                        };
-
-                        let mut halves = uri_fromstr.split('?');
-
-                        let uri_parsed = match halves.next() {
-                            Some(u) => Uri(u),
-                            None => return Err(HpptError::Parsing), // need a first half of the URI
-                        };
-
-                        // but the post ? part of the URI is optional
-                        let query_parsed = match halves.next() {
-                            Some(q) => if q.len() > 0 { Some(Query(q)) } else { None },
-                            None => None,
-                        };
+                // targets must have at least one character
+                Some(u) if u.len() > 0 => {
+                    let raw_target = match from_utf8(u) {
+                        Ok(s) => s,
+                        Err(_) => return Err(HpptError::Parsing),
+                    };
 
-                        uri = uri_parsed;
-                        query = query_parsed;
+                    let (target_parsed, query_parsed) = try!(RequestTarget::parse(raw_target));
 
-                    } else {
-                        // this isn't an incomplete request -- we were able to get the next
-                        // space-separated token but it's 0-length
-                        return Err(HpptError::Parsing);
-                    }
+                    raw = raw_target;
+                    target = target_parsed;
+                    query = query_parsed;
                 }
-                None => return Err(HpptError::Parsing),
+                _ => return Err(HpptError::Parsing),
             }
 
             version = match request_line_tokens.next() {
@@ -111,11 +404,33 @@ impl<'a> Request<'a> {
                 None => return Err(HpptError::Parsing),
             };
 
+            if mode == ParseMode::Strict && request_line_tokens.next().is_some() {
+                return Err(HpptError::Parsing);
+            }
+
             // SIDE EFFECTFUL -- parsing each line will increment out body_start value
             for l in lines.take_while(|l| l.len() > 0) {
-                match from_utf8(l) {
-                    Ok(s) => headers.push(s),
+                if header_lines == limits.max_headers {
+                    return Err(HpptError::TooManyHeaders);
+                }
+                header_lines += 1;
+
+                if mode == ParseMode::Strict && !is_strict_header_line(l) {
+                    return Err(HpptError::Parsing);
+                }
+
+                if !is_unambiguous_header_line(l) {
+                    return Err(HpptError::Parsing);
+                }
+
+                let line = match from_utf8(l) {
+                    Ok(s) => s,
                     Err(_) => return Err(HpptError::Parsing),
+                };
+
+                match Headers::parse_line(line) {
+                    Some((name, value)) => headers.push(name, value),
+                    None => debug!("Ignoring header line without a colon: {:?}", line),
                 }
             }
         }
@@ -123,42 +438,602 @@ impl<'a> Request<'a> {
 
         let request = Request {
             method: method,
-            uri: uri,
+            raw_target: raw,
+            target: target,
             query: query,
             version: version,
-            header_lines: headers,
-            body: &bytes[body_start..],
+            headers: headers,
+            // we'll have counted one past the end if there was no body and no final newline
+            body: &bytes[::std::cmp::min(body_start, bytes.len())..],
+            addrs: None,
         };
 
+        // HTTP/1.1 requests have to say which host they're for, exactly once (RFC 7230 section
+        // 5.4), and there's no telling which of several a client meant
+        let hosts = request.headers().get_all("Host");
+        if hosts.len() > 1 || !hosts.iter().all(|h| is_valid_host(h)) {
+            return Err(HpptError::Parsing);
+        }
+        if hosts.is_empty() && request.version == Version::OneDotOne {
+            return Err(HpptError::Parsing);
+        }
+
+        // a proxy might go by either, so we can't know which the client meant
+        if request.header("Transfer-Encoding").is_some() &&
+           request.header("Content-Length").is_some() {
+            return Err(HpptError::Parsing);
+        }
+
+        // chunked has to be the last coding applied, for us to tell where the body ends (RFC 7230
+        // section 3.3.3), and it's the only one we can decode
+        let codings = request.headers.transfer_codings();
+        match codings.last() {
+            Some(last) if !last.eq_ignore_ascii_case("chunked") => {
+                return Err(HpptError::Parsing)
+            }
+            Some(_) if codings.len() > 1 => return Err(HpptError::UnsupportedTransferCoding),
+            _ => (),
+        }
+        if request.header("Transfer-Encoding").is_some() && codings.is_empty() {
+            return Err(HpptError::Parsing);
+        }
+
+        try!(request.content_length());
+
         debug!("request parsed: {:?}", &request);
 
         Ok(request)
     }
 
+    /// This request, as received at `local` from a client at `remote`.
+    pub fn with_addrs(mut self, local: SocketAddr, remote: SocketAddr) -> Self {
+        self.addrs = Some((local, remote));
+        self
+    }
+
+    /// The address the client connected to, which may be any of the server's if it listens on
+    /// all interfaces.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.addrs.map(|a| a.0)
+    }
+
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.addrs.map(|a| a.1)
+    }
+
     pub fn method(&self) -> Method {
         self.method
     }
 
-    pub fn uri(&self) -> &Uri {
-        &self.uri
+    /// The request-target exactly as it appeared in the request line, query and leading slash
+    /// (or scheme and authority, for an absolute-form target) included.
+    pub fn raw_target(&self) -> &'a str {
+        self.raw_target
+    }
+
+    pub fn target(&self) -> &RequestTarget<'a> {
+        &self.target
+    }
+
+    /// The path of the request-target as it appeared in the request line, still escaped and
+    /// with its leading slash but without any scheme, authority or query, e.g. `/some%20dir/`
+    /// for `http://example.com/some%20dir/?q`. It's `*` for an asterisk-form target.
+    pub fn raw_path(&self) -> &'a str {
+        let raw = match self.target {
+            RequestTarget::Absolute(authority, _) => {
+                let after = self.raw_target.find("://").map_or(0, |i| i + 3 + authority.len());
+                &self.raw_target[after..]
+            }
+            _ => self.raw_target,
+        };
+
+        &raw[..raw.find('?').unwrap_or(raw.len())]
+    }
+
+    /// This request, but for another (origin-form) target, as after a rewrite. An absolute-form
+    /// target's authority is kept, so the request is still for the same host.
+    pub fn with_target(mut self, raw: &'a str) -> HpptResult<Self> {
+        let (target, query) = match try!(RequestTarget::parse(raw)) {
+            (RequestTarget::Origin(uri), query) => (uri, query),
+            _ => return Err(HpptError::Parsing),
+        };
+
+        self.target = match self.target {
+            RequestTarget::Absolute(authority, _) => RequestTarget::Absolute(authority, target),
+            _ => RequestTarget::Origin(target),
+        };
+        self.raw_target = raw;
+        self.query = query;
+        Ok(self)
+    }
+
+    /// The path the request is for, or `None` if it isn't for any one path.
+    pub fn uri(&self) -> Option<&Uri<'a>> {
+        match self.target {
+            RequestTarget::Origin(ref uri) |
+            RequestTarget::Absolute(_, ref uri) => Some(uri),
+            RequestTarget::Asterisk => None,
+        }
     }
 
     pub fn query(&self) -> Option<&Query> {
         self.query.as_ref()
     }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers<'a> {
+        &self.headers
+    }
+
+    /// Value of the first header with a matching (case-insensitive) name, whitespace-trimmed.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers.get(name)
+    }
+
+    /// The host (and maybe port) the request is for: the authority of an absolute-form target,
+    /// which takes precedence over the Host header (RFC 7230 section 5.4), or else the Host
+    /// header.
+    fn authority(&self) -> Option<&'a str> {
+        match self.target {
+            RequestTarget::Absolute(authority, _) => Some(authority),
+            _ => self.header("Host"),
+        }
+    }
+
+    /// The host name the request is for, from its target if that's in absolute form or else its
+    /// Host header: without any port or trailing dot, and lowercased, e.g. `example.com` for
+    /// `Example.COM.:8080`. An internationalized name comes in its ASCII form, whichever form it
+    /// was sent in, and one which isn't valid counts as none.
+    pub fn host(&self) -> Option<String> {
+        let host = match self.authority() {
+            Some(h) if !h.is_empty() => h,
+            _ => return None,
+        };
+
+        // an IPv6 literal has colons of its own
+        let end = if host.starts_with('[') {
+            host.find(']').map_or(host.len(), |i| i + 1)
+        } else {
+            host.find(':').unwrap_or(host.len())
+        };
+
+        let host = host[..end].trim_end_matches('.');
+        if host.starts_with('[') {
+            Some(host.to_ascii_lowercase())
+        } else {
+            idna::to_ascii(host)
+        }
+    }
+
+    /// The port the request is for, from the same place as `host`, if it names one.
+    pub fn port(&self) -> Option<u16> {
+        let host = match self.authority() {
+            Some(h) => h,
+            None => return None,
+        };
+
+        let after_name = if host.starts_with('[') {
+            host.find(']').map_or("", |i| &host[i + 1..])
+        } else {
+            host.find(':').map_or("", |i| &host[i..])
+        };
+
+        if after_name.starts_with(':') {
+            after_name[1..].parse().ok()
+        } else {
+            None
+        }
+    }
+
+    /// Whether the body is sent in chunks (RFC 7230 section 4.1), rather than all at once.
+    pub fn is_chunked(&self) -> bool {
+        !self.headers.transfer_codings().is_empty()
+    }
+
+    /// Length of the body according to the Content-Length header, zero if there isn't one. The
+    /// same length may be repeated (in several fields, or a comma-separated list), but lengths
+    /// which differ, or aren't just digits, are an error.
+    pub fn content_length(&self) -> HpptResult<usize> {
+        let mut length = None;
+
+        for value in self.headers.get_all("Content-Length") {
+            for l in value.split(',').map(|l| l.trim()) {
+                if l.is_empty() || !l.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(HpptError::Parsing);
+                }
+
+                let l = try!(l.parse::<usize>().map_err(|_| HpptError::Parsing));
+                match length {
+                    Some(other) if other != l => return Err(HpptError::Parsing),
+                    _ => length = Some(l),
+                }
+            }
+        }
+
+        Ok(length.unwrap_or(0))
+    }
+
+    /// The entity-tags listed in an If-None-Match header (quotes and any `W/` included), or just
+    /// `*`.
+    pub fn if_none_match(&self) -> Option<Vec<&'a str>> {
+        self.header("If-None-Match").map(|v| {
+            v.split(',')
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty())
+                .collect()
+        })
+    }
+
+    /// The If-Modified-Since date in seconds since the Unix epoch, if there's one we can parse.
+    pub fn if_modified_since(&self) -> Option<i64> {
+        self.header("If-Modified-Since").and_then(http_date::parse)
+    }
+
+    /// The byte range asked for by a Range header, if there's one we understand. RFC 7233 has us
+    /// ignore (and so serve the whole body for) other units, malformed ranges and, since we don't
+    /// do multipart responses, requests for more than one range.
+    pub fn range(&self) -> Option<ByteRange> {
+        let value = match self.header("Range") {
+            Some(v) => v,
+            None => return None,
+        };
+
+        if value.len() < 6 || !value[..6].eq_ignore_ascii_case("bytes=") || value.contains(',') {
+            return None;
+        }
+
+        let mut positions = value[6..].trim().splitn(2, '-');
+
+        match (positions.next(), positions.next()) {
+            (Some(""), Some(suffix)) => suffix.parse().ok().map(ByteRange::Suffix),
+            (Some(first), Some("")) => first.parse().ok().map(ByteRange::From),
+            (Some(first), Some(last)) => {
+                match (first.parse(), last.parse()) {
+                    (Ok(first), Ok(last)) if first <= last => Some(ByteRange::FromTo(first, last)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the client is happy for the connection to stay open after the response, which is
+    /// the default in HTTP/1.1 unless it sends `Connection: close`.
+    pub fn keep_alive(&self) -> bool {
+        !self.headers.connection_options().iter().any(|o| o.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// A single range from a Range header, e.g. `bytes=0-499`, `bytes=500-` or `bytes=-500`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ByteRange {
+    /// First and last byte positions, inclusive.
+    FromTo(u64, u64),
+    /// Everything from this position on.
+    From(u64),
+    /// The last so many bytes.
+    Suffix(u64),
 }
 
+impl ByteRange {
+    /// The first and last (inclusive) positions this range covers in a body of `len` bytes, or
+    /// `None` if it doesn't overlap the body at all.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => Some((first, last.min(len - 1))),
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Suffix(n) if n > 0 && len > 0 => Some((len - n.min(len), len - 1)),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a header line is a token, a colon, and a value without control characters. This rules
+/// out whitespace before the colon and obsolete line folding, among other things.
+fn is_strict_header_line(line: &[u8]) -> bool {
+    let colon = match line.iter().position(|&b| b == b':') {
+        Some(c) => c,
+        None => return false,
+    };
+
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+    !name.is_empty() && name.iter().all(|&b| is_tchar(b)) &&
+    value.iter().all(|&b| b == b'\t' || (b >= b' ' && b != 0x7f))
+}
+
+/// Whether a header line (its line ending already stripped) can't be read differently by
+/// different parsers: it has no CR left in it, and no whitespace before its colon.
+fn is_unambiguous_header_line(line: &[u8]) -> bool {
+    if line.contains(&b'\r') {
+        return false;
+    }
+
+    match line.iter().position(|&b| b == b':') {
+        Some(colon) if colon > 0 => line[colon - 1] != b' ' && line[colon - 1] != b'\t',
+        _ => true,
+    }
+}
+
+/// Whether a Host header's value is a host (a name, an IPv4 address or a bracketed IPv6 address)
+/// with an optional port, or empty, as it may be for a target without one (RFC 7230 section 5.4).
+fn is_valid_host(value: &str) -> bool {
+    let (name, port) = if value.starts_with('[') {
+        match value.find(']') {
+            Some(end) if value[1..end].parse::<Ipv6Addr>().is_ok() => ("", &value[end + 1..]),
+            _ => return false,
+        }
+    } else {
+        match value.find(':') {
+            Some(colon) => (&value[..colon], &value[colon..]),
+            None => (value, ""),
+        }
+    };
+
+    // internationalized names are taken as they are, as well as in their ASCII form
+    let name_ok = name.bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b >= 0x80 || b"-._~%!$&'()*+,;=".contains(&b));
+    let port_ok = port.is_empty() ||
+                  (port.starts_with(':') && port[1..].bytes().all(|b| b.is_ascii_digit()));
+
+    name_ok && port_ok
+}
+
+/// Decode `%XX` escapes (and, in query strings, `+` as a space), rejecting the whole thing if an
+/// escape is malformed, encodes a NUL, or the result isn't UTF-8.
+///
+/// Borrows when there's nothing to decode.
+pub fn percent_decode(s: &str, plus_as_space: bool) -> HpptResult<Cow<str>> {
+    if !s.contains('%') && !(plus_as_space && s.contains('+')) {
+        return Ok(Cow::Borrowed(s));
+    }
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let escape = match bytes.get(i + 1..i + 3) {
+                    Some(hex) => from_utf8(hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()),
+                    None => None,
+                };
+
+                match escape {
+                    // u8::from_str_radix would take a sign too
+                    Some(b) if b != 0 && bytes[i + 1] != b'+' => decoded.push(b),
+                    _ => return Err(HpptError::Parsing),
+                }
+
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            b => {
+                decoded.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).map(Cow::Owned).map_err(|_| HpptError::Parsing)
+}
+
+/// What a request is for, in one of the forms a request-target can take (RFC 7230, section 5.3).
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Uri<'a>(&'a str);
+pub enum RequestTarget<'a> {
+    /// A path (and maybe a query) on this server, like `/docs/index.html?q`. The path of `/` is
+    /// the empty one.
+    Origin(Uri<'a>),
+    /// `*`, for an `OPTIONS` request asking about the server as a whole rather than any one path.
+    Asterisk,
+    /// A whole `http` or `https` URI, like `http://example.com/docs/index.html?q`, as clients
+    /// talking to a proxy send: its authority (host and maybe port, as in a Host header) and its
+    /// path, which is looked up like that of an origin-form target.
+    Absolute(&'a str, Uri<'a>),
+}
+
+impl<'a> RequestTarget<'a> {
+    /// Parse a raw request-target into the target and its query, failing with `Parsing` if it's
+    /// in none of the forms (or is an absolute URI with some other scheme, or no valid host).
+    fn parse(raw: &'a str) -> HpptResult<(Self, Option<Query<'a>>)> {
+        if raw == "*" {
+            return Ok((RequestTarget::Asterisk, None));
+        }
+
+        // joining this path onto an OS path won't work if it has a preceding slash
+        if raw.starts_with('/') {
+            let (uri, query) = try!(parse_path(&raw[1..]));
+            return Ok((RequestTarget::Origin(uri), query));
+        }
+
+        let rest = match raw.find("://") {
+            Some(i) if raw[..i].eq_ignore_ascii_case("http") ||
+                       raw[..i].eq_ignore_ascii_case("https") => &raw[i + 3..],
+            _ => return Err(HpptError::Parsing),
+        };
+
+        // the path may be left out altogether, as in `http://example.com` or `http://a?q`
+        let authority_len = rest.find(|c| c == '/' || c == '?').unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(authority_len);
+        if authority.is_empty() || !is_valid_host(authority) {
+            return Err(HpptError::Parsing);
+        }
+
+        let path = if path.starts_with('/') { &path[1..] } else { path };
+        let (uri, query) = try!(parse_path(path));
+
+        Ok((RequestTarget::Absolute(authority, uri), query))
+    }
+}
+
+/// Split the part of a target after the path's leading slash into the decoded path and its
+/// query.
+fn parse_path(raw: &str) -> HpptResult<(Uri, Option<Query>)> {
+    let mut halves = raw.splitn(2, '?');
+
+    // split off the query before decoding, so an escaped ? stays in the path
+    let uri = Uri(try!(percent_decode(halves.next().unwrap(), false)));
+
+    // but the post ? part of the target is optional
+    let query = match halves.next() {
+        Some(q) if q.len() > 0 => Some(Query(q)),
+        _ => None,
+    };
+
+    Ok((uri, query))
+}
+
+/// The (percent-decoded, slash-stripped) path of a request, or one it leads to (an index file, a
+/// precompressed sibling, ...), as a sequence of `/`-separated segments.
+///
+/// Decoding may turn up `/`, `..` or a leading slash which weren't visible in the raw target, so
+/// a path is only ever looked up through `under`, which won't leave the directory it's given.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Uri<'a>(Cow<'a, str>);
 
 impl<'a> Deref for Uri<'a> {
     type Target = str;
 
     fn deref(&self) -> &str {
-        &self.0
+        &*self.0
+    }
+}
+
+impl<'a, 'b> PartialEq<&'b str> for Uri<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl<'a> Uri<'a> {
+    /// A (slash-stripped, already decoded) path from somewhere other than a request line.
+    pub fn new<P: Into<Cow<'a, str>>>(path: P) -> Self {
+        Uri(path.into())
+    }
+
+    /// This path, no longer borrowed from the request it was in.
+    pub fn into_owned(self) -> Uri<'static> {
+        Uri(Cow::Owned(self.0.into_owned()))
+    }
+
+    /// The path as a relative filesystem-style path, as content sources look things up by.
+    pub fn as_path(&self) -> &Path {
+        Path::new(&*self.0)
+    }
+
+    /// The non-empty segments, e.g. `docs` and `page.html` for `docs/page.html`.
+    pub fn segments(&self) -> Vec<&str> {
+        self.0.split('/').filter(|s| !s.is_empty()).collect()
+    }
+
+    /// The last segment, unless the path names a directory (it's empty or ends in a slash).
+    pub fn file_name(&self) -> Option<&str> {
+        match self.0.rsplit('/').next() {
+            Some(name) if !name.is_empty() => Some(name),
+            _ => None,
+        }
+    }
+
+    /// What follows the last dot in `file_name`, if there's one which doesn't start it.
+    pub fn extension(&self) -> Option<&str> {
+        self.file_name().and_then(|name| match name.rfind('.') {
+            Some(0) | None => None,
+            Some(i) => Some(&name[i + 1..]),
+        })
+    }
+
+    /// The directory this path is in, ending in a slash unless it's the root (the empty path):
+    /// `docs/` for `docs/page.html` or `docs/api/`. The root has none.
+    pub fn parent(&self) -> Option<Uri<'static>> {
+        let trimmed = self.0.trim_end_matches('/');
+        if trimmed.is_empty() {
+            return None;
+        }
+
+        let parent = match trimmed.rfind('/') {
+            Some(i) => trimmed[..i + 1].to_owned(),
+            None => String::new(),
+        };
+
+        Some(Uri(parent.into()))
+    }
+
+    /// The path of `name` in the directory this path names: `docs/index.html` for `index.html`
+    /// in `docs/` (or `docs`).
+    pub fn join(&self, name: &str) -> Uri<'static> {
+        let dir = self.0.trim_end_matches('/');
+        let name = name.trim_start_matches('/');
+
+        if dir.is_empty() {
+            Uri(name.to_owned().into())
+        } else {
+            Uri(format!("{}/{}", dir, name).into())
+        }
+    }
+
+    /// This path with `suffix` added to its last segment, e.g. `page.html.gz` for `.gz`.
+    pub fn with_suffix(&self, suffix: &str) -> Uri<'static> {
+        Uri(format!("{}{}", self.0, suffix).into())
+    }
+
+    /// Each way of splitting the path at a segment boundary, shortest first part first: for
+    /// `cgi-bin/app.py/users`, `cgi-bin` and `/app.py/users`, then `cgi-bin/app.py` and `/users`,
+    /// then the whole path and nothing.
+    pub fn splits(&self) -> Vec<(&str, &str)> {
+        self.0
+            .match_indices('/')
+            .map(|(i, _)| i)
+            .chain(Some(self.0.len()))
+            .map(|end| self.0.split_at(end))
+            .collect()
+    }
+
+    /// The full path under `root` this names, or `None` if it might not be under it at all: if a
+    /// segment (other than a trailing one) is empty, as for an absolute path, or is `.` or `..`,
+    /// or if there's a NUL anywhere in it.
+    pub fn under(&self, root: &Path) -> Option<PathBuf> {
+        let segments = self.0.split('/').collect::<Vec<_>>();
+        let (last, init) = segments.split_last().unwrap();
+
+        let unsafe_segment = |s: &&str| s.is_empty() || *s == "." || *s == ".." || s.contains('\0');
+        if init.iter().any(&unsafe_segment) || (!last.is_empty() && unsafe_segment(last)) {
+            return None;
+        }
+
+        // a trailing slash stays, so that only a directory can be found there
+        let mut full_path = root.to_path_buf();
+        full_path.extend(&segments);
+
+        Some(full_path)
+    }
+
+    /// Whether the (slash-stripped) path contains empty segments, as in `foo//bar` or `/foo`. A
+    /// single trailing slash doesn't count.
+    pub fn has_empty_segments(&self) -> bool {
+        let segments = self.0.split('/').collect::<Vec<_>>();
+        segments[..segments.len() - 1].iter().any(|s| s.is_empty())
+    }
+
+    /// The path with any empty segments dropped: `/foo//bar/` becomes `foo/bar/`.
+    pub fn collapse_empty_segments(&self) -> Uri<'static> {
+        let mut collapsed = self.segments().join("/");
+
+        if self.0.ends_with('/') && !collapsed.is_empty() {
+            collapsed.push('/');
+        }
+
+        Uri(collapsed.into())
     }
 }
 
+/// The raw, still-encoded query string of a request, as CGI scripts expect to be handed it.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Query<'a>(&'a str);
 
@@ -170,6 +1045,24 @@ impl<'a> Deref for Query<'a> {
     }
 }
 
+impl<'a> Query<'a> {
+    /// The decoded `name=value` pairs of a form-encoded query, in order. A name without a value
+    /// gets an empty one.
+    pub fn params(&self) -> HpptResult<Vec<(Cow<'a, str>, Cow<'a, str>)>> {
+        let mut params = Vec::new();
+
+        for pair in self.0.split('&').filter(|p| !p.is_empty()) {
+            let mut halves = pair.splitn(2, '=');
+            let name = try!(percent_decode(halves.next().unwrap(), true));
+            let value = try!(percent_decode(halves.next().unwrap_or(""), true));
+
+            params.push((name, value));
+        }
+
+        Ok(params)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Method {
     Options,
@@ -183,10 +1076,16 @@ pub enum Method {
 }
 
 impl Method {
+    /// Parse a method token, failing with `Parsing` if it isn't a valid token at all and with
+    /// `UnknownMethod` if it's a method we don't know.
     pub fn from_bytes(method: &[u8]) -> HpptResult<Self> {
 
         // TODO find a way to differentiate between a partially filled version and an incorrect one
 
+        if method.is_empty() || !method.iter().all(|&b| is_tchar(b)) {
+            return Err(HpptError::Parsing);
+        }
+
         match method {
             b"OPTIONS" => Ok(Method::Options),
             b"GET" => Ok(Method::Get),
@@ -196,7 +1095,7 @@ impl Method {
             b"DELETE" => Ok(Method::Delete),
             b"TRACE" => Ok(Method::Trace),
             b"CONNECT" => Ok(Method::Connect),
-            _ => Err(HpptError::Parsing),
+            _ => Err(HpptError::UnknownMethod),
         }
     }
 
@@ -233,57 +1132,76 @@ impl Version {
 
 #[cfg(test)]
 mod test {
+    use std::str;
+
+    use error::HpptError;
     use super::*;
 
+    fn lenient(bytes: &[u8]) -> HpptResult<Request> {
+        Request::from_bytes(bytes, &Limits::default(), ParseMode::Lenient)
+    }
+
+    fn strict(bytes: &[u8]) -> HpptResult<Request> {
+        Request::from_bytes(bytes, &Limits::default(), ParseMode::Strict)
+    }
+
     #[test]
     fn successful_get() {
-        let request_bytes = "GET / HTTP/1.1\r\n\r\n".as_bytes();
+        let request_bytes = "GET / HTTP/1.1\r\nHost: a\r\n\r\n".as_bytes();
         let expected = Request {
             method: Method::Get,
-            uri: Uri(""),
+            raw_target: "/",
+            target: RequestTarget::Origin(Uri("".into())),
             query: None,
             version: Version::OneDotOne,
             body: b"",
-            header_lines: Vec::new(),
+            headers: Headers::from(vec![("Host", "a")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
 
     #[test]
     fn successful_post() {
-        let request_bytes = "POST /posturi HTTP/1.1\r\n\r\nKey1=Value1&Key2=Value2+SpacedValue"
+        let request_bytes = "POST /posturi HTTP/1.1\r\nHost: a\r\n\r\n\
+                             Key1=Value1&Key2=Value2+SpacedValue"
             .as_bytes();
         let expected = Request {
             method: Method::Post,
-            uri: Uri("posturi"),
+            raw_target: "/posturi",
+            target: RequestTarget::Origin(Uri("posturi".into())),
             query: None,
             version: Version::OneDotOne,
             body: b"Key1=Value1&Key2=Value2+SpacedValue",
-            header_lines: Vec::new(),
+            headers: Headers::from(vec![("Host", "a")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
 
     #[test]
     fn successful_with_headers() {
-        let request_bytes = "GET /extended/path HTTP/1.1\r\nAccept-Charset: utf-8\r\n\r\n"
+        let request_bytes = "GET /extended/path HTTP/1.1\r\nHost: a\r\n\
+                             Accept-Charset: utf-8\r\n\r\n"
             .as_bytes();
         let expected = Request {
             method: Method::Get,
-            uri: Uri("extended/path"),
+            raw_target: "/extended/path",
+            target: RequestTarget::Origin(Uri("extended/path".into())),
             query: None,
             version: Version::OneDotOne,
             body: b"",
-            header_lines: vec!["Accept-Charset: utf-8"],
+            headers: Headers::from(vec![("Host", "a"), ("Accept-Charset", "utf-8")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
@@ -291,20 +1209,23 @@ mod test {
     #[test]
     fn successful_with_query() {
         let request_bytes = "GET /extended/path?key1=val1&key2=val2 HTTP/1.1\r
+Host: a\r
 Accept-Charset: utf-8\r
 \r
 "
             .as_bytes();
         let expected = Request {
             method: Method::Get,
-            uri: Uri("extended/path"),
+            raw_target: "/extended/path?key1=val1&key2=val2",
+            target: RequestTarget::Origin(Uri("extended/path".into())),
             query: Some(Query("key1=val1&key2=val2")),
             version: Version::OneDotOne,
             body: b"",
-            header_lines: vec!["Accept-Charset: utf-8"],
+            headers: Headers::from(vec![("Host", "a"), ("Accept-Charset", "utf-8")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
@@ -312,38 +1233,44 @@ Accept-Charset: utf-8\r
     #[test]
     fn successful_with_empty_query() {
         let request_bytes = "GET /extended/path? HTTP/1.1\r
+Host: a\r
 Accept-Charset: utf-8\r
 \r
 "
             .as_bytes();
         let expected = Request {
             method: Method::Get,
-            uri: Uri("extended/path"),
+            raw_target: "/extended/path?",
+            target: RequestTarget::Origin(Uri("extended/path".into())),
             query: None,
             version: Version::OneDotOne,
             body: b"",
-            header_lines: vec!["Accept-Charset: utf-8"],
+            headers: Headers::from(vec![("Host", "a"), ("Accept-Charset", "utf-8")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
 
     #[test]
     fn successful_get_ignore_body() {
-        let request_bytes = "GET /extended/path HTTP/1.1\r\nAccept-Charset: utf-8\r\n\r\n"
+        let request_bytes = "GET /extended/path HTTP/1.1\r\nHost: a\r\n\
+                             Accept-Charset: utf-8\r\n\r\n"
             .as_bytes();
         let expected = Request {
             method: Method::Get,
-            uri: Uri("extended/path"),
+            raw_target: "/extended/path",
+            target: RequestTarget::Origin(Uri("extended/path".into())),
             query: None,
             version: Version::OneDotOne,
             body: b"",
-            header_lines: vec!["Accept-Charset: utf-8"],
+            headers: Headers::from(vec![("Host", "a"), ("Accept-Charset", "utf-8")]),
+            addrs: None,
         };
 
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
 
         assert_eq!(request, expected);
     }
@@ -352,49 +1279,498 @@ Accept-Charset: utf-8\r
     #[should_panic]
     fn fail_empty() {
         let request_bytes = "".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_only_newlines() {
         let request_bytes = "\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_bad_version() {
         let request_bytes = "GET / HTTP/0.9\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_bad_method() {
         let request_bytes = "HRY / HTTP/1.1\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_no_method() {
         let request_bytes = " / HTTP/1.1\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_missing_uri() {
         let request_bytes = "GET HTTP/1.1\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
     }
 
     #[test]
     #[should_panic]
     fn fail_empty_uri() {
         let request_bytes = "GET  HTTP/1.1\r\n\r\n".as_bytes();
-        let request = Request::from_bytes(&request_bytes).unwrap();
+        let request = lenient(&request_bytes).unwrap();
+    }
+
+    #[test]
+    fn header_lookup() {
+        let request_bytes = "GET / HTTP/1.1\r\nAccept-Language:  de, en;q=0.5 \r\nHost: a\r\n\r\n"
+            .as_bytes();
+
+        let request = lenient(&request_bytes).unwrap();
+
+        assert_eq!(request.header("accept-language"), Some("de, en;q=0.5"));
+        assert_eq!(request.header("HOST"), Some("a"));
+        assert_eq!(request.header("Accept"), None);
+    }
+
+    #[test]
+    fn accessors() {
+        let request_bytes = "GET /a/b?c=d HTTP/1.1\r\nHost: a\r\nAccept: */*\r\n\r\n".as_bytes();
+
+        let request = lenient(&request_bytes).unwrap();
+
+        assert_eq!(request.raw_target(), "/a/b?c=d");
+        assert_eq!(request.version(), Version::OneDotOne);
+        assert_eq!(request.headers().iter().collect::<Vec<_>>(),
+                   vec![&("Host", "a"), &("Accept", "*/*")]);
+    }
+
+    #[test]
+    fn header_count_limit() {
+        let request_bytes = "GET / HTTP/1.1\r\nHost: a\r\nB: 2\r\nC: 3\r\n\r\n".as_bytes();
+
+        let mut limits = Limits::default();
+        limits.max_headers = 3;
+
+        let request = Request::from_bytes(&request_bytes, &limits, ParseMode::Lenient).unwrap();
+        assert_eq!(request.headers().iter().count(), 3);
+
+        limits.max_headers = 2;
+        match Request::from_bytes(&request_bytes, &limits, ParseMode::Lenient) {
+            Err(HpptError::TooManyHeaders) => (),
+            other => panic!("expected TooManyHeaders, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("plain/path", false).unwrap(), "plain/path");
+        assert_eq!(percent_decode("a%20b/gr%C3%BC%c3%9fe", false).unwrap(), "a b/grüße");
+        assert_eq!(percent_decode("a%2Fb+c", false).unwrap(), "a/b+c");
+        assert_eq!(percent_decode("a+b%2B", true).unwrap(), "a b+");
+
+        for bad in &["%00", "%", "%2", "%zz", "%+1", "%C3", "%FF"] {
+            assert!(percent_decode(bad, false).is_err(), "{} should be rejected", bad);
+        }
+
+        let request = lenient(b"GET /some%20dir/%3Fodd%25name?q=%3F HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        assert_eq!(&**request.uri().unwrap(), "some dir/?odd%name");
+        assert_eq!(&**request.query().unwrap(), "q=%3F");
+        assert_eq!(request.raw_target(), "/some%20dir/%3Fodd%25name?q=%3F");
+
+        assert!(lenient(b"GET /nul%00byte HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+
+        let request = lenient(b"GET /?a=1+2&b=%26&c HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.query().unwrap().params().unwrap(),
+                   vec![("a".into(), "1 2".into()),
+                        ("b".into(), "&".into()),
+                        ("c".into(), "".into())]);
+    }
+
+    #[test]
+    fn targets() {
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(*request.target(), RequestTarget::Origin(Uri("".into())));
+        assert_eq!(&**request.uri().unwrap(), "");
+
+        let request = lenient(b"OPTIONS * HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(*request.target(), RequestTarget::Asterisk);
+        assert_eq!(request.uri(), None);
+        assert_eq!(request.query(), None);
+
+        // only the first ? starts the query, and any after it are part of it
+        let request = lenient(b"GET /old?next=/a?b=1 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(&**request.uri().unwrap(), "old");
+        assert_eq!(&**request.query().unwrap(), "next=/a?b=1");
+
+        // the target's authority wins over the Host header
+        let request = lenient(b"GET HTTP://Example.com:8080/some%20dir/?q HTTP/1.1\r\n\
+                                Host: a\r\n\r\n")
+            .unwrap();
+        assert_eq!(*request.target(),
+                   RequestTarget::Absolute("Example.com:8080", Uri("some dir/".into())));
+        assert_eq!(&**request.query().unwrap(), "q");
+        assert_eq!(request.host(), Some("example.com".to_owned()));
+        assert_eq!(request.port(), Some(8080));
+
+        assert_eq!(request.raw_path(), "/some%20dir/");
+
+        for (target, path) in &[("https://[::1]", ""), ("http://a?q", ""), ("http://a/b", "b")] {
+            let request = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", target);
+            let request = lenient(request.as_bytes()).unwrap();
+            assert_eq!(&**request.uri().unwrap(), *path);
+            assert_eq!(request.raw_path(), format!("/{}", path).trim_end_matches('/'));
+        }
+
+        for bad in &["foo", "?q", "**", "*/", "ftp://a/", "http://", "http:///a",
+                     "http://user@a/", "http://a\"b/", "http://a:x/"] {
+            let request = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", bad);
+            assert!(lenient(request.as_bytes()).is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn rewritten_targets() {
+        let request = lenient(b"POST /old/a%20b?q HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.raw_path(), "/old/a%20b");
+        let request = request.with_target("/new/a%20b?r").unwrap();
+        assert_eq!(request.method(), Method::Post);
+        assert_eq!(request.raw_target(), "/new/a%20b?r");
+        assert_eq!(&**request.uri().unwrap(), "new/a b");
+        assert_eq!(&**request.query().unwrap(), "r");
+
+        let request = lenient(b"GET http://b/old HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        let request = request.with_target("/new").unwrap();
+        assert_eq!(*request.target(), RequestTarget::Absolute("b", Uri("new".into())));
+        assert_eq!(request.host(), Some("b".to_owned()));
+
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert!(request.with_target("http://b/").is_err());
+    }
+
+    #[test]
+    fn uri_paths() {
+        let page = Uri::new("docs/api/page.html");
+        assert_eq!(page.segments(), ["docs", "api", "page.html"]);
+        assert_eq!(page.file_name(), Some("page.html"));
+        assert_eq!(page.extension(), Some("html"));
+        assert_eq!(page.parent(), Some(Uri::new("docs/api/")));
+        assert_eq!(page.with_suffix(".gz"), "docs/api/page.html.gz");
+        assert_eq!(page.splits(),
+                   [("docs", "/api/page.html"),
+                    ("docs/api", "/page.html"),
+                    ("docs/api/page.html", "")]);
+
+        let dir = Uri::new("docs/");
+        assert_eq!(dir.file_name(), None);
+        assert_eq!(dir.extension(), None);
+        assert_eq!(dir.parent(), Some(Uri::new("")));
+        assert_eq!(dir.join("index.html"), "docs/index.html");
+        assert_eq!(Uri::new("docs").join("index.html"), "docs/index.html");
+
+        let root = Uri::new("");
+        assert_eq!(root.parent(), None);
+        assert_eq!(root.join("index.html"), "index.html");
+
+        assert_eq!(Uri::new(".htaccess").extension(), None);
+        assert_eq!(Uri::new("archive.tar.gz").extension(), Some("gz"));
+    }
+
+    #[test]
+    fn uri_under_root() {
+        let root = Path::new("/srv/www");
+        assert_eq!(Uri::new("docs/page.html").under(root),
+                   Some(PathBuf::from("/srv/www/docs/page.html")));
+        assert_eq!(Uri::new("").under(root), Some(PathBuf::from("/srv/www/")));
+        assert_eq!(Uri::new("docs/").under(root).unwrap().to_str(), Some("/srv/www/docs/"));
+
+        for escape in &["..", "docs/../../etc/passwd", "/etc/passwd", "docs//page.html", "./docs",
+                        "docs/.", "nul\0byte"] {
+            assert_eq!(Uri::new(*escape).under(root), None, "{:?} should be refused", escape);
+        }
+    }
+
+    #[test]
+    fn empty_segments() {
+        assert!(!Uri("".into()).has_empty_segments());
+        assert!(!Uri("foo/bar".into()).has_empty_segments());
+        assert!(!Uri("foo/bar/".into()).has_empty_segments());
+        assert!(Uri("foo//bar".into()).has_empty_segments());
+        assert!(Uri("/etc/passwd".into()).has_empty_segments());
+        assert!(Uri("foo//".into()).has_empty_segments());
+
+        assert_eq!(Uri("/etc//passwd".into()).collapse_empty_segments(), "etc/passwd");
+        assert_eq!(Uri("foo//bar//".into()).collapse_empty_segments(), "foo/bar/");
+        assert_eq!(Uri("//".into()).collapse_empty_segments(), "");
+    }
+
+    #[test]
+    fn method_validation() {
+        match Method::from_bytes(b"BREW") {
+            Err(HpptError::UnknownMethod) => (),
+            other => panic!("expected UnknownMethod, got {:?}", other),
+        }
+
+        match Method::from_bytes(b"G(T") {
+            Err(HpptError::Parsing) => (),
+            other => panic!("expected Parsing, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn method_prefix() {
+        assert!(check_method_prefix(b"GE").is_ok());
+        assert!(check_method_prefix(b"GET /\x00").is_ok());
+        assert!(check_method_prefix(b"SOMELONGEXTENSION").is_err());
+        assert!(check_method_prefix(b"G\r\n").is_err());
+
+        match check_method_prefix(&[b'A'; 17]) {
+            Err(HpptError::UnknownMethod) => (),
+            other => panic!("expected UnknownMethod, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn framing() {
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\n"), None);
+        assert_eq!(head_len(b"GET / HTTP/1.1\r\nHost: a\r\n\r\nGET"), Some(27));
+        assert_eq!(head_len(b"GET / HTTP/1.1\n\n"), Some(16));
+
+        let pipelined = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc\
+                          GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        let mode = ParseMode::Lenient;
+        let limits = Limits::default();
+        assert_eq!(request_len(pipelined, &limits, mode).unwrap(), Some(50));
+        assert_eq!(request_len(&pipelined[..49], &limits, mode).unwrap(), None);
+
+        let request = lenient(&pipelined[..50]).unwrap();
+        assert_eq!(request.body, b"abc");
+
+        let bad_length = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: lots\r\n\r\n";
+        assert!(request_len(bad_length, &limits, mode).is_err());
+
+        let bare = lenient(b"GET / HTTP/1.1\nHost: a\n\n").unwrap();
+        assert_eq!(bare.body, b"");
+
+        let repeated = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\
+                         Content-Length: 3, 3\r\n\r\nabc";
+        assert_eq!(request_len(repeated, &limits, mode).unwrap(), Some(repeated.len()));
+    }
+
+    #[test]
+    fn chunked() {
+        let limits = Limits::default();
+        let mode = ParseMode::Lenient;
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+                        3;ext=\"x\"\r\nabc\r\n10\r\n0123456789abcdef\r\n0\r\n\
+                        X-Sum: 1\r\nContent-Length: 3\r\nx-forwarded-for: 10.0.0.1\r\n\r\nGET";
+        let len = request.len() - 3;
+
+        assert_eq!(request_len(request, &limits, mode).unwrap(), Some(len));
+        for end in 56..len {
+            assert_eq!(request_len(&request[..end], &limits, mode).unwrap(), None);
+        }
+
+        let decoded = dechunk(&request[..len], &limits, mode).unwrap();
+        assert_eq!(str::from_utf8(&decoded).unwrap(),
+                   "POST / HTTP/1.1\r\nHost: a\r\nX-Sum: 1\r\nContent-Length: 19\r\n\r\n\
+                    abc0123456789abcdef");
+
+        let request = Request::from_bytes(&decoded, &limits, mode).unwrap();
+        assert!(!request.is_chunked());
+        assert_eq!(request.header("X-Sum"), Some("1"));
+        assert_eq!(request.body, b"abc0123456789abcdef");
+
+        // anything else is left alone
+        let plain = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+        assert_eq!(&*dechunk(plain, &limits, mode).unwrap(), &plain[..]);
+
+        let bad: &[&[u8]] = &[b"3\r\nabcd\r\n0\r\n\r\n",
+                              b"3\nabc\n0\n\n",
+                              b"3\r\nabc\r\n0\r\n\n",
+                              b"x\r\n",
+                              b"-1\r\n",
+                              b"\r\n",
+                              b"fffffffffffffffff\r\n",
+                              b"3\rX\r\nabc\r\n0\r\n\r\n",
+                              b"0\r\nX-Sum : 1\r\n\r\n"];
+
+        for body in bad {
+            let mut request = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n"
+                .to_vec();
+            request.extend_from_slice(body);
+            assert!(request_len(&request, &limits, mode).is_err(), "{:?}", body);
+        }
+
+        let codings = |te: &str| {
+            let request = format!("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: {}\r\n\r\n",
+                                  te);
+            lenient(request.as_bytes()).map(|r| r.is_chunked())
+        };
+        assert!(codings("Chunked").unwrap());
+        match codings("gzip, chunked") {
+            Err(HpptError::UnsupportedTransferCoding) => (),
+            other => panic!("expected UnsupportedTransferCoding, got {:?}", other),
+        }
+        assert!(codings("chunked, gzip").is_err());
+        assert!(codings("").is_err());
+    }
+
+    #[test]
+    fn ambiguous_framing() {
+        let ambiguous: &[&[u8]] = &[b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\
+                                      Transfer-Encoding: chunked\r\n\r\n",
+                                    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\
+                                      Content-Length: 4\r\n\r\n",
+                                    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3, 4\r\n\r\n",
+                                    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +3\r\n\r\n",
+                                    b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length:\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nHost: a\rX-Smuggled: b\r\n\r\n",
+                                    b"GET / HTTP/1.1\rHost: a\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length : 3\r\n\r\n",
+                                    b"GET / HTTP/1.1\r\nHost\t: a\r\n\r\n"];
+
+        for request in ambiguous {
+            for mode in &[ParseMode::Lenient, ParseMode::Strict] {
+                match Request::from_bytes(request, &Limits::default(), *mode) {
+                    Err(HpptError::Parsing) => (),
+                    other => panic!("expected Parsing for {:?}, got {:?}", request, other),
+                }
+            }
+        }
+
+        // a CR in the body is none of our business
+        let request = lenient(b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\n\ra");
+        assert_eq!(request.unwrap().body, b"\ra");
+    }
+
+    #[test]
+    fn keep_alive() {
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert!(request.keep_alive());
+
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade, Close\r\n\r\n")
+            .unwrap();
+        assert!(!request.keep_alive());
+
+        // in whichever of several fields it's in
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: Upgrade\r\n\
+                                Connection: close\r\n\r\n")
+            .unwrap();
+        assert!(!request.keep_alive());
+    }
+
+    #[test]
+    fn host() {
+        let host = |request: &[u8]| lenient(request).unwrap().host();
+
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost: Example.COM.:8080\r\n\r\n"),
+                   Some("example.com".to_owned()));
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"), Some("[::1]".to_owned()));
+        assert_eq!(host("GET / HTTP/1.1\r\nHost: Bücher.example:80\r\n\r\n".as_bytes()),
+                   Some("xn--bcher-kva.example".to_owned()));
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost: xn--Bcher-KVA.example\r\n\r\n"),
+                   Some("xn--bcher-kva.example".to_owned()));
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost: xn--.example\r\n\r\n"), None);
+        assert_eq!(host(b"GET / HTTP/1.1\r\nHost:\r\n\r\n"), None);
+
+        let port = |request: &[u8]| lenient(request).unwrap().port();
+        assert_eq!(port(b"GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n"), Some(8080));
+        assert_eq!(port(b"GET / HTTP/1.1\r\nHost: [::1]:8080\r\n\r\n"), Some(8080));
+        assert_eq!(port(b"GET / HTTP/1.1\r\nHost: [::1]\r\n\r\n"), None);
+        assert_eq!(port(b"GET / HTTP/1.1\r\nHost: example.com:\r\n\r\n"), None);
+
+        // an HTTP/1.1 request without exactly one Host we can make sense of is rejected outright
+        let bad: &[&[u8]] = &[b"GET / HTTP/1.1\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: a\r\nhost: a\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: exa\x01mple.com\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: a b\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: a/b\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: user@example.com\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: example.com:80x\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: [::1\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: [example.com]\r\n\r\n",
+                              b"GET / HTTP/1.1\r\nHost: [::1]x\r\n\r\n"];
+
+        for request in bad {
+            match lenient(request) {
+                Err(HpptError::Parsing) => (),
+                other => panic!("expected Parsing for {:?}, got {:?}", request, other),
+            }
+        }
+    }
+
+    #[test]
+    fn strict_mode() {
+        let good = b"GET / HTTP/1.1\r\nHost: example.com\r\nAccept:\t*/*\r\n\r\nbody";
+        assert!(strict(good).is_ok());
+        assert_eq!(strict(good).unwrap().body, b"body");
+
+        let sloppy: &[&[u8]] = &[b"GET / HTTP/1.1\nHost: example.com\n\n",
+                                 b"GET / HTTP/1.1\r\nHost: example.com\n\r\n",
+                                 b"GET / HTTP/1.1\r\nHost: example.com\r\n",
+                                 b"GET / HTTP/1.1 extra\r\nHost: example.com\r\n\r\n",
+                                 b"GET / HTTP/1.1\r\nHost: example.com\r\n folded\r\n\r\n",
+                                 b"GET / HTTP/1.1\r\nHost: example.com\r\nNoColon\r\n\r\n",
+                                 b"GET / HTTP/1.1\r\nHost: example.com\r\nX: a\x01b\r\n\r\n"];
+
+        for request in sloppy {
+            assert!(lenient(request).is_ok(), "{:?}", request);
+
+            match strict(request) {
+                Err(HpptError::Parsing) => (),
+                other => panic!("expected Parsing for {:?}, got {:?}", request, other),
+            }
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let range = |value: &str| {
+            let request = format!("GET / HTTP/1.1\r\nHost: a\r\nRange: {}\r\n\r\n", value);
+            lenient(request.as_bytes()).unwrap().range()
+        };
+
+        assert_eq!(range("bytes=0-499"), Some(ByteRange::FromTo(0, 499)));
+        assert_eq!(range("Bytes=500-"), Some(ByteRange::From(500)));
+        assert_eq!(range("bytes=-500"), Some(ByteRange::Suffix(500)));
+        assert_eq!(range("bytes=5-1"), None);
+        assert_eq!(range("bytes=0-1,5-6"), None);
+        assert_eq!(range("lines=0-1"), None);
+        assert_eq!(range("bytes=x-"), None);
+        assert_eq!(lenient(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n").unwrap().range(), None);
+
+        assert_eq!(ByteRange::FromTo(0, 499).resolve(100), Some((0, 99)));
+        assert_eq!(ByteRange::From(99).resolve(100), Some((99, 99)));
+        assert_eq!(ByteRange::From(100).resolve(100), None);
+        assert_eq!(ByteRange::Suffix(500).resolve(100), Some((0, 99)));
+        assert_eq!(ByteRange::Suffix(10).resolve(100), Some((90, 99)));
+        assert_eq!(ByteRange::Suffix(0).resolve(100), None);
+        assert_eq!(ByteRange::Suffix(10).resolve(0), None);
+    }
+
+    #[test]
+    fn conditional_headers() {
+        let request = lenient(b"GET / HTTP/1.1\r
+Host: a\r
+If-None-Match: \"abc\", W/\"def\",\r
+If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r
+\r
+")
+            .unwrap();
+
+        assert_eq!(request.if_none_match(), Some(vec!["\"abc\"", "W/\"def\""]));
+        assert_eq!(request.if_modified_since(), Some(784111777));
+
+        let request = lenient(b"GET / HTTP/1.1\r\nHost: a\r\nIf-Modified-Since: last week\r\n\r\n")
+            .unwrap();
+        assert_eq!(request.if_none_match(), None);
+        assert_eq!(request.if_modified_since(), None);
     }
 
     // TODO test header parsing
@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::Read;
+use std::time::Duration;
+
+use libc;
+
+use limits::Limits;
+use peers::MAX_TURNED_AWAY;
+use proxy::MAX_MIRRORING;
+
+/// File descriptors the server needs whatever its load: stdio, the listener, mioco's own, and
+/// some slack for files being served.
+const RESERVED_FDS: u64 = 64;
+
+/// Below this many descriptors, a server without a cap on its connections is likely to hit
+/// EMFILE under any real load.
+const COMFORTABLE_FDS: u64 = 1024;
+
+/// Check the limits the host places on the process against what the configured limits may call
+/// for, warning about any which can't be met so an EMFILE or OOM under load doesn't come as a
+/// surprise. `mirroring` is whether any requests are to be copied to shadow upstreams, and
+/// `cache_size` how many bytes of files may be kept in memory, if any are. With `raise_fd_limit`,
+/// the soft limit on open files is raised to the hard limit first.
+pub fn check(limits: &Limits, mirroring: bool, cache_size: Option<usize>, raise_fd_limit: bool) {
+    match fd_limits() {
+        Some((soft, hard)) => {
+            let soft = if raise_fd_limit && soft < hard {
+                match raise_fd_soft_limit(hard) {
+                    Ok(()) => {
+                        info!("Raised the open file limit from {} to {}", soft, hard);
+                        hard
+                    }
+                    Err(why) => {
+                        warn!("Unable to raise the open file limit to {}: {}", hard, why);
+                        soft
+                    }
+                }
+            } else {
+                soft
+            };
+
+            let needed = fds_needed(limits, mirroring);
+            if soft < needed {
+                warn!("The open file limit of {} is below the {} the configured limits may need",
+                      soft,
+                      needed);
+            } else if limits.max_connections.is_none() && soft < COMFORTABLE_FDS {
+                warn!("The open file limit of {} may run out under load (try \
+                       --raise-fd-limit)",
+                      soft);
+            }
+        }
+        None => debug!("Unable to find out the open file limit"),
+    }
+
+    let mut meminfo = String::new();
+    let available = File::open("/proc/meminfo")
+        .and_then(|mut f| f.read_to_string(&mut meminfo))
+        .ok()
+        .and_then(|_| mem_available(&meminfo));
+
+    match (available, memory_needed(limits, cache_size)) {
+        (Some(available), Some(needed)) if available < needed => {
+            warn!("Only {} bytes of memory are available, but CGI output and the file cache \
+                   alone may take up to {}",
+                  available,
+                  needed);
+        }
+        (None, _) => debug!("Unable to find out how much memory is available"),
+        _ => (),
+    }
+}
+
+/// The soft and hard limits on open files.
+fn fd_limits() -> Option<(u64, u64)> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } == 0 {
+        Some((rlim.rlim_cur as u64, rlim.rlim_max as u64))
+    } else {
+        None
+    }
+}
+
+fn raise_fd_soft_limit(to: u64) -> Result<(), String> {
+    let rlim = libc::rlimit {
+        rlim_cur: to as libc::rlim_t,
+        rlim_max: to as libc::rlim_t,
+    };
+
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &rlim) } == 0 {
+        Ok(())
+    } else {
+        Err(format!("{}", ::std::io::Error::last_os_error()))
+    }
+}
+
+/// CPU time the calling thread has used so far, or none if that can't be found out. The
+/// difference across some work is what the work cost, which (unlike the time it took) doesn't
+/// count time when other threads had the CPU.
+pub fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+
+    if unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) } == 0 {
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+/// Descriptors the server may need at once, as far as that's capped: one per connection (and, if
+/// they're capped, per connection being turned away), three (the stdin, stdout and stderr pipes)
+/// per CGI script and one per FastCGI request (and, if `mirroring`, per copy of a request in
+/// flight to a shadow upstream), on top of those it always needs.
+fn fds_needed(limits: &Limits, mirroring: bool) -> u64 {
+    let connections = limits.max_connections.map_or(0, |n| n + MAX_TURNED_AWAY) as u64;
+    let mirrored = if mirroring { MAX_MIRRORING as u64 } else { 0 };
+
+    RESERVED_FDS + connections + mirrored +
+    3 * limits.max_cgi_processes.unwrap_or(0) as u64 +
+    limits.max_fastcgi_requests.unwrap_or(0) as u64
+}
+
+/// Memory the server may need for what it buffers or keeps, as far as that's capped: CGI output,
+/// and the files in the cache, if there is one.
+fn memory_needed(limits: &Limits, cache_size: Option<usize>) -> Option<u64> {
+    let cgi = limits.max_cgi_processes.map(|n| n as u64 * limits.max_cgi_output as u64);
+
+    match (cgi, cache_size) {
+        (None, None) => None,
+        (cgi, cache) => Some(cgi.unwrap_or(0) + cache.unwrap_or(0) as u64),
+    }
+}
+
+/// `MemAvailable` from the contents of `/proc/meminfo`, in bytes.
+fn mem_available(meminfo: &str) -> Option<u64> {
+    meminfo.lines()
+        .find(|l| l.starts_with("MemAvailable:"))
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+#[cfg(test)]
+mod test {
+    use limits::Limits;
+
+    use super::*;
+
+    #[test]
+    fn needs() {
+        let mut limits = Limits::default();
+        assert_eq!(fds_needed(&limits, false), RESERVED_FDS);
+        assert_eq!(memory_needed(&limits, None), None);
+        assert_eq!(memory_needed(&limits, Some(4096)), Some(4096));
+
+        limits.max_cgi_processes = Some(10);
+        limits.max_cgi_output = 1000;
+        assert_eq!(fds_needed(&limits, false), RESERVED_FDS + 30);
+        assert_eq!(memory_needed(&limits, None), Some(10_000));
+        assert_eq!(memory_needed(&limits, Some(4096)), Some(14_096));
+
+        limits.max_connections = Some(500);
+        assert_eq!(fds_needed(&limits, false), RESERVED_FDS + MAX_TURNED_AWAY as u64 + 530);
+
+        limits.max_fastcgi_requests = Some(20);
+        assert_eq!(fds_needed(&limits, false), RESERVED_FDS + MAX_TURNED_AWAY as u64 + 550);
+        assert_eq!(fds_needed(&limits, true),
+                   RESERVED_FDS + MAX_TURNED_AWAY as u64 + MAX_MIRRORING as u64 + 550);
+
+        assert!(fd_limits().is_some());
+    }
+
+    #[test]
+    fn meminfo() {
+        let meminfo = "MemTotal:       16318864 kB\nMemFree:         1853344 kB\n\
+                       MemAvailable:   10342908 kB\nBuffers:          520376 kB\n";
+        assert_eq!(mem_available(meminfo), Some(10342908 * 1024));
+        assert_eq!(mem_available("MemTotal: 1 kB\n"), None);
+    }
+}
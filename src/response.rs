@@ -1,27 +1,132 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::io;
 use std::io::{Read, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mioco;
 
 use error::*;
+use idna;
+use mime;
+use stats::Compression;
 
 pub enum Status {
     Ok,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
     BadRequest,
+    Unauthorized,
+    Forbidden,
     NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    RequestTimeout,
     RequestEntityTooLarge,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
     InternalServerError,
     NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
     HttpVersionNotSupported,
+    /// A status we have no variant for, e.g. one chosen by a CGI script: the code and reason
+    /// phrase.
+    Custom(u16, String),
 }
 
 impl Status {
-    fn status_line(&self) -> &'static [u8] {
-        match *self {
+    fn status_line(&self) -> Cow<'static, [u8]> {
+        let line: &'static [u8] = match *self {
             Status::Ok => b"HTTP/1.1 200 OK\r\n",
+            Status::PartialContent => b"HTTP/1.1 206 Partial Content\r\n",
+            Status::MovedPermanently => b"HTTP/1.1 301 Moved Permanently\r\n",
+            Status::Found => b"HTTP/1.1 302 Found\r\n",
+            Status::NotModified => b"HTTP/1.1 304 Not Modified\r\n",
+            Status::TemporaryRedirect => b"HTTP/1.1 307 Temporary Redirect\r\n",
+            Status::PermanentRedirect => b"HTTP/1.1 308 Permanent Redirect\r\n",
             Status::BadRequest => b"HTTP/1.1 400 Bad Request\r\n",
+            Status::Unauthorized => b"HTTP/1.1 401 Unauthorized\r\n",
+            Status::Forbidden => b"HTTP/1.1 403 Forbidden\r\n",
             Status::NotFound => b"HTTP/1.1 404 Not Found\r\n",
+            Status::MethodNotAllowed => b"HTTP/1.1 405 Method Not Allowed\r\n",
+            Status::NotAcceptable => b"HTTP/1.1 406 Not Acceptable\r\n",
+            Status::RequestTimeout => b"HTTP/1.1 408 Request Timeout\r\n",
             Status::RequestEntityTooLarge => b"HTTP/1.1 413 Request Entity Too Large\r\n",
+            Status::RequestHeaderFieldsTooLarge => {
+                b"HTTP/1.1 431 Request Header Fields Too Large\r\n"
+            }
+            Status::RangeNotSatisfiable => b"HTTP/1.1 416 Range Not Satisfiable\r\n",
+            Status::ExpectationFailed => b"HTTP/1.1 417 Expectation Failed\r\n",
+            Status::MisdirectedRequest => b"HTTP/1.1 421 Misdirected Request\r\n",
+            Status::TooManyRequests => b"HTTP/1.1 429 Too Many Requests\r\n",
             Status::InternalServerError => b"HTTP/1.1 500 Internal Server Error\r\n",
             Status::NotImplemented => b"HTTP/1.1 501 Not Implemented\r\n",
+            Status::BadGateway => b"HTTP/1.1 502 Bad Gateway\r\n",
+            Status::ServiceUnavailable => b"HTTP/1.1 503 Service Unavailable\r\n",
+            Status::GatewayTimeout => b"HTTP/1.1 504 Gateway Timeout\r\n",
             Status::HttpVersionNotSupported => b"HTTP/1.1 505 HTTP Version not supported\r\n",
+            Status::Custom(code, ref reason) => {
+                return Cow::Owned(format!("HTTP/1.1 {} {}\r\n", code, reason).into_bytes())
+            }
+        };
+
+        Cow::Borrowed(line)
+    }
+
+    /// The reason phrase, like `Not Found`.
+    pub fn reason(&self) -> String {
+        let line = self.status_line();
+        let reason = line.splitn(3, |&b| b == b' ').nth(2).unwrap_or(b"");
+        String::from_utf8_lossy(reason).trim_end().to_owned()
+    }
+
+    pub fn code(&self) -> u16 {
+        match *self {
+            Status::Ok => 200,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::NotModified => 304,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::NotAcceptable => 406,
+            Status::RequestTimeout => 408,
+            Status::RequestEntityTooLarge => 413,
+            Status::RangeNotSatisfiable => 416,
+            Status::ExpectationFailed => 417,
+            Status::MisdirectedRequest => 421,
+            Status::TooManyRequests => 429,
+            Status::RequestHeaderFieldsTooLarge => 431,
+            Status::InternalServerError => 500,
+            Status::NotImplemented => 501,
+            Status::BadGateway => 502,
+            Status::ServiceUnavailable => 503,
+            Status::GatewayTimeout => 504,
+            Status::HttpVersionNotSupported => 505,
+            Status::Custom(code, _) => code,
+        }
+    }
+
+    /// Whether a response with this status may carry a body at all.
+    fn allows_body(&self) -> bool {
+        match self.code() {
+            204 | 304 => false,
+            code => code >= 200,
         }
     }
 }
@@ -29,26 +134,101 @@ impl Status {
 pub struct Response {
     status: Status,
     data: Option<Box<Read>>,
+    /// Known up front for bodies which are streamed rather than buffered.
+    data_len: Option<u64>,
+    /// Whether a body of unknown length is streamed in chunks, rather than buffered.
+    chunked: bool,
     content_type: Option<ContentType>,
-    data_includes_headers: bool,
+    headers: Vec<(Cow<'static, str>, String)>,
+    send_body: bool,
+    compression: Option<Compression>,
+    /// Most body bytes to send per second, if the body's to be sent no faster than that.
+    rate_limit: Option<u64>,
 }
 
 impl Response {
-    pub fn new(status: Status,
-               data: Option<Box<Read>>,
-               content_type: Option<ContentType>,
-               data_includes_headers: bool)
-               -> Response {
-
-        Response {
-            status: status,
-            data: data,
-            content_type: content_type,
-            data_includes_headers: data_includes_headers,
+    pub fn builder() -> ResponseBuilder {
+        ResponseBuilder {
+            response: Response {
+                status: Status::Ok,
+                data: None,
+                data_len: None,
+                chunked: false,
+                content_type: None,
+                headers: Vec::new(),
+                send_body: true,
+                compression: None,
+                rate_limit: None,
+            },
         }
     }
 
-    pub fn send<C: Write>(self, mut target: C) -> HpptResult<()> {
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// How the body was compressed, if it was, for the server's stats.
+    pub fn compression(&self) -> Option<&Compression> {
+        self.compression.as_ref()
+    }
+
+    /// Send only the status line and headers (describing the body as if it were sent), as for a
+    /// HEAD request.
+    pub fn without_body(mut self) -> Response {
+        self.send_body = false;
+        self
+    }
+
+    /// The value of a header which has been added to the response, if it has (the first, if more
+    /// than one has been).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0.eq_ignore_ascii_case(name)).map(|h| &h.1[..])
+    }
+
+    /// This response with another header added, for headers which are decided after the response
+    /// has been built.
+    pub fn with_header<N, V>(mut self, name: N, value: V) -> Response
+        where N: Into<Cow<'static, str>>,
+              V: Into<String>
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send the body no faster than `bytes_per_sec`, if that's set, so one big download doesn't
+    /// take up all of the uplink.
+    pub fn with_rate_limit(mut self, bytes_per_sec: Option<u64>) -> Response {
+        self.rate_limit = bytes_per_sec;
+        self
+    }
+
+    /// Whether the response has a body (even an empty one), rather than none at all.
+    pub fn has_body(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Whether the body will go to the client, rather than only be described in the head, as for
+    /// a HEAD or a 304.
+    pub fn sends_body(&self) -> bool {
+        self.send_body && self.status.allows_body() && self.has_body()
+    }
+
+    /// This response with its body replaced by `len` bytes of `data`, for bodies which are decided
+    /// after the response has been built.
+    pub fn with_body<R: Read + 'static>(mut self,
+                                        content_type: ContentType,
+                                        data: R,
+                                        len: u64)
+                                        -> Response {
+        self.content_type = Some(content_type);
+        self.data = Some(Box::new(data));
+        self.data_len = Some(len);
+        self.chunked = false;
+        self
+    }
+
+    /// Write the response, returning the number of body bytes delivered.
+    pub fn send<C: Write>(self, mut target: C) -> HpptResult<usize> {
 
         // from http 1.1 spec:
         //
@@ -68,35 +248,331 @@ impl Response {
 
         let status = self.status.status_line();
 
-        buf.extend_from_slice(status);
+        // a 304 describes the body the client already has, much as a HEAD does
+        let send_body = self.send_body && self.status.allows_body();
 
-        let mut content_buf = Vec::with_capacity(1024);
+        buf.extend_from_slice(&status);
 
-        // write message body (usually file contents) if present
-        if let Some(mut data) = self.data {
-            // shuffle bytes from the data source (usually a file)
-            // to the target (usually a socket)
-            try!(data.read_to_end(&mut content_buf));
-        }
+        // bodies of unknown length have to be buffered to find out their Content-Length, unless
+        // they're to be sent in chunks, and the rest are streamed after the head has been written
+        let mut content_buf = Vec::new();
+        let mut stream = None;
+        let mut chunks = None;
+        let has_body = self.data.is_some();
+
+        let content_len = match (self.data, self.data_len) {
+            (Some(data), None) if self.chunked => {
+                chunks = Some(data);
+                0
+            }
+            (Some(data), Some(len)) => {
+                stream = Some(data.take(len));
+                len
+            }
+            (Some(mut data), None) => {
+                try!(data.read_to_end(&mut content_buf));
+                content_buf.len() as u64
+            }
+            (None, _) => 0,
+        };
 
-        if !self.data_includes_headers {
-            // TODO write any headers here
+        let code = self.status.code();
+        if code < 200 || code == 204 {
+            // these never have a body, so they mustn't be framed as if they did (RFC 7230
+            // sections 3.3.1 and 3.3.2)
+        } else if code == 304 && (chunks.is_some() || !has_body) {
+            // the length of a body which was never worked out (as when answering before
+            // compressing it, or for a body sent in chunks) goes unsaid, as any we gave would be
+            // taken for the body's
+        } else if chunks.is_some() {
+            buf.extend_from_slice(b"Transfer-Encoding: chunked\r\n");
+        } else {
             buf.extend_from_slice(b"Content-Length: ");
-            buf.extend_from_slice(&content_buf.len().to_string().as_bytes());
+            buf.extend_from_slice(&content_len.to_string().as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+
+        if let Some(ct) = self.content_type {
+            buf.extend_from_slice(b"Content-Type: ");
+            buf.extend_from_slice(ct.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
 
-            if let Some(ct) = self.content_type {
-                buf.extend_from_slice(b"\r\nContent-Type: ");
-                buf.extend_from_slice(ct.as_bytes());
+        for (name, value) in self.headers {
+            buf.extend_from_slice(name.as_bytes());
+            buf.extend_from_slice(b": ");
+            buf.extend_from_slice(value.as_bytes());
+            buf.extend_from_slice(b"\r\n");
+        }
+
+        buf.extend_from_slice(b"\r\n");
+
+        let head_len = buf.len();
+
+        // a buffered body goes out along with the head, unless it has to be paced like a stream
+        let paced = self.rate_limit.map(Pace::new);
+        if send_body && paced.is_none() {
+            buf.extend_from_slice(&content_buf);
+        }
+
+        let mut written = 0;
+        let mut result = write_fully(&mut target, &buf, &mut written);
+
+        // the chunks' framing isn't body, so they count what they deliver of it themselves
+        let mut chunked_written = 0;
+
+        if let (Ok(()), true) = (result.as_ref(), send_body) {
+            result = match (stream, chunks, paced) {
+                (_, Some(mut data), pace) => {
+                    stream_chunks(&mut target, &mut *data, pace, &mut chunked_written)
+                }
+                (Some(mut data), _, pace) => {
+                    stream_fully(&mut target, &mut data, content_len, pace, &mut written)
+                }
+                (None, None, Some(pace)) => {
+                    let mut data = &content_buf[..];
+                    stream_fully(&mut target, &mut data, content_len, Some(pace), &mut written)
+                }
+                (None, None, None) => Ok(()),
+            };
+        }
+
+        let result = result.and_then(|_| target.flush());
+        let body_written = if self.chunked {
+            chunked_written
+        } else {
+            written.saturating_sub(head_len)
+        };
+
+        match result {
+            Ok(()) => Ok(body_written),
+            Err(why) => {
+                debug!("Delivered {} of {} body bytes before failing: {:?}",
+                       body_written,
+                       content_len,
+                       why);
+                Err(HpptError::IncompleteWrite(body_written, why))
             }
+        }
+    }
+}
 
-            buf.extend_from_slice(b"\r\n\r\n");
+/// Write all of `buf`, riding out partial writes, interrupts and spurious WouldBlocks, and counting
+/// the bytes which made it out in `written` even if we fail part of the way through.
+fn write_fully<C: Write>(target: &mut C, buf: &[u8], written: &mut usize) -> io::Result<()> {
+    let mut remaining = buf;
+
+    while !remaining.is_empty() {
+        match target.write(remaining) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                          "connection stopped accepting data"))
+            }
+            Ok(n) => {
+                *written += n;
+                remaining = &remaining[n..];
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // let the other coroutines (or threads) run before trying again
+                if mioco::in_coroutine() {
+                    mioco::yield_now();
+                } else {
+                    thread::yield_now();
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// How much of a streamed body to read into memory at once.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Keeps a body to a rate, by pausing between chunks for as long as it takes for the bytes sent
+/// so far not to have been sent too soon.
+struct Pace {
+    bytes_per_sec: u64,
+    started: Instant,
+}
+
+impl Pace {
+    fn new(bytes_per_sec: u64) -> Self {
+        Pace {
+            bytes_per_sec: bytes_per_sec,
+            started: Instant::now(),
         }
+    }
+
+    /// How much to send at once: about a tenth of a second's worth, so the body trickles out
+    /// rather than bursting a whole chunk at a time at low rates.
+    fn chunk_len(&self) -> usize {
+        cmp::max(cmp::min(self.bytes_per_sec / 10, CHUNK_SIZE as u64), 1) as usize
+    }
+
+    /// Wait until `sent` bytes are due to have been sent.
+    fn wait(&self, sent: u64) {
+        let due = self.started + Duration::from_millis(sent * 1000 / self.bytes_per_sec);
+        let now = Instant::now();
+        if due <= now {
+            return;
+        }
+
+        if mioco::in_coroutine() {
+            mioco::sleep(due - now);
+        } else {
+            thread::sleep(due - now);
+        }
+    }
+}
+
+/// Copy exactly `len` bytes from `data` to `target` a chunk at a time (keeping to `pace`, if
+/// there is one), failing if `data` runs out early since we've already promised the client that
+/// many bytes.
+fn stream_fully<C: Write>(target: &mut C,
+                          data: &mut Read,
+                          len: u64,
+                          pace: Option<Pace>,
+                          written: &mut usize)
+                          -> io::Result<()> {
+    let mut chunk = [0; CHUNK_SIZE];
+    let chunk_len = pace.as_ref().map_or(CHUNK_SIZE, Pace::chunk_len);
+    let mut streamed = 0;
+
+    while streamed < len {
+        if let Some(ref pace) = pace {
+            pace.wait(streamed);
+        }
+
+        let n = match data.read(&mut chunk[..chunk_len]) {
+            Ok(0) => {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                          "body ended before its Content-Length"))
+            }
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        try!(write_fully(target, &chunk[..n], written));
+        streamed += n as u64;
+    }
 
-        buf.extend_from_slice(&content_buf);
+    Ok(())
+}
 
-        try!(target.write_all(&buf));
+/// Copy `data` to `target` until it runs out, as chunks (RFC 7230 section 4.1) of whatever each
+/// read turns up (keeping to `pace`, if there is one), then the last chunk to say that's all.
+/// `written` counts the bytes of the body which made it out, leaving out the chunks' framing.
+fn stream_chunks<C: Write>(target: &mut C,
+                           data: &mut Read,
+                           pace: Option<Pace>,
+                           written: &mut usize)
+                           -> io::Result<()> {
+    let mut chunk = [0; CHUNK_SIZE];
+    let chunk_len = pace.as_ref().map_or(CHUNK_SIZE, Pace::chunk_len);
+    let mut framing = 0;
+
+    loop {
+        if let Some(ref pace) = pace {
+            pace.wait(*written as u64);
+        }
 
-        Ok(())
+        let n = match data.read(&mut chunk[..chunk_len]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        try!(write_fully(target, format!("{:x}\r\n", n).as_bytes(), &mut framing));
+        try!(write_fully(target, &chunk[..n], written));
+        try!(write_fully(target, b"\r\n", &mut framing));
+        try!(target.flush());
+    }
+
+    write_fully(target, b"0\r\n\r\n", &mut framing)
+}
+
+/// Incrementally describes a `Response`. Anything not set defaults to a `200 OK` with no body.
+pub struct ResponseBuilder {
+    response: Response,
+}
+
+impl ResponseBuilder {
+    pub fn status(mut self, status: Status) -> Self {
+        self.response.status = status;
+        self
+    }
+
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.response.content_type = Some(content_type);
+        self
+    }
+
+    /// Add a header to be written after Content-Length and Content-Type.
+    pub fn header<N, V>(mut self, name: N, value: V) -> Self
+        where N: Into<Cow<'static, str>>,
+              V: Into<String>
+    {
+        self.response.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Point the client elsewhere, as for a redirect. Header values have to be ASCII, so an
+    /// internationalized host name goes out in its ASCII form.
+    pub fn location(self, location: &str) -> Self {
+        self.header("Location", idna::url_to_ascii(location))
+    }
+
+    /// Describe the body as bytes `first` to `last` (inclusive) of a `total` byte representation,
+    /// as for a 206, or with no range as for a 416 saying how long the representation really is.
+    pub fn content_range(self, range: Option<(u64, u64)>, total: u64) -> Self {
+        let value = match range {
+            Some((first, last)) => format!("bytes {}-{}/{}", first, last, total),
+            None => format!("bytes */{}", total),
+        };
+
+        self.header("Content-Range", value)
+    }
+
+    /// A body of unknown length, which will be buffered in memory to find out its length.
+    pub fn body_reader<R: Read + 'static>(mut self, data: R) -> Self {
+        self.response.data = Some(Box::new(data));
+        self.response.data_len = None;
+        self.response.chunked = false;
+        self
+    }
+
+    /// A body of `len` bytes, which will be streamed to the client rather than buffered.
+    pub fn body_reader_with_length<R: Read + 'static>(mut self, data: R, len: u64) -> Self {
+        self.response.data = Some(Box::new(data));
+        self.response.data_len = Some(len);
+        self.response.chunked = false;
+        self
+    }
+
+    /// A body of unknown length, which will be streamed to the client in chunks, as it's read,
+    /// rather than buffered. Each read which turns anything up is sent straight away, so a body
+    /// which comes a bit at a time (like a script's output) reaches the client as it comes.
+    pub fn body_reader_chunked<R: Read + 'static>(mut self, data: R) -> Self {
+        self.response.data = Some(Box::new(data));
+        self.response.data_len = None;
+        self.response.chunked = true;
+        self
+    }
+
+    /// Note that the body was compressed, and how, for the server's stats. This doesn't add a
+    /// Content-Encoding header of its own.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.response.compression = Some(compression);
+        self
+    }
+
+    pub fn build(self) -> Response {
+        self.response
     }
 }
 
@@ -106,9 +582,14 @@ pub enum ContentType {
     Markdown,
     Pdf,
     Binary,
+    /// A type from the built-in table.
+    Known(&'static str),
+    /// A configured MIME type, possibly with parameters (e.g. `text/csv; charset=latin1`).
+    Custom(String),
 }
 
 impl ContentType {
+    /// The built-in type for a path's extension, or `Binary` for an extension it doesn't know.
     pub fn from_path(path: &str) -> Self {
         let extension_offset = match path.rfind('.') {
             Some(o) => o,
@@ -117,33 +598,110 @@ impl ContentType {
 
         let (_, extension) = path.split_at(extension_offset + 1);
 
-        match extension {
-            "htm" => ContentType::Html,
-            "html" => ContentType::Html,
-            "toml" => ContentType::Text,
-            "txt" => ContentType::Text,
-            "md" => ContentType::Markdown,
-            "pdf" => ContentType::Pdf,
-            _ => ContentType::Binary,
+        match mime::for_extension(extension) {
+            Some(mime) => ContentType::Known(mime),
+            None => ContentType::Binary,
         }
     }
 
-    pub fn as_bytes(&self) -> &'static [u8] {
+    pub fn as_bytes(&self) -> &[u8] {
         match *self {
             ContentType::Html => b"text/html",
             ContentType::Text => b"text/plain",
             ContentType::Pdf => b"application/pdf",
             ContentType::Markdown => b"text/markdown",
             ContentType::Binary => b"application/octet-stream",
+            ContentType::Known(mime) => mime.as_bytes(),
+            ContentType::Custom(ref mime) => mime.as_bytes(),
+        }
+    }
+
+    /// Whether bodies of this type are worth compressing: text, rather than formats which are
+    /// compressed already.
+    pub fn is_compressible(&self) -> bool {
+        let mime = self.as_bytes();
+
+        mime.starts_with(b"text/") || mime.starts_with(b"application/json") ||
+        mime.starts_with(b"application/javascript") || mime.starts_with(b"image/svg+xml")
+    }
+
+    /// This type with a charset parameter added, if it's a text type which doesn't have one yet.
+    pub fn with_charset(self, charset: &str) -> Self {
+        let is_text = self.as_bytes().starts_with(b"text/");
+        let has_charset = self.as_bytes().windows(8).any(|w| w.eq_ignore_ascii_case(b"charset="));
+
+        if is_text && !has_charset {
+            let mime = String::from_utf8_lossy(self.as_bytes()).into_owned();
+            ContentType::Custom(format!("{}; charset={}", mime, charset))
+        } else {
+            self
         }
     }
+
+    /// The value of this type's charset parameter, if it has one.
+    pub fn charset(&self) -> Option<String> {
+        let mime = String::from_utf8_lossy(self.as_bytes()).to_ascii_lowercase();
+
+        mime.find("charset=").map(|i| {
+            let value = &mime[i + "charset=".len()..];
+            let end = value.find(';').unwrap_or(value.len());
+            value[..end].trim().trim_matches('"').to_owned()
+        })
+    }
 }
 
 #[cfg(test)]
 mod test {
+    use std::io;
+    use std::io::{Read, Write};
     use std::str;
+
+    use error::HpptError;
     use super::*;
 
+    /// Accepts at most `chunk` bytes per write, interleaved with interrupts and WouldBlocks, and
+    /// fails outright after `limit` bytes.
+    struct StutteringWriter {
+        received: Vec<u8>,
+        chunk: usize,
+        limit: usize,
+        calls: usize,
+    }
+
+    impl Write for StutteringWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+
+            match self.calls % 3 {
+                0 => return Err(io::Error::new(io::ErrorKind::WouldBlock, "not yet")),
+                1 => return Err(io::Error::new(io::ErrorKind::Interrupted, "signal")),
+                _ => (),
+            }
+
+            if self.received.len() >= self.limit {
+                return Err(io::Error::new(io::ErrorKind::BrokenPipe, "went away"));
+            }
+
+            let n = buf.len().min(self.chunk).min(self.limit - self.received.len());
+            self.received.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Reads at most two bytes at a time.
+    struct Stutter<R>(R);
+
+    impl<R: Read> Read for Stutter<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = buf.len().min(2);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
     fn check_response_write(response: Response, expected: &[u8]) {
         let mut recv_buf = Vec::new();
 
@@ -159,7 +717,7 @@ mod test {
 
     #[test]
     fn empty() {
-        let response = Response::new(Status::Ok, None, Some(ContentType::Text), false);
+        let response = Response::builder().content_type(ContentType::Text).build();
         let expected = b"HTTP/1.1 200 OK\r
 Content-Length: 0\r
 Content-Type: text/plain\r
@@ -171,10 +729,10 @@ Content-Type: text/plain\r
 
     #[test]
     fn with_text() {
-        let response = Response::new(Status::Ok,
-                                     Some(Box::new("ABCDEFGHIJK1234567890".as_bytes())),
-                                     Some(ContentType::Text),
-                                     false);
+        let response = Response::builder()
+            .body_reader("ABCDEFGHIJK1234567890".as_bytes())
+            .content_type(ContentType::Text)
+            .build();
         let expected = b"HTTP/1.1 200 OK\r
 Content-Length: 21\r
 Content-Type: text/plain\r
@@ -184,9 +742,254 @@ ABCDEFGHIJK1234567890";
         check_response_write(response, expected);
     }
 
+    #[test]
+    fn rate_limited() {
+        let body = vec![b'x'; 2000];
+        for &buffered in &[false, true] {
+            let builder = Response::builder().content_type(ContentType::Text);
+            let response = if buffered {
+                builder.body_reader(io::Cursor::new(body.clone())).build()
+            } else {
+                builder.body_reader_with_length(io::Cursor::new(body.clone()), 2000).build()
+            };
+
+            let started = Instant::now();
+            let mut received = Vec::new();
+            assert_eq!(response.with_rate_limit(Some(4000)).send(&mut received).unwrap(), 2000);
+
+            // the last of five chunks is due 0.4 seconds in
+            assert!(started.elapsed() >= Duration::from_millis(400));
+            assert!(received.ends_with(&body));
+        }
+    }
+
+    #[test]
+    fn extra_headers() {
+        let response = Response::builder()
+            .body_reader("Hallo".as_bytes())
+            .content_type(ContentType::Html)
+            .header("Content-Language", "de")
+            .header("Vary", "Accept-Language")
+            .build();
+        let expected = b"HTTP/1.1 200 OK\r
+Content-Length: 5\r
+Content-Type: text/html\r
+Content-Language: de\r
+Vary: Accept-Language\r
+\r
+Hallo";
+
+        check_response_write(response, expected);
+    }
+
+    #[test]
+    fn partial_writes() {
+        let mut writer = StutteringWriter {
+            received: Vec::new(),
+            chunk: 4,
+            limit: usize::max_value(),
+            calls: 0,
+        };
+
+        let response = Response::builder().body_reader("ABCDEFGHIJK1234567890".as_bytes()).build();
+        let body_bytes = response.send(&mut writer).unwrap();
+
+        assert_eq!(body_bytes, 21);
+        assert_eq!(str::from_utf8(&writer.received).unwrap(),
+                   "HTTP/1.1 200 OK\r\nContent-Length: 21\r\n\r\nABCDEFGHIJK1234567890");
+    }
+
+    #[test]
+    fn streamed_body() {
+        let data = (0..20_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        // anything past the declared length is left unsent
+        let mut source = data.clone();
+        source.extend_from_slice(b"extra");
+
+        let response = Response::builder()
+            .body_reader_with_length(io::Cursor::new(source), data.len() as u64)
+            .build();
+
+        let mut received = Vec::new();
+        assert_eq!(response.send(&mut received).unwrap(), data.len());
+
+        let mut expected = b"HTTP/1.1 200 OK\r\nContent-Length: 20000\r\n\r\n".to_vec();
+        expected.extend_from_slice(&data);
+        assert!(received == expected);
+    }
+
+    #[test]
+    fn chunked_body() {
+        // a chunk for each read
+        let response = Response::builder()
+            .body_reader_chunked(Stutter("abcde".as_bytes()))
+            .content_type(ContentType::Text)
+            .build();
+
+        let mut received = Vec::new();
+        assert_eq!(response.send(&mut received).unwrap(), 5);
+        assert_eq!(str::from_utf8(&received).unwrap(),
+                   "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+                    Content-Type: text/plain\r\n\r\n2\r\nab\r\n2\r\ncd\r\n1\r\ne\r\n0\r\n\r\n");
+
+        let response = Response::builder().body_reader_chunked("abcde".as_bytes()).build();
+        check_response_write(response.without_body(),
+                             b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n");
+    }
+
+    #[test]
+    fn streamed_body_too_short() {
+        let response = Response::builder().body_reader_with_length("abc".as_bytes(), 5).build();
+
+        match response.send(&mut Vec::new()) {
+            Err(HpptError::IncompleteWrite(3, ref why)) => {
+                assert_eq!(why.kind(), io::ErrorKind::UnexpectedEof)
+            }
+            other => panic!("unexpected send result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn streamed_without_body() {
+        let response = Response::builder()
+            .body_reader_with_length("abcde".as_bytes(), 5)
+            .build()
+            .without_body();
+
+        check_response_write(response, b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n");
+    }
+
+    #[test]
+    fn incomplete_write_counts_body_bytes() {
+        let head_len = "HTTP/1.1 200 OK\r\nContent-Length: 21\r\n\r\n".len();
+        let mut writer = StutteringWriter {
+            received: Vec::new(),
+            chunk: 5,
+            limit: head_len + 7,
+            calls: 0,
+        };
+
+        let response = Response::builder().body_reader("ABCDEFGHIJK1234567890".as_bytes()).build();
+
+        match response.send(&mut writer) {
+            Err(HpptError::IncompleteWrite(7, ref why)) => {
+                assert_eq!(why.kind(), io::ErrorKind::BrokenPipe)
+            }
+            other => panic!("unexpected send result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn charsets() {
+        assert_eq!(ContentType::Html.with_charset("utf-8").as_bytes(),
+                   b"text/html; charset=utf-8");
+        assert_eq!(ContentType::Html.charset(), None);
+        assert_eq!(ContentType::Custom("text/csv; Charset=\"Latin1\"".to_owned()).charset(),
+                   Some("latin1".to_owned()));
+        assert_eq!(ContentType::Binary.with_charset("utf-8").as_bytes(),
+                   b"application/octet-stream");
+        assert_eq!(ContentType::Custom("text/csv; Charset=latin1".to_owned())
+                       .with_charset("utf-8")
+                       .as_bytes(),
+                   b"text/csv; Charset=latin1");
+    }
+
+    #[test]
+    fn status_codes() {
+        assert_eq!(Status::Ok.code(), 200);
+        assert_eq!(Status::RangeNotSatisfiable.code(), 416);
+        assert_eq!(Status::ServiceUnavailable.code(), 503);
+        assert_eq!(Status::GatewayTimeout.code(), 504);
+        assert_eq!(Status::Custom(299, "Whatever".to_owned()).code(), 299);
+        assert_eq!(Status::PermanentRedirect.code(), 308);
+        assert_eq!(Status::MovedPermanently.reason(), "Moved Permanently");
+        assert_eq!(Status::HttpVersionNotSupported.code(), 505);
+    }
+
+    #[test]
+    fn custom_status() {
+        let response = Response::builder()
+            .status(Status::Custom(299, "Script Says So".to_owned()))
+            .header("X-From".to_owned(), "script")
+            .build();
+
+        check_response_write(response,
+                             b"HTTP/1.1 299 Script Says So\r
+Content-Length: 0\r
+X-From: script\r
+\r
+");
+    }
+
+    #[test]
+    fn redirect() {
+        let response = Response::builder()
+            .status(Status::TemporaryRedirect)
+            .location("http://bücher.example/a")
+            .build();
+
+        check_response_write(response,
+                             b"HTTP/1.1 307 Temporary Redirect\r
+Content-Length: 0\r
+Location: http://xn--bcher-kva.example/a\r
+\r
+");
+    }
+
+    #[test]
+    fn not_modified() {
+        let response = Response::builder()
+            .status(Status::NotModified)
+            .body_reader_with_length("ABCDE".as_bytes(), 5)
+            .build();
+
+        check_response_write(response, b"HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\n\r\n");
+
+        let response = Response::builder().status(Status::NotModified).build();
+        check_response_write(response, b"HTTP/1.1 304 Not Modified\r\n\r\n");
+
+        let response = Response::builder()
+            .status(Status::NotModified)
+            .body_reader_chunked("ABCDE".as_bytes())
+            .build();
+        check_response_write(response, b"HTTP/1.1 304 Not Modified\r\n\r\n");
+    }
+
+    #[test]
+    fn no_content() {
+        // neither a length nor chunks, whatever the body would have been
+        let response = Response::builder()
+            .status(Status::Custom(204, "No Content".to_owned()))
+            .body_reader_chunked("abcde".as_bytes())
+            .build();
+        check_response_write(response, b"HTTP/1.1 204 No Content\r\n\r\n");
+
+        let response = Response::builder()
+            .status(Status::Custom(204, "No Content".to_owned()))
+            .build();
+        check_response_write(response, b"HTTP/1.1 204 No Content\r\n\r\n");
+    }
+
+    #[test]
+    fn without_body() {
+        let response = Response::builder()
+            .body_reader("ABCDEFGHIJK1234567890".as_bytes())
+            .content_type(ContentType::Text)
+            .build()
+            .without_body();
+        let expected = b"HTTP/1.1 200 OK\r
+Content-Length: 21\r
+Content-Type: text/plain\r
+\r
+";
+
+        check_response_write(response, expected);
+    }
+
     #[test]
     fn not_found() {
-        let response = Response::new(Status::NotFound, None, None, false);
+        let response = Response::builder().status(Status::NotFound).build();
         let expected = b"HTTP/1.1 404 Not Found\r
 Content-Length: 0\r
 \r
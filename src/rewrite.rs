@@ -0,0 +1,301 @@
+//! Rules which rewrite a request's path before anything looks it up, or send the client
+//! elsewhere with a redirect, so moved pages keep working.
+//!
+//! A rule is `PATTERN -> TARGET` for an internal rewrite, or `PATTERN -> STATUS TARGET` for a
+//! redirect with a 301, 302, 307 or 308. A pattern is a path, or a prefix ending in `*` which
+//! matches whatever follows it; a `*` in the target is replaced with what the pattern's matched,
+//! e.g. `/old/* -> /new/*`, or `/blog/* -> 301 https://blog.example.com/*`. Rules are tried in
+//! order, and the first one matching a request's path applies, once: a rewritten path isn't
+//! matched against the rules again. Paths are matched as they're looked up, decoded and with any
+//! empty segments collapsed, so `/%6fld/x` or `//old/x` can't get around a rule for `/old/*`, and
+//! what a `*` matched is escaped again in the target.
+//!
+//! What becomes of the request's query is up to the rule, with a last word of `query=merge` (the
+//! default), `query=drop` or `query=replace`: see `QueryPolicy`.
+
+use std::fmt;
+use std::str::FromStr;
+
+use response::Status;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    /// With its leading slash, and a trailing `*` if it's a prefix.
+    pattern: String,
+    target: String,
+    /// The status to redirect with, or `None` to rewrite.
+    redirect: Option<u16>,
+    query: QueryPolicy,
+}
+
+/// What a rule does with the query of a request it matches, whether it rewrites or redirects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QueryPolicy {
+    /// Keep it, after any query the target has of its own: `/find?from=search` and `q=x` make
+    /// `/find?from=search&q=x`.
+    Merge,
+    /// Leave it out, so the target's own query (if any) is all there is.
+    Drop,
+    /// Use it instead of the target's own query, which is only kept if the request has none.
+    Replace,
+}
+
+impl FromStr for QueryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "merge" => Ok(QueryPolicy::Merge),
+            "drop" => Ok(QueryPolicy::Drop),
+            "replace" => Ok(QueryPolicy::Replace),
+            _ => Err(format!("{} isn't a query policy (merge, drop or replace)", s)),
+        }
+    }
+}
+
+impl fmt::Display for QueryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            QueryPolicy::Merge => "merge",
+            QueryPolicy::Drop => "drop",
+            QueryPolicy::Replace => "replace",
+        })
+    }
+}
+
+/// What a rule says to do with a request.
+pub enum Action {
+    /// Answer it as if it were for this (origin-form) target.
+    Rewrite(String),
+    /// Send the client to this location instead.
+    Redirect(Status, String),
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.splitn(2, "->");
+        let (pattern, rest) = match (halves.next(), halves.next()) {
+            (Some(p), Some(r)) => (p.trim(), r.trim()),
+            _ => return Err(format!("{} is not of the form PATTERN -> [STATUS] TARGET", s)),
+        };
+
+        if !pattern.starts_with('/') || pattern[..pattern.len() - 1].contains('*') {
+            return Err(format!("{} isn't a path, or a prefix of one ending in *", pattern));
+        }
+
+        let mut words = rest.split_whitespace().collect::<Vec<_>>();
+        let query = match words.last().cloned() {
+            Some(word) if word.starts_with("query=") && words.len() > 1 => {
+                words.pop();
+                try!(word["query=".len()..].parse())
+            }
+            _ => QueryPolicy::Merge,
+        };
+        let (redirect, target) = match (words.get(0), words.get(1), words.get(2)) {
+            (Some(&target), None, _) => (None, target),
+            (Some(status), Some(&target), None) => {
+                match status.parse::<u16>() {
+                    Ok(code) if [301, 302, 307, 308].contains(&code) => (Some(code), target),
+                    _ => return Err(format!("{} isn't a redirect status (301, 302, 307 or 308)",
+                                            status)),
+                }
+            }
+            _ => return Err(format!("{} is not of the form [STATUS] TARGET [query=POLICY]", rest)),
+        };
+
+        if target.matches('*').count() > 1 || target.contains('*') && !pattern.ends_with('*') {
+            return Err(format!("{} has a * which {} doesn't match anything for", target, pattern));
+        }
+        // a rewrite's target is looked up like a request's, so it has to be a path here
+        if redirect.is_none() && !target.starts_with('/') {
+            return Err(format!("{} isn't a path to rewrite to", target));
+        }
+
+        Ok(Rule {
+            pattern: pattern.to_owned(),
+            target: target.to_owned(),
+            redirect: redirect,
+            query: query,
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(match self.redirect {
+            Some(code) => write!(f, "{} -> {} {}", self.pattern, code, self.target),
+            None => write!(f, "{} -> {}", self.pattern, self.target),
+        });
+
+        match self.query {
+            QueryPolicy::Merge => Ok(()),
+            policy => write!(f, " query={}", policy),
+        }
+    }
+}
+
+impl Rule {
+    /// What this rule makes of a (decoded) path, if it matches it.
+    fn apply(&self, path: &str) -> Option<String> {
+        let target = if self.pattern.ends_with('*') {
+            let prefix = &self.pattern[..self.pattern.len() - 1];
+            if !path.starts_with(prefix) {
+                return None;
+            }
+            self.target.replace('*', &encode_path(&path[prefix.len()..]))
+        } else if path == self.pattern {
+            self.target.clone()
+        } else {
+            return None;
+        };
+
+        // what was matched mustn't turn a path into `//host/...`, which is another host's
+        if target.starts_with("//") && !self.target.starts_with("//") {
+            Some(format!("/{}", target.trim_start_matches('/')))
+        } else {
+            Some(target)
+        }
+    }
+}
+
+/// What the first of the rules matching a request's path (decoded and slash-stripped, as
+/// `Request::uri` has it) says to do with it, if any does.
+pub fn apply(rules: &[Rule], path: &str, query: Option<&str>) -> Option<Action> {
+    let path = format!("/{}", path);
+
+    for rule in rules {
+        let mut target = match rule.apply(&path) {
+            Some(t) => t,
+            None => continue,
+        };
+
+        match (rule.query, query) {
+            (QueryPolicy::Merge, Some(query)) => {
+                target.push(if target.contains('?') { '&' } else { '?' });
+                target.push_str(query);
+            }
+            (QueryPolicy::Replace, Some(query)) => {
+                if let Some(i) = target.find('?') {
+                    target.truncate(i);
+                }
+                target.push('?');
+                target.push_str(query);
+            }
+            _ => (),
+        }
+
+        return Some(match rule.redirect {
+            Some(301) => Action::Redirect(Status::MovedPermanently, target),
+            Some(302) => Action::Redirect(Status::Found, target),
+            Some(307) => Action::Redirect(Status::TemporaryRedirect, target),
+            Some(_) => Action::Redirect(Status::PermanentRedirect, target),
+            None => Action::Rewrite(target),
+        });
+    }
+
+    None
+}
+
+/// Percent-encode everything in a path except unreserved characters and slashes.
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Action, Rule, apply};
+
+    fn rules(rules: &[&str]) -> Vec<Rule> {
+        rules.iter().map(|r| r.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule = "/old/*  ->  /new/*".parse::<Rule>().unwrap();
+        assert_eq!(rule.to_string(), "/old/* -> /new/*");
+        let rule = "/blog/* -> 301 https://blog.example.com/*".parse::<Rule>().unwrap();
+        assert_eq!(rule.to_string(), "/blog/* -> 301 https://blog.example.com/*");
+        let rule = "/a -> 302 /b?c query=drop".parse::<Rule>().unwrap();
+        assert_eq!(rule.to_string(), "/a -> 302 /b?c query=drop");
+        let rule = "/a -> /b query=merge".parse::<Rule>().unwrap();
+        assert_eq!(rule.to_string(), "/a -> /b");
+
+        for bad in &["/old/*", "/old/* /new/*", "old/* -> /new/*", "/*/old -> /new",
+                     "/old -> /new/*", "/old/* -> /new/*/*", "/old -> new", "/old -> 303 /new",
+                     "/old -> 301", "/old -> 301 /new extra", "/old -> /new query=keep",
+                     "/old -> query=drop"] {
+            assert!(bad.parse::<Rule>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn actions() {
+        let rules = rules(&["/old/* -> /new/*",
+                            "/about -> 308 /about-us",
+                            "/blog/* -> 301 https://blog.example.com/*",
+                            "/search -> /find?from=search",
+                            "/* -> 302 /elsewhere"]);
+        let action = |path, query| match apply(&rules, path, query) {
+            Some(Action::Rewrite(target)) => format!("rewrite {}", target),
+            Some(Action::Redirect(status, location)) => {
+                format!("{} {}", status.code(), location)
+            }
+            None => "none".to_owned(),
+        };
+
+        assert_eq!(action("old/a b/c", None), "rewrite /new/a%20b/c");
+        assert_eq!(action("old/", Some("q=1")), "rewrite /new/?q=1");
+        assert_eq!(action("about", None), "308 /about-us");
+        assert_eq!(action("blog/2017/post", Some("a")),
+                   "301 https://blog.example.com/2017/post?a");
+        assert_eq!(action("search", Some("q=x")), "rewrite /find?from=search&q=x");
+        assert_eq!(action("about/team", None), "302 /elsewhere");
+
+        assert!(apply(&rules[..3], "older", None).is_none());
+        assert!(apply(&rules[..3], "about/", None).is_none());
+
+        let policies = ["/merge -> /t?x=1", "/drop -> /t?x=1 query=drop",
+                        "/replace -> 302 /t?x=1 query=replace"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect::<Vec<Rule>>();
+        let target = |path, query| match apply(&policies, path, query) {
+            Some(Action::Rewrite(target)) |
+            Some(Action::Redirect(_, target)) => target,
+            None => "none".to_owned(),
+        };
+        assert_eq!(target("merge", Some("y=2")), "/t?x=1&y=2");
+        assert_eq!(target("merge", None), "/t?x=1");
+        assert_eq!(target("drop", Some("y=2")), "/t?x=1");
+        assert_eq!(target("replace", Some("y=2")), "/t?y=2");
+        assert_eq!(target("replace", None), "/t?x=1");
+
+        // a query with a ? of its own is carried whole, whatever the target's query holds
+        assert_eq!(target("merge", Some("next=/a?b=1")), "/t?x=1&next=/a?b=1");
+        assert_eq!(target("replace", Some("next=/a?b=1")), "/t?next=/a?b=1");
+
+        // a path which would be another host's is kept to this one
+        let redirects = ["/old/* -> 301 /*", "/cdn/* -> 302 //cdn.example.com/*"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect::<Vec<Rule>>();
+        let action = |path| match apply(&redirects, path, None) {
+            Some(Action::Redirect(_, location)) => location,
+            _ => "none".to_owned(),
+        };
+        assert_eq!(action("old//evil.example/"), "/evil.example/");
+        assert_eq!(action("cdn/x.js"), "//cdn.example.com/x.js");
+    }
+}
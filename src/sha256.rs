@@ -0,0 +1,227 @@
+//! SHA-256 (FIPS 180-4), for checksum sidecars.
+
+const K: [u32; 64] = [0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1,
+                      0x923f82a4, 0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3,
+                      0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786,
+                      0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+                      0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147,
+                      0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13,
+                      0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+                      0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+                      0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a,
+                      0x5b9cca4f, 0x682e6ff3, 0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208,
+                      0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2];
+
+const H0: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c,
+                      0x1f83d9ab, 0x5be0cd19];
+
+/// An incremental SHA-256 computation.
+pub struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    block_len: usize,
+    total_len: u64,
+}
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Sha256 {
+            state: H0,
+            block: [0; 64],
+            block_len: 0,
+            total_len: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+
+        while !data.is_empty() {
+            let n = ::std::cmp::min(64 - self.block_len, data.len());
+            self.block[self.block_len..self.block_len + n].copy_from_slice(&data[..n]);
+            self.block_len += n;
+            data = &data[n..];
+
+            if self.block_len == 64 {
+                let block = self.block;
+                self.compress(&block);
+                self.block_len = 0;
+            }
+        }
+    }
+
+    /// The digest as lowercase hex, as `sha256sum` prints it.
+    pub fn finish_hex(self) -> String {
+        hex(&self.finish())
+    }
+
+    pub fn finish(mut self) -> [u8; 32] {
+        let bit_len = self.total_len * 8;
+
+        // a 1 bit, zeroes up to 8 bytes short of a block boundary, then the length
+        self.update(&[0x80]);
+        while self.block_len != 56 {
+            self.update(&[0]);
+        }
+        let mut len_bytes = [0; 8];
+        for (i, b) in len_bytes.iter_mut().enumerate() {
+            *b = (bit_len >> (56 - 8 * i)) as u8;
+        }
+        self.update(&len_bytes);
+
+        let mut digest = [0; 32];
+        for (i, w) in self.state.iter().enumerate() {
+            digest[4 * i] = (w >> 24) as u8;
+            digest[4 * i + 1] = (w >> 16) as u8;
+            digest[4 * i + 2] = (w >> 8) as u8;
+            digest[4 * i + 3] = *w as u8;
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for i in 0..16 {
+            w[i] = (block[4 * i] as u32) << 24 | (block[4 * i + 1] as u32) << 16 |
+                   (block[4 * i + 2] as u32) << 8 | block[4 * i + 3] as u32;
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+
+        let mut v = self.state;
+        for i in 0..64 {
+            let s1 = v[4].rotate_right(6) ^ v[4].rotate_right(11) ^ v[4].rotate_right(25);
+            let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+            let t1 = v[7].wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = v[0].rotate_right(2) ^ v[0].rotate_right(13) ^ v[0].rotate_right(22);
+            let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+            let t2 = s0.wrapping_add(maj);
+
+            v = [t1.wrapping_add(t2), v[0], v[1], v[2], v[3].wrapping_add(t1), v[4], v[5], v[6]];
+        }
+
+        for (s, x) in self.state.iter_mut().zip(v.iter()) {
+            *s = s.wrapping_add(*x);
+        }
+    }
+}
+
+/// HMAC-SHA256 (RFC 2104) of `data` under `key`.
+pub fn hmac(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut block_key = [0; 64];
+    if key.len() > 64 {
+        let mut hash = Sha256::new();
+        hash.update(key);
+        block_key[..32].copy_from_slice(&hash.finish());
+    } else {
+        block_key[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(&block_key.iter().map(|b| b ^ 0x36).collect::<Vec<_>>());
+    inner.update(data);
+
+    let mut outer = Sha256::new();
+    outer.update(&block_key.iter().map(|b| b ^ 0x5c).collect::<Vec<_>>());
+    outer.update(&inner.finish());
+    outer.finish()
+}
+
+/// PBKDF2 (RFC 8018) with HMAC-SHA256, for a single 32-byte block of key: what's stored of a
+/// password, so that one leaked takes `iterations` hashes per guess to crack.
+pub fn pbkdf2(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut block = salt.to_vec();
+    block.extend_from_slice(&[0, 0, 0, 1]);
+
+    let mut u = hmac(password, &block);
+    let mut key = u;
+    for _ in 1..iterations {
+        u = hmac(password, &u);
+        for (k, x) in key.iter_mut().zip(u.iter()) {
+            *k ^= *x;
+        }
+    }
+
+    key
+}
+
+/// Bytes as lowercase hex.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The digest written in hex (of either case), if that's what it is.
+pub fn from_hex(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = match u8::from_str_radix(&hex[2 * i..2 * i + 2], 16) {
+            Ok(b) => b,
+            Err(_) => return None,
+        };
+    }
+
+    Some(digest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sha256, from_hex, hmac, pbkdf2};
+
+    fn hex(data: &[u8]) -> String {
+        let mut hash = Sha256::new();
+        hash.update(data);
+        hash.finish_hex()
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(hex(b""),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(b"abc"),
+                   "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+                   "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+
+        // fed in pieces which straddle block boundaries
+        let mut hash = Sha256::new();
+        for _ in 0..1000 {
+            hash.update(&[b'a'; 1000]);
+        }
+        assert_eq!(hash.finish_hex(),
+                   "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0");
+    }
+
+    #[test]
+    fn hmac_digests() {
+        // RFC 4231 test cases 2 and 6
+        assert_eq!(super::hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+                   "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(super::hex(&hmac(&[0xaa; 131],
+                                    b"Test Using Larger Than Block-Size Key - Hash Key First")),
+                   "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+    }
+
+    #[test]
+    fn pbkdf2_keys() {
+        assert_eq!(super::hex(&pbkdf2(b"password", b"salt", 1)),
+                   "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        assert_eq!(super::hex(&pbkdf2(b"password", b"salt", 4096)),
+                   "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+    }
+
+    #[test]
+    fn hex_digests() {
+        let digest = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        assert_eq!(super::hex(&from_hex(digest).unwrap()), digest.to_ascii_lowercase());
+
+        assert_eq!(from_hex(&digest[2..]), None);
+        assert_eq!(from_hex(&format!("+{}", &digest[1..])), None);
+    }
+}
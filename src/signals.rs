@@ -0,0 +1,134 @@
+//! Turning SIGINT and SIGTERM into requests for the server to shut down, and SIGHUP into one to
+//! reload whatever can be without a restart.
+
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use libc;
+use libc::{c_int, c_void};
+
+use stats::ShutdownReason;
+
+/// The write end of the pipe through which the signal handler passes signals on to the thread
+/// which deals with them, once there is one.
+static PIPE: AtomicIsize = AtomicIsize::new(-1);
+
+extern "C" fn on_signal(signum: c_int) {
+    // only async-signal-safe calls in here
+    let byte = signum as u8;
+    unsafe {
+        libc::write(PIPE.load(Ordering::SeqCst) as c_int,
+                    &byte as *const u8 as *const c_void,
+                    1)
+    };
+}
+
+fn name(signum: c_int) -> &'static str {
+    match signum {
+        libc::SIGINT => "SIGINT",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGHUP => "SIGHUP",
+        _ => "a signal",
+    }
+}
+
+/// Send a shutdown request when the process gets a SIGINT or SIGTERM, and exit straight away on
+/// a second one, for whoever doesn't care to wait for open connections to finish. Call `reload`
+/// (on a thread of its own) on every SIGHUP.
+pub fn forward<F>(shutdown: Sender<ShutdownReason>, reload: F) -> io::Result<()>
+    where F: Fn() + Send + 'static
+{
+    let mut fds = [0 as c_int; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // CGI scripts have no business with either end
+    for &fd in &fds {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+
+    let (read_fd, write_fd) = (fds[0], fds[1]);
+    PIPE.store(write_fd as isize, Ordering::SeqCst);
+
+    for &signum in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        let handler = on_signal as extern "C" fn(c_int) as libc::sighandler_t;
+        if unsafe { libc::signal(signum, handler) } == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    try!(thread::Builder::new().name("signals".to_owned()).spawn(move || {
+        let mut received = 0;
+
+        loop {
+            let mut byte = 0u8;
+            let n = unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut c_void, 1) };
+
+            if n != 1 {
+                let why = io::Error::last_os_error();
+                if why.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                error!("Unable to wait for signals any more: {:?}", why);
+                return;
+            }
+
+            let name = name(byte as c_int);
+            if byte as c_int == libc::SIGHUP {
+                info!("Got {}, reloading", name);
+                reload();
+                continue;
+            }
+
+            received += 1;
+
+            if received == 1 {
+                info!("Got {}, shutting down", name);
+                let _ = shutdown.send(ShutdownReason::Signal(name));
+            } else {
+                warn!("Got {} again, exiting without waiting for connections to finish", name);
+                process::exit(1);
+            }
+        }
+    }));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::sync::mpsc::RecvTimeoutError;
+    use std::time::Duration;
+
+    use libc;
+
+    use stats::ShutdownReason;
+
+    use super::*;
+
+    #[test]
+    fn forwarding() {
+        let (send, recv) = mpsc::channel();
+        let (reloaded, reloads) = mpsc::channel();
+        forward(send, move || reloaded.send(()).unwrap()).unwrap();
+
+        // which isn't a shutdown
+        unsafe { libc::raise(libc::SIGHUP) };
+        reloads.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(recv.recv_timeout(Duration::from_millis(100)).unwrap_err(),
+                   RecvTimeoutError::Timeout);
+
+        unsafe { libc::raise(libc::SIGTERM) };
+
+        match recv.recv_timeout(Duration::from_secs(5)) {
+            Ok(ShutdownReason::Signal("SIGTERM")) => (),
+            other => panic!("expected a SIGTERM shutdown, got {:?}", other),
+        }
+    }
+}
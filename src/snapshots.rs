@@ -0,0 +1,211 @@
+//! Listings of directories too big to scan on every request (like those of a large mirror),
+//! written to disk ahead of time and refreshed on a schedule, for the server to serve as they
+//! are.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use listing;
+
+/// How often to check whether the server's stopped, while waiting to refresh snapshots.
+const STOP_POLL: Duration = Duration::from_millis(100);
+
+/// `DIR=SECS`, e.g. `/pub=300`: a directory (relative to the root) whose listing, and those of
+/// every directory under it, are served from snapshots refreshed every so many seconds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexSnapshot {
+    /// Slash-stripped, like a request's URI.
+    pub dir: String,
+    pub every: Duration,
+}
+
+impl FromStr for IndexSnapshot {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut halves = s.rsplitn(2, '=');
+
+        match (halves.next().map(|secs| secs.parse::<u64>()), halves.next()) {
+            (Some(Ok(secs)), Some(dir)) if secs > 0 => {
+                Ok(IndexSnapshot {
+                    dir: dir.trim_matches('/').to_owned(),
+                    every: Duration::from_secs(secs),
+                })
+            }
+            _ => Err(format!("{} is not of the form DIR=SECS, with SECS at least 1", s)),
+        }
+    }
+}
+
+impl fmt::Display for IndexSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "/{}={}", self.dir, self.every.as_secs())
+    }
+}
+
+/// The snapshot of the listing of the directory at a (slash-stripped) URI, as HTML or (if
+/// `json`) JSON, if it's listed from snapshots and there's one yet. Directories which have gone
+/// since the last snapshot aren't listed.
+pub fn read(config: &Config, uri: &str, json: bool) -> Option<Vec<u8>> {
+    let snapshot_dir = match config.snapshot_dir {
+        Some(ref dir) if config.lists_from_snapshots(uri) => dir,
+        _ => return None,
+    };
+
+    if !config.source.metadata(Path::new(uri)).map_or(false, |m| m.is_dir) {
+        return None;
+    }
+
+    fs::read(path(snapshot_dir, &config.root_dir, uri, json)).ok()
+}
+
+/// Where the snapshot of a directory's listing is kept: in a directory of the snapshot
+/// directory for the root it's from, so virtual hosts' snapshots don't get mixed up.
+fn path(snapshot_dir: &Path, root_dir: &Path, uri: &str, json: bool) -> PathBuf {
+    let extension = if json { "json" } else { "html" };
+
+    snapshot_dir.join(file_name(&root_dir.to_string_lossy()))
+        .join(format!("{}.{}", file_name(&format!("/{}", uri.trim_matches('/'))), extension))
+}
+
+/// A path made fit to be a single file name, keeping different paths' names different.
+fn file_name(path: &str) -> String {
+    path.replace('%', "%25").replace('/', "%2F")
+}
+
+/// Write snapshots of the listing of the directory at a (slash-stripped) URI, and of every
+/// directory under it, returning how many were written. Each replaces the last all at once, so a
+/// request never sees one half-written.
+pub fn write(config: &Config, snapshot_dir: &Path, uri: &str) -> io::Result<usize> {
+    let dir = snapshot_dir.join(file_name(&config.root_dir.to_string_lossy()));
+    try!(fs::create_dir_all(&dir));
+
+    let mut written = 0;
+    let mut pending = vec![uri.trim_matches('/').to_owned()];
+
+    while let Some(uri) = pending.pop() {
+        let entries = match listing::entries(config, &uri) {
+            Some(e) => e,
+            None => continue,
+        };
+
+        for json in &[false, true] {
+            let listing = if *json {
+                listing::to_json(&entries)
+            } else {
+                listing::render_html(&uri, &entries)
+            };
+
+            let path = path(snapshot_dir, &config.root_dir, &uri, *json);
+            let mut partial = path.clone().into_os_string();
+            partial.push(".partial");
+            try!(fs::write(&partial, listing));
+            try!(fs::rename(&partial, &path));
+        }
+        written += 1;
+
+        for entry in entries.into_iter().filter(|e| e.metadata.is_dir) {
+            pending.push(if uri.is_empty() {
+                entry.name
+            } else {
+                format!("{}/{}", uri, entry.name)
+            });
+        }
+    }
+
+    Ok(written)
+}
+
+/// Refresh the snapshots of each of a config's snapshotted directories on a thread of its own,
+/// straight away and then on their schedules, until `stop` is set.
+pub fn keep_fresh(config: Arc<Config>, stop: Arc<AtomicBool>) {
+    let snapshot_dir = match config.snapshot_dir {
+        Some(ref dir) => dir.clone(),
+        None => return,
+    };
+
+    for snapshot in config.index_snapshots.clone() {
+        let config = config.clone();
+        let snapshot_dir = snapshot_dir.clone();
+        let stop = stop.clone();
+
+        thread::spawn(move || {
+            while !stop.load(Ordering::SeqCst) {
+                let started = Instant::now();
+                match write(&config, &snapshot_dir, &snapshot.dir) {
+                    Ok(n) => debug!("Wrote {} index snapshots for /{}", n, snapshot.dir),
+                    Err(why) => warn!("Unable to snapshot /{}: {:?}", snapshot.dir, why),
+                }
+
+                while !stop.load(Ordering::SeqCst) && started.elapsed() < snapshot.every {
+                    thread::sleep(STOP_POLL);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    use config::Config;
+    use listing;
+
+    use super::{IndexSnapshot, read, write};
+
+    #[test]
+    fn parsing() {
+        assert_eq!("/pub/=300".parse::<IndexSnapshot>(),
+                   Ok(IndexSnapshot {
+                       dir: "pub".to_owned(),
+                       every: Duration::from_secs(300),
+                   }));
+        assert_eq!("/pub/=300".parse::<IndexSnapshot>().unwrap().to_string(), "/pub=300");
+        assert_eq!("/=60".parse::<IndexSnapshot>().unwrap().dir, "");
+
+        for bad in &["pub", "pub=0", "pub=soon", "=", ""] {
+            assert!(bad.parse::<IndexSnapshot>().is_err(), "{} should be rejected", bad);
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let snapshot_dir = env::temp_dir().join(format!("hppt-snapshots-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&snapshot_dir);
+        fs::create_dir_all(&snapshot_dir).unwrap();
+
+        let mut config = Config::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        config.snapshot_dir = Some(snapshot_dir.clone());
+        config.index_snapshots.push("test=60".parse().unwrap());
+
+        // nothing until they've been written
+        assert_eq!(read(&config, "test/lang", false), None);
+
+        // test, and the eight directories in it which aren't hidden
+        assert_eq!(write(&config, &snapshot_dir, "test").unwrap(), 9);
+
+        let html = listing::render(&config, "test/lang").unwrap();
+        assert_eq!(read(&config, "test/lang", false), Some(html.into_bytes()));
+        assert!(read(&config, "test/lang/", false).is_some());
+        let json = listing::render_json(&config, "test").unwrap();
+        assert_eq!(read(&config, "test", true), Some(json.into_bytes()));
+
+        // only for the directories configured, and only those still there
+        assert_eq!(read(&config, "cgi-bin", false), None);
+        assert_eq!(read(&config, "test/foo.html", false), None);
+
+        fs::remove_dir_all(&snapshot_dir).unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! Where the files a server serves come from. Everything served as-is (static files, index
+//! files, listings, language variants and checksum sidecars) is looked up through a
+//! `ContentSource`, so another kind of store can stand in for the local filesystem by
+//! implementing it. CGI scripts, tar downloads and live reload still need a real directory, and
+//! use `Config::root_dir` directly.
+
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use files::{find_file_relative, full_path, is_contained};
+
+/// What's known about a file or directory without reading it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Metadata {
+    pub len: u64,
+    /// `None` if the source doesn't know.
+    pub modified: Option<SystemTime>,
+    pub is_dir: bool,
+}
+
+/// Whether something is a regular file or a directory, rather than a FIFO, socket or device,
+/// which aren't served or listed.
+fn is_servable(metadata: &fs::Metadata) -> bool {
+    metadata.is_file() || metadata.is_dir()
+}
+
+impl<'a> From<&'a fs::Metadata> for Metadata {
+    fn from(metadata: &fs::Metadata) -> Self {
+        Metadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            is_dir: metadata.is_dir(),
+        }
+    }
+}
+
+/// A file opened for serving.
+pub struct Content {
+    /// Its contents, from the start.
+    pub reader: Box<Body>,
+    /// As of when it was opened, so it agrees with what `reader` reads.
+    pub metadata: Metadata,
+    /// The MIME type it was stored with, for sources which keep one, to use in place of the
+    /// type its extension maps to.
+    pub content_type: Option<String>,
+    /// Where it really lives (for a local file, its canonical path), which tells it apart from
+    /// everything else the source serves.
+    pub full_path: PathBuf,
+}
+
+/// What a file's contents are read from. A range of them is read from the same reader, rather
+/// than by looking the file up again, so it's from the same version as the whole would have been.
+pub trait Body: Read {
+    /// Pass over the next `n` bytes, by seeking where the reader can, or reading them where it
+    /// can't.
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        let skipped = try!(io::copy(&mut (&mut *self).take(n), &mut io::sink()));
+        if skipped < n {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "shorter than it was"));
+        }
+        Ok(())
+    }
+}
+
+impl Body for fs::File {
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(n as i64)).map(|_| ())
+    }
+}
+
+impl<T: AsRef<[u8]>> Body for Cursor<T> {
+    fn skip(&mut self, n: u64) -> io::Result<()> {
+        self.seek(SeekFrom::Current(n as i64)).map(|_| ())
+    }
+}
+
+impl<'a> Body for &'a [u8] {}
+
+impl Body for Box<Read> {}
+
+/// One entry of a listed directory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// A store of files and directories, addressed by (root-relative) paths like `docs/page.html`.
+///
+/// Anything which can't be found, or which mustn't be served, is `None` (or an error) without
+/// saying which, so nothing about what's hidden leaks out through the difference.
+pub trait ContentSource: Debug + Send + Sync {
+    /// Open the file at a path.
+    fn open(&self, path: &Path) -> Option<Content>;
+
+    /// What's known about the file or directory at a path.
+    fn metadata(&self, path: &Path) -> Option<Metadata>;
+
+    /// The entries of the directory at a path, in no particular order.
+    fn list(&self, path: &Path) -> Option<Vec<DirEntry>>;
+}
+
+/// Files in a directory on the local filesystem, which nothing outside it (by way of `..` or a
+/// symlink) can be served from.
+#[derive(Debug)]
+pub struct LocalFs {
+    root_dir: PathBuf,
+    follow_symlinks: bool,
+}
+
+impl LocalFs {
+    pub fn new(root_dir: PathBuf) -> Self {
+        LocalFs {
+            root_dir: root_dir,
+            follow_symlinks: false,
+        }
+    }
+
+    /// Serve files and directories reached through symlinks, as long as what they lead to is
+    /// still inside the root. Otherwise only a path which is already canonical is served.
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+
+    /// The canonical path for a relative one, if it's something in the root, by the same rule as
+    /// `find_file_relative`.
+    fn contained(&self, path: &Path) -> Option<PathBuf> {
+        let full_path = match full_path(&self.root_dir, path) {
+            Some(p) => p,
+            None => return None,
+        };
+
+        match full_path.canonicalize() {
+            Ok(canonical) if is_contained(&self.root_dir,
+                                          &full_path,
+                                          &canonical,
+                                          self.follow_symlinks) => Some(canonical),
+            _ => None,
+        }
+    }
+}
+
+impl ContentSource for LocalFs {
+    fn open(&self, path: &Path) -> Option<Content> {
+        let (file, full_path) = match find_file_relative(&self.root_dir,
+                                                         path,
+                                                         self.follow_symlinks) {
+            Some(f) => f,
+            None => return None,
+        };
+
+        // from the open file, so a replacement made since it was found can't be mixed up with it
+        file.metadata().ok().map(|m| {
+            Content {
+                reader: Box::new(file),
+                metadata: Metadata::from(&m),
+                content_type: None,
+                full_path: full_path,
+            }
+        })
+    }
+
+    fn metadata(&self, path: &Path) -> Option<Metadata> {
+        self.contained(path)
+            .and_then(|full_path| fs::metadata(full_path).ok())
+            .filter(is_servable)
+            .map(|m| Metadata::from(&m))
+    }
+
+    fn list(&self, path: &Path) -> Option<Vec<DirEntry>> {
+        // by the same rule as a file, for the directory and for each entry in it
+        let dir = match self.contained(path) {
+            Some(d) => d,
+            None => return None,
+        };
+
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(r) => r,
+            Err(why) => {
+                debug!("Can't list {:?}: {:?}", dir, why);
+                return None;
+            }
+        };
+
+        let entries = read_dir.filter_map(|e| e.ok())
+            .filter_map(|e| {
+                let metadata = match e.file_type() {
+                    Ok(ref t) if t.is_symlink() => {
+                        self.contained(&path.join(e.file_name()))
+                            .and_then(|full_path| fs::metadata(full_path).ok())
+                    }
+                    _ => e.metadata().ok(),
+                };
+
+                metadata.filter(is_servable).map(|m| {
+                    DirEntry {
+                        name: e.file_name().to_string_lossy().into_owned(),
+                        metadata: Metadata::from(&m),
+                    }
+                })
+            })
+            .collect();
+
+        Some(entries)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    fn source() -> LocalFs {
+        LocalFs::new(PathBuf::from(env!("CARGO_MANIFEST_DIR")))
+    }
+
+    #[test]
+    fn files() {
+        let mut content = source().open(Path::new("test/foo.html")).unwrap();
+        assert_eq!(content.metadata.len, 28);
+        assert!(!content.metadata.is_dir);
+        assert_eq!(content.full_path,
+                   PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("test/foo.html"));
+
+        let mut whole = Vec::new();
+        content.reader.read_to_end(&mut whole).unwrap();
+
+        let mut part = Vec::new();
+        let mut content = source().open(Path::new("test/foo.html")).unwrap();
+        content.reader.skip(3).unwrap();
+        content.reader.take(5).read_to_end(&mut part).unwrap();
+        assert_eq!(part, &whole[3..8]);
+
+        assert!(source().open(Path::new("test")).is_none());
+        assert!(source().open(Path::new("../../../../../../../etc/passwd")).is_none());
+
+        // whatever can't seek is read past
+        let mut slice = &whole[..];
+        slice.skip(3).unwrap();
+        assert_eq!(slice, &whole[3..]);
+        assert!(slice.skip(100).is_err());
+    }
+
+    #[test]
+    fn directories() {
+        assert!(source().metadata(Path::new("test/site/")).unwrap().is_dir);
+        assert_eq!(source().metadata(Path::new("test/foo.html")).unwrap().len, 28);
+        assert!(source().metadata(Path::new("test/nonexistent")).is_none());
+        assert!(source().metadata(Path::new("/etc")).is_none());
+
+        let entries = source().list(Path::new("test")).unwrap();
+        assert!(entries.iter().any(|e| e.name == "foo.html" && !e.metadata.is_dir));
+        assert!(entries.iter().any(|e| e.name == "lang" && e.metadata.is_dir));
+        assert!(source().list(Path::new("..")).is_none());
+    }
+
+    #[test]
+    fn symlinks() {
+        use std::env;
+        use std::fs;
+        use std::os::unix::fs::symlink;
+
+        let dir = env::temp_dir().join(format!("hppt-symlinks-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("releases/123")).unwrap();
+        fs::write(root.join("releases/123/index.html"), "release 123").unwrap();
+        fs::write(dir.join("secret"), "outside").unwrap();
+        symlink("releases/123", root.join("current")).unwrap();
+        symlink("../secret", root.join("escape")).unwrap();
+
+        let strict = LocalFs::new(root.clone());
+        assert!(strict.open(Path::new("current/index.html")).is_none());
+        assert!(strict.metadata(Path::new("current")).is_none());
+        assert!(strict.list(Path::new("current")).is_none());
+        let names = |entries: Vec<DirEntry>| {
+            let mut names = entries.into_iter().map(|e| e.name).collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(strict.list(Path::new("")).unwrap()), vec!["releases"]);
+
+        let following = LocalFs::new(root.clone()).follow_symlinks(true);
+        let content = following.open(Path::new("current/index.html")).unwrap();
+        assert_eq!(content.full_path,
+                   root.canonicalize().unwrap().join("releases/123/index.html"));
+        assert!(following.metadata(Path::new("current")).unwrap().is_dir);
+        assert_eq!(names(following.list(Path::new("current")).unwrap()), vec!["index.html"]);
+        assert_eq!(names(following.list(Path::new("")).unwrap()),
+                   vec!["current", "releases"]);
+
+        // as long as they lead somewhere inside the root
+        assert!(following.open(Path::new("escape")).is_none());
+        assert!(following.metadata(Path::new("escape")).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
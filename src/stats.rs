@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use json;
+
+/// Where the admin endpoint serves them, relative to the root.
+pub const ADMIN_URI: &'static str = "__admin/stats";
+
+/// Why the server stopped listening.
+#[derive(Debug)]
+pub enum ShutdownReason {
+    /// Someone asked for it over the shutdown channel.
+    Requested,
+    /// The process got a signal (named here) asking it to stop.
+    Signal(&'static str),
+    /// Something went wrong which the server can't carry on from.
+    Fatal(String),
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShutdownReason::Requested => write!(f, "requested"),
+            ShutdownReason::Signal(name) => write!(f, "{}", name),
+            ShutdownReason::Fatal(ref why) => write!(f, "fatal error: {}", why),
+        }
+    }
+}
+
+/// What went into compressing one response's body, or into finding a copy compressed ahead of
+/// time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Compression {
+    /// The content-coding, e.g. `gzip`.
+    pub coding: &'static str,
+    /// Whether the body was a sibling file compressed ahead of time, e.g. `page.html.br`.
+    pub precompressed: bool,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+    /// Spent compressing the body, so none for a precompressed one.
+    pub cpu_time: Duration,
+}
+
+impl Compression {
+    /// What the totals for this sort of compression are reported as, e.g. `gzip` or
+    /// `precompressed_br`.
+    fn name(&self) -> String {
+        if self.precompressed {
+            format!("precompressed_{}", self.coding)
+        } else {
+            self.coding.to_owned()
+        }
+    }
+}
+
+/// Compression of one sort, added up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct CompressionTotals {
+    responses: usize,
+    original_bytes: u64,
+    compressed_bytes: u64,
+    cpu_time: Duration,
+}
+
+impl CompressionTotals {
+    /// Compressed size as a fraction of the original, smaller being better.
+    fn ratio(&self) -> f64 {
+        if self.original_bytes == 0 {
+            1.0
+        } else {
+            self.compressed_bytes as f64 / self.original_bytes as f64
+        }
+    }
+}
+
+/// Running totals over the server's lifetime, for the summary logged when it shuts down and the
+/// admin endpoint. Shared between all of the connection coroutines.
+#[derive(Debug)]
+pub struct Stats {
+    started: Instant,
+    requests: AtomicUsize,
+    client_errors: AtomicUsize,
+    server_errors: AtomicUsize,
+    incomplete_responses: AtomicUsize,
+    body_bytes: AtomicUsize,
+    /// By `Compression::name`, so they're reported in a stable order.
+    compression: Mutex<BTreeMap<String, CompressionTotals>>,
+    /// Failed TLS handshakes, by `HandshakeFailure::name`. These aren't requests, so they aren't
+    /// counted as errors along with them.
+    handshake_failures: Mutex<BTreeMap<&'static str, usize>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            requests: AtomicUsize::new(0),
+            client_errors: AtomicUsize::new(0),
+            server_errors: AtomicUsize::new(0),
+            incomplete_responses: AtomicUsize::new(0),
+            body_bytes: AtomicUsize::new(0),
+            compression: Mutex::new(BTreeMap::new()),
+            handshake_failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Count a response by its status code, along with however many of its body bytes made it to
+    /// the client and whether that was all of them.
+    pub fn record_response(&self, status: u16, body_bytes: usize, complete: bool) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.body_bytes.fetch_add(body_bytes, Ordering::Relaxed);
+
+        if status >= 500 {
+            self.server_errors.fetch_add(1, Ordering::Relaxed);
+        } else if status >= 400 {
+            self.client_errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if !complete {
+            self.incomplete_responses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Count a response whose body was compressed. The work's counted whether or not the body was
+    /// `sent`, as it was done either way, but the response and its sizes only if it was (rather
+    /// than left off, as for a HEAD), so the ratio is of what clients actually got.
+    pub fn record_compression(&self, compression: &Compression, sent: bool) {
+        let mut totals = self.compression.lock().unwrap();
+        let totals = totals.entry(compression.name()).or_insert_with(CompressionTotals::default);
+
+        totals.cpu_time += compression.cpu_time;
+        if sent {
+            totals.responses += 1;
+            totals.original_bytes += compression.original_bytes;
+            totals.compressed_bytes += compression.compressed_bytes;
+        }
+    }
+
+    /// Count a client's TLS handshake failing, for the reason named.
+    pub fn record_handshake_failure(&self, reason: &'static str) {
+        *self.handshake_failures.lock().unwrap().entry(reason).or_insert(0) += 1;
+    }
+
+    /// A single `key=value` line describing the server's lifetime, to be logged on shutdown.
+    pub fn summary(&self, reason: &ShutdownReason) -> String {
+        let uptime = self.started.elapsed();
+
+        let mut summary = format!("shutdown reason=\"{}\" uptime={}.{:03}s requests={} \
+                                   client_errors={} server_errors={} incomplete_responses={} \
+                                   body_bytes={}",
+                                  reason,
+                                  uptime.as_secs(),
+                                  uptime.subsec_nanos() / 1_000_000,
+                                  self.requests.load(Ordering::Relaxed),
+                                  self.client_errors.load(Ordering::Relaxed),
+                                  self.server_errors.load(Ordering::Relaxed),
+                                  self.incomplete_responses.load(Ordering::Relaxed),
+                                  self.body_bytes.load(Ordering::Relaxed));
+
+        for (name, totals) in self.compression.lock().unwrap().iter() {
+            summary.push_str(&format!(" {0}_responses={1} {0}_ratio={2:.3} {0}_cpu={3:.3}s",
+                                      name,
+                                      totals.responses,
+                                      totals.ratio(),
+                                      seconds(totals.cpu_time)));
+        }
+
+        for (reason, count) in self.handshake_failures.lock().unwrap().iter() {
+            summary.push_str(&format!(" tls_{}_failures={}", reason, count));
+        }
+
+        summary
+    }
+
+    /// The totals so far as a JSON object, e.g. `{"uptime_seconds":12.5,"requests":3,...,
+    /// "compression":{"gzip":{"responses":1,...}},"tls_handshake_failures":{"protocol":2}}`.
+    pub fn to_json(&self) -> String {
+        let compression = self.compression
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, totals)| {
+                format!("{}:{{\"responses\":{},\"original_bytes\":{},\"compressed_bytes\":{},\
+                         \"ratio\":{:.3},\"cpu_seconds\":{:.6}}}",
+                        json::string(name),
+                        totals.responses,
+                        totals.original_bytes,
+                        totals.compressed_bytes,
+                        totals.ratio(),
+                        seconds(totals.cpu_time))
+            })
+            .collect::<Vec<_>>();
+        let handshake_failures = self.handshake_failures
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(reason, count)| format!("{}:{}", json::string(reason), count))
+            .collect::<Vec<_>>();
+
+        format!("{{\"uptime_seconds\":{:.3},\"requests\":{},\"client_errors\":{},\
+                 \"server_errors\":{},\"incomplete_responses\":{},\"body_bytes\":{},\
+                 \"compression\":{{{}}},\"tls_handshake_failures\":{{{}}}}}",
+                seconds(self.started.elapsed()),
+                self.requests.load(Ordering::Relaxed),
+                self.client_errors.load(Ordering::Relaxed),
+                self.server_errors.load(Ordering::Relaxed),
+                self.incomplete_responses.load(Ordering::Relaxed),
+                self.body_bytes.load(Ordering::Relaxed),
+                compression.join(","),
+                handshake_failures.join(","))
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summary() {
+        let stats = Stats::new();
+        stats.record_response(200, 100, true);
+        stats.record_response(404, 0, true);
+        stats.record_response(500, 0, true);
+        stats.record_response(206, 20, false);
+
+        let summary = stats.summary(&ShutdownReason::Fatal("out of sockets".to_owned()));
+
+        assert!(summary.starts_with("shutdown reason=\"fatal error: out of sockets\" uptime="));
+        assert!(summary.ends_with(" requests=4 client_errors=1 server_errors=1 \
+                                   incomplete_responses=1 body_bytes=120"));
+    }
+
+    #[test]
+    fn compression() {
+        let stats = Stats::new();
+        let gzip = Compression {
+            coding: "gzip",
+            precompressed: false,
+            original_bytes: 1000,
+            compressed_bytes: 300,
+            cpu_time: Duration::from_millis(2),
+        };
+        stats.record_compression(&gzip, true);
+        stats.record_compression(&Compression { compressed_bytes: 100, ..gzip }, true);
+        // as for a HEAD: the work was done, but nothing saved
+        stats.record_compression(&Compression { compressed_bytes: 900, ..gzip }, false);
+        stats.record_compression(&Compression {
+                                     coding: "br",
+                                     precompressed: true,
+                                     original_bytes: 1000,
+                                     compressed_bytes: 250,
+                                     cpu_time: Duration::from_secs(0),
+                                 },
+                                 true);
+
+        let summary = stats.summary(&ShutdownReason::Requested);
+        assert!(summary.ends_with(" body_bytes=0 gzip_responses=2 gzip_ratio=0.200 \
+                                   gzip_cpu=0.006s precompressed_br_responses=1 \
+                                   precompressed_br_ratio=0.250 precompressed_br_cpu=0.000s"));
+
+        let json = stats.to_json();
+        assert!(json.starts_with("{\"uptime_seconds\":"));
+        assert!(json.ends_with(",\"requests\":0,\"client_errors\":0,\"server_errors\":0,\
+                                \"incomplete_responses\":0,\"body_bytes\":0,\"compression\":{\
+                                \"gzip\":{\"responses\":2,\"original_bytes\":2000,\
+                                \"compressed_bytes\":400,\"ratio\":0.200,\"cpu_seconds\":0.006000},\
+                                \"precompressed_br\":{\"responses\":1,\"original_bytes\":1000,\
+                                \"compressed_bytes\":250,\"ratio\":0.250,\
+                                \"cpu_seconds\":0.000000}},\"tls_handshake_failures\":{}}"));
+    }
+
+    #[test]
+    fn handshake_failures() {
+        let stats = Stats::new();
+        stats.record_handshake_failure("protocol");
+        stats.record_handshake_failure("sni");
+        stats.record_handshake_failure("protocol");
+
+        let summary = stats.summary(&ShutdownReason::Requested);
+        assert!(summary.ends_with(" body_bytes=0 tls_protocol_failures=2 tls_sni_failures=1"));
+        assert!(stats.to_json()
+            .ends_with(",\"tls_handshake_failures\":{\"protocol\":2,\"sni\":1}}"));
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use clock::Clock;
+use config::CostClass;
+
+/// Fewest buckets to keep before bothering to forget the full ones.
+const PRUNE_MIN: usize = 1024;
+
+/// How many requests each client address may make, as a token bucket per address shared between
+/// all of the listener coroutines: a bucket holds a second's worth of requests, refills at the
+/// allowed rate, and a request which finds it empty is turned away.
+#[derive(Debug)]
+pub struct RequestRates {
+    per_sec: Option<usize>,
+    trusted: Vec<IpAddr>,
+    clock: Arc<Clock>,
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    by_ip: HashMap<IpAddr, Bucket>,
+    /// How many buckets there may be before the full ones (those of clients which have been
+    /// quiet for a while) are forgotten, making room for more without growing forever.
+    prune_at: usize,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    filled: Instant,
+}
+
+impl RequestRates {
+    /// Requests from addresses in `trusted` (e.g. a reverse proxy, behind which many clients
+    /// share an address) are never limited, and nothing is if `per_sec` is `None`.
+    pub fn new(per_sec: Option<usize>, trusted: Vec<IpAddr>, clock: Arc<Clock>) -> Self {
+        RequestRates {
+            per_sec: per_sec,
+            trusted: trusted,
+            clock: clock,
+            buckets: Mutex::new(Buckets {
+                by_ip: HashMap::new(),
+                prune_at: PRUNE_MIN,
+            }),
+        }
+    }
+
+    /// Take one of a client's requests out of its bucket, or say how long until there'll be one
+    /// to take if it's empty.
+    pub fn admit(&self, ip: IpAddr) -> Result<(), Duration> {
+        let rate = match self.per_sec {
+            Some(n) if !self.trusted.contains(&ip) => n as f64,
+            _ => return Ok(()),
+        };
+
+        let now = self.clock.instant();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.by_ip.len() >= buckets.prune_at && !buckets.by_ip.contains_key(&ip) {
+            buckets.by_ip.retain(|_, bucket| bucket.level(now, rate) < rate);
+            buckets.prune_at = (buckets.by_ip.len() * 2).max(PRUNE_MIN);
+        }
+
+        let bucket = buckets.by_ip.entry(ip).or_insert(Bucket {
+            tokens: rate,
+            filled: now,
+        });
+        bucket.tokens = bucket.level(now, rate);
+        bucket.filled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) / rate;
+            Err(Duration::from_millis((wait * 1000.0).ceil() as u64))
+        }
+    }
+
+    /// How many clients have buckets.
+    #[cfg(test)]
+    fn tracked(&self) -> usize {
+        self.buckets.lock().unwrap().by_ip.len()
+    }
+}
+
+/// What's left of each cost class's budgets, shared between all of the listener coroutines (and
+/// every site) so a burst of one class's requests can't take up the server's time to answer
+/// those of another.
+#[derive(Debug)]
+pub struct ClassBudgets {
+    clock: Arc<Clock>,
+    by_name: HashMap<String, Budget>,
+}
+
+#[derive(Debug)]
+struct Budget {
+    class: CostClass,
+    in_flight: Mutex<usize>,
+    bucket: Mutex<Bucket>,
+}
+
+/// A claim on one of a class's requests at once, given back when dropped.
+#[derive(Debug)]
+pub struct ClassSlot<'a> {
+    budget: Option<&'a Budget>,
+}
+
+impl ClassBudgets {
+    pub fn new(classes: &[CostClass], clock: Arc<Clock>) -> Self {
+        let now = clock.instant();
+        let by_name = classes.iter()
+            .map(|class| {
+                let budget = Budget {
+                    class: class.clone(),
+                    in_flight: Mutex::new(0),
+                    bucket: Mutex::new(Bucket {
+                        tokens: class.per_sec.unwrap_or(0) as f64,
+                        filled: now,
+                    }),
+                };
+                (class.name.clone(), budget)
+            })
+            .collect();
+
+        ClassBudgets {
+            clock: clock,
+            by_name: by_name,
+        }
+    }
+
+    /// Take one of a class's requests out of its budgets, or (if `class` is `None` or isn't one
+    /// of these) out of none at all. If there's no room, say how long to wait before trying
+    /// again.
+    pub fn admit(&self, class: Option<&CostClass>) -> Result<ClassSlot, Duration> {
+        let budget = match class.and_then(|c| self.by_name.get(&c.name)) {
+            Some(b) => b,
+            None => return Ok(ClassSlot { budget: None }),
+        };
+
+        let mut in_flight = budget.in_flight.lock().unwrap();
+        if budget.class.max.map_or(false, |max| *in_flight >= max) {
+            // nobody knows how long those will take, but they're usually short
+            return Err(Duration::from_secs(1));
+        }
+
+        if let Some(per_sec) = budget.class.per_sec {
+            let rate = per_sec as f64;
+            let now = self.clock.instant();
+            let mut bucket = budget.bucket.lock().unwrap();
+            bucket.tokens = bucket.level(now, rate);
+            bucket.filled = now;
+
+            if bucket.tokens < 1.0 {
+                let wait = (1.0 - bucket.tokens) / rate;
+                return Err(Duration::from_millis((wait * 1000.0).ceil() as u64));
+            }
+            bucket.tokens -= 1.0;
+        }
+
+        *in_flight += 1;
+        Ok(ClassSlot { budget: Some(budget) })
+    }
+}
+
+impl<'a> Drop for ClassSlot<'a> {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget {
+            *budget.in_flight.lock().unwrap() -= 1;
+        }
+    }
+}
+
+impl Bucket {
+    /// How many requests there are in the bucket by `now`, refilling at `rate` a second up to
+    /// a second's worth.
+    fn level(&self, now: Instant, rate: f64) -> f64 {
+        let elapsed = now.duration_since(self.filled);
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+        (self.tokens + secs * rate).min(rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use clock::ManualClock;
+    use config::CostClass;
+
+    use super::{ClassBudgets, PRUNE_MIN, RequestRates};
+
+    #[test]
+    fn buckets() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let rates = RequestRates::new(Some(2), Vec::new(), clock.clone());
+        let a: IpAddr = "192.0.2.1".parse().unwrap();
+        let b: IpAddr = "192.0.2.2".parse().unwrap();
+
+        assert_eq!(rates.admit(a), Ok(()));
+        assert_eq!(rates.admit(a), Ok(()));
+        assert_eq!(rates.admit(a), Err(Duration::from_millis(500)));
+        assert_eq!(rates.admit(b), Ok(()));
+
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(rates.admit(a), Err(Duration::from_millis(250)));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(rates.admit(a), Ok(()));
+
+        // a quiet client's bucket only fills up to a second's worth
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(rates.admit(a), Ok(()));
+        assert_eq!(rates.admit(a), Ok(()));
+        assert!(rates.admit(a).is_err());
+    }
+
+    #[test]
+    fn exemptions() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let proxy: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let rates = RequestRates::new(Some(1), vec![proxy], clock.clone());
+        assert!((0..10).all(|_| rates.admit(proxy).is_ok()));
+        assert_eq!(rates.tracked(), 0);
+
+        let rates = RequestRates::new(None, Vec::new(), clock);
+        assert!((0..10).all(|_| rates.admit(proxy).is_ok()));
+    }
+
+    #[test]
+    fn pruning() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let rates = RequestRates::new(Some(1), Vec::new(), clock.clone());
+        let addr = |n: usize| IpAddr::V4(Ipv4Addr::new(10, 0, (n >> 8) as u8, n as u8));
+
+        for n in 0..PRUNE_MIN {
+            assert!(rates.admit(addr(n)).is_ok());
+        }
+        assert_eq!(rates.tracked(), PRUNE_MIN);
+
+        // clients which have since had their fill are forgotten, to make room for new ones
+        clock.advance(Duration::from_secs(1));
+        assert!(rates.admit(addr(PRUNE_MIN)).is_ok());
+        assert_eq!(rates.tracked(), 1);
+    }
+
+    #[test]
+    fn class_budgets() {
+        let clock = Arc::new(ManualClock::new(UNIX_EPOCH));
+        let cgi: CostClass = "cgi=2/3".parse().unwrap();
+        let other: CostClass = "other=1".parse().unwrap();
+        let budgets = ClassBudgets::new(&[cgi.clone()], clock.clone());
+
+        // at most two at once
+        let first = budgets.admit(Some(&cgi)).unwrap();
+        let second = budgets.admit(Some(&cgi)).unwrap();
+        assert_eq!(budgets.admit(Some(&cgi)).err(), Some(Duration::from_secs(1)));
+
+        // other requests aren't held up by them
+        assert!(budgets.admit(None).is_ok());
+        assert!((0..10).all(|_| budgets.admit(Some(&other)).is_ok()));
+
+        // and three a second, room or no room
+        drop(first);
+        let third = budgets.admit(Some(&cgi)).unwrap();
+        drop(second);
+        drop(third);
+        assert!(budgets.admit(Some(&cgi)).is_err());
+
+        clock.advance(Duration::from_millis(400));
+        assert!(budgets.admit(Some(&cgi)).is_ok());
+    }
+}
@@ -0,0 +1,422 @@
+//! TLS for client connections, through the system's OpenSSL (1.1.0 or later).
+//!
+//! Each connection's `SSL` gets a pair of memory buffers in place of a socket, and bytes are moved
+//! between those and the connection here, so OpenSSL never touches (or blocks) the coroutine's
+//! socket, and the connection's timeouts apply to the handshake as they do to everything else.
+
+use std::cmp;
+use std::ffi::CString;
+use std::io;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use libc::{c_char, c_int, c_long, c_ulong, c_void, size_t};
+
+use connection::ReadDeadline;
+use stats::Stats;
+
+#[allow(non_camel_case_types)]
+enum SSL_CTX {}
+#[allow(non_camel_case_types)]
+enum SSL_METHOD {}
+#[allow(non_camel_case_types)]
+enum SSL {}
+#[allow(non_camel_case_types)]
+enum BIO {}
+#[allow(non_camel_case_types)]
+enum BIO_METHOD {}
+
+const SSL_FILETYPE_PEM: c_int = 1;
+const SSL_CTRL_SET_MIN_PROTO_VERSION: c_int = 123;
+const TLS1_2_VERSION: c_long = 0x0303;
+
+const SSL_ERROR_WANT_READ: c_int = 2;
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+#[link(name = "ssl")]
+#[link(name = "crypto")]
+extern "C" {
+    fn TLS_server_method() -> *const SSL_METHOD;
+    fn SSL_CTX_new(method: *const SSL_METHOD) -> *mut SSL_CTX;
+    fn SSL_CTX_free(ctx: *mut SSL_CTX);
+    fn SSL_CTX_ctrl(ctx: *mut SSL_CTX, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_use_certificate_chain_file(ctx: *mut SSL_CTX, file: *const c_char) -> c_int;
+    fn SSL_CTX_use_PrivateKey_file(ctx: *mut SSL_CTX, file: *const c_char, kind: c_int) -> c_int;
+    fn SSL_CTX_check_private_key(ctx: *const SSL_CTX) -> c_int;
+
+    fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
+    fn SSL_free(ssl: *mut SSL);
+    fn SSL_set_bio(ssl: *mut SSL, rbio: *mut BIO, wbio: *mut BIO);
+    fn SSL_set_accept_state(ssl: *mut SSL);
+    fn SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
+    fn SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int;
+    fn SSL_get_error(ssl: *const SSL, ret: c_int) -> c_int;
+    fn SSL_shutdown(ssl: *mut SSL) -> c_int;
+    fn SSL_is_init_finished(ssl: *const SSL) -> c_int;
+
+    fn BIO_new(kind: *const BIO_METHOD) -> *mut BIO;
+    fn BIO_s_mem() -> *const BIO_METHOD;
+    fn BIO_read(bio: *mut BIO, data: *mut c_void, len: c_int) -> c_int;
+    fn BIO_write(bio: *mut BIO, data: *const c_void, len: c_int) -> c_int;
+    fn BIO_ctrl_pending(bio: *mut BIO) -> size_t;
+
+    fn ERR_get_error() -> c_ulong;
+    fn ERR_error_string_n(e: c_ulong, buf: *mut c_char, len: size_t);
+}
+
+/// Most to move between a connection and its `SSL` at a time: one TLS record's worth.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// What OpenSSL has to say about the last thing to go wrong on this thread, as an I/O error.
+fn last_error() -> io::Error {
+    let mut messages = Vec::new();
+
+    loop {
+        let code = unsafe { ERR_get_error() };
+        if code == 0 {
+            break;
+        }
+
+        let mut buf = [0u8; 256];
+        unsafe { ERR_error_string_n(code, buf.as_mut_ptr() as *mut c_char, buf.len()) };
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        messages.push(String::from_utf8_lossy(&buf[..len]).into_owned());
+    }
+
+    if messages.is_empty() {
+        messages.push("unknown TLS error".to_owned());
+    }
+
+    io::Error::new(io::ErrorKind::Other, messages.join("; "))
+}
+
+/// Why a client's handshake failed, as far as OpenSSL's errors tell, so that attack noise can be
+/// told apart from misconfiguration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HandshakeFailure {
+    /// The client asked for a host name (by SNI) we have no certificate for.
+    Sni,
+    /// No protocol version or cipher suite in common, or not TLS at all, as from a client
+    /// speaking plain HTTP.
+    Protocol,
+    /// The client's certificate was rejected.
+    ClientCertificate,
+    Other,
+}
+
+impl HandshakeFailure {
+    /// Going by the messages of an error from `last_error`.
+    fn classify(why: &str) -> HandshakeFailure {
+        let why = why.to_ascii_lowercase();
+        let any = |phrases: &[&str]| phrases.iter().any(|p| why.contains(p));
+
+        if any(&["unrecognized name", "servername"]) {
+            HandshakeFailure::Sni
+        } else if any(&["peer did not return a certificate", "certificate verify failed"]) {
+            HandshakeFailure::ClientCertificate
+        } else if any(&["wrong version number",
+                        "unsupported protocol",
+                        "version too low",
+                        "unknown protocol",
+                        "no protocols available",
+                        "no shared cipher",
+                        "http request",
+                        "inappropriate fallback"]) {
+            HandshakeFailure::Protocol
+        } else {
+            HandshakeFailure::Other
+        }
+    }
+
+    /// What it's counted as in the server's stats.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            HandshakeFailure::Sni => "sni",
+            HandshakeFailure::Protocol => "protocol",
+            HandshakeFailure::ClientCertificate => "client_certificate",
+            HandshakeFailure::Other => "other",
+        }
+    }
+}
+
+fn c_path(path: &Path) -> io::Result<CString> {
+    path.to_str()
+        .and_then(|p| CString::new(p).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "unusable path"))
+}
+
+/// A certificate (chain) and private key to accept TLS connections with.
+#[derive(Debug)]
+pub struct TlsAcceptor {
+    ctx: *mut SSL_CTX,
+}
+
+// an SSL_CTX is safe to share between threads once it's set up, as it is by the time `new` is
+// done with it
+unsafe impl Send for TlsAcceptor {}
+unsafe impl Sync for TlsAcceptor {}
+
+impl TlsAcceptor {
+    /// Load a PEM certificate chain (the server's certificate first) and the PEM private key
+    /// which goes with it. Clients have to speak TLS 1.2 or later.
+    pub fn new(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+        let (cert, key) = (try!(c_path(cert)), try!(c_path(key)));
+
+        let ctx = unsafe { SSL_CTX_new(TLS_server_method()) };
+        if ctx.is_null() {
+            return Err(last_error());
+        }
+        // freed on the way out if anything below fails
+        let acceptor = TlsAcceptor { ctx: ctx };
+
+        let ok = unsafe {
+            SSL_CTX_ctrl(ctx, SSL_CTRL_SET_MIN_PROTO_VERSION, TLS1_2_VERSION, ptr::null_mut()) ==
+            1 && SSL_CTX_use_certificate_chain_file(ctx, cert.as_ptr()) == 1 &&
+            SSL_CTX_use_PrivateKey_file(ctx, key.as_ptr(), SSL_FILETYPE_PEM) == 1 &&
+            SSL_CTX_check_private_key(ctx) == 1
+        };
+
+        if ok {
+            Ok(acceptor)
+        } else {
+            Err(last_error())
+        }
+    }
+
+    /// Start on the server side of a TLS connection over a stream from a client at `peer`. The
+    /// handshake happens as part of the first read, and if it fails, why is logged and counted in
+    /// `stats`.
+    pub fn accept<S: Read + Write>(&self,
+                                   stream: S,
+                                   peer: SocketAddr,
+                                   stats: Arc<Stats>)
+                                   -> io::Result<TlsStream<S>> {
+        unsafe {
+            let ssl = SSL_new(self.ctx);
+            if ssl.is_null() {
+                return Err(last_error());
+            }
+
+            let (rbio, wbio) = (BIO_new(BIO_s_mem()), BIO_new(BIO_s_mem()));
+            // the SSL owns the buffers from here on, and frees them with itself
+            SSL_set_bio(ssl, rbio, wbio);
+            SSL_set_accept_state(ssl);
+
+            Ok(TlsStream {
+                ssl: ssl,
+                rbio: rbio,
+                wbio: wbio,
+                stream: stream,
+                peer: peer,
+                stats: stats,
+            })
+        }
+    }
+}
+
+impl Drop for TlsAcceptor {
+    fn drop(&mut self) {
+        unsafe { SSL_CTX_free(self.ctx) };
+    }
+}
+
+/// A stream with TLS on top, read and written in plaintext.
+pub struct TlsStream<S: Read + Write> {
+    ssl: *mut SSL,
+    /// What's been received from the client, for OpenSSL to decrypt.
+    rbio: *mut BIO,
+    /// What OpenSSL has for the client, to be sent.
+    wbio: *mut BIO,
+    stream: S,
+    peer: SocketAddr,
+    stats: Arc<Stats>,
+}
+
+impl<S: Read + Write> TlsStream<S> {
+    /// Read what the client has sent next into OpenSSL, returning how much that was.
+    fn receive(&mut self) -> io::Result<usize> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+
+        let n = try!(self.stream.read(&mut chunk));
+
+        // a memory BIO grows to take all it's given, so short of running out of memory this is one
+        // write, but whatever it didn't take would be lost from the middle of the stream
+        let mut written = 0;
+        while written < n {
+            let rest = &chunk[written..n];
+            let taken = unsafe {
+                BIO_write(self.rbio, rest.as_ptr() as *const c_void, rest.len() as c_int)
+            };
+            if taken <= 0 {
+                return Err(io::Error::new(io::ErrorKind::Other,
+                                          "unable to pass what the client sent on to OpenSSL"));
+            }
+            written += taken as usize;
+        }
+
+        Ok(n)
+    }
+
+    /// What OpenSSL has to say about an `SSL_read` or `SSL_write` failing, after logging and
+    /// counting it if it was the handshake which failed.
+    fn failure(&self) -> io::Error {
+        let why = last_error();
+
+        if unsafe { SSL_is_init_finished(self.ssl) } == 0 {
+            let failure = HandshakeFailure::classify(&why.to_string());
+            debug!("TLS handshake with {} failed ({}): {}", self.peer, failure.name(), why);
+            self.stats.record_handshake_failure(failure.name());
+        }
+
+        why
+    }
+
+    /// Send whatever OpenSSL has for the client.
+    fn send_pending(&mut self) -> io::Result<()> {
+        let mut chunk = [0u8; CHUNK_SIZE];
+
+        while unsafe { BIO_ctrl_pending(self.wbio) } > 0 {
+            let n = unsafe {
+                BIO_read(self.wbio, chunk.as_mut_ptr() as *mut c_void, chunk.len() as c_int)
+            };
+            if n <= 0 {
+                break;
+            }
+
+            try!(self.stream.write_all(&chunk[..n as usize]));
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Read + Write + ReadDeadline> ReadDeadline for TlsStream<S> {
+    fn set_read_deadline(&mut self, deadline: Option<Instant>) {
+        self.stream.set_read_deadline(deadline);
+    }
+}
+
+impl<S: Read + Write> Read for TlsStream<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = cmp::min(buf.len(), c_int::max_value() as usize) as c_int;
+
+        loop {
+            let n = unsafe { SSL_read(self.ssl, buf.as_mut_ptr() as *mut c_void, len) };
+            if n > 0 {
+                return Ok(n as usize);
+            }
+
+            let error = unsafe { SSL_get_error(self.ssl, n) };
+
+            // in the middle of the handshake, OpenSSL will have something to say before it can
+            // hear any more
+            try!(self.send_pending());
+
+            match error {
+                // a client which hangs up without a close_notify is treated like any other which
+                // hangs up, as HTTP's own framing tells whether anything went missing
+                SSL_ERROR_WANT_READ => {
+                    if try!(self.receive()) == 0 {
+                        return Ok(0);
+                    }
+                }
+                SSL_ERROR_ZERO_RETURN => return Ok(0),
+                _ => return Err(self.failure()),
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> Write for TlsStream<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = cmp::min(buf.len(), c_int::max_value() as usize) as c_int;
+
+        loop {
+            let n = unsafe { SSL_write(self.ssl, buf.as_ptr() as *const c_void, len) };
+            let error = if n > 0 { 0 } else { unsafe { SSL_get_error(self.ssl, n) } };
+
+            try!(self.send_pending());
+
+            if n > 0 {
+                return Ok(n as usize);
+            }
+
+            match error {
+                // the handshake isn't done yet
+                SSL_ERROR_WANT_READ => {
+                    if try!(self.receive()) == 0 {
+                        return Err(io::Error::new(io::ErrorKind::WriteZero,
+                                                  "client hung up during the handshake"));
+                    }
+                }
+                _ => return Err(self.failure()),
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        try!(self.send_pending());
+        self.stream.flush()
+    }
+}
+
+impl<S: Read + Write> Drop for TlsStream<S> {
+    fn drop(&mut self) {
+        unsafe {
+            // say goodbye properly, if the client's still there to hear it
+            SSL_shutdown(self.ssl);
+            let _ = self.send_pending();
+
+            // clear out anything a failed goodbye left for the next connection on this thread
+            while ERR_get_error() != 0 {}
+
+            SSL_free(self.ssl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn loading() {
+        let (cert, key) = (Path::new("test/tls/cert.pem"), Path::new("test/tls/key.pem"));
+
+        assert!(TlsAcceptor::new(cert, key).is_ok());
+        assert!(TlsAcceptor::new(cert, Path::new("test/tls/nonexistent.pem")).is_err());
+
+        // a certificate is no private key
+        let why = TlsAcceptor::new(cert, cert).unwrap_err();
+        assert!(!why.to_string().is_empty());
+    }
+
+    #[test]
+    fn handshake_failures() {
+        let classify = HandshakeFailure::classify;
+
+        assert_eq!(classify("error:0A00009C:SSL routines::http request"),
+                   HandshakeFailure::Protocol);
+        assert_eq!(classify("error:1408F10B:SSL routines:ssl3_get_record:wrong version number"),
+                   HandshakeFailure::Protocol);
+        assert_eq!(classify("error:0A0000C7:SSL routines::peer did not return a certificate"),
+                   HandshakeFailure::ClientCertificate);
+        assert_eq!(classify("error:0A000458:SSL routines::tlsv1 unrecognized name"),
+                   HandshakeFailure::Sni);
+        assert_eq!(classify("unknown TLS error"), HandshakeFailure::Other);
+    }
+}
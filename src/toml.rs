@@ -0,0 +1,360 @@
+//! Just enough of TOML for a file of settings: `key = value` pairs at the top level, with basic
+//! and literal strings, integers, booleans and (possibly multi-line) arrays of those as values.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+impl Value {
+    /// What sort of value this is, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+            Value::Array(_) => "an array",
+        }
+    }
+}
+
+/// A setting, and the line it's on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+/// Where and why a file couldn't be parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Error {
+    /// Counting from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// The settings in a file, in the order they appear.
+pub fn parse(text: &str) -> Result<Vec<Entry>, Error> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let lines = text.lines().collect::<Vec<_>>();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line_no = i + 1;
+        let error = |message: String| {
+            Error {
+                line: line_no,
+                message: message,
+            }
+        };
+
+        let line = lines[i].trim();
+        i += 1;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if line.starts_with('[') {
+            return Err(error("tables aren't supported: settings go at the top level".to_owned()));
+        }
+
+        let (key, rest) = try!(parse_key(line).map_err(&error));
+        let rest = rest.trim_start();
+        if !rest.starts_with('=') {
+            return Err(error(format!("expected = after {}", key)));
+        }
+
+        // an array may go on over several lines, until its brackets balance
+        let mut text = rest[1..].to_owned();
+        while i < lines.len() && open_brackets(&text) > 0 {
+            text.push('\n');
+            text.push_str(lines[i]);
+            i += 1;
+        }
+
+        let mut chars = Chars::new(&text);
+        chars.skip_blank();
+        let value = try!(parse_value(&mut chars).map_err(&error));
+        chars.skip_blank();
+        if !chars.at_end() {
+            return Err(error(format!("unexpected {:?} after the value of {}",
+                                     chars.rest(),
+                                     key)));
+        }
+
+        if entries.iter().any(|e| e.key == key) {
+            return Err(error(format!("{} is set more than once", key)));
+        }
+
+        entries.push(Entry {
+            key: key,
+            value: value,
+            line: line_no,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// A bare (`threads`) or quoted (`"threads"`) key from the start of a line, and what follows it.
+fn parse_key(line: &str) -> Result<(String, &str), String> {
+    if line.starts_with('"') || line.starts_with('\'') {
+        let mut chars = Chars::new(line);
+        let key = match try!(parse_value(&mut chars)) {
+            Value::String(s) => s,
+            _ => unreachable!(),
+        };
+        return Ok((key, chars.rest()));
+    }
+
+    let end = line.find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+        .unwrap_or(line.len());
+    if end == 0 {
+        return Err(format!("expected a setting, found {:?}", line));
+    }
+
+    Ok((line[..end].to_owned(), &line[end..]))
+}
+
+/// How many more `[` than `]` there are in some text, outside of strings and comments.
+fn open_brackets(text: &str) -> isize {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut comment = false;
+
+    for c in text.chars() {
+        if comment {
+            comment = c != '\n';
+            continue;
+        }
+
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => {
+                match c {
+                    '"' | '\'' => quote = Some(c),
+                    '[' => depth += 1,
+                    ']' => depth -= 1,
+                    '#' => comment = true,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    depth
+}
+
+/// The characters of a value being parsed.
+struct Chars<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Chars<'a> {
+    fn new(text: &'a str) -> Self {
+        Chars {
+            text: text,
+            pos: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += c.len_utf8();
+        }
+        c
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos == self.text.len()
+    }
+
+    /// Skip whitespace, newlines and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.next();
+                }
+                Some('#') => {
+                    while self.peek().map_or(false, |c| c != '\n') {
+                        self.next();
+                    }
+                }
+                _ => return,
+            }
+        }
+    }
+}
+
+fn parse_value(chars: &mut Chars) -> Result<Value, String> {
+    match chars.peek() {
+        Some('"') => parse_basic_string(chars).map(Value::String),
+        Some('\'') => {
+            chars.next();
+            let rest = chars.rest();
+            match rest.find('\'') {
+                Some(end) if !rest[..end].contains('\n') => {
+                    chars.pos += end + 1;
+                    Ok(Value::String(rest[..end].to_owned()))
+                }
+                _ => Err("unterminated string".to_owned()),
+            }
+        }
+        Some('[') => {
+            chars.next();
+            let mut values = Vec::new();
+
+            loop {
+                chars.skip_blank();
+                if chars.peek() == Some(']') {
+                    chars.next();
+                    return Ok(Value::Array(values));
+                }
+
+                values.push(try!(parse_value(chars)));
+
+                chars.skip_blank();
+                match chars.next() {
+                    Some(',') => (),
+                    Some(']') => return Ok(Value::Array(values)),
+                    _ => return Err("expected , or ] in an array".to_owned()),
+                }
+            }
+        }
+        Some(_) => {
+            let rest = chars.rest();
+            let end = rest.find(|c: char| c.is_whitespace() || c == ',' || c == ']' || c == '#')
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            chars.pos += end;
+
+            match word {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => {
+                    let digits = word.replace('_', "");
+                    match digits.parse::<i64>() {
+                        Ok(n) if !word.starts_with('_') && !word.ends_with('_') => {
+                            Ok(Value::Integer(n))
+                        }
+                        _ => Err(format!("{:?} isn't a value (strings need quotes)", word)),
+                    }
+                }
+            }
+        }
+        None => Err("missing value".to_owned()),
+    }
+}
+
+fn parse_basic_string(chars: &mut Chars) -> Result<String, String> {
+    chars.next();
+    let mut s = String::new();
+
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(s),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('r') => '\r',
+                    Some('u') => {
+                        let hex = chars.rest().chars().take(4).collect::<String>();
+                        chars.pos += hex.len();
+                        match u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+                            Some(c) if hex.len() == 4 => c,
+                            _ => return Err(format!("bad escape \\u{}", hex)),
+                        }
+                    }
+                    Some(c) => return Err(format!("bad escape \\{}", c)),
+                    None => return Err("unterminated string".to_owned()),
+                };
+                s.push(escaped);
+            }
+            Some('\n') | None => return Err("unterminated string".to_owned()),
+            Some(c) => s.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn values(text: &str) -> Vec<(String, Value)> {
+        parse(text).unwrap().into_iter().map(|e| (e.key, e.value)).collect()
+    }
+
+    #[test]
+    fn settings() {
+        let text = "# a comment\n\
+                    threads = 4\n\
+                    autoindex = true   # another\n\
+                    \"root\" = \"/srv/www\"\n\
+                    mime-type = ['map=application/json', \"md=text/\\u0078-markdown\"]\n\
+                    index-file = [\n  \"index.html\", # the usual\n  \"home.html\",\n]\n\
+                    max-request-size = 1_048_576\n";
+
+        assert_eq!(values(text),
+                   vec![("threads".to_owned(), Value::Integer(4)),
+                        ("autoindex".to_owned(), Value::Boolean(true)),
+                        ("root".to_owned(), Value::String("/srv/www".to_owned())),
+                        ("mime-type".to_owned(),
+                         Value::Array(vec![Value::String("map=application/json".to_owned()),
+                                           Value::String("md=text/x-markdown".to_owned())])),
+                        ("index-file".to_owned(),
+                         Value::Array(vec![Value::String("index.html".to_owned()),
+                                           Value::String("home.html".to_owned())])),
+                        ("max-request-size".to_owned(), Value::Integer(1048576))]);
+
+        assert_eq!(parse("\n\nthreads = 4\n").unwrap()[0].line, 3);
+    }
+
+    #[test]
+    fn errors() {
+        let line_of = |text: &str| parse(text).unwrap_err().line;
+
+        assert_eq!(line_of("threads = 4\nroot = /srv/www\n"), 2);
+        assert_eq!(line_of("threads = 4\n[limits]\n"), 2);
+        assert_eq!(line_of("threads 4\n"), 1);
+        assert_eq!(line_of("root = \"/srv\n"), 1);
+        assert_eq!(line_of("threads = 4 5\n"), 1);
+        assert_eq!(line_of("threads = 4\nthreads = 5\n"), 2);
+        assert_eq!(line_of("index-file = [\"a\",\n\"b\"\n"), 1);
+    }
+}
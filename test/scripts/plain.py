@@ -0,0 +1 @@
+print('Content-Type: text/plain\r\n\r\nHello from an interpreter!')